@@ -76,18 +76,23 @@ where
     where
         Q: Borrow<E> + Eq + PartialEq,
     {
-        let stride = usize::from(&self.element_size);
-        let data: &'me [u8] = self.data.as_ref();
-
-        // this is O(N) in operations, but the search is O(log(N)) in comparissons
-        let wrapper: Vec<&'me [u8]> = data[0..self.len() * stride].chunks_exact(stride).collect();
-
-        let de: Vec<<E as Storeable>::Output> = wrapper
-            .iter()
-            .map(|slice| E::read(&slice[..]).unwrap())
-            .collect();
+        // only the probed elements are deserialized, as reading a key may allocate
+        let element = element.borrow();
+        let mut low = 0;
+        let mut high = self.len();
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let probe = self.get(mid).unwrap();
+
+            match probe.borrow().cmp(element) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
 
-        de.binary_search_by_key(&element.borrow(), |s| s.borrow())
+        Err(low)
     }
 
     pub(crate) fn linear_search<'me, Q: 'me>(&'me self, element: Q) -> Option<usize>
@@ -199,7 +204,6 @@ where
         }
     }
 
-    #[allow(dead_code)]
     pub(crate) fn delete(&mut self, pos: usize) -> Result<(), ()> {
         if pos < self.len() {
             unsafe {
@@ -0,0 +1,352 @@
+use super::node::Node;
+use super::pages::{PageRef, Pages};
+use super::version::Version;
+use super::PageId;
+use crate::storage::{Backend, Disk, Storage};
+use crate::{Key, Value};
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/// A position in the tree, represented as the path of pages from the root to a leaf.
+/// Each entry of the path keeps the index of the child (for internal nodes) or the key
+/// (for the leaf) the cursor is pointing to.
+///
+/// There are no sibling pointers in the leaves, so moving to the next (or previous) leaf
+/// is done by going up in the path until there is a parent with a child to the right (left),
+/// and then descending to the leftmost (rightmost) leaf of that subtree.
+///
+/// An empty path means the cursor is exhausted.
+pub(crate) struct Cursor<'a, K, S> {
+    pages: &'a Pages<S>,
+    path: Vec<(PageRef, usize)>,
+    phantom: PhantomData<[K]>,
+}
+
+enum Seek<'k, K> {
+    First,
+    Last,
+    GreaterOrEqual(&'k K),
+    Greater(&'k K),
+    LessOrEqual(&'k K),
+    Less(&'k K),
+}
+
+// derive would require K: Copy
+impl<'k, K> Clone for Seek<'k, K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'k, K> Copy for Seek<'k, K> {}
+
+impl<'a, K, S> Cursor<'a, K, S>
+where
+    K: Key,
+    S: for<'b> Storage<'b>,
+{
+    fn new(pages: &'a Pages<S>) -> Self {
+        Cursor {
+            pages,
+            path: vec![],
+            phantom: PhantomData,
+        }
+    }
+
+    fn get_page(&self, id: PageId) -> PageRef {
+        self.pages
+            .get_page(id)
+            .expect("tree points to a non existent page")
+    }
+
+    fn seek(root: PageId, pages: &'a Pages<S>, seek: Seek<K>) -> Self {
+        let mut cursor = Cursor::new(pages);
+        let mut current = cursor.get_page(root);
+
+        loop {
+            let next = current.as_node(|node: Node<K, &[u8]>| {
+                node.as_internal().map(|inode| {
+                    let last = inode.children().len().checked_sub(1).unwrap();
+                    let pos = match seek {
+                        Seek::First => 0,
+                        Seek::Last => last,
+                        Seek::GreaterOrEqual(key)
+                        | Seek::Greater(key)
+                        | Seek::LessOrEqual(key)
+                        | Seek::Less(key) => match inode.keys().binary_search(key) {
+                            Ok(pos) => pos + 1,
+                            Err(pos) => pos,
+                        }
+                        .min(last),
+                    };
+                    (pos, inode.children().get(pos).unwrap())
+                })
+            });
+
+            match next {
+                Some((pos, child)) => {
+                    let child = cursor.get_page(child);
+                    cursor.path.push((current, pos));
+                    current = child;
+                }
+                None => break,
+            }
+        }
+
+        let (len, search) = current.as_node(|node: Node<K, &[u8]>| {
+            let leaf = node.as_leaf().unwrap();
+            let search = match seek {
+                Seek::First | Seek::Last => Err(0),
+                Seek::GreaterOrEqual(key)
+                | Seek::Greater(key)
+                | Seek::LessOrEqual(key)
+                | Seek::Less(key) => leaf.keys().binary_search(key),
+            };
+            (leaf.keys().len(), search)
+        });
+
+        // position to start from and whether we need to move to a previous key
+        let (pos, backwards) = match (seek, search) {
+            (Seek::First, _) => (0, false),
+            (Seek::Last, _) => (len, true),
+            (Seek::GreaterOrEqual(_), Ok(pos)) => (pos, false),
+            (Seek::GreaterOrEqual(_), Err(pos)) => (pos, false),
+            (Seek::Greater(_), Ok(pos)) => (pos + 1, false),
+            (Seek::Greater(_), Err(pos)) => (pos, false),
+            (Seek::LessOrEqual(_), Ok(pos)) => (pos, false),
+            (Seek::LessOrEqual(_), Err(pos)) => (pos, true),
+            (Seek::Less(_), Ok(pos)) => (pos, true),
+            (Seek::Less(_), Err(pos)) => (pos, true),
+        };
+
+        cursor.path.push((current, pos));
+
+        if backwards {
+            cursor.retreat();
+        } else if pos >= len {
+            cursor.next_leaf();
+        }
+
+        cursor
+    }
+
+    /// key and value the cursor is currently pointing to
+    pub(crate) fn current(&self) -> Option<(K, Value)> {
+        let (page, pos) = self.path.last()?;
+
+        page.as_node(|node: Node<K, &[u8]>| {
+            let leaf = node.as_leaf().unwrap();
+            let key = leaf.keys().get(*pos)?.borrow().clone();
+            let value = *leaf.values().get(*pos)?.borrow();
+            Some((key, value))
+        })
+    }
+
+    /// move the cursor to the next key, in ascending order
+    pub(crate) fn advance(&mut self) {
+        let len = match self.path.last_mut() {
+            Some((page, pos)) => {
+                *pos += 1;
+                page.as_node(|node: Node<K, &[u8]>| node.as_leaf().unwrap().keys().len())
+            }
+            None => return,
+        };
+
+        if self.path.last().unwrap().1 >= len {
+            self.next_leaf();
+        }
+    }
+
+    /// move the cursor to the previous key, in ascending order
+    pub(crate) fn retreat(&mut self) {
+        match self.path.last_mut() {
+            Some((_, pos)) if *pos > 0 => *pos -= 1,
+            Some(_) => self.previous_leaf(),
+            None => (),
+        }
+    }
+
+    fn next_leaf(&mut self) {
+        loop {
+            self.path.pop();
+
+            let child = match self.path.last_mut() {
+                Some((page, pos)) => {
+                    let children_len = page.as_node(|node: Node<K, &[u8]>| {
+                        node.as_internal().unwrap().children().len()
+                    });
+
+                    if *pos + 1 >= children_len {
+                        continue;
+                    }
+
+                    *pos += 1;
+                    page.as_node(|node: Node<K, &[u8]>| {
+                        node.as_internal().unwrap().children().get(*pos).unwrap()
+                    })
+                }
+                None => return,
+            };
+
+            if self.descend(child, Seek::First) {
+                return;
+            }
+        }
+    }
+
+    fn previous_leaf(&mut self) {
+        loop {
+            self.path.pop();
+
+            let child = match self.path.last_mut() {
+                Some((page, pos)) => {
+                    if *pos == 0 {
+                        continue;
+                    }
+
+                    *pos -= 1;
+                    page.as_node(|node: Node<K, &[u8]>| {
+                        node.as_internal().unwrap().children().get(*pos).unwrap()
+                    })
+                }
+                None => return,
+            };
+
+            if self.descend(child, Seek::Last) {
+                return;
+            }
+        }
+    }
+
+    /// descend to the leftmost or rightmost leaf of the subtree rooted at `id`. Returns
+    /// false if the leaf found is empty, in which case it is left at the end of the path
+    fn descend(&mut self, id: PageId, seek: Seek<K>) -> bool {
+        let mut current = self.get_page(id);
+        loop {
+            let next = current.as_node(|node: Node<K, &[u8]>| match node.as_internal() {
+                Some(inode) => {
+                    let pos = match seek {
+                        Seek::Last => inode.children().len().checked_sub(1).unwrap(),
+                        _ => 0,
+                    };
+                    Ok((pos, inode.children().get(pos).unwrap()))
+                }
+                None => Err(node.as_leaf().unwrap().keys().len()),
+            });
+
+            match next {
+                Ok((pos, child)) => {
+                    let child = self.get_page(child);
+                    self.path.push((current, pos));
+                    current = child;
+                }
+                Err(0) => {
+                    self.path.push((current, 0));
+                    return false;
+                }
+                Err(len) => {
+                    let pos = match seek {
+                        Seek::Last => len - 1,
+                        _ => 0,
+                    };
+                    self.path.push((current, pos));
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the (key, value) pairs of a range of keys, in ascending order (or descending,
+/// when iterated from the back).
+///
+/// The iterator works over a snapshot of the tree: the version it was created on is kept
+/// alive, so the pages it walks can't be reclaimed, and insertions made after its creation
+/// are not visible.
+pub struct RangeIter<'a, K, B: Backend = Disk> {
+    front: Cursor<'a, K, B::Pages>,
+    back: Cursor<'a, K, B::Pages>,
+    finished: bool,
+    _version: Arc<Version>,
+}
+
+impl<'a, K, B> RangeIter<'a, K, B>
+where
+    K: Key,
+    B: Backend,
+{
+    pub(crate) fn new<R: RangeBounds<K>>(
+        pages: &'a Pages<B::Pages>,
+        version: Arc<Version>,
+        range: R,
+    ) -> Self {
+        let root = version.root();
+
+        let front = match range.start_bound() {
+            Bound::Included(key) => Cursor::seek(root, pages, Seek::GreaterOrEqual(key)),
+            Bound::Excluded(key) => Cursor::seek(root, pages, Seek::Greater(key)),
+            Bound::Unbounded => Cursor::seek(root, pages, Seek::First),
+        };
+
+        let back = match range.end_bound() {
+            Bound::Included(key) => Cursor::seek(root, pages, Seek::LessOrEqual(key)),
+            Bound::Excluded(key) => Cursor::seek(root, pages, Seek::Less(key)),
+            Bound::Unbounded => Cursor::seek(root, pages, Seek::Last),
+        };
+
+        RangeIter {
+            front,
+            back,
+            finished: false,
+            _version: version,
+        }
+    }
+
+    /// the remaining elements are the ones between both cursors (inclusive)
+    fn remaining_bounds(&mut self) -> Option<((K, Value), (K, Value))> {
+        if self.finished {
+            return None;
+        }
+
+        match (self.front.current(), self.back.current()) {
+            (Some(front), Some(back)) if front.0 <= back.0 => {
+                if front.0 == back.0 {
+                    self.finished = true;
+                }
+                Some((front, back))
+            }
+            _ => {
+                self.finished = true;
+                None
+            }
+        }
+    }
+}
+
+impl<'a, K, B> Iterator for RangeIter<'a, K, B>
+where
+    K: Key,
+    B: Backend,
+{
+    type Item = (K, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (front, _) = self.remaining_bounds()?;
+        self.front.advance();
+        Some(front)
+    }
+}
+
+impl<'a, K, B> DoubleEndedIterator for RangeIter<'a, K, B>
+where
+    K: Key,
+    B: Backend,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (_, back) = self.remaining_bounds()?;
+        self.back.retreat();
+        Some(back)
+    }
+}
@@ -1,11 +1,13 @@
 use super::page_manager::PageManager;
 use super::{BTreeStoreError, PageId};
+use crate::storage::Storage;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::Reverse;
 use std::convert::TryInto;
 use std::io::{Read, Write};
 
 const MAGIC_SIZE: usize = 8;
-const MAGIC: [u8; MAGIC_SIZE] = [0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8];
+const MAGIC: [u8; MAGIC_SIZE] = [0xA1, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA9];
 
 use super::page_manager::{FIRST_PAGE_ID, NULL_PAGE_ID};
 
@@ -50,68 +52,206 @@ impl Metadata {
         }
     }
 
-    pub(crate) fn read(reader: &mut impl Read) -> Result<Metadata, BTreeStoreError> {
-        let mut magic = [0u8; MAGIC_SIZE];
-        reader.read(&mut magic)?;
+    pub(crate) fn set_root(&mut self, id: PageId) {
+        self.root = id;
+    }
+}
+
+const SLOTS: u64 = 2;
+const SLOT_SIZE: u64 = 512;
+// magic | generation | root | next page | crc32 of the previous fields
+const SLOT_LEN: usize = MAGIC_SIZE + 8 + 4 + 4 + 4;
+
+/// A version of the tree committed to the metadata file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CommittedVersion {
+    pub generation: u64,
+    pub root: PageId,
+    pub next_page: PageId,
+}
+
+/// The metadata file has two slots, and commits alternate between them, each one with a bigger
+/// generation number than the previous. This way a commit interrupted by a crash can only
+/// damage the slot being written (which is detected with its checksum), and the previous
+/// version can still be recovered from the other slot.
+///
+/// The free pages are not stored, they are the ones not reachable from the root of the
+/// recovered version.
+pub(crate) struct MetadataFile<S> {
+    storage: S,
+    generation: u64,
+}
 
-        if magic != MAGIC {
-            return Err(BTreeStoreError::WrongMagicNumber);
+impl<S> MetadataFile<S>
+where
+    S: for<'a> Storage<'a>,
+{
+    pub(crate) fn new(storage: S) -> MetadataFile<S> {
+        MetadataFile {
+            storage,
+            generation: 0,
         }
+    }
+
+    /// read the versions committed in the slots, newest first. Slots that are empty or were not
+    /// completely written are skipped
+    pub(crate) fn open(storage: S) -> (MetadataFile<S>, Vec<CommittedVersion>) {
+        let mut versions: Vec<CommittedVersion> = (0..SLOTS)
+            .filter_map(|slot| {
+                let buf = storage.get(slot * SLOT_SIZE, SLOT_LEN as u64).ok()?;
+                read_slot(buf.as_ref()).filter(|version| version.generation % SLOTS == slot)
+            })
+            .collect();
 
-        let root: u32 = reader.read_u32::<LittleEndian>()?;
-        let page_manager = PageManager::read(reader)?;
+        versions.sort_by_key(|version| Reverse(version.generation));
 
-        Ok(Metadata { root, page_manager })
+        let generation = versions.first().map(|v| v.generation).unwrap_or(0);
+
+        (
+            MetadataFile {
+                storage,
+                generation,
+            },
+            versions,
+        )
     }
 
-    pub(crate) fn write(&self, writer: &mut impl Write) -> Result<(), BTreeStoreError> {
-        writer.write(&MAGIC)?;
-        writer.write_u32::<LittleEndian>(self.root)?;
+    /// set the generation the next commits will follow, this should be the one of the version
+    /// the tree was recovered from, so the slot of a discarded version is overwritten first
+    pub(crate) fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    /// durably write the metadata in the slot of the next generation
+    pub(crate) fn commit(&mut self, metadata: &Metadata) -> Result<(), BTreeStoreError> {
+        let generation = self.generation + 1;
+
+        let mut buf = Vec::with_capacity(SLOT_LEN);
+        buf.write_all(&MAGIC)?;
+        buf.write_u64::<LittleEndian>(generation)?;
+        buf.write_u32::<LittleEndian>(metadata.root)?;
+        buf.write_u32::<LittleEndian>(metadata.page_manager.next_page())?;
+        let checksum = crc32fast::hash(&buf);
+        buf.write_u32::<LittleEndian>(checksum)?;
 
-        self.page_manager.write(writer)?;
+        self.storage.put((generation % SLOTS) * SLOT_SIZE, &buf)?;
+        self.storage.sync()?;
+
+        self.generation = generation;
 
         Ok(())
     }
+}
 
-    pub(crate) fn set_root(&mut self, id: PageId) {
-        self.root = id;
+fn read_slot(mut buf: &[u8]) -> Option<CommittedVersion> {
+    let checksum = crc32fast::hash(&buf[..SLOT_LEN - 4]);
+
+    let mut magic = [0u8; MAGIC_SIZE];
+    buf.read_exact(&mut magic).ok()?;
+    let generation = buf.read_u64::<LittleEndian>().ok()?;
+    let root = buf.read_u32::<LittleEndian>().ok()?;
+    let next_page = buf.read_u32::<LittleEndian>().ok()?;
+
+    if magic != MAGIC || buf.read_u32::<LittleEndian>().ok()? != checksum {
+        return None;
     }
+
+    Some(CommittedVersion {
+        generation,
+        root,
+        next_page,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    // TODO: use some RAII pattern to clean files after test (it's probably needed somewhere else too)
-
+    use crate::storage::FileStorage;
     use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom};
+
+    fn metadata(root: PageId, next_page: PageId) -> Metadata {
+        Metadata {
+            root,
+            page_manager: PageManager {
+                free_pages: vec![],
+                next_page,
+            },
+        }
+    }
 
     #[test]
     fn open_works() {
-        let path = "metadata_test";
-
-        {
-            let metadata = Metadata::new();
-            let mut file = OpenOptions::new()
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metadata");
+        let open = || {
+            OpenOptions::new()
                 .write(true)
                 .read(true)
                 .create(true)
-                .open(path)
-                .unwrap();
+                .open(&path)
+                .unwrap()
+        };
+
+        {
+            let (_, versions) = MetadataFile::open(FileStorage::new(open()));
+            assert_eq!(versions, vec![]);
+        }
 
-            metadata.write(&mut file).unwrap();
+        {
+            let mut metadata_file = MetadataFile::new(FileStorage::new(open()));
+            metadata_file.commit(&metadata(1, 2)).unwrap();
+            metadata_file.commit(&metadata(3, 4)).unwrap();
+            metadata_file.commit(&metadata(5, 6)).unwrap();
         }
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .open(path)
-            .unwrap();
-        let metadata = Metadata::read(&mut file).unwrap();
+        let (mut metadata_file, versions) = MetadataFile::open(FileStorage::new(open()));
+        assert_eq!(
+            versions,
+            vec![
+                CommittedVersion {
+                    generation: 3,
+                    root: 5,
+                    next_page: 6
+                },
+                CommittedVersion {
+                    generation: 2,
+                    root: 3,
+                    next_page: 4
+                },
+            ]
+        );
+
+        // recovering from the older version overwrites the newer one on the next commit
+        metadata_file.set_generation(2);
+        metadata_file.commit(&metadata(7, 8)).unwrap();
+
+        let (_, versions) = MetadataFile::open(FileStorage::new(open()));
+        assert_eq!(versions[0].generation, 3);
+        assert_eq!(versions[0].root, 7);
+        assert_eq!(versions[1].generation, 2);
+    }
+
+    #[test]
+    fn torn_slot_is_skipped() {
+        let mut file = tempfile::tempfile().unwrap();
+
+        let mut metadata_file = MetadataFile::new(FileStorage::new(file.try_clone().unwrap()));
+        metadata_file.commit(&metadata(1, 2)).unwrap();
+        metadata_file.commit(&metadata(3, 4)).unwrap();
 
-        let page_manager = metadata.page_manager;
-        assert_eq!(page_manager.next_page(), FIRST_PAGE_ID);
-        assert_eq!(page_manager.free_pages(), &vec![]);
+        // the root of the newest version (in the first slot) is changed, but not its checksum
+        file.seek(SeekFrom::Start(MAGIC_SIZE as u64 + 8)).unwrap();
+        file.write_all(&[0xff]).unwrap();
 
-        std::fs::remove_file("metadata_test").unwrap();
+        let (_, versions) = MetadataFile::open(FileStorage::new(file));
+        assert_eq!(
+            versions,
+            vec![CommittedVersion {
+                generation: 1,
+                root: 1,
+                next_page: 2
+            }]
+        );
     }
 }
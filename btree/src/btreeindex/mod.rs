@@ -1,3 +1,4 @@
+mod iter;
 mod metadata;
 mod node;
 mod page_manager;
@@ -7,30 +8,39 @@ mod version;
 
 use version::*;
 
+pub use iter::RangeIter;
+
 use crate::mem_page::MemPage;
 use crate::BTreeStoreError;
-use metadata::{Metadata, StaticSettings};
-use node::{InternalInsertStatus, LeafInsertStatus, Node};
+use metadata::{CommittedVersion, Metadata, MetadataFile, StaticSettings};
+use node::{InternalInsertStatus, LeafDeleteStatus, LeafInsertStatus, Node};
+use page_manager::{PageManager, FIRST_PAGE_ID};
 use pages::*;
 use std::borrow::Borrow;
+use std::collections::HashSet;
 
 use crate::{Key, Value};
 
+use crate::storage::{Backend, Disk};
 use std::convert::{TryFrom, TryInto};
 use std::fs::{File, OpenOptions};
-use std::io::{Seek, SeekFrom};
 use std::marker::PhantomData;
+use std::ops::RangeBounds;
 use std::path::Path;
 use std::sync::Mutex;
 
 pub(crate) type PageId = u32;
 
-pub struct BTree<K> {
-    // The metadata file contains the latests confirmed version of the tree
-    // this is, the root node, and the list of free pages
-    metadata: Mutex<(Metadata, File)>,
+/// Copy-on-write B+tree. Transactions never modify the pages reachable from the last
+/// checkpointed version, and the pages they release are not reused until a newer version is
+/// checkpointed, so a checkpoint is atomic: pages are synced first and then the root of the new
+/// version is committed to the metadata file. After a crash, `open` recovers the newest
+/// committed version whose pages are intact.
+pub struct BTree<K, B: Backend = Disk> {
+    // The metadata file contains the latests confirmed versions of the tree
+    metadata: Mutex<MetadataFile<B::Metadata>>,
     static_settings: StaticSettings,
-    pages: Pages,
+    pages: Pages<B::Pages>,
     transaction_manager: TransactionManager,
     phantom_keys: PhantomData<[K]>,
 }
@@ -45,9 +55,10 @@ pub(crate) type ValuesMut<'a> = ArrayView<'a, &'a mut [u8], Value>;
 pub(crate) type Keys<'a, K> = ArrayView<'a, &'a [u8], K>;
 pub(crate) type KeysMut<'a, K> = ArrayView<'a, &'a mut [u8], K>;
 
-impl<'me, K: 'me> BTree<K>
+impl<K, B> BTree<K, B>
 where
     K: Key,
+    B: Backend,
 {
     // TODO: add a builder with defaults?
     pub fn new(
@@ -56,13 +67,13 @@ where
         mut static_settings_file: File,
         page_size: u16,
         key_buffer_size: u32,
-    ) -> Result<BTree<K>, BTreeStoreError> {
+    ) -> Result<BTree<K, B>, BTreeStoreError> {
         let mut root_page = MemPage::new(page_size.try_into().unwrap());
         Node::<K, &mut [u8]>::new_leaf(key_buffer_size.try_into().unwrap(), root_page.as_mut());
 
         let mut metadata = Metadata::new();
 
-        let pages_storage = crate::storage::MmapStorage::new(tree_file)?;
+        let pages_storage = B::pages(tree_file)?;
 
         let pages = Pages::new(PagesInitializationParams {
             storage: pages_storage,
@@ -72,13 +83,11 @@ where
 
         let first_page_id = metadata.page_manager.new_id();
 
-        pages
-            .write_page(Page {
-                page_id: first_page_id,
-                key_buffer_size,
-                mem_page: root_page,
-            })
-            .expect("Couldn't write first page");
+        pages.write_page(Page {
+            page_id: first_page_id,
+            key_buffer_size,
+            mem_page: root_page,
+        })?;
 
         metadata.set_root(first_page_id);
 
@@ -88,11 +97,17 @@ where
         };
 
         static_settings.write(&mut static_settings_file)?;
+        static_settings_file.sync_all()?;
+
+        // commit the empty tree, so it can be opened
+        pages.sync_file()?;
+        let mut metadata_file = MetadataFile::new(B::metadata(metadata_file));
+        metadata_file.commit(&metadata)?;
 
         let transaction_manager = TransactionManager::new(&metadata);
 
         Ok(BTree {
-            metadata: Mutex::new((metadata, metadata_file)),
+            metadata: Mutex::new(metadata_file),
             pages,
             static_settings,
             transaction_manager,
@@ -100,25 +115,27 @@ where
         })
     }
 
+    /// open an existing tree, recovering the latest version that was completely checkpointed.
+    /// Transactions that were not checkpointed are discarded.
     pub fn open(
         metadata_file: impl AsRef<Path>,
         tree_file: impl AsRef<Path>,
         static_settings_file: impl AsRef<Path>,
-    ) -> Result<BTree<K>, BTreeStoreError> {
+    ) -> Result<BTree<K, B>, BTreeStoreError> {
         let tree_file = OpenOptions::new().write(true).read(true).open(tree_file)?;
-        let pages_storage = crate::storage::MmapStorage::new(tree_file)?;
+        let pages_storage = B::pages(tree_file)?;
 
         let mut static_settings_file = OpenOptions::new()
             .write(true)
             .read(true)
             .open(static_settings_file)?;
 
-        let mut metadata_file = OpenOptions::new()
+        let metadata_file = OpenOptions::new()
             .read(true)
             .write(true)
             .open(metadata_file)?;
 
-        let metadata = Metadata::read(&mut metadata_file)?;
+        let (mut metadata_file, versions) = MetadataFile::open(B::metadata(metadata_file));
 
         let static_settings = StaticSettings::read(&mut static_settings_file)?;
 
@@ -128,10 +145,21 @@ where
             key_buffer_size: static_settings.key_buffer_size,
         });
 
+        if versions.is_empty() {
+            return Err(BTreeStoreError::WrongMagicNumber);
+        }
+
+        let (version, metadata) = versions
+            .into_iter()
+            .find_map(|version| Self::recover(&pages, &version).map(|metadata| (version, metadata)))
+            .ok_or(BTreeStoreError::Corrupted)?;
+
+        metadata_file.set_generation(version.generation);
+
         let transaction_manager = TransactionManager::new(&metadata);
 
         Ok(BTree {
-            metadata: Mutex::new((metadata, metadata_file)),
+            metadata: Mutex::new(metadata_file),
             pages,
             static_settings,
             transaction_manager,
@@ -139,30 +167,69 @@ where
         })
     }
 
-    // sync files to disk and collect old transactions pages
-    pub(crate) fn checkpoint(&self) -> Result<(), BTreeStoreError> {
-        if let Some(checkpoint) = self.transaction_manager.collect_pending() {
-            let new_metadata = checkpoint.new_metadata;
+    /// walk the tree of a committed version checking the pages, and rebuild the list of free
+    /// pages (the ones that are not reachable from the root). Returns None if some page is
+    /// missing or doesn't match its checksum
+    fn recover(pages: &Pages<B::Pages>, version: &CommittedVersion) -> Option<Metadata> {
+        let in_range = |id: PageId| id >= FIRST_PAGE_ID && id < version.next_page;
+
+        let mut reachable = HashSet::new();
+        let mut pending = vec![version.root];
 
-            self.pages.sync_file()?;
+        while let Some(id) = pending.pop() {
+            if !in_range(id) || !reachable.insert(id) {
+                return None;
+            }
 
-            let mut guard = self.metadata.lock().unwrap();
-            let (_metadata, metadata_file) = &mut *guard;
+            let page = pages.get_verified_page(id)?;
+            page.as_node(|node: Node<K, &[u8]>| {
+                if let Some(inode) = node.as_internal() {
+                    pending.extend(inode.children().into_iter().map(|id| *id.borrow()));
+                }
+            });
+        }
 
-            metadata_file.seek(SeekFrom::Start(0))?;
+        let free_pages = (FIRST_PAGE_ID..version.next_page)
+            .rev()
+            .filter(|id| !reachable.contains(id))
+            .collect();
+
+        Some(Metadata {
+            root: version.root,
+            page_manager: PageManager {
+                next_page: version.next_page,
+                free_pages,
+            },
+        })
+    }
 
-            new_metadata.write(metadata_file)?;
-            metadata_file.sync_all()?;
+    pub(crate) fn key_buffer_size(&self) -> u32 {
+        self.static_settings.key_buffer_size
+    }
+
+    // sync files to disk and collect old transactions pages
+    pub(crate) fn checkpoint(&self) -> Result<(), BTreeStoreError> {
+        // the checkpoint holds the page manager lock until it is dropped, so the pages released
+        // by the collection can't be reused before the new version is committed
+        let checkpoint = self.transaction_manager.collect_pending();
+
+        self.pages.sync_file()?;
+
+        self.metadata
+            .lock()
+            .unwrap()
+            .commit(&checkpoint.new_metadata)?;
 
-            // this part is not actually important
-            guard.0 = new_metadata;
-        }
         Ok(())
     }
 
     pub fn insert_async(&self, key: K, value: Value) -> Result<(), BTreeStoreError> {
         let mut tx = self.transaction_manager.insert_transaction(&self.pages);
-        self.insert(&mut tx, key, value)?;
+
+        if let Err(e) = self.insert(&mut tx, key, value) {
+            tx.abort();
+            return Err(e);
+        }
 
         tx.commit::<K>();
 
@@ -180,21 +247,159 @@ where
     pub fn insert_many(
         &self,
         iter: impl IntoIterator<Item = (K, Value)>,
+    ) -> Result<(), BTreeStoreError> {
+        self.insert_many_async(iter)?;
+
+        self.checkpoint()?;
+
+        Ok(())
+    }
+
+    /// insert many keys in one transaction, without a checkpoint
+    pub fn insert_many_async(
+        &self,
+        iter: impl IntoIterator<Item = (K, Value)>,
     ) -> Result<(), BTreeStoreError> {
         let mut tx = self.transaction_manager.insert_transaction(&self.pages);
 
         for (key, value) in iter {
-            self.insert(&mut tx, key, value)?;
+            if let Err(e) = self.insert(&mut tx, key, value) {
+                tx.abort();
+                return Err(e);
+            }
         }
 
         tx.commit::<K>();
+        Ok(())
+    }
+
+    pub fn delete_async(&self, key: &K) -> Result<(), BTreeStoreError> {
+        self.delete_many_async(Some(key))
+    }
+
+    pub fn delete_one(&self, key: &K) -> Result<(), BTreeStoreError> {
+        self.delete_async(key)?;
+
+        self.checkpoint()?;
+
+        Ok(())
+    }
+
+    /// delete all the given keys in one transaction. If some key is not found nothing is deleted
+    pub fn delete_many<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a K>,
+    ) -> Result<(), BTreeStoreError>
+    where
+        K: 'a,
+    {
+        self.delete_many_async(keys)?;
+
         self.checkpoint()?;
+
         Ok(())
     }
 
+    fn delete_many_async<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a K>,
+    ) -> Result<(), BTreeStoreError>
+    where
+        K: 'a,
+    {
+        let mut tx = self.transaction_manager.insert_transaction(&self.pages);
+
+        for key in keys {
+            if let Err(e) = self.delete(&mut tx, key) {
+                tx.abort();
+                return Err(e);
+            }
+        }
+
+        tx.commit::<K>();
+
+        Ok(())
+    }
+
+    fn delete<'a>(
+        &self,
+        tx: &mut InsertTransactionBuilder<'a, 'a, B::Pages>,
+        key: &K,
+    ) -> Result<(), BTreeStoreError> {
+        let mut backtrack = tx.backtrack();
+        backtrack.search_for(key);
+
+        let (leaf_id, status) = {
+            let leaf = backtrack.get_next().unwrap();
+            let status = leaf.as_node_mut(|mut node: Node<K, &mut [u8]>| {
+                node.as_leaf_mut().unwrap().delete(key)
+            });
+            (leaf.id(), status)
+        };
+
+        let mut needs_rebalance = match status {
+            LeafDeleteStatus::Ok => false,
+            LeafDeleteStatus::NeedsRebalance => true,
+            LeafDeleteStatus::NotFound => return Err(BTreeStoreError::KeyNotFound),
+        };
+
+        // the root is allowed to have less keys than the minimum, so we stop when there is no parent
+        let mut current_id = leaf_id;
+        while needs_rebalance && backtrack.has_next() {
+            needs_rebalance = backtrack.rebalance(current_id);
+
+            if needs_rebalance {
+                current_id = backtrack.get_next().unwrap().id();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// replace the values of existing keys in one transaction. If some key is not found nothing is
+    /// updated
+    pub(crate) fn update_many(
+        &self,
+        iter: impl IntoIterator<Item = (K, Value)>,
+    ) -> Result<(), BTreeStoreError> {
+        let mut tx = self.transaction_manager.insert_transaction(&self.pages);
+
+        for (key, value) in iter {
+            if let Err(e) = self.update(&mut tx, &key, value) {
+                tx.abort();
+                return Err(e);
+            }
+        }
+
+        tx.commit::<K>();
+        self.checkpoint()?;
+        Ok(())
+    }
+
+    fn update<'a>(
+        &self,
+        tx: &mut InsertTransactionBuilder<'a, 'a, B::Pages>,
+        key: &K,
+        value: Value,
+    ) -> Result<(), BTreeStoreError> {
+        let mut backtrack = tx.backtrack();
+        backtrack.search_for(key);
+
+        let leaf = backtrack.get_next().unwrap();
+        let found = leaf.as_node_mut(|mut node: Node<K, &mut [u8]>| {
+            node.as_leaf_mut().unwrap().update(key, value)
+        });
+
+        if found {
+            Ok(())
+        } else {
+            Err(BTreeStoreError::KeyNotFound)
+        }
+    }
+
     fn insert<'a>(
         &self,
-        tx: &mut InsertTransactionBuilder<'a, 'a>,
+        tx: &mut InsertTransactionBuilder<'a, 'a, B::Pages>,
         key: K,
         value: Value,
     ) -> Result<(), BTreeStoreError> {
@@ -259,7 +464,7 @@ where
         &self,
         key: K,
         to_insert: PageId,
-        backtrack: &mut InsertBacktrack<K>,
+        backtrack: &mut InsertBacktrack<K, B::Pages>,
     ) -> Result<(), BTreeStoreError> {
         let mut split_key = key;
         let mut right_id = to_insert;
@@ -340,6 +545,36 @@ where
         })
     }
 
+    /// iterate over the keys in the given range (and their values), in ascending order.
+    /// The iterator is double ended, so it can be reversed to walk the keys backwards
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> RangeIter<'_, K, B> {
+        RangeIter::new(
+            &self.pages,
+            self.transaction_manager.read_transaction(),
+            range,
+        )
+    }
+
+    /// iterate over all the keys in ascending order
+    pub fn iter(&self) -> RangeIter<'_, K, B> {
+        self.range(..)
+    }
+
+    /// iterate over all the keys sharing a prefix, in ascending order. `start` must be the
+    /// smallest key with the given prefix, and `has_prefix` must hold for a contiguous range of
+    /// keys starting at `start`
+    pub fn prefix<'a, F>(
+        &'a self,
+        start: K,
+        mut has_prefix: F,
+    ) -> impl Iterator<Item = (K, Value)> + 'a
+    where
+        F: FnMut(&K) -> bool + 'a,
+    {
+        self.range(start..)
+            .take_while(move |(key, _)| has_prefix(key))
+    }
+
     fn search(&self, key: &K) -> PageRef {
         // TODO: Care, requesting a read transaction should enforce that it's not released until is finished
         // in this case, this acts like a lock, but if it does get released then the data may be overwritten
@@ -379,25 +614,15 @@ where
     }
 }
 
-impl<K> Drop for BTree<K> {
-    fn drop(&mut self) {
-        let mut guard = self.metadata.lock().unwrap();
-        let (metadata, metadata_file) = &mut *guard;
-
-        metadata_file.seek(SeekFrom::Start(0)).unwrap();
-        metadata.write(metadata_file).unwrap();
-
-        self.pages.sync_file().expect("tree file sync failed");
-    }
-}
-
 #[cfg(test)]
 mod tests {
     extern crate rand;
     extern crate tempfile;
     use super::*;
+    use crate::storage::fault::FaultyDisk;
     use crate::tests::U64Key;
     use crate::Key;
+    use std::io::{Read, Seek, SeekFrom, Write};
     use std::sync::Arc;
     use tempfile::tempfile;
 
@@ -405,10 +630,6 @@ mod tests {
     where
         K: Key,
     {
-        fn key_buffer_size(&self) -> u32 {
-            self.static_settings.key_buffer_size
-        }
-
         fn page_size(&self) -> u16 {
             self.static_settings.page_size
         }
@@ -418,7 +639,7 @@ mod tests {
             let root_id = read_tx.root();
 
             // TODO: get the next page but IN the read transaction
-            for n in 1..self.metadata.lock().unwrap().0.page_manager.next_page() {
+            for n in 1..self.transaction_manager.next_page() {
                 let page_ref = self.pages.get_page(n).unwrap();
 
                 println!("-----------------------");
@@ -512,6 +733,524 @@ mod tests {
         prop
     }
 
+    #[quickcheck]
+    fn qc_range_is_sorted_and_bounded(xs: Vec<u64>, start: u64, end: u64) -> bool {
+        let reference: std::collections::BTreeSet<u64> = xs.into_iter().collect();
+
+        let tree = new_tree();
+        tree.insert_many(reference.iter().map(|k| (U64Key(*k), *k)))
+            .unwrap();
+
+        let expected: Vec<u64> = reference.range(start..end.max(start)).cloned().collect();
+        let forward: Vec<u64> = tree
+            .range(U64Key(start)..U64Key(end.max(start)))
+            .map(|(k, v)| {
+                assert_eq!(k.0, v);
+                k.0
+            })
+            .collect();
+        let mut backward: Vec<u64> = tree
+            .range(U64Key(start)..U64Key(end.max(start)))
+            .rev()
+            .map(|(k, _)| k.0)
+            .collect();
+        backward.reverse();
+
+        forward == expected && backward == expected
+    }
+
+    #[test]
+    fn range_bounds() {
+        let tree = new_tree();
+        let n: u64 = 2000;
+
+        // only even keys
+        tree.insert_many((0..n).step_by(2).map(|i| (U64Key(i), i)))
+            .unwrap();
+
+        let keys = |iter: RangeIter<U64Key>| iter.map(|(k, _)| k.0).collect::<Vec<_>>();
+
+        assert_eq!(keys(tree.range(U64Key(10)..U64Key(16))), vec![10, 12, 14]);
+        assert_eq!(
+            keys(tree.range(U64Key(10)..=U64Key(16))),
+            vec![10, 12, 14, 16]
+        );
+        assert_eq!(keys(tree.range(U64Key(9)..U64Key(15))), vec![10, 12, 14]);
+        assert_eq!(
+            keys(tree.range(U64Key(1990)..)),
+            vec![1990, 1992, 1994, 1996, 1998]
+        );
+        assert_eq!(keys(tree.range(..U64Key(5))), vec![0, 2, 4]);
+        assert_eq!(keys(tree.range(U64Key(11)..U64Key(12))), Vec::<u64>::new());
+        assert_eq!(keys(tree.range(U64Key(3000)..)), Vec::<u64>::new());
+        assert_eq!(tree.iter().count(), 1000);
+
+        // iterating from both ends meets in the middle without repeating keys
+        let mut iter = tree.range(U64Key(10)..U64Key(20));
+        assert_eq!(iter.next().map(|(k, _)| k.0), Some(10));
+        assert_eq!(iter.next_back().map(|(k, _)| k.0), Some(18));
+        assert_eq!(keys(iter), vec![12, 14, 16]);
+
+        let mut iter = tree.range(U64Key(10)..U64Key(14));
+        assert_eq!(iter.next_back().map(|(k, _)| k.0), Some(12));
+        assert_eq!(iter.next().map(|(k, _)| k.0), Some(10));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn prefix_scan() {
+        let tree = new_tree();
+
+        // use the high 32 bits as a prefix
+        tree.insert_many(
+            (0..5u64)
+                .flat_map(|prefix| (0..100u64).map(move |i| (prefix << 32) | i))
+                .map(|k| (U64Key(k), k)),
+        )
+        .unwrap();
+
+        let keys: Vec<u64> = tree
+            .prefix(U64Key(3 << 32), |k| k.0 >> 32 == 3)
+            .map(|(k, _)| k.0)
+            .collect();
+
+        assert_eq!(keys, (0..100u64).map(|i| (3 << 32) | i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn range_on_empty_tree() {
+        let tree = new_tree();
+        assert_eq!(tree.iter().next(), None);
+        assert_eq!(tree.iter().next_back(), None);
+    }
+
+    #[test]
+    fn range_is_a_snapshot() {
+        let tree = new_tree();
+        let n: u64 = 1000;
+
+        tree.insert_many((0..n).map(|i| (U64Key(i * 2), i)))
+            .unwrap();
+
+        let iter = tree.range(..);
+
+        // these need to shadow (and possibly split) the pages the iterator uses
+        tree.insert_many((0..n).map(|i| (U64Key(i * 2 + 1), i)))
+            .unwrap();
+        tree.insert_many((n..2 * n).map(|i| (U64Key(i * 2), i)))
+            .unwrap();
+
+        let keys: Vec<u64> = iter.map(|(k, _)| k.0).collect();
+        assert_eq!(keys, (0..n).map(|i| i * 2).collect::<Vec<_>>());
+
+        assert_eq!(tree.iter().count() as u64, 3 * n);
+    }
+
+    fn next_page<B: Backend>(tree: &BTree<U64Key, B>) -> PageId {
+        tree.transaction_manager.next_page()
+    }
+
+    #[quickcheck]
+    fn qc_deleted_keys_are_not_found(xs: Vec<u64>, to_delete: Vec<usize>) -> bool {
+        let mut reference: std::collections::BTreeSet<u64> = xs.into_iter().collect();
+
+        let tree = new_tree();
+        tree.insert_many(reference.iter().map(|k| (U64Key(*k), *k)))
+            .unwrap();
+
+        let keys: Vec<u64> = reference.iter().cloned().collect();
+        let mut deleted = vec![];
+        for i in to_delete {
+            if keys.is_empty() {
+                break;
+            }
+            let key = keys[i % keys.len()];
+            if reference.remove(&key) {
+                deleted.push(U64Key(key));
+            }
+        }
+
+        tree.delete_many(deleted.iter()).unwrap();
+
+        let remaining: Vec<u64> = tree.iter().map(|(k, _)| k.0).collect();
+
+        deleted.iter().all(|k| tree.lookup(k).is_none())
+            && reference
+                .iter()
+                .all(|k| tree.lookup(&U64Key(*k)) == Some(*k))
+            && remaining == reference.into_iter().collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn delete_all_keys() {
+        let n: u64 = 2000;
+        let orders: Vec<Vec<u64>> = vec![
+            (0..n).collect(),
+            (0..n).rev().collect(),
+            (0..n).step_by(2).chain((0..n).skip(1).step_by(2)).collect(),
+        ];
+
+        for order in orders {
+            let tree = new_tree();
+            tree.insert_many((0..n).map(|i| (U64Key(i), i))).unwrap();
+
+            for (deleted, i) in order.iter().enumerate() {
+                tree.delete_one(&U64Key(*i)).unwrap();
+
+                if deleted % 100 == 0 {
+                    let remaining: Vec<u64> = tree.iter().map(|(k, _)| k.0).collect();
+                    let mut expected: Vec<u64> = order[deleted + 1..].to_vec();
+                    expected.sort();
+                    assert_eq!(remaining, expected);
+                }
+            }
+
+            assert_eq!(tree.iter().next(), None);
+            assert_eq!(tree.lookup(&U64Key(0)), None);
+        }
+    }
+
+    #[test]
+    fn deleted_pages_are_reused() {
+        let tree = new_tree();
+        let n: u64 = 2000;
+
+        tree.insert_many((0..n).map(|i| (U64Key(i), i))).unwrap();
+        tree.delete_many((0..n).map(U64Key).collect::<Vec<_>>().iter())
+            .unwrap();
+
+        // two more transactions, so the pages of the deleted tree are not used by any version
+        tree.insert_one(U64Key(0), 0).unwrap();
+        tree.delete_one(&U64Key(0)).unwrap();
+
+        let pages_after_first_round = next_page(&tree);
+
+        tree.insert_many((0..n).map(|i| (U64Key(i), i))).unwrap();
+
+        assert_eq!(next_page(&tree), pages_after_first_round);
+
+        for i in 0..n {
+            assert_eq!(tree.lookup(&U64Key(i)), Some(i));
+        }
+    }
+
+    #[test]
+    fn failed_delete_is_not_applied() {
+        let tree = new_tree();
+        let n: u64 = 1000;
+
+        tree.insert_many((0..n).map(|i| (U64Key(i), i))).unwrap();
+
+        let mut pages_after_failure = None;
+        for _ in 0..3 {
+            match tree.delete_many([U64Key(1), U64Key(2), U64Key(n + 1)].iter()) {
+                Err(BTreeStoreError::KeyNotFound) => (),
+                _ => panic!("deleting a non existent key should fail"),
+            }
+
+            // the pages allocated by a failed transaction are reused by the next one
+            let pages = next_page(&tree);
+            assert_eq!(*pages_after_failure.get_or_insert(pages), pages);
+        }
+
+        assert_eq!(tree.iter().count() as u64, n);
+        assert_eq!(tree.lookup(&U64Key(1)), Some(1));
+        assert_eq!(tree.lookup(&U64Key(2)), Some(2));
+    }
+
+    #[test]
+    fn deletions_are_not_visible_to_open_iterators() {
+        let tree = new_tree();
+        let n: u64 = 1000;
+
+        tree.insert_many((0..n).map(|i| (U64Key(i), i))).unwrap();
+
+        let iter = tree.iter();
+
+        tree.delete_many((0..n).map(U64Key).collect::<Vec<_>>().iter())
+            .unwrap();
+
+        assert_eq!(iter.count() as u64, n);
+        assert_eq!(tree.iter().count(), 0);
+    }
+
+    #[test]
+    fn update_values() {
+        let tree = new_tree();
+        let n: u64 = 1000;
+
+        tree.insert_many((0..n).map(|i| (U64Key(i), i))).unwrap();
+        tree.update_many((0..n).map(|i| (U64Key(i), i * 2)))
+            .unwrap();
+
+        for i in 0..n {
+            assert_eq!(tree.lookup(&U64Key(i)), Some(i * 2));
+        }
+
+        match tree.update_many(vec![(U64Key(0), 0), (U64Key(n), 0)]) {
+            Err(BTreeStoreError::KeyNotFound) => (),
+            _ => panic!("updating a non existent key should fail"),
+        }
+        assert_eq!(tree.lookup(&U64Key(0)), Some(0));
+    }
+
+    #[test]
+    fn last_transaction_is_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let n: u64 = 1000;
+
+        {
+            let create = |name: &str| {
+                OpenOptions::new()
+                    .create(true)
+                    .truncate(true)
+                    .write(true)
+                    .read(true)
+                    .open(path(name))
+                    .unwrap()
+            };
+
+            let tree = BTree::<U64Key>::new(
+                create("metadata"),
+                create("tree"),
+                create("static"),
+                86,
+                size_of::<U64Key>().try_into().unwrap(),
+            )
+            .unwrap();
+
+            tree.insert_many((0..n).map(|i| (U64Key(i), i))).unwrap();
+            tree.delete_one(&U64Key(0)).unwrap();
+        }
+
+        let restored =
+            BTree::<U64Key>::open(path("metadata"), path("tree"), path("static")).unwrap();
+
+        assert_eq!(restored.lookup(&U64Key(0)), None);
+        for i in 1..n {
+            assert_eq!(restored.lookup(&U64Key(i)), Some(i));
+        }
+    }
+
+    fn create_files(dir: &Path) -> (File, File, File) {
+        let create = |name: &str| {
+            OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .read(true)
+                .open(dir.join(name))
+                .unwrap()
+        };
+
+        (create("metadata"), create("tree"), create("static"))
+    }
+
+    /// tree whose storage can be made to crash, see `crate::storage::fault`
+    type FaultyTree = BTree<U64Key, FaultyDisk>;
+
+    fn create_tree(dir: &Path) -> FaultyTree {
+        let (metadata_file, tree_file, static_file) = create_files(dir);
+        BTree::new(
+            metadata_file,
+            tree_file,
+            static_file,
+            86,
+            size_of::<U64Key>().try_into().unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn open_tree(dir: &Path) -> Result<FaultyTree, BTreeStoreError> {
+        BTree::open(dir.join("metadata"), dir.join("tree"), dir.join("static"))
+    }
+
+    fn contents(tree: &FaultyTree) -> Vec<(u64, Value)> {
+        tree.iter().map(|(key, value)| (key.0, value)).collect()
+    }
+
+    const CRASH_WORKLOAD_STEPS: usize = 5;
+
+    /// run one step of the workload used to simulate crashes, ending with a checkpoint
+    fn crash_workload_step(tree: &FaultyTree, step: usize) {
+        match step {
+            1 => tree.insert_many((50..300).map(|i| (U64Key(i), i))).unwrap(),
+            2 => {
+                let keys: Vec<U64Key> = (0..300).filter(|i| i % 3 == 0).map(U64Key).collect();
+                tree.delete_many(&keys).unwrap();
+            }
+            3 => {
+                tree.insert_many_async((300..350).map(|i| (U64Key(i), i)))
+                    .unwrap();
+                tree.insert_many_async((350..400).map(|i| (U64Key(i), i)))
+                    .unwrap();
+                tree.checkpoint().unwrap();
+            }
+            4 => tree
+                .update_many(
+                    (0..400)
+                        .filter(|i| i % 3 != 0 && i % 5 == 1)
+                        .map(|i| (U64Key(i), i + 1000)),
+                )
+                .unwrap(),
+            5 => {
+                let keys: Vec<U64Key> = (0..200).filter(|i| i % 3 != 0).map(U64Key).collect();
+                tree.delete_many(&keys).unwrap();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// contents of the tree after the given step of the workload
+    fn crash_workload_state(step: usize) -> Vec<(u64, Value)> {
+        let last_key = match step {
+            0 => 50,
+            1 | 2 => 300,
+            _ => 400,
+        };
+
+        (0..last_key)
+            .filter(|i| step < 2 || *i >= 300 || i % 3 != 0)
+            .filter(|i| step < 5 || *i >= 200)
+            .map(|i| {
+                if step >= 4 && i % 3 != 0 && i % 5 == 1 {
+                    (i, i + 1000)
+                } else {
+                    (i, i)
+                }
+            })
+            .collect()
+    }
+
+    fn crash_at_every_write_point(lose_unsynced: bool) {
+        for crash_at in 0.. {
+            let dir = tempfile::tempdir().unwrap();
+            let tree = create_tree(dir.path());
+            tree.insert_many((0..50).map(|i| (U64Key(i), i))).unwrap();
+
+            let completed = std::cell::Cell::new(0);
+
+            crate::storage::fault::crash_at(crash_at, lose_unsynced);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                // the tree is dropped while unwinding, before the crash is disarmed
+                let tree = tree;
+                for step in 1..=CRASH_WORKLOAD_STEPS {
+                    crash_workload_step(&tree, step);
+                    completed.set(step);
+                }
+            }));
+            let crashed = crate::storage::fault::disarm();
+            assert_eq!(crashed, result.is_err());
+
+            let restored = open_tree(dir.path()).unwrap();
+            let recovered = contents(&restored);
+            let completed = completed.get();
+
+            // the interrupted step may have been committed before the crash
+            assert!(
+                recovered == crash_workload_state(completed)
+                    || (crashed && recovered == crash_workload_state(completed + 1)),
+                "wrong state after crashing at write {} during step {}",
+                crash_at,
+                completed + 1
+            );
+
+            // the recovered tree can be written again
+            restored
+                .insert_many((1000..1100).map(|i| (U64Key(i), i)))
+                .unwrap();
+            drop(restored);
+            let reopened = open_tree(dir.path()).unwrap();
+            assert_eq!(contents(&reopened)[..recovered.len()], recovered[..]);
+            assert_eq!(reopened.iter().count(), recovered.len() + 100);
+
+            if !crashed {
+                assert_eq!(completed, CRASH_WORKLOAD_STEPS);
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn crash_at_every_write_point_keeping_unsynced_writes() {
+        crash_at_every_write_point(false);
+    }
+
+    #[test]
+    fn crash_at_every_write_point_losing_unsynced_writes() {
+        crash_at_every_write_point(true);
+    }
+
+    #[test]
+    fn unreachable_pages_are_reclaimed_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let committed_next_page = {
+            let tree = create_tree(dir.path());
+            tree.insert_many((0..100).map(|i| (U64Key(i), i))).unwrap();
+
+            // the pages shadowed by this insertion can't be released on its checkpoint
+            let snapshot = tree.iter();
+            tree.insert_many((100..200).map(|i| (U64Key(i), i)))
+                .unwrap();
+            drop(snapshot);
+            assert!(tree.transaction_manager.free_pages().is_empty());
+
+            let committed_next_page = next_page(&tree);
+
+            // not checkpointed
+            tree.insert_many_async((200..1000).map(|i| (U64Key(i), i)))
+                .unwrap();
+            committed_next_page
+        };
+
+        let restored = open_tree(dir.path()).unwrap();
+        assert_eq!(restored.iter().count(), 200);
+        assert_eq!(next_page(&restored), committed_next_page);
+        assert!(!restored.transaction_manager.free_pages().is_empty());
+    }
+
+    #[test]
+    fn corrupted_version_is_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = {
+            let tree = create_tree(dir.path());
+            tree.insert_many((0..100).map(|i| (U64Key(i), i))).unwrap();
+            tree.insert_many((100..200).map(|i| (U64Key(i), i)))
+                .unwrap();
+            tree.transaction_manager.latest_version().root()
+        };
+
+        // flip a byte of the root page of the last version
+        let mut tree_file = OpenOptions::new()
+            .write(true)
+            .read(true)
+            .open(dir.path().join("tree"))
+            .unwrap();
+        let offset = u64::from(root - 1) * (86 + 4) + 20;
+        let mut byte = [0u8];
+        tree_file.seek(SeekFrom::Start(offset)).unwrap();
+        tree_file.read_exact(&mut byte).unwrap();
+        byte[0] ^= 0xff;
+        tree_file.seek(SeekFrom::Start(offset)).unwrap();
+        tree_file.write_all(&byte).unwrap();
+        drop(tree_file);
+
+        let restored = open_tree(dir.path()).unwrap();
+        assert_eq!(
+            contents(&restored),
+            (0..100).map(|i| (i, i)).collect::<Vec<_>>()
+        );
+
+        // the corrupted version is overwritten by the next checkpoint
+        restored
+            .insert_many((100..200).map(|i| (U64Key(i), i + 1)))
+            .unwrap();
+        drop(restored);
+        assert_eq!(open_tree(dir.path()).unwrap().iter().count(), 200);
+    }
+
     #[test]
     fn saves_and_restores_right() {
         let key_buffer_size: u32 = size_of::<U64Key>().try_into().unwrap();
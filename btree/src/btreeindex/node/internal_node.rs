@@ -111,6 +111,10 @@ where
             0
         };
 
+        self.children_with_len_mut(len)
+    }
+
+    fn children_with_len_mut<'me>(&'me mut self, len: usize) -> ChildrenMut<'me> {
         let base = LEN_SIZE + (self.max_keys * self.key_buffer_size);
         let data = &mut self.data.as_mut()
             [base..base + (self.max_keys.checked_add(1).unwrap()) * size_of::<PageId>()];
@@ -259,6 +263,49 @@ where
         }
     }
 
+    // The following operations are used to rebalance nodes after deletions. Unlike the views
+    // returned by `children`, they work with nodes that have no keys and a single child, which can
+    // happen transiently while a node is being rebalanced
+
+    /// insert key at key_pos and child at child_pos, without splitting
+    pub(crate) fn insert_key_and_child(
+        &mut self,
+        key_pos: usize,
+        key: &K,
+        child_pos: usize,
+        child: PageId,
+    ) -> Result<(), ()> {
+        let current_len = self.keys().len();
+        if current_len >= self.max_keys {
+            return Err(());
+        }
+
+        self.keys_mut().insert(key_pos, key)?;
+        self.children_with_len_mut(current_len.checked_add(1).unwrap())
+            .insert(child_pos, &child)?;
+        self.set_len(current_len.checked_add(1).unwrap());
+        Ok(())
+    }
+
+    /// remove the key at key_pos and the child at child_pos, returning them
+    pub(crate) fn remove_key_and_child(&mut self, key_pos: usize, child_pos: usize) -> (K, PageId) {
+        let current_len = self.keys().len();
+        let key: K = self.keys().get(key_pos).unwrap().borrow().clone();
+        let child = self.child_at(child_pos).unwrap();
+
+        self.keys_mut().delete(key_pos).unwrap();
+        self.children_with_len_mut(current_len.checked_add(1).unwrap())
+            .delete(child_pos)
+            .unwrap();
+        self.set_len(current_len.checked_sub(1).unwrap());
+
+        (key, child)
+    }
+
+    pub(crate) fn update_key(&mut self, pos: usize, key: &K) {
+        self.keys_mut().update(pos, key).unwrap();
+    }
+
     fn set_len(&mut self, new_len: usize) {
         let new_len = u64::try_from(new_len).unwrap();
         LittleEndian::write_u64(&mut self.data.as_mut()[0..LEN_SIZE], new_len);
@@ -301,6 +348,22 @@ where
         }
     }
 
+    pub(crate) fn max_keys(&self) -> usize {
+        self.max_keys
+    }
+
+    /// true if the node has less keys than the minimum. This can only happen after deletions
+    pub(crate) fn needs_rebalance(&self) -> bool {
+        self.keys().len() < self.lower_bound().checked_sub(1).unwrap()
+    }
+
+    /// get the child at the given position, assuming the node has `keys().len() + 1` children.
+    /// Contrary to `children`, this works for nodes with a single child and no keys
+    pub(crate) fn child_at(&self, pos: usize) -> Option<PageId> {
+        self.children_with_len(self.keys().len().checked_add(1).unwrap())
+            .get(pos)
+    }
+
     pub(crate) fn children<'me>(&'me self) -> Children<'me> {
         let len = if self.keys().len() > 0 {
             self.keys().len().checked_add(1).unwrap() as usize
@@ -308,6 +371,10 @@ where
             0
         };
 
+        self.children_with_len(len)
+    }
+
+    fn children_with_len<'me>(&'me self, len: usize) -> Children<'me> {
         let base = LEN_SIZE + (self.max_keys * self.key_buffer_size);
         let data = &self.data.as_ref()
             [base..base + (self.max_keys.checked_add(1).unwrap()) * size_of::<PageId>()];
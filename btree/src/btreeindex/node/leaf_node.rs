@@ -18,6 +18,12 @@ pub(crate) enum LeafInsertStatus<K> {
     DuplicatedKey(K),
 }
 
+pub(crate) enum LeafDeleteStatus {
+    Ok,
+    NeedsRebalance,
+    NotFound,
+}
+
 /// LeafNode is a wrapper over a slice of bytes (T). The layout is the following
 /// LEN | KEYS | VALUES(u64)
 /// For the time being, is assumed that the memory region is aligned to an 8 byte boundary,
@@ -218,6 +224,60 @@ where
         }
     }
 
+    /// delete the given key (and its value). NeedsRebalance is returned if the node ends up with less
+    /// keys than the minimum, in which case it needs to be merged with a sibling (or take keys from it)
+    pub(crate) fn delete(&mut self, key: &K) -> LeafDeleteStatus {
+        match self.keys().binary_search(key) {
+            Ok(pos) => {
+                self.remove_at(pos);
+                if self.needs_rebalance() {
+                    LeafDeleteStatus::NeedsRebalance
+                } else {
+                    LeafDeleteStatus::Ok
+                }
+            }
+            Err(_) => LeafDeleteStatus::NotFound,
+        }
+    }
+
+    /// replace the value of an existing key, returns false if the key is not in the node
+    pub(crate) fn update(&mut self, key: &K, value: V) -> bool {
+        match self.keys().binary_search(key) {
+            Ok(pos) => {
+                self.values_mut().update(pos, &value).unwrap();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// insert key and value at the given position, without splitting. This is used to move keys between
+    /// siblings, so it doesn't check the keys are kept in order
+    pub(crate) fn insert_at(&mut self, pos: usize, key: &K, value: V) -> Result<(), ()> {
+        let current_len = self.keys().len();
+        if current_len >= self.max_keys {
+            return Err(());
+        }
+
+        self.keys_mut().insert(pos, key)?;
+        self.values_mut().insert(pos, &value)?;
+        self.set_len(current_len.checked_add(1).unwrap());
+        Ok(())
+    }
+
+    /// remove the key and value at the given position, returning them
+    pub(crate) fn remove_at(&mut self, pos: usize) -> (K, V) {
+        let current_len = self.keys().len();
+        let key: K = self.keys().get(pos).unwrap().borrow().clone();
+        let value: V = *self.values().get(pos).unwrap().borrow();
+
+        self.keys_mut().delete(pos).unwrap();
+        self.values_mut().delete(pos).unwrap();
+        self.set_len(current_len.checked_sub(1).unwrap());
+
+        (key, value)
+    }
+
     fn values_mut(&mut self) -> ValuesMut {
         let len = self.keys().len();
 
@@ -277,6 +337,15 @@ where
         }
     }
 
+    pub(crate) fn max_keys(&self) -> usize {
+        self.max_keys
+    }
+
+    /// true if the node has less keys than the minimum. This can only happen after deletions
+    pub(crate) fn needs_rebalance(&self) -> bool {
+        self.keys().len() < self.lower_bound()
+    }
+
     /// inmutable view over the keys
     pub(crate) fn keys(&self) -> Keys<K> {
         let len = LittleEndian::read_u64(&self.data.as_ref()[0..LEN_SIZE]);
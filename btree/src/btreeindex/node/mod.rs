@@ -5,7 +5,7 @@ use std::marker::PhantomData;
 
 use crate::Key;
 pub(crate) use internal_node::{InternalInsertStatus, InternalNode};
-pub(crate) use leaf_node::{LeafInsertStatus, LeafNode};
+pub(crate) use leaf_node::{LeafDeleteStatus, LeafInsertStatus, LeafNode};
 
 const LEN_SIZE: usize = 8;
 const TAG_SIZE: usize = 8;
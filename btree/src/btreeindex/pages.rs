@@ -1,35 +1,42 @@
 use crate::btreeindex::node::Node;
 use crate::btreeindex::PageId;
-use crate::storage::{MmapStorage, Storage};
+use crate::storage::Storage;
 use crate::Key;
 use crate::MemPage;
 use byteorder::{ByteOrder, LittleEndian};
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryInto;
 use std::sync::{Arc, RwLock};
 
+/// every page is stored followed by the crc32 of its content, so pages that were not completely
+/// written can be detected when recovering the tree
+const CHECKSUM_SIZE: u16 = 4;
+
 /// An abstraction over a paged file, Pages is kind of an array but backed from disk. Page represents at the moment
 /// a heap allocated read/write page, while PageRef is a wrapper to share a read only page in an Arc
 /// when we move to mmap, this things may change to take advantage of zero copy.
 
-pub(crate) struct Pages {
-    storage: RwLock<MmapStorage>,
+pub(crate) struct Pages<S> {
+    storage: RwLock<S>,
     page_size: u16,
     // TODO: we need to remove this from here
     key_buffer_size: u32,
 }
 
 // TODO: move this unsafe impls to MmapStorage? although what is most safe is saying that RwLock<MmapStorage> is Sync + Send
-unsafe impl Send for Pages {}
-unsafe impl Sync for Pages {}
+unsafe impl<S> Send for Pages<S> {}
+unsafe impl<S> Sync for Pages<S> {}
 
-pub(crate) struct PagesInitializationParams {
-    pub(crate) storage: MmapStorage,
+pub(crate) struct PagesInitializationParams<S> {
+    pub(crate) storage: S,
     pub(crate) page_size: u16,
     pub(crate) key_buffer_size: u32,
 }
 
-impl Pages {
-    pub(crate) fn new(params: PagesInitializationParams) -> Self {
+impl<S> Pages<S>
+where
+    S: for<'a> Storage<'a>,
+{
+    pub(crate) fn new(params: PagesInitializationParams<S>) -> Self {
         let PagesInitializationParams {
             storage,
             page_size,
@@ -45,15 +52,22 @@ impl Pages {
         }
     }
 
-    fn read_page(&self, id: PageId) -> MemPage {
+    fn page_location(&self, id: PageId) -> u64 {
+        u64::from(id.checked_sub(1).expect("0 page is used as a null ptr"))
+            * u64::from(self.page_size + CHECKSUM_SIZE)
+    }
+
+    /// read the page and its stored checksum. Returns None if the page is past the end of the
+    /// file
+    fn read_page(&self, id: PageId) -> Option<(MemPage, u32)> {
         let storage = self.storage.read().unwrap();
         let buf = storage
             .get(
-                u64::from(id.checked_sub(1).expect("0 page is used as a null ptr"))
-                    * u64::from(self.page_size),
-                self.page_size.into(),
+                self.page_location(id),
+                (self.page_size + CHECKSUM_SIZE).into(),
             )
-            .unwrap();
+            .ok()?;
+        let buf = buf.as_ref();
 
         let page_size = self.page_size.try_into().unwrap();
         let mut page = MemPage::new(page_size);
@@ -62,36 +76,46 @@ impl Pages {
         // storage thread safe (specially if the mmap gets remapped)
         page.as_mut().copy_from_slice(&buf[..page_size]);
 
-        page
+        Some((page, LittleEndian::read_u32(&buf[page_size..])))
     }
 
     pub(crate) fn write_page(&self, page: Page) -> Result<(), std::io::Error> {
         let mem_page = &page.mem_page;
         let page_id = page.page_id;
 
-        let mut storage = self.storage.write().unwrap();
+        let mut buf = Vec::with_capacity(mem_page.len() + usize::from(CHECKSUM_SIZE));
+        buf.extend_from_slice(mem_page.as_ref());
+        buf.extend_from_slice(&crc32fast::hash(mem_page.as_ref()).to_le_bytes());
 
-        storage
-            .put(
-                u64::from(page_id.checked_sub(1).unwrap()) * u64::try_from(mem_page.len()).unwrap(),
-                &mem_page.as_ref(),
-            )
-            .unwrap();
+        let mut storage = self.storage.write().unwrap();
 
-        Ok(())
+        storage.put(self.page_location(page_id), &buf)
     }
 
     pub(crate) fn get_page<'a>(&'a self, id: PageId) -> Option<PageRef> {
-        // TODO: Check the id is in range?
-        let page = self.read_page(id);
+        // checksums are not verified here, only when recovering the tree, as the pages reachable
+        // from a committed version are never overwritten
+        let (page, _) = self.read_page(id)?;
 
-        let page_ref = PageRef::new(Page {
+        Some(self.page_ref(id, page))
+    }
+
+    /// like `get_page`, but returns None if the page doesn't match its checksum
+    pub(crate) fn get_verified_page(&self, id: PageId) -> Option<PageRef> {
+        let (page, checksum) = self.read_page(id)?;
+        if checksum == crc32fast::hash(page.as_ref()) {
+            Some(self.page_ref(id, page))
+        } else {
+            None
+        }
+    }
+
+    fn page_ref(&self, id: PageId, mem_page: MemPage) -> PageRef {
+        PageRef::new(Page {
             page_id: id,
             key_buffer_size: self.key_buffer_size,
-            mem_page: page,
-        });
-
-        Some(page_ref.clone())
+            mem_page,
+        })
     }
 
     pub(crate) fn sync_file(&self) -> Result<(), std::io::Error> {
@@ -1,11 +1,14 @@
 use super::metadata::Metadata;
 use super::page_manager::PageManager;
 
+use super::node::{InternalNode, LeafNode};
 use super::pages::*;
 use super::Node;
 use super::PageId;
 use crate::mem_page::MemPage;
+use crate::storage::Storage;
 use crate::Key;
+use std::borrow::Borrow;
 use std::collections::{HashMap, VecDeque};
 use std::marker::PhantomData;
 
@@ -42,14 +45,14 @@ impl Version {
     }
 }
 
-pub(crate) enum WriteTransactionBuilder<'a, 'index> {
-    Insert(InsertTransactionBuilder<'a, 'index>),
+pub(crate) enum WriteTransactionBuilder<'a, 'index, S> {
+    Insert(InsertTransactionBuilder<'a, 'index, S>),
 }
 
 /// staging area for batched insertions, it will keep track of pages already shadowed and reuse them,
 /// it can be used to create a new `Version` at the end with all the insertions done atomically
-pub(crate) struct InsertTransactionBuilder<'index, 'locks: 'index> {
-    pages: &'index Pages,
+pub(crate) struct InsertTransactionBuilder<'index, 'locks: 'index, S> {
+    pages: &'index Pages<S>,
     current_root: PageId,
     extra: HashMap<PageId, Page>,
     old_ids: Vec<PageId>,
@@ -61,11 +64,12 @@ pub(crate) struct InsertTransactionBuilder<'index, 'locks: 'index> {
 
 /// this is basically a stack, but it will rename pointers and interact with the builder in order to reuse
 /// already cloned pages
-pub(crate) struct InsertBacktrack<'txbuilder, 'txmanager: 'txbuilder, 'index: 'txmanager, K>
+pub(crate) struct InsertBacktrack<'txbuilder, 'txmanager: 'txbuilder, 'index: 'txmanager, K, S>
 where
     K: Key,
+    S: for<'a> Storage<'a>,
 {
-    builder: &'txbuilder mut InsertTransactionBuilder<'txmanager, 'index>,
+    builder: &'txbuilder mut InsertTransactionBuilder<'txmanager, 'index, S>,
     backtrack: Vec<(Option<PageId>, Page)>,
     new_root: Option<PageId>,
     phantom_key: PhantomData<[K]>,
@@ -96,14 +100,24 @@ impl TransactionManager {
         self.latest_version.read().unwrap().clone()
     }
 
+    #[cfg(test)]
+    pub(crate) fn next_page(&self) -> PageId {
+        self.page_manager.lock().unwrap().next_page()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn free_pages(&self) -> Vec<PageId> {
+        self.page_manager.lock().unwrap().free_pages().clone()
+    }
+
     pub fn read_transaction(&self) -> Arc<Version> {
         self.latest_version()
     }
 
-    pub fn insert_transaction<'me, 'index: 'me>(
+    pub fn insert_transaction<'me, 'index: 'me, S>(
         &'me self,
-        pages: &'index Pages,
-    ) -> InsertTransactionBuilder<'me, 'me> {
+        pages: &'index Pages<S>,
+    ) -> InsertTransactionBuilder<'me, 'me, S> {
         let page_manager = self.page_manager.lock().unwrap();
         let versions = self.versions.lock().unwrap();
 
@@ -119,14 +133,13 @@ impl TransactionManager {
         }
     }
 
-    /// collect versions without readers, in order to reuse its pages (the ones that are shadow in transactions after that)
-    pub fn collect_pending(&self) -> Option<Checkpoint> {
+    /// collect versions without readers, in order to reuse its pages (the ones that are shadow in transactions after that).
+    /// The returned checkpoint has the metadata of the latest version, that is the one that needs to be persisted
+    pub fn collect_pending(&self) -> Checkpoint<'_> {
         let mut page_manager = self.page_manager.lock().unwrap();
         let mut versions = self.versions.lock().unwrap();
 
         let mut pages_to_release = vec![];
-        let mut next_page_at_end = None;
-        let mut new_root = None;
 
         while versions.len() > 0 && Arc::strong_count(versions.front().unwrap()) == 1 {
             // there is no race conditions between the check and this, because versions is locked and count == 1 means is the only reference
@@ -135,40 +148,33 @@ impl TransactionManager {
             for id in version.transaction.shadowed_pages.iter().cloned() {
                 pages_to_release.push(id)
             }
-
-            next_page_at_end = Some(version.transaction.next_page_id);
-            new_root = Some(version.transaction.new_root);
         }
 
-        let next_page: PageId = if let Some(next_page) = next_page_at_end {
-            next_page
-        } else {
-            return None;
-        };
-
         for page in pages_to_release {
             page_manager.remove_page(page);
         }
 
-        let page_manager_to_commit = PageManager {
-            next_page,
-            ..page_manager.clone()
-        };
+        // there can't be a write transaction in progress, as we hold the page manager lock, so
+        // the latest version is the last one committed
+        let latest_version = self.latest_version();
 
-        Some(Checkpoint {
+        Checkpoint {
             new_metadata: Metadata {
-                root: new_root.unwrap(),
-                page_manager: page_manager_to_commit,
+                root: latest_version.root(),
+                page_manager: page_manager.clone(),
             },
             page_manager,
             versions,
-        })
+        }
     }
 }
 
-impl<'txmanager, 'index: 'txmanager> InsertTransactionBuilder<'txmanager, 'index> {
+impl<'txmanager, 'index: 'txmanager, S> InsertTransactionBuilder<'txmanager, 'index, S>
+where
+    S: for<'a> Storage<'a>,
+{
     /// create a staging area for a single insert
-    pub(crate) fn backtrack<'me, K>(&'me mut self) -> InsertBacktrack<'me, 'txmanager, 'index, K>
+    pub(crate) fn backtrack<'me, K>(&'me mut self) -> InsertBacktrack<'me, 'txmanager, 'index, K, S>
     where
         K: Key,
     {
@@ -261,16 +267,27 @@ impl<'txmanager, 'index: 'txmanager> InsertTransactionBuilder<'txmanager, 'index
         });
     }
 
-    // not really needed because the destructor has basically the same effect right now
-    pub(crate) fn abort(self) {
-        unimplemented!()
+    /// discard the transaction. The pages allocated by it are not reachable from any version, so
+    /// their ids can be reused right away
+    pub(crate) fn abort(mut self) {
+        for id in self.extra.keys() {
+            self.page_manager.remove_page(*id);
+        }
+    }
+
+    /// release a page allocated in this transaction that is not going to be used by the new version.
+    /// As no reader can see it, its id can be reused right away
+    fn release_new_node(&mut self, id: PageId) {
+        self.extra.remove(&id);
+        self.page_manager.remove_page(id);
     }
 }
 
-impl<'txbuilder, 'txmanager: 'txbuilder, 'index: 'txmanager, K>
-    InsertBacktrack<'txbuilder, 'txmanager, 'index, K>
+impl<'txbuilder, 'txmanager: 'txbuilder, 'index: 'txmanager, K, S>
+    InsertBacktrack<'txbuilder, 'txmanager, 'index, K, S>
 where
     K: Key,
+    S: for<'a> Storage<'a>,
 {
     pub(crate) fn search_for(&mut self, key: &K) {
         let mut current = self.builder.current_root();
@@ -364,15 +381,211 @@ where
     }
 }
 
-impl<'txbuilder, 'txmanager: 'txbuilder, 'index: 'txmanager, K> Drop
-    for InsertBacktrack<'txbuilder, 'txmanager, 'index, K>
+enum Rebalance<K> {
+    Merged,
+    Borrowed(K),
+}
+
+// deletion support
+impl<'txbuilder, 'txmanager: 'txbuilder, 'index: 'txmanager, K, S>
+    InsertBacktrack<'txbuilder, 'txmanager, 'index, K, S>
 where
     K: Key,
+    S: for<'a> Storage<'a>,
 {
-    fn drop(&mut self) {
-        while let Some(_) = InsertBacktrack::<'txbuilder, 'txmanager, 'index, K>::get_next(self) {
-            ()
+    /// rebalance the node `child_id` (the last one returned by `get_next`), that has less keys than
+    /// the minimum after a deletion. The node is merged with a sibling if both fit in one node, otherwise
+    /// a key is moved from the sibling. Returns true if the parent needs to be rebalanced too
+    pub(crate) fn rebalance(&mut self, child_id: PageId) -> bool {
+        let is_root_parent = self.backtrack.len() == 1;
+        let (_, parent) = self
+            .backtrack
+            .last_mut()
+            .expect("only the root can't be rebalanced");
+
+        let (child_pos, sibling_pos, sibling_id, separator) =
+            parent.as_node(|node: Node<K, &[u8]>| {
+                let inode = node.as_internal().unwrap();
+                let child_pos = inode.children().linear_search(child_id).unwrap();
+                // prefer the left sibling, only the leftmost child doesn't have one
+                let sibling_pos = if child_pos > 0 {
+                    child_pos - 1
+                } else {
+                    child_pos + 1
+                };
+                let separator: K = inode
+                    .keys()
+                    .get(child_pos.min(sibling_pos))
+                    .unwrap()
+                    .borrow()
+                    .clone();
+
+                (
+                    child_pos,
+                    sibling_pos,
+                    inode.children().get(sibling_pos).unwrap(),
+                    separator,
+                )
+            });
+
+        let child = self.builder.extra.remove(&child_id).unwrap();
+        let sibling = match self.builder.mut_page(sibling_id).unwrap() {
+            (Some(old_id), shadow) => {
+                let new_id = shadow.id();
+                parent.as_node_mut(|mut node: Node<K, &mut [u8]>| {
+                    node.as_internal_mut()
+                        .unwrap()
+                        .children_mut()
+                        .update(sibling_pos, &new_id)
+                        .unwrap()
+                });
+                self.builder.old_ids.push(old_id);
+                shadow
+            }
+            (None, page) => page,
+        };
+
+        let borrow_from_left = sibling_pos < child_pos;
+        let separator_pos = child_pos.min(sibling_pos);
+        let (mut left, mut right) = if borrow_from_left {
+            (sibling, child)
+        } else {
+            (child, sibling)
+        };
+
+        let result = left.as_node_mut(|mut left: Node<K, &mut [u8]>| {
+            right.as_node_mut(|mut right: Node<K, &mut [u8]>| {
+                match (left.as_leaf_mut(), right.as_leaf_mut()) {
+                    (Some(left), Some(right)) => {
+                        Self::rebalance_leaves(left, right, borrow_from_left)
+                    }
+                    _ => Self::rebalance_internals(
+                        left.as_internal_mut().unwrap(),
+                        right.as_internal_mut().unwrap(),
+                        separator,
+                        borrow_from_left,
+                    ),
+                }
+            })
+        });
+
+        let left_id = left.id();
+        self.builder.extra.insert(left_id, left);
+
+        match result {
+            Rebalance::Borrowed(new_separator) => {
+                parent.as_node_mut(|mut node: Node<K, &mut [u8]>| {
+                    node.as_internal_mut()
+                        .unwrap()
+                        .update_key(separator_pos, &new_separator)
+                });
+                self.builder.extra.insert(right.id(), right);
+                false
+            }
+            Rebalance::Merged => {
+                self.builder.release_new_node(right.id());
+
+                let parent_len = parent.as_node_mut(|mut node: Node<K, &mut [u8]>| {
+                    let mut inode = node.as_internal_mut().unwrap();
+                    inode.remove_key_and_child(separator_pos, separator_pos + 1);
+                    inode.keys().len()
+                });
+
+                if !is_root_parent {
+                    return parent.as_node(|node: Node<K, &[u8]>| {
+                        node.as_internal().unwrap().needs_rebalance()
+                    });
+                }
+
+                if parent_len == 0 {
+                    // the root has only one child left, so that child becomes the new root
+                    let (old_id, root) = self.backtrack.pop().unwrap();
+                    if let Some(old_id) = old_id {
+                        self.builder.old_ids.push(old_id);
+                    }
+                    self.builder.release_new_node(root.id());
+                    self.new_root = Some(left_id);
+                }
+
+                false
+            }
+        }
+    }
+
+    fn rebalance_leaves(
+        mut left: LeafNode<K, &mut [u8]>,
+        mut right: LeafNode<K, &mut [u8]>,
+        borrow_from_left: bool,
+    ) -> Rebalance<K> {
+        let left_len = left.keys().len();
+        let right_len = right.keys().len();
+
+        if left_len + right_len <= left.max_keys() {
+            for i in 0..right_len {
+                let key: K = right.keys().get(i).unwrap().borrow().clone();
+                let value = right.values().get(i).unwrap();
+                left.insert_at(left_len + i, &key, value).unwrap();
+            }
+            Rebalance::Merged
+        } else if borrow_from_left {
+            let (key, value) = left.remove_at(left_len - 1);
+            right.insert_at(0, &key, value).unwrap();
+            Rebalance::Borrowed(key)
+        } else {
+            let (key, value) = right.remove_at(0);
+            left.insert_at(left_len, &key, value).unwrap();
+            Rebalance::Borrowed(right.keys().get(0).unwrap().borrow().clone())
+        }
+    }
+
+    fn rebalance_internals(
+        mut left: InternalNode<K, &mut [u8]>,
+        mut right: InternalNode<K, &mut [u8]>,
+        separator: K,
+        borrow_from_left: bool,
+    ) -> Rebalance<K> {
+        let left_len = left.keys().len();
+        let right_len = right.keys().len();
+
+        if left_len + right_len < left.max_keys() {
+            // the separator goes down, between the children of both nodes
+            left.insert_key_and_child(
+                left_len,
+                &separator,
+                left_len + 1,
+                right.child_at(0).unwrap(),
+            )
+            .unwrap();
+            for i in 0..right_len {
+                let key: K = right.keys().get(i).unwrap().borrow().clone();
+                let child = right.child_at(i + 1).unwrap();
+                left.insert_key_and_child(left_len + 1 + i, &key, left_len + 2 + i, child)
+                    .unwrap();
+            }
+            Rebalance::Merged
+        } else if borrow_from_left {
+            // rotate right: the last child of the left node moves to the right node
+            let (key, child) = left.remove_key_and_child(left_len - 1, left_len);
+            right.insert_key_and_child(0, &separator, 0, child).unwrap();
+            Rebalance::Borrowed(key)
+        } else {
+            // rotate left: the first child of the right node moves to the left node
+            let (key, child) = right.remove_key_and_child(0, 0);
+            left.insert_key_and_child(left_len, &separator, left_len + 1, child)
+                .unwrap();
+            Rebalance::Borrowed(key)
         }
+    }
+}
+
+impl<'txbuilder, 'txmanager: 'txbuilder, 'index: 'txmanager, K, S> Drop
+    for InsertBacktrack<'txbuilder, 'txmanager, 'index, K, S>
+where
+    K: Key,
+    S: for<'a> Storage<'a>,
+{
+    fn drop(&mut self) {
+        while InsertBacktrack::<'txbuilder, 'txmanager, 'index, K, S>::get_next(self).is_some() {}
 
         self.builder.current_root = self.new_root.unwrap();
     }
@@ -0,0 +1,107 @@
+//! Write-ahead log of a flatfile compaction.
+//!
+//! A compaction copies the live blobs to a new flatfile, and then needs to update the positions
+//! in the index and replace the old flatfile, which can't be done atomically. So, once the new
+//! flatfile is synced, the new positions are written to the log, which commits the compaction.
+//! If the store is opened with a complete log the compaction is finished, and if the log is
+//! missing or incomplete it is rolled back.
+
+use crate::{BTreeStoreError, Key, Value};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::borrow::Borrow;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+const MAGIC_SIZE: usize = 8;
+const MAGIC: [u8; MAGIC_SIZE] = [0xC1, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8];
+const CHECKSUM_SIZE: usize = 4;
+
+/// write and sync the log with the new positions of the keys
+pub(crate) fn write_log<K: Key>(
+    path: &Path,
+    positions: &[(K, Value)],
+    key_buffer_size: u32,
+) -> Result<(), BTreeStoreError> {
+    let mut buf = Vec::new();
+    buf.write_all(&MAGIC)?;
+    buf.write_u64::<LittleEndian>(positions.len().try_into().unwrap())?;
+
+    let mut key_buffer = vec![0u8; key_buffer_size.try_into().unwrap()];
+    for (key, value) in positions {
+        key.write(&mut key_buffer).expect("Couldn't serialize key");
+        buf.write_all(&key_buffer)?;
+        buf.write_u64::<LittleEndian>(*value)?;
+    }
+
+    let checksum = crc32fast::hash(&buf);
+    buf.write_u32::<LittleEndian>(checksum)?;
+
+    let mut file = fs::File::create(path)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// read the positions in the log. Returns None if the log was not completely written
+pub(crate) fn read_log<K: Key>(
+    path: &Path,
+    key_buffer_size: u32,
+) -> Result<Option<Vec<(K, Value)>>, BTreeStoreError> {
+    let bytes = fs::read(path)?;
+
+    if bytes.len() < MAGIC_SIZE + CHECKSUM_SIZE {
+        return Ok(None);
+    }
+
+    let (content, mut checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+    if content[..MAGIC_SIZE] != MAGIC
+        || checksum.read_u32::<LittleEndian>()? != crc32fast::hash(content)
+    {
+        return Ok(None);
+    }
+
+    let mut reader = &content[MAGIC_SIZE..];
+    let len = reader.read_u64::<LittleEndian>()?;
+
+    let mut key_buffer = vec![0u8; key_buffer_size.try_into().unwrap()];
+    let mut positions = Vec::with_capacity(len.try_into().unwrap());
+    for _ in 0..len {
+        reader.read_exact(&mut key_buffer)?;
+        let key = K::read(&key_buffer).unwrap().borrow().clone();
+        let value = reader.read_u64::<LittleEndian>()?;
+        positions.push((key, value));
+    }
+
+    Ok(Some(positions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::U64Key;
+
+    #[test]
+    fn incomplete_log_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let positions: Vec<(U64Key, Value)> = (0..10).map(|i| (U64Key(i), i * 100)).collect();
+
+        write_log(&path, &positions, 8).unwrap();
+        let read: Vec<(u64, Value)> = read_log::<U64Key>(&path, 8)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|(key, value)| (key.0, value))
+            .collect();
+        assert_eq!(read, (0..10).map(|i| (i, i * 100)).collect::<Vec<_>>());
+
+        let bytes = fs::read(&path).unwrap();
+        for len in 0..bytes.len() {
+            fs::write(&path, &bytes[..len]).unwrap();
+            assert!(read_log::<U64Key>(&path, 8).unwrap().is_none());
+        }
+    }
+}
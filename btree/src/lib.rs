@@ -4,6 +4,7 @@ extern crate quickcheck_macros;
 
 mod arrayview;
 pub mod btreeindex;
+mod compaction;
 pub mod flatfile;
 mod mem_page;
 pub mod storage;
@@ -13,17 +14,20 @@ use std::sync::Mutex;
 const METADATA_FILE: &'static str = "metadata";
 const TREE_FILE: &'static str = "pages";
 const TREE_SETTINGS_FILE: &'static str = "settings";
-// const BACKUP_FILE: &'static str = "commit_backup";
 const APPENDER_FILE_PATH: &'static str = "flatfile";
+const COMPACTED_APPENDER_FILE_PATH: &str = "flatfile.compact";
+const COMPACTION_LOG_FILE: &str = "compaction.log";
 
 use mem_page::MemPage;
 
-use crate::btreeindex::BTree;
+use crate::btreeindex::{BTree, RangeIter};
+use crate::storage::{Backend, Disk};
 use std::borrow::Borrow;
 use std::convert::TryInto;
 use std::fmt::Debug;
 use std::fs::OpenOptions;
-use std::path::Path;
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
 
 use thiserror::Error;
 
@@ -44,25 +48,30 @@ pub enum BTreeStoreError {
     KeyNotFound,
     #[error("wrong magic number")]
     WrongMagicNumber,
+    #[error("no consistent version of the index was found")]
+    Corrupted,
 }
 
-pub struct BTreeStore<K>
+pub struct BTreeStore<K, B = Disk>
 where
     K: Key,
+    B: Backend,
 {
-    index: BTree<K>,
+    index: BTree<K, B>,
     flatfile: Mutex<Appender>,
+    path: PathBuf,
 }
 
-impl<K> BTreeStore<K>
+impl<K, B> BTreeStore<K, B>
 where
     K: Key,
+    B: Backend,
 {
     pub fn new(
         path: impl AsRef<Path>,
         key_buffer_size: u32,
         page_size: u16,
-    ) -> Result<BTreeStore<K>, BTreeStoreError> {
+    ) -> Result<BTreeStore<K, B>, BTreeStoreError> {
         std::fs::create_dir_all(path.as_ref())?;
 
         let flatfile = Appender::new(path.as_ref().join(APPENDER_FILE_PATH))
@@ -83,9 +92,10 @@ where
         let metadata_file = OpenOptions::new()
             .create(true)
             .write(true)
+            .read(true)
             .open(path.as_ref().join(METADATA_FILE))?;
 
-        let index = BTree::<K>::new(
+        let index = BTree::<K, B>::new(
             metadata_file,
             tree_file,
             static_settings_file,
@@ -96,10 +106,11 @@ where
         Ok(BTreeStore {
             index,
             flatfile: Mutex::new(flatfile),
+            path: path.as_ref().to_path_buf(),
         })
     }
 
-    pub fn open(directory: impl AsRef<Path>) -> Result<BTreeStore<K>, BTreeStoreError> {
+    pub fn open(directory: impl AsRef<Path>) -> Result<BTreeStore<K, B>, BTreeStoreError> {
         if !directory.as_ref().is_dir() {
             return Err(BTreeStoreError::InvalidDirectory("path is not a directory"));
         }
@@ -112,6 +123,8 @@ where
 
         let index = BTree::open(metadata, file, static_file)?;
 
+        recover_compaction(directory.as_ref(), &index)?;
+
         let mut flatfile = directory.as_ref().to_path_buf();
         flatfile.push(APPENDER_FILE_PATH);
 
@@ -120,6 +133,7 @@ where
         Ok(BTreeStore {
             index,
             flatfile: Mutex::new(appender),
+            path: directory.as_ref().to_path_buf(),
         })
     }
 
@@ -127,18 +141,19 @@ where
         let mut flatfile = self.flatfile.lock().unwrap();
         let offset = flatfile.append(&blob)?;
 
-        let result = self.index.insert_one(key, offset.into());
+        self.index.insert_async(key, offset.into())?;
 
+        // the blob must be durable before the index version that points to it
         flatfile.sync()?;
         self.index.checkpoint()?;
 
-        result
+        Ok(())
     }
 
     /// insert many values in one transaction (with only one fsync)
-    pub fn insert_many<B: AsRef<[u8]>>(
+    pub fn insert_many<V: AsRef<[u8]>>(
         &self,
-        iter: impl IntoIterator<Item = (K, B)>,
+        iter: impl IntoIterator<Item = (K, V)>,
     ) -> Result<(), BTreeStoreError> {
         let mut flatfile = self.flatfile.lock().unwrap();
 
@@ -148,13 +163,69 @@ where
             offsets.push((key, offset.into()));
         }
 
-        self.index.insert_many(offsets.drain(..))?;
+        self.index.insert_many_async(offsets.drain(..))?;
 
         flatfile.sync()?;
         self.index.checkpoint()?;
         Ok(())
     }
 
+    /// delete the key from the index. The space used by its blob is not reclaimed until
+    /// `compact` is called
+    pub fn delete(&self, key: &K) -> Result<(), BTreeStoreError> {
+        self.index.delete_one(key)
+    }
+
+    /// delete many keys in one transaction. If some key is not found nothing is deleted
+    pub fn delete_many<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a K>,
+    ) -> Result<(), BTreeStoreError>
+    where
+        K: 'a,
+    {
+        self.index.delete_many(keys)
+    }
+
+    /// rewrite the flatfile keeping only the blobs that are still reachable from the index, in
+    /// order to reclaim the space used by deleted keys.
+    ///
+    /// The new flatfile is written and synced first, and then the new positions of the blobs are
+    /// written to a log. After that, the index is updated and the old flatfile is replaced. If
+    /// this is interrupted, `open` finishes the compaction if the log was completely written, or
+    /// discards it otherwise.
+    pub fn compact(&mut self) -> Result<(), BTreeStoreError> {
+        let compacted_path = self.path.join(COMPACTED_APPENDER_FILE_PATH);
+
+        // leftover of an interrupted compaction
+        if compacted_path.exists() {
+            std::fs::remove_file(&compacted_path)?;
+        }
+
+        let mut compacted = Appender::new(&compacted_path)?;
+        let flatfile = self.flatfile.get_mut().unwrap();
+
+        let mut offsets: Vec<(K, Value)> = vec![];
+        for (key, pos) in self.index.iter() {
+            let blob = flatfile.get_at(pos.into())?;
+            let new_pos = compacted.append(&blob)?;
+            offsets.push((key, new_pos.into()));
+        }
+
+        compacted.sync()?;
+
+        compaction::write_log(
+            &self.path.join(COMPACTION_LOG_FILE),
+            &offsets,
+            self.index.key_buffer_size(),
+        )?;
+
+        finish_compaction(&self.path, &self.index, offsets)?;
+        *flatfile = compacted;
+
+        Ok(())
+    }
+
     pub fn get(&self, key: &K) -> Result<Option<Box<[u8]>>, BTreeStoreError> {
         self.index
             .lookup(&key)
@@ -162,6 +233,133 @@ where
             .transpose()
             .map_err(|e| e.into())
     }
+
+    /// iterate over the keys in the given range and their blobs, in ascending order.
+    /// The iterator can be reversed to walk the keys in descending order.
+    ///
+    /// The keys are read from a snapshot of the index taken when the iterator is created,
+    /// the blobs are read lazily from the appender as the iterator advances.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, B> {
+        Iter {
+            keys: self.index.range(range),
+            flatfile: &self.flatfile,
+        }
+    }
+
+    /// iterate over all the keys and their blobs in ascending order
+    pub fn iter(&self) -> Iter<'_, K, B> {
+        self.range(..)
+    }
+
+    /// iterate over all the keys sharing a prefix (and their blobs), in ascending order.
+    /// `start` must be the smallest key with the given prefix, and `has_prefix` must hold for
+    /// a contiguous range of keys starting at `start`
+    pub fn prefix<'a, F>(
+        &'a self,
+        start: K,
+        mut has_prefix: F,
+    ) -> impl Iterator<Item = Result<(K, Box<[u8]>), BTreeStoreError>> + 'a
+    where
+        F: FnMut(&K) -> bool + 'a,
+    {
+        self.range(start..).take_while(move |entry| match entry {
+            Ok((key, _)) => has_prefix(key),
+            Err(_) => true,
+        })
+    }
+}
+
+/// finish or roll back a compaction interrupted by a crash, depending on whether its log was
+/// completely written
+fn recover_compaction<K: Key, B: Backend>(
+    path: &Path,
+    index: &BTree<K, B>,
+) -> Result<(), BTreeStoreError> {
+    let log_path = path.join(COMPACTION_LOG_FILE);
+
+    if log_path.exists() {
+        if let Some(offsets) = compaction::read_log(&log_path, index.key_buffer_size())? {
+            return finish_compaction(path, index, offsets);
+        }
+
+        std::fs::remove_file(&log_path)?;
+    }
+
+    let compacted_path = path.join(COMPACTED_APPENDER_FILE_PATH);
+    if compacted_path.exists() {
+        std::fs::remove_file(&compacted_path)?;
+    }
+
+    Ok(())
+}
+
+/// point the index to the compacted flatfile and replace the old one. Every step can be
+/// repeated, so this can be retried if it's interrupted
+fn finish_compaction<K: Key, B: Backend>(
+    path: &Path,
+    index: &BTree<K, B>,
+    offsets: Vec<(K, Value)>,
+) -> Result<(), BTreeStoreError> {
+    index.update_many(offsets)?;
+
+    let compacted_path = path.join(COMPACTED_APPENDER_FILE_PATH);
+    if compacted_path.exists() {
+        std::fs::rename(&compacted_path, path.join(APPENDER_FILE_PATH))?;
+        sync_directory(path)?;
+    }
+
+    std::fs::remove_file(path.join(COMPACTION_LOG_FILE))?;
+
+    Ok(())
+}
+
+/// make the renames in the directory durable
+fn sync_directory(path: &Path) -> Result<(), std::io::Error> {
+    // directories can't be opened as files on windows (and renames are durable there)
+    if cfg!(unix) {
+        std::fs::File::open(path)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Iterator over a range of keys of a `BTreeStore`, resolving each key to its blob
+pub struct Iter<'a, K, B: Backend = Disk> {
+    keys: RangeIter<'a, K, B>,
+    flatfile: &'a Mutex<Appender>,
+}
+
+impl<'a, K, B> Iter<'a, K, B>
+where
+    K: Key,
+    B: Backend,
+{
+    fn resolve(&self, (key, pos): (K, Value)) -> Result<(K, Box<[u8]>), BTreeStoreError> {
+        let blob = self.flatfile.lock().unwrap().get_at(pos.into())?;
+        Ok((key, blob))
+    }
+}
+
+impl<'a, K, B> Iterator for Iter<'a, K, B>
+where
+    K: Key,
+    B: Backend,
+{
+    type Item = Result<(K, Box<[u8]>), BTreeStoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.keys.next().map(|entry| self.resolve(entry))
+    }
+}
+
+impl<'a, K, B> DoubleEndedIterator for Iter<'a, K, B>
+where
+    K: Key,
+    B: Backend,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.keys.next_back().map(|entry| self.resolve(entry))
+    }
 }
 
 // the reference in this trait is because at some point we could just serve bytes directly as
@@ -185,6 +383,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::Storeable;
+    use crate::storage::fault::FaultyDisk;
     use crate::BTreeStore;
     use byteorder::{ByteOrder, LittleEndian};
     #[derive(Debug, Clone, Ord, Eq, PartialEq, PartialOrd)]
@@ -202,6 +401,177 @@ mod tests {
         }
     }
 
+    #[test]
+    fn range_resolves_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let db: BTreeStore<U64Key> =
+            BTreeStore::new(dir.path(), std::mem::size_of::<U64Key>() as u32, 4096).unwrap();
+
+        db.insert_many((0..500u64).map(|i| (U64Key(i), i.to_le_bytes())))
+            .unwrap();
+
+        let forward: Vec<(u64, Box<[u8]>)> = db
+            .range(U64Key(100)..U64Key(200))
+            .map(|entry| entry.map(|(k, blob)| (k.0, blob)).unwrap())
+            .collect();
+
+        assert_eq!(forward.len(), 100);
+        for (i, (k, blob)) in forward.iter().enumerate() {
+            assert_eq!(*k, 100 + i as u64);
+            assert_eq!(&blob[..], &k.to_le_bytes()[..]);
+        }
+
+        let backward: Vec<u64> = db
+            .iter()
+            .rev()
+            .take(3)
+            .map(|entry| entry.unwrap().0 .0)
+            .collect();
+        assert_eq!(backward, vec![499, 498, 497]);
+
+        let prefixed: Vec<u64> = db
+            .prefix(U64Key(400), |k| k.0 / 100 == 4)
+            .map(|entry| entry.unwrap().0 .0)
+            .collect();
+        assert_eq!(prefixed, (400..500).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn compact_reclaims_deleted_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let flatfile_len = || {
+            std::fs::metadata(dir.path().join(super::APPENDER_FILE_PATH))
+                .unwrap()
+                .len()
+        };
+
+        let blob = |i: u64| vec![(i % 256) as u8; 1000];
+
+        {
+            let mut db: BTreeStore<U64Key> =
+                BTreeStore::new(dir.path(), std::mem::size_of::<U64Key>() as u32, 4096).unwrap();
+
+            db.insert_many((0..1000u64).map(|i| (U64Key(i), blob(i))))
+                .unwrap();
+
+            let to_delete: Vec<U64Key> = (0..1000u64).filter(|i| i % 10 != 0).map(U64Key).collect();
+            db.delete_many(to_delete.iter()).unwrap();
+
+            assert_eq!(db.get(&U64Key(1)).unwrap(), None);
+            let len_before = flatfile_len();
+
+            db.compact().unwrap();
+
+            assert!(flatfile_len() * 5 < len_before);
+
+            for i in 0..1000u64 {
+                let expected = if i % 10 == 0 {
+                    Some(blob(i).into_boxed_slice())
+                } else {
+                    None
+                };
+                assert_eq!(db.get(&U64Key(i)).unwrap(), expected);
+            }
+
+            // the compacted flatfile can still be appended to
+            db.insert_many(Some((U64Key(1), blob(1)))).unwrap();
+            assert_eq!(
+                db.get(&U64Key(1)).unwrap(),
+                Some(blob(1).into_boxed_slice())
+            );
+        }
+
+        let db: BTreeStore<U64Key> = BTreeStore::open(dir.path()).unwrap();
+        assert_eq!(db.iter().count(), 101);
+        for i in (0..1000u64).step_by(10) {
+            assert_eq!(
+                db.get(&U64Key(i)).unwrap(),
+                Some(blob(i).into_boxed_slice())
+            );
+        }
+        assert_eq!(
+            db.get(&U64Key(1)).unwrap(),
+            Some(blob(1).into_boxed_slice())
+        );
+    }
+
+    fn store_with_deletions(path: &std::path::Path) -> BTreeStore<U64Key, FaultyDisk> {
+        let db: BTreeStore<U64Key, FaultyDisk> =
+            BTreeStore::new(path, std::mem::size_of::<U64Key>() as u32, 4096).unwrap();
+        db.insert_many((0..200u64).map(|i| (U64Key(i), i.to_le_bytes())))
+            .unwrap();
+        let to_delete: Vec<U64Key> = (0..200u64).filter(|i| i % 2 == 0).map(U64Key).collect();
+        db.delete_many(to_delete.iter()).unwrap();
+        db
+    }
+
+    fn assert_store_with_deletions(path: &std::path::Path) {
+        let db: BTreeStore<U64Key> = BTreeStore::open(path).unwrap();
+        for i in 0..200u64 {
+            let expected = if i % 2 == 0 {
+                None
+            } else {
+                Some(i.to_le_bytes().to_vec().into_boxed_slice())
+            };
+            assert_eq!(db.get(&U64Key(i)).unwrap(), expected);
+        }
+
+        assert!(!path.join(super::COMPACTION_LOG_FILE).exists());
+        assert!(!path.join(super::COMPACTED_APPENDER_FILE_PATH).exists());
+    }
+
+    #[test]
+    fn interrupted_compaction_is_finished_on_open() {
+        for crash_at in 0.. {
+            let dir = tempfile::tempdir().unwrap();
+            let db = store_with_deletions(dir.path());
+            let flatfile_len = std::fs::metadata(dir.path().join(super::APPENDER_FILE_PATH))
+                .unwrap()
+                .len();
+
+            crate::storage::fault::crash_at(crash_at, true);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let mut db = db;
+                db.compact().unwrap();
+            }));
+            let crashed = crate::storage::fault::disarm();
+            assert_eq!(crashed, result.is_err());
+
+            // the log is written before the first write to the index, so the compaction is
+            // always finished
+            assert_store_with_deletions(dir.path());
+            assert!(
+                std::fs::metadata(dir.path().join(super::APPENDER_FILE_PATH))
+                    .unwrap()
+                    .len()
+                    < flatfile_len
+            );
+
+            if !crashed {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn incomplete_compaction_is_rolled_back_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        drop(store_with_deletions(dir.path()));
+
+        let log_path = dir.path().join(super::COMPACTION_LOG_FILE);
+        let positions: Vec<(U64Key, u64)> = (0..200u64).map(|i| (U64Key(i), i)).collect();
+        crate::compaction::write_log(&log_path, &positions, 8).unwrap();
+        let log = std::fs::read(&log_path).unwrap();
+        std::fs::write(&log_path, &log[..log.len() - 1]).unwrap();
+        std::fs::write(
+            dir.path().join(super::COMPACTED_APPENDER_FILE_PATH),
+            b"garbage",
+        )
+        .unwrap();
+
+        assert_store_with_deletions(dir.path());
+    }
+
     #[test]
     fn is_send() {
         // test (at compile time) that certain types implement the auto-trait Send, either directly for
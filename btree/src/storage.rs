@@ -2,15 +2,42 @@ use memmap::MmapMut;
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::fs::File;
-use std::io;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::mem::ManuallyDrop;
 
+#[cfg(test)]
+pub(crate) mod fault;
+
+/// the storages used by the index, for its pages and for its metadata. Tests use a backend that
+/// wraps them in order to simulate crashes at any write
+pub trait Backend {
+    type Pages: for<'a> Storage<'a>;
+    type Metadata: for<'a> Storage<'a> + Send;
+
+    fn pages(file: File) -> Result<Self::Pages, io::Error>;
+    fn metadata(file: File) -> Self::Metadata;
+}
+
+/// the default backend: the pages are mmaped, and the metadata is written to a plain file
+pub struct Disk;
+
+impl Backend for Disk {
+    type Pages = MmapStorage;
+    type Metadata = FileStorage;
+
+    fn pages(file: File) -> Result<Self::Pages, io::Error> {
+        MmapStorage::new(file)
+    }
+
+    fn metadata(file: File) -> Self::Metadata {
+        FileStorage::new(file)
+    }
+}
+
 pub trait Storage<'a> {
-    type Output;
+    type Output: AsRef<[u8]>;
 
-    fn get(&'a self, location: u64, count: u64) -> Result<Self::Output, io::Error>
-    where
-        Self::Output: AsRef<[u8]> + 'a;
+    fn get(&'a self, location: u64, count: u64) -> Result<Self::Output, io::Error>;
 
     fn put(&mut self, location: u64, bytes: impl AsRef<[u8]>) -> Result<(), io::Error>;
 
@@ -59,7 +86,9 @@ impl<'a> Storage<'a> for MmapStorage {
     fn get(&'a self, location: u64, count: u64) -> Result<Self::Output, io::Error> {
         let location: usize = location.try_into().unwrap();
         let count: usize = count.try_into().unwrap();
-        Ok(&self.mmap[location..location + count])
+        self.mmap
+            .get(location..location + count)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "read past the end"))
     }
 
     fn put(&mut self, location: u64, bytes: impl AsRef<[u8]>) -> Result<(), std::io::Error> {
@@ -106,6 +135,39 @@ impl Drop for MmapStorage {
     }
 }
 
+/// Storage over a plain file, for small files that are written with explicit seeks (like the
+/// metadata) and don't benefit from being mmaped
+pub struct FileStorage {
+    file: File,
+}
+
+impl FileStorage {
+    pub fn new(file: File) -> Self {
+        FileStorage { file }
+    }
+}
+
+impl<'a> Storage<'a> for FileStorage {
+    type Output = Vec<u8>;
+
+    fn get(&'a self, location: u64, count: u64) -> Result<Self::Output, io::Error> {
+        let mut file = &self.file;
+        let mut buf = vec![0u8; count.try_into().unwrap()];
+        file.seek(SeekFrom::Start(location))?;
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn put(&mut self, location: u64, bytes: impl AsRef<[u8]>) -> Result<(), io::Error> {
+        self.file.seek(SeekFrom::Start(location))?;
+        self.file.write_all(bytes.as_ref())
+    }
+
+    fn sync(&self) -> Result<(), io::Error> {
+        self.file.sync_all()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -124,4 +186,19 @@ mod tests {
 
         assert_eq!(result, &expected[..]);
     }
+
+    #[test]
+    fn file_put_and_get() {
+        let file = tempfile().unwrap();
+        let mut storage = FileStorage::new(file);
+
+        let expected = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+        storage.put(30, &expected).unwrap();
+
+        let result = storage.get(30, expected.len().try_into().unwrap()).unwrap();
+
+        assert_eq!(result, expected);
+        assert!(storage.get(35, 10).is_err());
+    }
 }
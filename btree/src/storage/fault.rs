@@ -0,0 +1,218 @@
+//! Fault injection for the storage of the index, used to test crash recovery.
+//!
+//! A crash can be planned at the n-th write (or sync) done through a `FaultyStorage` in the
+//! current thread. When it is reached, a write is torn (only the first half of the bytes are
+//! written), a sync is skipped, and the thread panics, unwinding as if the process had died.
+//! Anything written after that is ignored. Optionally, the writes that were not synced before the
+//! crash are reverted when the storage is dropped, as if the OS never flushed them to disk.
+
+use super::{Backend, FileStorage, MmapStorage, Storage};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::sync::Mutex;
+
+struct Plan {
+    crash_at: usize,
+    writes: usize,
+    crashed: bool,
+    lose_unsynced: bool,
+}
+
+thread_local! {
+    static PLAN: RefCell<Option<Plan>> = const { RefCell::new(None) };
+}
+
+enum Fault {
+    Unplanned,
+    Alive,
+    Crash,
+    Dead,
+}
+
+/// plan a crash at the `crash_at`-th write point (counting from 0) from now on
+pub(crate) fn crash_at(crash_at: usize, lose_unsynced: bool) {
+    PLAN.with(|plan| {
+        *plan.borrow_mut() = Some(Plan {
+            crash_at,
+            writes: 0,
+            crashed: false,
+            lose_unsynced,
+        })
+    })
+}
+
+/// remove the planned crash, returning true if it happened
+pub(crate) fn disarm() -> bool {
+    PLAN.with(|plan| {
+        plan.borrow_mut()
+            .take()
+            .map(|plan| plan.crashed)
+            .unwrap_or(false)
+    })
+}
+
+fn next_write() -> Fault {
+    PLAN.with(|plan| match &mut *plan.borrow_mut() {
+        None => Fault::Unplanned,
+        Some(plan) if plan.crashed => Fault::Dead,
+        Some(plan) => {
+            let fault = if plan.writes == plan.crash_at {
+                plan.crashed = true;
+                Fault::Crash
+            } else {
+                Fault::Alive
+            };
+            plan.writes += 1;
+            fault
+        }
+    })
+}
+
+fn unsynced_writes_are_lost() -> bool {
+    PLAN.with(|plan| {
+        plan.borrow()
+            .as_ref()
+            .map(|plan| plan.crashed && plan.lose_unsynced)
+            .unwrap_or(false)
+    })
+}
+
+/// the storages that can be wrapped, with their operations over plain byte buffers
+pub(crate) trait Wrappable {
+    fn read(&self, location: u64, count: u64) -> Result<Vec<u8>, io::Error>;
+    fn write(&mut self, location: u64, bytes: &[u8]) -> Result<(), io::Error>;
+    fn sync(&self) -> Result<(), io::Error>;
+}
+
+impl Wrappable for MmapStorage {
+    fn read(&self, location: u64, count: u64) -> Result<Vec<u8>, io::Error> {
+        Storage::get(self, location, count).map(|bytes| bytes.to_vec())
+    }
+
+    fn write(&mut self, location: u64, bytes: &[u8]) -> Result<(), io::Error> {
+        Storage::put(self, location, bytes)
+    }
+
+    fn sync(&self) -> Result<(), io::Error> {
+        Storage::sync(self)
+    }
+}
+
+impl Wrappable for FileStorage {
+    fn read(&self, location: u64, count: u64) -> Result<Vec<u8>, io::Error> {
+        Storage::get(self, location, count)
+    }
+
+    fn write(&mut self, location: u64, bytes: &[u8]) -> Result<(), io::Error> {
+        Storage::put(self, location, bytes)
+    }
+
+    fn sync(&self) -> Result<(), io::Error> {
+        Storage::sync(self)
+    }
+}
+
+pub(crate) struct FaultyStorage<S>
+where
+    S: Wrappable,
+{
+    inner: S,
+    // previous content of the locations written since the last sync, in write order
+    unsynced: Mutex<Vec<(u64, Vec<u8>)>>,
+}
+
+/// backend wrapping the storages of `Disk` in a `FaultyStorage`
+pub(crate) struct FaultyDisk;
+
+impl Backend for FaultyDisk {
+    type Pages = FaultyStorage<MmapStorage>;
+    type Metadata = FaultyStorage<FileStorage>;
+
+    fn pages(file: File) -> Result<Self::Pages, io::Error> {
+        MmapStorage::new(file).map(FaultyStorage::wrap)
+    }
+
+    fn metadata(file: File) -> Self::Metadata {
+        FaultyStorage::wrap(FileStorage::new(file))
+    }
+}
+
+impl<S> FaultyStorage<S>
+where
+    S: Wrappable,
+{
+    fn wrap(inner: S) -> Self {
+        FaultyStorage {
+            inner,
+            unsynced: Mutex::new(vec![]),
+        }
+    }
+
+    fn tracked_write(&mut self, location: u64, bytes: &[u8]) -> Result<(), io::Error> {
+        // locations past the end of the storage are restored as zeros
+        let previous = self
+            .inner
+            .read(location, bytes.len() as u64)
+            .unwrap_or_else(|_| vec![0; bytes.len()]);
+
+        self.unsynced.lock().unwrap().push((location, previous));
+        self.inner.write(location, bytes)
+    }
+}
+
+impl<'a, S> Storage<'a> for FaultyStorage<S>
+where
+    S: Wrappable,
+{
+    type Output = Vec<u8>;
+
+    fn get(&'a self, location: u64, count: u64) -> Result<Self::Output, io::Error> {
+        self.inner.read(location, count)
+    }
+
+    fn put(&mut self, location: u64, bytes: impl AsRef<[u8]>) -> Result<(), io::Error> {
+        let bytes = bytes.as_ref();
+        match next_write() {
+            Fault::Unplanned => self.inner.write(location, bytes),
+            Fault::Alive => self.tracked_write(location, bytes),
+            Fault::Crash => {
+                self.tracked_write(location, &bytes[..bytes.len() / 2])?;
+                panic!("simulated crash while writing")
+            }
+            Fault::Dead => Ok(()),
+        }
+    }
+
+    fn sync(&self) -> Result<(), io::Error> {
+        match next_write() {
+            Fault::Unplanned | Fault::Alive => {
+                self.inner.sync()?;
+                self.unsynced.lock().unwrap().clear();
+                Ok(())
+            }
+            Fault::Crash => panic!("simulated crash while syncing"),
+            Fault::Dead => Ok(()),
+        }
+    }
+}
+
+impl<S> Drop for FaultyStorage<S>
+where
+    S: Wrappable,
+{
+    fn drop(&mut self) {
+        if !unsynced_writes_are_lost() {
+            return;
+        }
+
+        let unsynced = match self.unsynced.get_mut() {
+            Ok(unsynced) => unsynced,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        while let Some((location, previous)) = unsynced.pop() {
+            let _ = self.inner.write(location, &previous);
+        }
+    }
+}
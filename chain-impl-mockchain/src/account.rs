@@ -1,5 +1,6 @@
 use crate::accounting::account;
-use crate::key::{deserialize_public_key, serialize_public_key};
+use crate::key::{deserialize_public_key, serialize_public_key, Hash};
+use crate::merkle::Commitment;
 use crate::transaction::WitnessAccountData;
 use chain_core::{
     mempack::{ReadBuf, ReadError, Readable},
@@ -35,6 +36,12 @@ impl AsRef<PublicKey<AccountAlg>> for Identifier {
     }
 }
 
+impl Commitment for Identifier {
+    fn commitment(&self) -> Hash {
+        Hash::hash_bytes(self.0.as_ref())
+    }
+}
+
 impl property::Serialize for Identifier {
     type Error = std::io::Error;
     fn serialize<W: std::io::Write>(&self, writer: W) -> Result<(), Self::Error> {
@@ -1,7 +1,11 @@
-use crate::certificate::PoolId;
+use crate::certificate::{deserialize_delegation_type, serialize_delegation_type, PoolId};
 use crate::header::Epoch;
+use crate::key::Hash;
+use crate::merkle::Commitment;
 use crate::value::*;
+use chain_core::mempack::{ReadBuf, ReadError, Readable};
 use imhamt::HamtIter;
+use typed_bytes::ByteBuilder;
 
 use super::{LastRewards, LedgerError};
 
@@ -121,6 +125,41 @@ impl<Extra> AccountState<Extra> {
     }
 }
 
+impl AccountState<()> {
+    pub fn serialize_in(&self, bb: ByteBuilder<Self>) -> ByteBuilder<Self> {
+        bb.u32(self.counter.into())
+            .sub(|bb| serialize_delegation_type(&self.delegation, bb))
+            .u64(self.value.0)
+            .u32(self.last_rewards.epoch)
+            .u64(self.last_rewards.reward.0)
+    }
+}
+
+impl Readable for AccountState<()> {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        let counter = SpendingCounter(buf.get_u32()?);
+        let delegation = deserialize_delegation_type(buf)?;
+        let value = Value::read(buf)?;
+        let last_rewards = LastRewards {
+            epoch: buf.get_u32()?,
+            reward: Value::read(buf)?,
+        };
+        Ok(AccountState {
+            counter,
+            delegation,
+            value,
+            last_rewards,
+            extra: (),
+        })
+    }
+}
+
+impl Commitment for AccountState<()> {
+    fn commitment(&self) -> Hash {
+        Hash::hash_bytes(&self.serialize_in(ByteBuilder::new()).finalize_as_vec())
+    }
+}
+
 impl<Extra: Clone> AccountState<Extra> {
     /// Same as add() except use a ValueError
     pub fn add_value(&self, v: Value) -> Result<Self, ValueError> {
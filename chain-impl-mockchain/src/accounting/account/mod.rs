@@ -8,6 +8,9 @@ pub mod account_state;
 pub mod last_rewards;
 
 use crate::header::Epoch;
+use crate::key;
+use crate::ledger::diff::{hamt_apply_entry, hamt_diff, DiffError, MapDiff};
+use crate::merkle::{Commitment, MerkleProof, MerkleTree};
 use crate::value::*;
 use imhamt::{Hamt, InsertError, UpdateError};
 use std::collections::hash_map::DefaultHasher;
@@ -54,12 +57,75 @@ impl From<InsertError> for LedgerError {
 
 /// The public ledger of all accounts associated with their current state
 #[derive(Clone, PartialEq, Eq)]
-pub struct Ledger<ID: Hash + Eq, Extra>(Hamt<DefaultHasher, ID, AccountState<Extra>>);
+pub struct Ledger<ID: Hash + Eq, Extra> {
+    accounts: Hamt<DefaultHasher, ID, AccountState<Extra>>,
+    state_tree: MerkleTree,
+}
 
-impl<ID: Clone + Eq + Hash, Extra: Clone> Ledger<ID, Extra> {
+impl<ID, Extra> Ledger<ID, Extra>
+where
+    ID: Clone + Eq + Hash + Commitment,
+    Extra: Clone,
+    AccountState<Extra>: Commitment,
+{
     /// Create a new empty account ledger
     pub fn new() -> Self {
-        Ledger(Hamt::new())
+        Ledger {
+            accounts: Hamt::new(),
+            state_tree: MerkleTree::new(),
+        }
+    }
+
+    /// update the state tree for an account which has been changed in the given accounts
+    fn with_accounts(
+        &self,
+        accounts: Hamt<DefaultHasher, ID, AccountState<Extra>>,
+        identifier: &ID,
+    ) -> Self {
+        let state_tree = match accounts.lookup(identifier) {
+            Some(state) => self
+                .state_tree
+                .insert(identifier.commitment(), &state.commitment()),
+            None => self.state_tree.remove(&identifier.commitment()),
+        };
+        Ledger {
+            accounts,
+            state_tree,
+        }
+    }
+
+    /// Accounts changed from this ledger to the `other` ledger
+    pub(crate) fn diff(&self, other: &Self) -> MapDiff<ID, AccountState<Extra>>
+    where
+        ID: Ord,
+        Extra: PartialEq,
+    {
+        hamt_diff(&self.accounts, &other.accounts)
+    }
+
+    /// Apply the changes of the accounts, checking the current states
+    pub(crate) fn apply_diff(
+        &self,
+        diff: &MapDiff<ID, AccountState<Extra>>,
+    ) -> Result<Self, DiffError>
+    where
+        Extra: PartialEq,
+    {
+        diff.iter()
+            .try_fold(self.clone(), |ledger, (identifier, entry)| {
+                let accounts = hamt_apply_entry(&ledger.accounts, identifier, entry)?;
+                Ok(ledger.with_accounts(accounts, identifier))
+            })
+    }
+
+    /// root of the merkle tree of all the accounts
+    pub fn state_root(&self) -> key::Hash {
+        self.state_tree.root()
+    }
+
+    /// Proof that the account is in the ledger, if it exists
+    pub fn proof(&self, identifier: &ID) -> Option<MerkleProof> {
+        self.state_tree.proof(&identifier.commitment())
     }
 
     /// Add a new account into this ledger.
@@ -71,9 +137,9 @@ impl<ID: Clone + Eq + Hash, Extra: Clone> Ledger<ID, Extra> {
         initial_value: Value,
         extra: Extra,
     ) -> Result<Self, LedgerError> {
-        self.0
+        self.accounts
             .insert(identifier.clone(), AccountState::new(initial_value, extra))
-            .map(Ledger)
+            .map(|accounts| self.with_accounts(accounts, identifier))
             .map_err(|e| e.into())
     }
 
@@ -83,32 +149,32 @@ impl<ID: Clone + Eq + Hash, Extra: Clone> Ledger<ID, Extra> {
         identifier: &ID,
         delegation: &DelegationType,
     ) -> Result<Self, LedgerError> {
-        self.0
+        self.accounts
             .update(identifier, |st| {
                 Ok(Some(st.set_delegation(delegation.clone())))
             })
-            .map(Ledger)
+            .map(|accounts| self.with_accounts(accounts, identifier))
             .map_err(|e| e.into())
     }
 
     /// check if an account already exist
     #[inline]
     pub fn exists(&self, identifier: &ID) -> bool {
-        self.0.contains_key(identifier)
+        self.accounts.contains_key(identifier)
     }
 
     /// Get account state
     ///
     /// If the identifier does not match any account, error out
     pub fn get_state(&self, account: &ID) -> Result<&AccountState<Extra>, LedgerError> {
-        self.0.lookup(account).ok_or(LedgerError::NonExistent)
+        self.accounts.lookup(account).ok_or(LedgerError::NonExistent)
     }
 
     /// Remove an account from this ledger
     ///
     /// If the account still have value > 0, then error
     pub fn remove_account(&self, identifier: &ID) -> Result<Self, LedgerError> {
-        self.0
+        self.accounts
             .update(identifier, |st| {
                 if st.value == Value::zero() {
                     Ok(None)
@@ -116,7 +182,7 @@ impl<ID: Clone + Eq + Hash, Extra: Clone> Ledger<ID, Extra> {
                     Err(LedgerError::NonZero)
                 }
             })
-            .map(Ledger)
+            .map(|accounts| self.with_accounts(accounts, identifier))
             .map_err(|e| e.into())
     }
 
@@ -124,9 +190,9 @@ impl<ID: Clone + Eq + Hash, Extra: Clone> Ledger<ID, Extra> {
     ///
     /// If the account doesn't exist, error out.
     pub fn add_value(&self, identifier: &ID, value: Value) -> Result<Self, LedgerError> {
-        self.0
+        self.accounts
             .update(identifier, |st| st.add(value).map(Some))
-            .map(Ledger)
+            .map(|accounts| self.with_accounts(accounts, identifier))
             .map_err(|e| e.into())
     }
 
@@ -139,11 +205,11 @@ impl<ID: Clone + Eq + Hash, Extra: Clone> Ledger<ID, Extra> {
         value: Value,
         extra: Extra,
     ) -> Result<Self, ValueError> {
-        self.0
+        self.accounts
             .insert_or_update(identifier.clone(), AccountState::new(value, extra), |st| {
                 st.add_value(value).map(Some)
             })
-            .map(Ledger)
+            .map(|accounts| self.with_accounts(accounts, identifier))
     }
 
     /// Add rewards to an existing account.
@@ -156,13 +222,13 @@ impl<ID: Clone + Eq + Hash, Extra: Clone> Ledger<ID, Extra> {
         value: Value,
         extra: Extra,
     ) -> Result<Self, ValueError> {
-        self.0
+        self.accounts
             .insert_or_update(
                 identifier.clone(),
                 AccountState::new_reward(epoch, value, extra),
                 |st| st.add_rewards(epoch, value).map(Some),
             )
-            .map(Ledger)
+            .map(|accounts| self.with_accounts(accounts, identifier))
     }
 
     /// Subtract value to an existing account.
@@ -175,25 +241,25 @@ impl<ID: Clone + Eq + Hash, Extra: Clone> Ledger<ID, Extra> {
     ) -> Result<(Self, SpendingCounter), LedgerError> {
         // ideally we don't need 2 calls to do this
         let counter = self
-            .0
+            .accounts
             .lookup(identifier)
             .map_or(Err(LedgerError::NonExistent), |st| Ok(st.counter))?;
-        self.0
+        self.accounts
             .update(identifier, |st| st.sub(value))
-            .map(|ledger| (Ledger(ledger), counter))
+            .map(|accounts| (self.with_accounts(accounts, identifier), counter))
             .map_err(|e| e.into())
     }
 
     pub fn get_total_value(&self) -> Result<Value, ValueError> {
         let values = self
-            .0
+            .accounts
             .iter()
             .map(|(_, account_state)| account_state.get_value());
         Value::sum(values)
     }
 
     pub fn iter<'a>(&'a self) -> Iter<'a, ID, Extra> {
-        Iter(self.0.iter())
+        Iter(self.accounts.iter())
     }
 }
 
@@ -202,7 +268,7 @@ impl<ID: Clone + Eq + Hash + Debug, Extra: Clone + Debug> Debug for Ledger<ID, E
         write!(
             f,
             "{:?}",
-            self.0
+            self.accounts
                 .iter()
                 .map(|(id, account)| (id.clone(), account.clone()))
                 .collect::<Vec<(ID, AccountState<Extra>)>>()
@@ -210,11 +276,19 @@ impl<ID: Clone + Eq + Hash + Debug, Extra: Clone + Debug> Debug for Ledger<ID, E
     }
 }
 
-impl<ID: Clone + Eq + Hash, Extra: Clone> std::iter::FromIterator<(ID, AccountState<Extra>)>
-    for Ledger<ID, Extra>
+impl<ID, Extra> std::iter::FromIterator<(ID, AccountState<Extra>)> for Ledger<ID, Extra>
+where
+    ID: Clone + Eq + Hash + Commitment,
+    Extra: Clone,
+    AccountState<Extra>: Commitment,
 {
     fn from_iter<I: IntoIterator<Item = (ID, AccountState<Extra>)>>(iter: I) -> Self {
-        Ledger(Hamt::from_iter(iter))
+        let accounts = Hamt::from_iter(iter);
+        let state_tree = accounts.iter().collect();
+        Ledger {
+            accounts,
+            state_tree,
+        }
     }
 }
 
@@ -142,7 +142,7 @@ impl Payload for StakeDelegation {
 // 0 (byte)
 // 1 (byte)     POOL_ID (32 bytes)
 // PARTS (byte) #POOLS (bytes) [ POOL_PART (1 byte) POOL_ID (32 bytes)] (repeated #POOLS time)
-fn serialize_delegation_type(
+pub(crate) fn serialize_delegation_type(
     d: &DelegationType,
     bb: ByteBuilder<DelegationType>,
 ) -> ByteBuilder<DelegationType> {
@@ -160,7 +160,7 @@ fn serialize_delegation_type(
     }
 }
 
-fn deserialize_delegation_type<'a>(buf: &mut ReadBuf<'a>) -> Result<DelegationType, ReadError> {
+pub(crate) fn deserialize_delegation_type<'a>(buf: &mut ReadBuf<'a>) -> Result<DelegationType, ReadError> {
     let parts = buf.get_u8()?;
     match parts {
         0 => Ok(DelegationType::NonDelegated),
@@ -7,6 +7,7 @@ mod test;
 use crate::transaction::{Payload, PayloadData, PayloadSlice};
 
 pub use delegation::{OwnerStakeDelegation, StakeDelegation};
+pub(crate) use delegation::{deserialize_delegation_type, serialize_delegation_type};
 pub use pool::{
     GenesisPraosLeaderHash, IndexSignatures, ManagementThreshold, PoolId, PoolOwnersSigned,
     PoolPermissions, PoolRegistration, PoolRegistrationHash, PoolRetirement, PoolSignature,
@@ -1,6 +1,13 @@
+use crate::date::BlockDate;
+use crate::fee::FeeAlgorithm;
+use crate::fragment::footprint::{footprint, Footprint, Resource};
 use crate::fragment::Fragment;
 use crate::key::Hash;
+use crate::ledger::{Ledger, LedgerParameters};
+use crate::value::Value;
 use chain_core::property::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
 use std::slice;
 
 pub type BlockContentHash = Hash;
@@ -80,4 +87,251 @@ impl ContentsBuilder {
         self.fragments.extend(fragments);
         self
     }
+
+    /// Adds the most profitable of the candidate fragments that can be
+    /// applied in sequence on top of `ledger`, within the block content
+    /// max size. Returns `ledger` with the added fragments applied.
+    ///
+    /// Candidates are added by decreasing fee per byte, the fee being
+    /// computed with the fee algorithm of the ledger parameters. A
+    /// candidate failing to apply is retried once a candidate using the
+    /// same UTxOs or accounts has been added, as it may depend on it, and
+    /// is skipped if it never applies. The fragments already in the
+    /// builder count towards the size limit and are expected to be
+    /// applied to `ledger`.
+    pub fn push_by_fee<I>(
+        &mut self,
+        ledger: &Ledger,
+        ledger_params: &LedgerParameters,
+        block_date: BlockDate,
+        candidates: I,
+    ) -> Ledger
+    where
+        I: IntoIterator<Item = Fragment>,
+    {
+        let max = ledger_params.block_content_max_size as usize;
+        let mut size: usize = self
+            .fragments
+            .iter()
+            .map(|fragment| fragment.to_raw().size_bytes_plus_size())
+            .sum();
+        let candidates: Vec<_> = candidates
+            .into_iter()
+            .map(|fragment| Candidate {
+                fee: fragment_fee(&fragment, ledger_params),
+                size: fragment.to_raw().size_bytes_plus_size(),
+                footprint: footprint(fragment.hash(), &fragment),
+                fragment,
+            })
+            .collect();
+        let mut queue: BinaryHeap<_> = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| candidate.queued(index))
+            .collect();
+
+        // candidates that failed to apply, by the resources they wait on
+        let mut parked = vec![false; candidates.len()];
+        let mut waiting: HashMap<&Resource, Vec<usize>> = HashMap::new();
+        let mut waiting_globals = Vec::new();
+        let mut ledger = ledger.clone();
+        while let Some(Queued { index, .. }) = queue.pop() {
+            let candidate = &candidates[index];
+            if size + candidate.size > max {
+                continue;
+            }
+            match ledger.apply_fragment(ledger_params, &candidate.fragment, block_date) {
+                Ok(new_ledger) => {
+                    ledger = new_ledger;
+                    size += candidate.size;
+                    self.fragments.push(candidate.fragment.clone());
+                    let mut woken: Vec<usize> = candidate
+                        .footprint
+                        .resources
+                        .iter()
+                        .filter_map(|resource| waiting.remove(resource))
+                        .flatten()
+                        .collect();
+                    if candidate.footprint.global {
+                        woken.append(&mut waiting_globals);
+                    }
+                    for other in woken {
+                        if parked[other] {
+                            parked[other] = false;
+                            queue.push(candidates[other].queued(other));
+                        }
+                    }
+                }
+                Err(_) => {
+                    parked[index] = true;
+                    for resource in &candidate.footprint.resources {
+                        waiting.entry(resource).or_default().push(index);
+                    }
+                    if candidate.footprint.global {
+                        waiting_globals.push(index);
+                    }
+                }
+            }
+        }
+        ledger
+    }
+}
+
+struct Candidate {
+    fragment: Fragment,
+    fee: Value,
+    size: usize,
+    footprint: Footprint,
+}
+
+impl Candidate {
+    fn queued(&self, index: usize) -> Queued {
+        Queued {
+            fee: self.fee,
+            size: self.size,
+            index,
+        }
+    }
+}
+
+// Candidate waiting to be applied, ordered by fee rate and then by
+// position in the candidates.
+#[derive(PartialEq, Eq)]
+struct Queued {
+    fee: Value,
+    size: usize,
+    index: usize,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let lhs = u128::from(self.fee.0) * other.size as u128;
+        let rhs = u128::from(other.fee.0) * self.size as u128;
+        lhs.cmp(&rhs).then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn fragment_fee(fragment: &Fragment, ledger_params: &LedgerParameters) -> Value {
+    let fees = &ledger_params.fees;
+    match fragment {
+        Fragment::Initial(_)
+        | Fragment::OldUtxoDeclaration(_)
+        | Fragment::UpdateProposal(_)
+        | Fragment::UpdateVote(_) => Value::zero(),
+        Fragment::Transaction(tx) => fees.calculate_tx(&tx.as_slice()),
+        Fragment::OwnerStakeDelegation(tx) => fees.calculate_tx(&tx.as_slice()),
+        Fragment::StakeDelegation(tx) => fees.calculate_tx(&tx.as_slice()),
+        Fragment::PoolRegistration(tx) => fees.calculate_tx(&tx.as_slice()),
+        Fragment::PoolRetirement(tx) => fees.calculate_tx(&tx.as_slice()),
+        Fragment::PoolUpdate(tx) => fees.calculate_tx(&tx.as_slice()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fee::LinearFee,
+        fragment::FragmentId,
+        testing::{
+            data::AddressDataValue, ConfigBuilder, LedgerBuilder, TestLedger, TestTxBuilder,
+        },
+    };
+    use chain_addr::Discrimination;
+
+    fn test_ledger(config: ConfigBuilder, funds: &Vec<AddressDataValue>) -> TestLedger {
+        LedgerBuilder::from_config(config.with_fee(LinearFee::new(0, 10, 0)))
+            .initial_funds(funds)
+            .build()
+            .expect("cannot build test ledger")
+    }
+
+    // Spends the value of the source to the given number of outputs.
+    fn transfer(test_ledger: &mut TestLedger, source: &AddressDataValue, outputs: u8) -> Fragment {
+        let fee = test_ledger.fee().calculate(None, 1, outputs);
+        let value = (source.value - fee).unwrap().0 / outputs as u64;
+        let destinations = (0..outputs)
+            .map(|_| AddressDataValue::account(Discrimination::Test, Value(value)))
+            .collect();
+        TestTxBuilder::new(&test_ledger.block0_hash)
+            .move_funds_multiple(test_ledger, &vec![source.clone()], &destinations)
+            .get_fragment()
+    }
+
+    fn push_by_fee(test_ledger: &TestLedger, candidates: Vec<Fragment>) -> Vec<FragmentId> {
+        let mut builder = ContentsBuilder::new();
+        builder.push_by_fee(
+            &test_ledger.ledger,
+            &test_ledger.parameters,
+            test_ledger.date(),
+            candidates,
+        );
+        let contents: Contents = builder.into();
+        contents.iter().map(|fragment| fragment.hash()).collect()
+    }
+
+    #[test]
+    fn highest_fee_rate_is_preferred() {
+        let sources = vec![
+            AddressDataValue::utxo(Discrimination::Test, Value(100)),
+            AddressDataValue::utxo(Discrimination::Test, Value(100)),
+        ];
+        // only one of the transfers fits in a block
+        let max_size = {
+            let mut test_ledger = test_ledger(ConfigBuilder::new(0), &sources);
+            transfer(&mut test_ledger, &sources[1], 2)
+                .to_raw()
+                .size_bytes_plus_size()
+        };
+        let config = ConfigBuilder::new(0).with_block_content_max_size(max_size as u32);
+        let mut test_ledger = test_ledger(config, &sources);
+        let cheap = transfer(&mut test_ledger, &sources[0], 1);
+        let profitable = transfer(&mut test_ledger, &sources[1], 2);
+        let cheap_rate = 20 * profitable.to_raw().size_bytes_plus_size();
+        let profitable_rate = 30 * cheap.to_raw().size_bytes_plus_size();
+        assert!(profitable_rate > cheap_rate);
+
+        let selected = push_by_fee(&test_ledger, vec![cheap, profitable.clone()]);
+        assert_eq!(selected, vec![profitable.hash()]);
+    }
+
+    #[test]
+    fn dependent_fragments_are_ordered() {
+        let funds = AddressDataValue::account(Discrimination::Test, Value(200));
+        let mut test_ledger = test_ledger(ConfigBuilder::new(0), &vec![funds.clone()]);
+        let mut source = AddressDataValue::new(funds.address_data(), Value(100));
+        let first = transfer(&mut test_ledger, &source, 1);
+        source.increment_spending_counter();
+        // the second spend has a higher fee rate, but depends on the first
+        let second = transfer(&mut test_ledger, &source, 2);
+        let invalid = transfer(&mut test_ledger, &source, 1);
+
+        let selected = push_by_fee(&test_ledger, vec![second.clone(), invalid, first.clone()]);
+        assert_eq!(selected, vec![first.hash(), second.hash()]);
+    }
+
+    #[test]
+    fn spend_waits_for_credit() {
+        let source = AddressDataValue::utxo(Discrimination::Test, Value(100));
+        let account = AddressDataValue::account(Discrimination::Test, Value(0));
+        let mut test_ledger = test_ledger(ConfigBuilder::new(0), &vec![source.clone()]);
+        let credited = AddressDataValue::new(account.address_data(), Value(80));
+        let credit = TestTxBuilder::new(&test_ledger.block0_hash)
+            .move_funds_multiple(&mut test_ledger, &vec![source], &vec![credited.clone()])
+            .get_fragment();
+        // the spend has a higher fee rate, but is only valid after the credit
+        let spend = transfer(&mut test_ledger, &credited, 2);
+        let credit_rate = 20 * spend.to_raw().size_bytes_plus_size();
+        let spend_rate = 30 * credit.to_raw().size_bytes_plus_size();
+        assert!(spend_rate > credit_rate);
+
+        let selected = push_by_fee(&test_ledger, vec![spend.clone(), credit.clone()]);
+        assert_eq!(selected, vec![credit.hash(), spend.hash()]);
+    }
 }
@@ -0,0 +1,84 @@
+//! Ledger state used by fragments, to track the dependencies between
+//! fragments not yet applied to a ledger.
+
+use crate::account;
+use crate::fragment::{Fragment, FragmentId};
+use crate::multisig;
+use crate::transaction::{InputEnum, UnspecifiedAccountIdentifier, UtxoPointer};
+use chain_addr::Kind;
+
+// Ledger state read or written by a fragment. Fragments sharing
+// a resource may depend on one another.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) enum Resource {
+    // The outputs of a transaction.
+    Utxo(FragmentId),
+    Account(UnspecifiedAccountIdentifier),
+}
+
+// What a fragment spends, and the resources it uses.
+pub(super) struct Footprint {
+    pub(super) utxos: Vec<UtxoPointer>,
+    pub(super) accounts: Vec<UnspecifiedAccountIdentifier>,
+    pub(super) resources: Vec<Resource>,
+    // Certificates and update fragments may depend on any fragment
+    // applied before them.
+    pub(super) global: bool,
+}
+
+pub(super) fn footprint(id: FragmentId, fragment: &Fragment) -> Footprint {
+    let (inputs, outputs, global) = match fragment {
+        Fragment::Initial(_)
+        | Fragment::OldUtxoDeclaration(_)
+        | Fragment::UpdateProposal(_)
+        | Fragment::UpdateVote(_) => {
+            return Footprint {
+                utxos: Vec::new(),
+                accounts: Vec::new(),
+                resources: Vec::new(),
+                global: true,
+            }
+        }
+        Fragment::Transaction(tx) => (tx.as_slice().inputs(), tx.as_slice().outputs(), false),
+        Fragment::OwnerStakeDelegation(tx) => {
+            (tx.as_slice().inputs(), tx.as_slice().outputs(), true)
+        }
+        Fragment::StakeDelegation(tx) => (tx.as_slice().inputs(), tx.as_slice().outputs(), true),
+        Fragment::PoolRegistration(tx) => (tx.as_slice().inputs(), tx.as_slice().outputs(), true),
+        Fragment::PoolRetirement(tx) => (tx.as_slice().inputs(), tx.as_slice().outputs(), true),
+        Fragment::PoolUpdate(tx) => (tx.as_slice().inputs(), tx.as_slice().outputs(), true),
+    };
+    let mut footprint = Footprint {
+        utxos: Vec::new(),
+        accounts: Vec::new(),
+        resources: vec![Resource::Utxo(id)],
+        global,
+    };
+    for input in inputs.iter() {
+        match input.to_enum() {
+            InputEnum::UtxoInput(pointer) => {
+                footprint
+                    .resources
+                    .push(Resource::Utxo(pointer.transaction_id));
+                footprint.utxos.push(pointer);
+            }
+            InputEnum::AccountInput(account, _) => {
+                footprint.resources.push(Resource::Account(account.clone()));
+                footprint.accounts.push(account);
+            }
+        }
+    }
+    for output in outputs.iter() {
+        let account = match output.address.kind() {
+            Kind::Account(key) => UnspecifiedAccountIdentifier::from_single_account(
+                account::Identifier::from(key.clone()),
+            ),
+            Kind::Multisig(key) => {
+                UnspecifiedAccountIdentifier::from_multi_account(multisig::Identifier::from(*key))
+            }
+            Kind::Single(_) | Kind::Group(_, _) => continue,
+        };
+        footprint.resources.push(Resource::Account(account));
+    }
+    footprint
+}
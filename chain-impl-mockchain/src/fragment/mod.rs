@@ -1,5 +1,7 @@
 pub mod config;
 mod content;
+mod footprint;
+pub mod pool;
 mod raw;
 
 use crate::legacy;
@@ -10,6 +12,7 @@ pub use config::ConfigParams;
 pub use raw::{FragmentId, FragmentRaw};
 
 pub use content::{BlockContentHash, BlockContentSize, Contents, ContentsBuilder};
+pub use pool::FragmentPool;
 
 use crate::{
     certificate,
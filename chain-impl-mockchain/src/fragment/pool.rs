@@ -0,0 +1,644 @@
+//! Pool of fragments pending inclusion in a block.
+//!
+//! The pool holds the fragments received by a node between blocks. Each
+//! fragment is validated against the ledger state at the tip of the chain
+//! with the fragments of the pool it depends on applied, in the order they
+//! have been accepted. A fragment depends on the earlier fragments sharing
+//! a UTxO or an account with it, and certificates and update fragments
+//! depend on all the earlier fragments. A fragment spending a UTxO, or
+//! using an account spending counter, already spent by another fragment
+//! of the pool is rejected as conflicting: the first fragment seen wins.
+//!
+//! When the tip changes, after a new block or a rollback, the pool is
+//! re-validated against the new tip and the fragments that are no longer
+//! valid, including those already included in the chain, are dropped.
+//! Fragments of the blocks rolled back are not known to the pool and
+//! should be inserted again by the caller. When fragments are removed,
+//! only the fragments depending on them are re-validated.
+
+use crate::block::BlockDate;
+use crate::fragment::footprint::{footprint, Footprint, Resource};
+use crate::fragment::{BlockContentSize, Contents, ContentsBuilder, Fragment, FragmentId};
+use crate::ledger::{self, Ledger, LedgerParameters};
+use crate::transaction::UtxoPointer;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("fragment is already in the pool")]
+    AlreadyInPool,
+    #[error("fragment size ({size}) exceeds the maximum size ({max})")]
+    TooLarge { size: usize, max: usize },
+    #[error("fragment conflicts with fragment {existing} in the pool")]
+    Conflict { existing: FragmentId },
+    #[error("invalid fragment")]
+    Invalid(#[from] ledger::Error),
+}
+
+/// Limits on the content of the pool.
+#[derive(Debug, Clone)]
+pub struct PoolLimits {
+    /// Maximum number of fragments in the pool.
+    pub max_entries: usize,
+    /// Maximum total size of the fragments in the pool, in bytes.
+    pub max_bytes: usize,
+    /// Time after which a fragment not included in a block is dropped.
+    pub ttl: Duration,
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        PoolLimits {
+            max_entries: 10_000,
+            max_bytes: 16 * 1024 * 1024,
+            ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    // Towards the fragments a fragment depends on.
+    Earlier,
+    // Towards the fragments depending on a fragment.
+    Later,
+}
+
+struct Entry {
+    id: FragmentId,
+    fragment: Fragment,
+    size: usize,
+    received: SystemTime,
+    footprint: Footprint,
+}
+
+/// Pool of fragments validated against the tip of the chain.
+pub struct FragmentPool {
+    limits: PoolLimits,
+    tip: Ledger,
+    parameters: LedgerParameters,
+    block_date: BlockDate,
+    // Fragments by sequence number, in the order they have been
+    // accepted, which is the order they are valid in.
+    entries: BTreeMap<u64, Entry>,
+    next_seq: u64,
+    index: HashMap<FragmentId, u64>,
+    total_bytes: usize,
+    // Fragments using each resource.
+    users: HashMap<Resource, BTreeSet<u64>>,
+    // Fragments depending on all the earlier ones.
+    globals: BTreeSet<u64>,
+    // Fragment spending each UTxO.
+    spenders: HashMap<UtxoPointer, u64>,
+}
+
+impl FragmentPool {
+    /// Creates an empty pool validating fragments against the `tip`
+    /// ledger, for inclusion in a block at `block_date`.
+    pub fn new(tip: Ledger, block_date: BlockDate, limits: PoolLimits) -> Self {
+        FragmentPool {
+            limits,
+            parameters: tip.get_ledger_parameters(),
+            tip,
+            block_date,
+            entries: BTreeMap::new(),
+            next_seq: 0,
+            index: HashMap::new(),
+            total_bytes: 0,
+            users: HashMap::new(),
+            globals: BTreeSet::new(),
+            spenders: HashMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> &PoolLimits {
+        &self.limits
+    }
+
+    /// The ledger at the tip of the chain.
+    pub fn tip(&self) -> &Ledger {
+        &self.tip
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of the fragments in the pool, in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    pub fn contains(&self, id: &FragmentId) -> bool {
+        self.index.contains_key(id)
+    }
+
+    pub fn get(&self, id: &FragmentId) -> Option<&Fragment> {
+        let seq = self.index.get(id)?;
+        self.entries.get(seq).map(|entry| &entry.fragment)
+    }
+
+    /// Iterates over the fragments of the pool, in the order they have
+    /// been accepted.
+    pub fn fragments(&self) -> impl Iterator<Item = &Fragment> {
+        self.entries.values().map(|entry| &entry.fragment)
+    }
+
+    /// Validates the fragment and adds it to the pool.
+    ///
+    /// If the pool is over its limits with the new fragment, the oldest
+    /// fragments are evicted, along with the fragments no longer valid
+    /// without them. Returns the identifier of the fragment and the
+    /// identifiers of the evicted fragments. If the fragment is rejected,
+    /// the pool is left unchanged.
+    pub fn insert(
+        &mut self,
+        fragment: Fragment,
+        now: SystemTime,
+    ) -> Result<(FragmentId, Vec<FragmentId>), Error> {
+        let id = fragment.hash();
+        if self.contains(&id) {
+            return Err(Error::AlreadyInPool);
+        }
+        let size = fragment.to_raw().size_bytes_plus_size();
+        let max = std::cmp::min(
+            self.parameters.block_content_max_size as usize,
+            self.limits.max_bytes,
+        );
+        if size > max {
+            return Err(Error::TooLarge { size, max });
+        }
+
+        let footprint = footprint(id, &fragment);
+        if let Some(existing) = footprint
+            .utxos
+            .iter()
+            .find_map(|pointer| self.spenders.get(pointer))
+        {
+            return Err(Error::Conflict {
+                existing: self.entries[existing].id,
+            });
+        }
+
+        let seq = self.link(Entry {
+            id,
+            fragment,
+            size,
+            received: now,
+            footprint,
+        });
+        let inserted = std::iter::once(seq).collect();
+        let dropped = self.replay(&inserted, &BTreeSet::new());
+        if let Some((_, e)) = dropped.into_iter().find(|(dropped, _)| *dropped == seq) {
+            let entry = self.unlink(seq);
+            return Err(self.account_conflict(&entry).unwrap_or_else(|| e.into()));
+        }
+
+        // the new fragment itself is never evicted
+        let mut evicted = BTreeSet::new();
+        let mut entries = self.entries.len();
+        let mut total_bytes = self.total_bytes;
+        for (old, entry) in self.entries.range(..seq) {
+            if entries <= self.limits.max_entries && total_bytes <= self.limits.max_bytes {
+                break;
+            }
+            evicted.insert(*old);
+            entries -= 1;
+            total_bytes -= entry.size;
+        }
+        if evicted.is_empty() {
+            return Ok((id, Vec::new()));
+        }
+        let mut dropped = self.revalidate(&evicted);
+        if let Some(index) = dropped.iter().position(|(dropped, _)| *dropped == seq) {
+            let (_, e) = dropped.swap_remove(index);
+            self.unlink(seq);
+            return Err(e.into());
+        }
+        let evicted = evicted
+            .into_iter()
+            .chain(dropped.into_iter().map(|(seq, _)| seq))
+            .map(|seq| self.unlink(seq).id)
+            .collect();
+        Ok((id, evicted))
+    }
+
+    // A fragment that is valid against the tip but not after the
+    // fragments of the pool spending from the same account conflicts
+    // with them on the account spending counter.
+    fn account_conflict(&self, entry: &Entry) -> Option<Error> {
+        let existing = entry.footprint.accounts.iter().find_map(|account| {
+            self.users
+                .get(&Resource::Account(account.clone()))?
+                .iter()
+                .rev()
+                .map(|seq| &self.entries[seq])
+                .find(|other| other.footprint.accounts.contains(account))
+        })?;
+        self.tip
+            .apply_fragment(&self.parameters, &entry.fragment, self.block_date)
+            .ok()?;
+        Some(Error::Conflict {
+            existing: existing.id,
+        })
+    }
+
+    /// Removes the fragment from the pool, along with the fragments
+    /// depending on it. Returns the removed fragment, if it was in the pool.
+    pub fn remove(&mut self, id: &FragmentId) -> Option<Fragment> {
+        let seq = *self.index.get(id)?;
+        let dropped = self.revalidate(&std::iter::once(seq).collect());
+        for (seq, _) in dropped {
+            self.unlink(seq);
+        }
+        Some(self.unlink(seq).fragment)
+    }
+
+    /// Removes the fragments received longer than the time to live
+    /// before `now`, along with the fragments depending on them.
+    /// Returns the identifiers of the removed fragments.
+    pub fn remove_expired(&mut self, now: SystemTime) -> Vec<FragmentId> {
+        let ttl = self.limits.ttl;
+        let expired: BTreeSet<u64> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                now.duration_since(entry.received)
+                    .map(|age| age > ttl)
+                    .unwrap_or(false)
+            })
+            .map(|(seq, _)| *seq)
+            .collect();
+        if expired.is_empty() {
+            return Vec::new();
+        }
+        let dropped = self.revalidate(&expired);
+        expired
+            .into_iter()
+            .chain(dropped.into_iter().map(|(seq, _)| seq))
+            .map(|seq| self.unlink(seq).id)
+            .collect()
+    }
+
+    /// Sets a new tip, after a block has been applied or the chain has
+    /// been rolled back, and the date of the next block.
+    ///
+    /// The fragments of the pool are validated again against the new tip,
+    /// and the ones that are no longer valid are dropped. Returns the
+    /// dropped fragments with the reason they have been rejected.
+    pub fn set_tip(
+        &mut self,
+        tip: Ledger,
+        block_date: BlockDate,
+    ) -> Vec<(FragmentId, ledger::Error)> {
+        self.parameters = tip.get_ledger_parameters();
+        self.tip = tip;
+        self.block_date = block_date;
+        let max = self.parameters.block_content_max_size as usize;
+        let mut dropped: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.size > max)
+            .map(|(seq, entry)| {
+                let e = ledger::Error::InvalidContentSize {
+                    actual: entry.size as BlockContentSize,
+                    max: max as BlockContentSize,
+                };
+                (*seq, e)
+            })
+            .collect();
+        let oversized = dropped.iter().map(|(seq, _)| *seq).collect();
+        let all = self.entries.keys().cloned().collect();
+        dropped.extend(self.replay(&all, &oversized));
+        dropped
+            .into_iter()
+            .map(|(seq, e)| (self.unlink(seq).id, e))
+            .collect()
+    }
+
+    fn link(&mut self, entry: Entry) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.index.insert(entry.id, seq);
+        self.total_bytes += entry.size;
+        for resource in &entry.footprint.resources {
+            self.users.entry(resource.clone()).or_default().insert(seq);
+        }
+        if entry.footprint.global {
+            self.globals.insert(seq);
+        }
+        for pointer in &entry.footprint.utxos {
+            self.spenders.insert(*pointer, seq);
+        }
+        self.entries.insert(seq, entry);
+        seq
+    }
+
+    fn unlink(&mut self, seq: u64) -> Entry {
+        let entry = self
+            .entries
+            .remove(&seq)
+            .expect("fragment should be in the pool");
+        self.index.remove(&entry.id);
+        self.total_bytes -= entry.size;
+        for resource in &entry.footprint.resources {
+            if let Some(users) = self.users.get_mut(resource) {
+                users.remove(&seq);
+                if users.is_empty() {
+                    self.users.remove(resource);
+                }
+            }
+        }
+        self.globals.remove(&seq);
+        for pointer in &entry.footprint.utxos {
+            self.spenders.remove(pointer);
+        }
+        entry
+    }
+
+    // Follows the dependencies between the fragments of the pool, from
+    // the `start` fragments to the earlier fragments they depend on, or
+    // to the later fragments depending on them. The `start` fragments
+    // are part of the result.
+    fn closure(&self, start: &BTreeSet<u64>, direction: Direction) -> BTreeSet<u64> {
+        let mut found = start.clone();
+        let mut pending: Vec<u64> = start.iter().cloned().collect();
+        while let Some(seq) = pending.pop() {
+            let entry = &self.entries[&seq];
+            let range = match direction {
+                Direction::Earlier => (Bound::Unbounded, Bound::Excluded(seq)),
+                Direction::Later => (Bound::Excluded(seq), Bound::Unbounded),
+            };
+            let mut related: Vec<u64> = match direction {
+                Direction::Earlier if entry.footprint.global => {
+                    self.entries.range(range).map(|(seq, _)| *seq).collect()
+                }
+                Direction::Earlier => Vec::new(),
+                Direction::Later => self.globals.range(range).cloned().collect(),
+            };
+            for resource in &entry.footprint.resources {
+                if let Some(users) = self.users.get(resource) {
+                    related.extend(users.range(range));
+                }
+            }
+            for seq in related {
+                if found.insert(seq) {
+                    pending.push(seq);
+                }
+            }
+        }
+        found
+    }
+
+    // Applies the `affected` fragments to the tip, after the fragments
+    // they depend on and skipping the `removed` ones. Returns the
+    // fragments that are not valid any more.
+    fn replay(
+        &self,
+        affected: &BTreeSet<u64>,
+        removed: &BTreeSet<u64>,
+    ) -> Vec<(u64, ledger::Error)> {
+        let mut state = self.tip.clone();
+        let mut dropped = Vec::new();
+        for seq in self.closure(affected, Direction::Earlier) {
+            if removed.contains(&seq) {
+                continue;
+            }
+            let entry = &self.entries[&seq];
+            match state.apply_fragment(&self.parameters, &entry.fragment, self.block_date) {
+                Ok(new_state) => state = new_state,
+                Err(e) => dropped.push((seq, e)),
+            }
+        }
+        dropped
+    }
+
+    // Validates again the fragments depending on the `removed` ones,
+    // without them. Returns the fragments that are not valid any more.
+    fn revalidate(&self, removed: &BTreeSet<u64>) -> Vec<(u64, ledger::Error)> {
+        self.replay(&self.closure(removed, Direction::Later), removed)
+    }
+
+    /// Selects the most profitable fragments of the pool for the content
+    /// of the next block, within the block content max size, as done by
+    /// `ContentsBuilder::push_by_fee`. The fragments remain in the pool
+    /// until the block is applied to the tip.
+    pub fn select(&self) -> Contents {
+        let mut contents = ContentsBuilder::new();
+        contents.push_by_fee(
+            &self.tip,
+            &self.parameters,
+            self.block_date,
+            self.fragments().cloned(),
+        );
+        contents.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{
+            data::AddressDataValue, ConfigBuilder, LedgerBuilder, TestLedger, TestTxBuilder,
+        },
+        value::Value,
+    };
+    use chain_addr::Discrimination;
+
+    fn test_ledger(config: ConfigBuilder, funds: &Vec<AddressDataValue>) -> TestLedger {
+        LedgerBuilder::from_config(config)
+            .initial_funds(funds)
+            .build()
+            .expect("cannot build test ledger")
+    }
+
+    fn transfer(test_ledger: &mut TestLedger, source: &AddressDataValue, value: Value) -> Fragment {
+        let destination = AddressDataValue::account(Discrimination::Test, Value(0));
+        transfer_to(test_ledger, source, &destination, value)
+    }
+
+    fn transfer_to(
+        test_ledger: &mut TestLedger,
+        source: &AddressDataValue,
+        destination: &AddressDataValue,
+        value: Value,
+    ) -> Fragment {
+        TestTxBuilder::new(&test_ledger.block0_hash)
+            .move_funds(test_ledger, source, destination, &value)
+            .get_fragment()
+    }
+
+    fn pool(test_ledger: &TestLedger, limits: PoolLimits) -> FragmentPool {
+        FragmentPool::new(test_ledger.ledger.clone(), test_ledger.date(), limits)
+    }
+
+    #[test]
+    fn utxo_double_spend_conflicts() {
+        let source = AddressDataValue::utxo(Discrimination::Test, Value(100));
+        let mut test_ledger = test_ledger(ConfigBuilder::new(0), &vec![source.clone()]);
+        let first = transfer(&mut test_ledger, &source, Value(100));
+        let second = transfer(&mut test_ledger, &source, Value(100));
+        let mut pool = pool(&test_ledger, PoolLimits::default());
+        let now = SystemTime::now();
+
+        let id = pool.insert(first.clone(), now).unwrap().0;
+        assert!(pool.contains(&id));
+        assert!(matches!(pool.insert(first, now), Err(Error::AlreadyInPool)));
+        assert!(matches!(
+            pool.insert(second, now),
+            Err(Error::Conflict { existing }) if existing == id
+        ));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn account_spends_are_chained() {
+        let mut source = AddressDataValue::account(Discrimination::Test, Value(1000));
+        let mut test_ledger = test_ledger(ConfigBuilder::new(0), &vec![source.clone()]);
+        let first = transfer(&mut test_ledger, &source, Value(100));
+        let same_counter = transfer(&mut test_ledger, &source, Value(200));
+        source.increment_spending_counter();
+        let second = transfer(&mut test_ledger, &source, Value(100));
+        let mut pool = pool(&test_ledger, PoolLimits::default());
+        let now = SystemTime::now();
+
+        let first_id = pool.insert(first, now).unwrap().0;
+        assert!(matches!(
+            pool.insert(same_counter, now),
+            Err(Error::Conflict { existing }) if existing == first_id
+        ));
+        let second_id = pool.insert(second, now).unwrap().0;
+        assert_eq!(pool.len(), 2);
+
+        // the second spend depends on the first one
+        assert!(pool.remove(&first_id).is_some());
+        assert!(!pool.contains(&second_id));
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn new_tip_drops_included_fragments() {
+        let first_source = AddressDataValue::utxo(Discrimination::Test, Value(100));
+        let second_source = AddressDataValue::utxo(Discrimination::Test, Value(100));
+        let mut test_ledger = test_ledger(
+            ConfigBuilder::new(0),
+            &vec![first_source.clone(), second_source.clone()],
+        );
+        let first = transfer(&mut test_ledger, &first_source, Value(100));
+        let second = transfer(&mut test_ledger, &second_source, Value(100));
+        let mut pool = pool(&test_ledger, PoolLimits::default());
+        let now = SystemTime::now();
+        let first_id = pool.insert(first.clone(), now).unwrap().0;
+        let second_id = pool.insert(second, now).unwrap().0;
+
+        test_ledger
+            .apply_fragment(&first, test_ledger.date())
+            .unwrap();
+        let dropped = pool.set_tip(test_ledger.ledger.clone(), test_ledger.date());
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].0, first_id);
+        assert!(pool.contains(&second_id));
+        let second_size = pool
+            .get(&second_id)
+            .unwrap()
+            .to_raw()
+            .size_bytes_plus_size();
+        assert_eq!(pool.total_bytes(), second_size);
+    }
+
+    #[test]
+    fn limits_and_expiry_evict_oldest() {
+        let sources: Vec<_> = (0..3)
+            .map(|_| AddressDataValue::utxo(Discrimination::Test, Value(100)))
+            .collect();
+        let mut test_ledger = test_ledger(ConfigBuilder::new(0), &sources);
+        let fragments: Vec<_> = sources
+            .iter()
+            .map(|source| transfer(&mut test_ledger, source, Value(100)))
+            .collect();
+        let limits = PoolLimits {
+            max_entries: 2,
+            ttl: Duration::from_secs(60),
+            ..PoolLimits::default()
+        };
+        let mut pool = pool(&test_ledger, limits);
+        let now = SystemTime::now();
+
+        let (ids, evicted): (Vec<_>, Vec<_>) = fragments
+            .into_iter()
+            .enumerate()
+            .map(|(i, fragment)| {
+                let received = now + Duration::from_secs(i as u64 * 30);
+                pool.insert(fragment, received).unwrap()
+            })
+            .unzip();
+        assert_eq!(pool.len(), 2);
+        assert!(!pool.contains(&ids[0]));
+        assert_eq!(evicted, vec![vec![], vec![], vec![ids[0]]]);
+
+        let expired = pool.remove_expired(now + Duration::from_secs(100));
+        assert_eq!(expired, vec![ids[1]]);
+        assert!(pool.contains(&ids[2]));
+    }
+
+    #[test]
+    fn rejected_fragment_evicts_nothing() {
+        let source = AddressDataValue::utxo(Discrimination::Test, Value(100));
+        let account = AddressDataValue::account(Discrimination::Test, Value(0));
+        let mut test_ledger = test_ledger(ConfigBuilder::new(0), &vec![source.clone()]);
+        let credit = transfer_to(&mut test_ledger, &source, &account, Value(100));
+        let spend = transfer(&mut test_ledger, &account, Value(100));
+        let limits = PoolLimits {
+            max_entries: 1,
+            ..PoolLimits::default()
+        };
+        let mut pool = pool(&test_ledger, limits);
+        let now = SystemTime::now();
+
+        let (credit_id, _) = pool.insert(credit, now).unwrap();
+        // the spend is only valid after the credit, which would be evicted
+        assert!(matches!(pool.insert(spend, now), Err(Error::Invalid(_))));
+        assert_eq!(pool.len(), 1);
+        assert!(pool.contains(&credit_id));
+        let credit_size = pool
+            .get(&credit_id)
+            .unwrap()
+            .to_raw()
+            .size_bytes_plus_size();
+        assert_eq!(pool.total_bytes(), credit_size);
+    }
+
+    #[test]
+    fn selection_fits_block_content_max_size() {
+        let sources: Vec<_> = (0..3)
+            .map(|_| AddressDataValue::utxo(Discrimination::Test, Value(100)))
+            .collect();
+        // all the transfers have the same size
+        let size = {
+            let mut test_ledger = test_ledger(ConfigBuilder::new(0), &sources);
+            transfer(&mut test_ledger, &sources[0], Value(100))
+                .to_raw()
+                .size_bytes_plus_size()
+        };
+        let config = ConfigBuilder::new(0).with_block_content_max_size(2 * size as u32);
+        let mut test_ledger = test_ledger(config, &sources);
+        let mut pool = pool(&test_ledger, PoolLimits::default());
+        let now = SystemTime::now();
+        for source in &sources {
+            let fragment = transfer(&mut test_ledger, source, Value(100));
+            pool.insert(fragment, now).unwrap();
+        }
+        assert_eq!(pool.len(), 3);
+
+        let (_, content_size) = pool.select().compute_hash_size();
+        assert_eq!(content_size as usize, 2 * size);
+    }
+}
@@ -1,5 +1,6 @@
 use super::{Block0Error, Error};
 use crate::certificate;
+use crate::date::BlockDate;
 use crate::transaction::*;
 use crate::value::Value;
 use chain_addr::Address;
@@ -51,6 +52,29 @@ pub(super) fn valid_block0_cert_transaction<'a, Extra>(
     )
 }
 
+/// Check that the block date is within the validity interval of the transaction
+pub(super) fn valid_transaction_date<'a, Extra>(
+    tx: &TransactionSlice<'a, Extra>,
+    block_date: BlockDate,
+) -> LedgerCheck {
+    let validity = tx.validity();
+    match (validity.valid_from, validity.valid_until) {
+        (Some(valid_from), _) if validity.is_before(block_date) => {
+            Err(Error::TransactionNotYetValid {
+                valid_from,
+                block_date,
+            })
+        }
+        (_, Some(valid_until)) if validity.is_expired(block_date) => {
+            Err(Error::TransactionExpired {
+                valid_until,
+                block_date,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Check that the output value is valid
 pub(super) fn valid_output_value(output: &Output<Address>) -> LedgerCheck {
     if_cond_fail_with!(
@@ -0,0 +1,168 @@
+//! Cryptographic commitment to the contents of a ledger
+//!
+//! Every sub-ledger (accounts, multisig accounts, utxos, stake pools) keeps a
+//! merkle tree of its contents which is updated along with the contents, so
+//! the state root of a ledger is cheap to compute after applying a block.
+//! The state root is the hash of the roots of all the components, and a
+//! `StateProof` allows to verify a single account or output against it.
+
+use super::ledger::Ledger;
+use super::pots::Pots;
+use crate::accounting::account::AccountState;
+use crate::fragment::FragmentId;
+use crate::key::Hash;
+use crate::merkle::{Commitment, MerkleProof};
+use crate::transaction::{Output, TransactionIndex};
+use crate::{account, utxo};
+use chain_addr::Address;
+use chain_core::mempack::{ReadBuf, ReadError, Readable};
+use typed_bytes::ByteBuilder;
+
+/// Roots of all the components of a ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentRoots {
+    pub accounts: Hash,
+    pub multisig_accounts: Hash,
+    pub utxos: Hash,
+    pub old_utxos: Hash,
+    pub stake_pools: Hash,
+    pub pots: Hash,
+}
+
+impl ComponentRoots {
+    /// State root of the ledger the roots have been taken from
+    pub fn state_root(&self) -> Hash {
+        Hash::hash_bytes(&self.serialize_in(ByteBuilder::new()).finalize_as_vec())
+    }
+
+    pub fn serialize_in(&self, bb: ByteBuilder<Self>) -> ByteBuilder<Self> {
+        bb.bytes(self.accounts.as_ref())
+            .bytes(self.multisig_accounts.as_ref())
+            .bytes(self.utxos.as_ref())
+            .bytes(self.old_utxos.as_ref())
+            .bytes(self.stake_pools.as_ref())
+            .bytes(self.pots.as_ref())
+    }
+}
+
+impl Readable for ComponentRoots {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        Ok(ComponentRoots {
+            accounts: Hash::read(buf)?,
+            multisig_accounts: Hash::read(buf)?,
+            utxos: Hash::read(buf)?,
+            old_utxos: Hash::read(buf)?,
+            stake_pools: Hash::read(buf)?,
+            pots: Hash::read(buf)?,
+        })
+    }
+}
+
+/// Proof of inclusion of an account or an unspent output in a ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateProof {
+    roots: ComponentRoots,
+    proof: MerkleProof,
+}
+
+impl StateProof {
+    pub fn roots(&self) -> &ComponentRoots {
+        &self.roots
+    }
+
+    /// Verify that the account has the given state in the ledger of the given state root
+    pub fn verify_account(
+        &self,
+        state_root: &Hash,
+        identifier: &account::Identifier,
+        state: &AccountState<()>,
+    ) -> bool {
+        self.roots.state_root() == *state_root
+            && self.proof.verify(
+                &self.roots.accounts,
+                &identifier.commitment(),
+                &state.commitment(),
+            )
+    }
+
+    /// Verify that the output is unspent in the ledger of the given state root
+    pub fn verify_utxo(
+        &self,
+        state_root: &Hash,
+        fragment_id: &FragmentId,
+        index: TransactionIndex,
+        output: &Output<Address>,
+    ) -> bool {
+        self.roots.state_root() == *state_root
+            && self.proof.verify(
+                &self.roots.utxos,
+                &utxo::output_path(fragment_id, index),
+                &output.commitment(),
+            )
+    }
+
+    pub fn serialize_in(&self, bb: ByteBuilder<Self>) -> ByteBuilder<Self> {
+        bb.sub(|bb| self.roots.serialize_in(bb))
+            .sub(|bb| self.proof.serialize_in(bb))
+    }
+}
+
+impl Readable for StateProof {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        Ok(StateProof {
+            roots: ComponentRoots::read(buf)?,
+            proof: MerkleProof::read(buf)?,
+        })
+    }
+}
+
+fn pots_commitment(pots: &Pots) -> Hash {
+    let bytes = ByteBuilder::<Pots>::new()
+        .u64(pots.fees.0)
+        .u64(pots.treasury.value().0)
+        .u64(pots.rewards.0)
+        .finalize_as_vec();
+    Hash::hash_bytes(&bytes)
+}
+
+impl Ledger {
+    /// Roots of all the components of the ledger
+    pub fn component_roots(&self) -> ComponentRoots {
+        ComponentRoots {
+            accounts: self.accounts.state_root(),
+            multisig_accounts: self.multisig.accounts_state_root(),
+            utxos: self.utxos.state_root(),
+            old_utxos: self.oldutxos.state_root(),
+            stake_pools: self.delegation.state_root(),
+            pots: pots_commitment(&self.pots),
+        }
+    }
+
+    /// Deterministic commitment to the accounts, utxos, multisig accounts,
+    /// stake pools and pots of the ledger
+    pub fn state_root(&self) -> Hash {
+        self.component_roots().state_root()
+    }
+
+    /// Proof of the current state of the account, if it exists
+    pub fn account_proof(&self, identifier: &account::Identifier) -> Option<StateProof> {
+        let proof = self.accounts.proof(identifier)?;
+        Some(StateProof {
+            roots: self.component_roots(),
+            proof,
+        })
+    }
+
+    /// Proof that the output is unspent, if it is
+    pub fn utxo_proof(
+        &self,
+        fragment_id: &FragmentId,
+        index: TransactionIndex,
+    ) -> Option<StateProof> {
+        let proof = self.utxos.proof(fragment_id, index)?;
+        Some(StateProof {
+            roots: self.component_roots(),
+            proof,
+        })
+    }
+}
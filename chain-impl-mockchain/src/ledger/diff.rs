@@ -0,0 +1,392 @@
+//! Typed difference between two states of the ledger.
+//!
+//! A `LedgerDiff` records, for each component of the ledger, the entries
+//! or values changed between an old and a new state. It can be applied to
+//! the old state to obtain the new one, and reverted from the new state to
+//! obtain the old one. Applying a diff checks that the ledger matches the
+//! old state of every changed entry, so a diff cannot be applied to
+//! an unrelated ledger.
+
+use super::ledger::Ledger;
+use super::pots::Pots;
+use crate::account;
+use crate::accounting::account::AccountState;
+use crate::block::{BlockDate, ChainLength, LeadersParticipationRecord};
+use crate::certificate::PoolId;
+use crate::fragment::FragmentId;
+use crate::legacy::OldAddress;
+use crate::multisig::{self, Declaration};
+use crate::setting::Settings;
+use crate::stake::PoolState;
+use crate::transaction::{Output, TransactionIndex};
+use crate::update::UpdateState;
+use chain_addr::Address;
+use chain_time::TimeEra;
+use imhamt::{DiffEntry, Hamt};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use thiserror::Error;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum DiffError {
+    #[error("Entry added by the diff already exists")]
+    EntryExists,
+    #[error("Entry changed by the diff does not exist")]
+    EntryNotFound,
+    #[error("Entry changed by the diff does not match the old value")]
+    EntryNotMatching,
+    #[error("Ledger state does not match the old state of the diff")]
+    StateNotMatching,
+}
+
+/// Change of an entry of a map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryDiff<V> {
+    Added(V),
+    Removed(V),
+    Changed { old: V, new: V },
+}
+
+impl<V> EntryDiff<V> {
+    /// The value of the entry in the old state, if any
+    pub fn old_value(&self) -> Option<&V> {
+        match self {
+            EntryDiff::Added(_) => None,
+            EntryDiff::Removed(old) | EntryDiff::Changed { old, .. } => Some(old),
+        }
+    }
+
+    /// The value of the entry in the new state, if any
+    pub fn new_value(&self) -> Option<&V> {
+        match self {
+            EntryDiff::Removed(_) => None,
+            EntryDiff::Added(new) | EntryDiff::Changed { new, .. } => Some(new),
+        }
+    }
+
+    /// The change from the new state to the old state
+    pub fn inverse(self) -> Self {
+        match self {
+            EntryDiff::Added(value) => EntryDiff::Removed(value),
+            EntryDiff::Removed(value) => EntryDiff::Added(value),
+            EntryDiff::Changed { old, new } => EntryDiff::Changed { old: new, new: old },
+        }
+    }
+
+    pub(crate) fn map<W, F>(self, f: F) -> EntryDiff<W>
+    where
+        F: Fn(V) -> W,
+    {
+        match self {
+            EntryDiff::Added(value) => EntryDiff::Added(f(value)),
+            EntryDiff::Removed(value) => EntryDiff::Removed(f(value)),
+            EntryDiff::Changed { old, new } => EntryDiff::Changed {
+                old: f(old),
+                new: f(new),
+            },
+        }
+    }
+}
+
+/// Changed entries of a map
+///
+/// The removed and changed entries come first, then the added entries,
+/// each sorted by key, so applying the difference checks the old values
+/// before adding any entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapDiff<K, V>(Vec<(K, EntryDiff<V>)>);
+
+impl<K: Ord, V> MapDiff<K, V> {
+    pub(crate) fn new(mut entries: Vec<(K, EntryDiff<V>)>) -> Self {
+        entries.sort_by(|(lkey, lentry), (rkey, rentry)| {
+            let ladded = lentry.old_value().is_none();
+            let radded = rentry.old_value().is_none();
+            ladded.cmp(&radded).then_with(|| lkey.cmp(rkey))
+        });
+        MapDiff(entries)
+    }
+
+    /// The changes from the new state to the old state
+    pub fn inverse(self) -> Self {
+        MapDiff::new(
+            self.0
+                .into_iter()
+                .map(|(key, entry)| (key, entry.inverse()))
+                .collect(),
+        )
+    }
+}
+
+impl<K, V> MapDiff<K, V> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, (K, EntryDiff<V>)> {
+        self.0.iter()
+    }
+
+    pub(crate) fn retain<F>(&mut self, f: F)
+    where
+        F: Fn(&K, &EntryDiff<V>) -> bool,
+    {
+        self.0.retain(|(key, entry)| f(key, entry))
+    }
+
+    pub(crate) fn map_values<W, F>(self, f: F) -> MapDiff<K, W>
+    where
+        F: Fn(V) -> W,
+    {
+        MapDiff(
+            self.0
+                .into_iter()
+                .map(|(key, entry)| (key, entry.map(&f)))
+                .collect(),
+        )
+    }
+}
+
+impl<K, V> IntoIterator for MapDiff<K, V> {
+    type Item = (K, EntryDiff<V>);
+    type IntoIter = std::vec::IntoIter<(K, EntryDiff<V>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Compute the changed entries between two versions of a map
+///
+/// Subtrees shared by both versions are skipped, so the cost is proportional
+/// to the changes.
+pub(crate) fn hamt_diff<K, V>(
+    old: &Hamt<DefaultHasher, K, V>,
+    new: &Hamt<DefaultHasher, K, V>,
+) -> MapDiff<K, V>
+where
+    K: Clone + Eq + Hash + Ord,
+    V: Clone + PartialEq,
+{
+    MapDiff::new(
+        old.diff(new)
+            .into_iter()
+            .map(|entry| match entry {
+                DiffEntry::Added(key, value) => (key.clone(), EntryDiff::Added(value.clone())),
+                DiffEntry::Removed(key, value) => (key.clone(), EntryDiff::Removed(value.clone())),
+                DiffEntry::Changed(key, old, new) => (
+                    key.clone(),
+                    EntryDiff::Changed {
+                        old: old.clone(),
+                        new: new.clone(),
+                    },
+                ),
+            })
+            .collect(),
+    )
+}
+
+/// Apply the change of an entry to a map, checking the old value
+pub(crate) fn hamt_apply_entry<K, V>(
+    hamt: &Hamt<DefaultHasher, K, V>,
+    key: &K,
+    entry: &EntryDiff<V>,
+) -> Result<Hamt<DefaultHasher, K, V>, DiffError>
+where
+    K: Clone + Eq + Hash,
+    V: Clone + PartialEq,
+{
+    hamt_apply_entry_by(hamt, key, entry, |current, expected| current == expected)
+}
+
+/// Apply the change of an entry to a map, checking the old value with
+/// the given equality
+pub(crate) fn hamt_apply_entry_by<K, V, F>(
+    hamt: &Hamt<DefaultHasher, K, V>,
+    key: &K,
+    entry: &EntryDiff<V>,
+    eq: F,
+) -> Result<Hamt<DefaultHasher, K, V>, DiffError>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+    F: Fn(&V, &V) -> bool,
+{
+    match entry {
+        EntryDiff::Added(value) => hamt
+            .insert(key.clone(), value.clone())
+            .map_err(|_| DiffError::EntryExists),
+        EntryDiff::Removed(value) => match hamt.lookup(key) {
+            None => Err(DiffError::EntryNotFound),
+            Some(current) if !eq(current, value) => Err(DiffError::EntryNotMatching),
+            Some(_) => hamt.remove(key).map_err(|_| DiffError::EntryNotFound),
+        },
+        EntryDiff::Changed { old, new } => match hamt.lookup(key) {
+            None => Err(DiffError::EntryNotFound),
+            Some(current) if !eq(current, old) => Err(DiffError::EntryNotMatching),
+            Some(_) => hamt
+                .replace(key, new.clone())
+                .map(|(hamt, _)| hamt)
+                .map_err(|_| DiffError::EntryNotFound),
+        },
+    }
+}
+
+/// Change of a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T> Change<T> {
+    /// The change from the new value to the old value
+    pub fn inverse(self) -> Self {
+        Change {
+            old: self.new,
+            new: self.old,
+        }
+    }
+}
+
+fn change<T: Clone + PartialEq>(old: &T, new: &T) -> Option<Change<T>> {
+    if old == new {
+        None
+    } else {
+        Some(Change {
+            old: old.clone(),
+            new: new.clone(),
+        })
+    }
+}
+
+fn apply_change<T: Clone + PartialEq>(
+    value: &mut T,
+    change: &Option<Change<T>>,
+) -> Result<(), DiffError> {
+    if let Some(change) = change {
+        if *value != change.old {
+            return Err(DiffError::StateNotMatching);
+        }
+        *value = change.new.clone();
+    }
+    Ok(())
+}
+
+fn inverse_change<T>(change: Option<Change<T>>) -> Option<Change<T>> {
+    change.map(Change::inverse)
+}
+
+/// Difference between two states of the ledger of the same chain
+///
+/// The static parameters of the chain are not part of the difference.
+#[derive(Clone)]
+pub struct LedgerDiff {
+    /// Unspent outputs, by transaction
+    pub utxos: MapDiff<FragmentId, Vec<(TransactionIndex, Output<Address>)>>,
+    /// Unspent legacy outputs, by transaction
+    pub oldutxos: MapDiff<FragmentId, Vec<(TransactionIndex, Output<OldAddress>)>>,
+    pub accounts: MapDiff<account::Identifier, AccountState<()>>,
+    pub multisig_accounts: MapDiff<multisig::Identifier, AccountState<()>>,
+    pub multisig_declarations: MapDiff<multisig::Identifier, Declaration>,
+    /// Registered stake pools
+    pub pools: MapDiff<PoolId, PoolState>,
+    pub pots: Option<Change<Pots>>,
+    pub settings: Option<Change<Settings>>,
+    /// Update proposals and their votes
+    pub updates: Option<Change<UpdateState>>,
+    pub date: Option<Change<BlockDate>>,
+    pub chain_length: Option<Change<ChainLength>>,
+    pub era: Option<Change<TimeEra>>,
+    pub leaders_log: Option<Change<LeadersParticipationRecord>>,
+}
+
+impl LedgerDiff {
+    /// Check whether the two states are the same
+    pub fn is_empty(&self) -> bool {
+        self.utxos.is_empty()
+            && self.oldutxos.is_empty()
+            && self.accounts.is_empty()
+            && self.multisig_accounts.is_empty()
+            && self.multisig_declarations.is_empty()
+            && self.pools.is_empty()
+            && self.pots.is_none()
+            && self.settings.is_none()
+            && self.updates.is_none()
+            && self.date.is_none()
+            && self.chain_length.is_none()
+            && self.era.is_none()
+            && self.leaders_log.is_none()
+    }
+
+    /// The difference from the new state to the old state
+    pub fn inverse(self) -> Self {
+        LedgerDiff {
+            utxos: self.utxos.inverse(),
+            oldutxos: self.oldutxos.inverse(),
+            accounts: self.accounts.inverse(),
+            multisig_accounts: self.multisig_accounts.inverse(),
+            multisig_declarations: self.multisig_declarations.inverse(),
+            pools: self.pools.inverse(),
+            pots: inverse_change(self.pots),
+            settings: inverse_change(self.settings),
+            updates: inverse_change(self.updates),
+            date: inverse_change(self.date),
+            chain_length: inverse_change(self.chain_length),
+            era: inverse_change(self.era),
+            leaders_log: inverse_change(self.leaders_log),
+        }
+    }
+}
+
+impl Ledger {
+    /// Compute the difference from this state to the `other` state
+    pub fn diff(&self, other: &Ledger) -> LedgerDiff {
+        let (multisig_accounts, multisig_declarations) = self.multisig.diff(&other.multisig);
+        LedgerDiff {
+            utxos: self.utxos.diff(&other.utxos),
+            oldutxos: self.oldutxos.diff(&other.oldutxos),
+            accounts: self.accounts.diff(&other.accounts),
+            multisig_accounts,
+            multisig_declarations,
+            pools: self.delegation.diff(&other.delegation),
+            pots: change(&self.pots, &other.pots),
+            settings: change(&self.settings, &other.settings),
+            updates: change(&self.updates, &other.updates),
+            date: change(&self.date, &other.date),
+            chain_length: change(&self.chain_length, &other.chain_length),
+            era: change(&self.era, &other.era),
+            leaders_log: change(&self.leaders_log, &other.leaders_log),
+        }
+    }
+
+    /// Apply the difference to this state, which is expected to be
+    /// the old state of the difference, returning the new state
+    pub fn apply_diff(&self, diff: &LedgerDiff) -> Result<Self, DiffError> {
+        let mut ledger = self.clone();
+        ledger.utxos = ledger.utxos.apply_diff(&diff.utxos)?;
+        ledger.oldutxos = ledger.oldutxos.apply_diff(&diff.oldutxos)?;
+        ledger.accounts = ledger.accounts.apply_diff(&diff.accounts)?;
+        ledger.multisig = ledger
+            .multisig
+            .apply_diff(&diff.multisig_accounts, &diff.multisig_declarations)?;
+        ledger.delegation = ledger.delegation.apply_diff(&diff.pools)?;
+        apply_change(&mut ledger.pots, &diff.pots)?;
+        apply_change(&mut ledger.settings, &diff.settings)?;
+        apply_change(&mut ledger.updates, &diff.updates)?;
+        apply_change(&mut ledger.date, &diff.date)?;
+        apply_change(&mut ledger.chain_length, &diff.chain_length)?;
+        apply_change(&mut ledger.era, &diff.era)?;
+        apply_change(&mut ledger.leaders_log, &diff.leaders_log)?;
+        Ok(ledger)
+    }
+
+    /// Revert the difference from this state, which is expected to be
+    /// the new state of the difference, returning the old state
+    pub fn revert_diff(&self, diff: &LedgerDiff) -> Result<Self, DiffError> {
+        self.apply_diff(&diff.clone().inverse())
+    }
+}
@@ -3,7 +3,6 @@ use super::pots::{self, Pots};
 use crate::block::LeadersParticipationRecord;
 use crate::config::ConfigParam;
 use crate::header::{BlockDate, ChainLength};
-use crate::stake::PoolsState;
 use crate::{account, legacy, multisig, setting, update, utxo};
 use chain_addr::Address;
 use chain_time::TimeEra;
@@ -43,6 +42,7 @@ pub enum Entry<'a> {
     LeaderParticipation((&'a crate::certificate::PoolId, &'a u32)),
 }
 
+#[derive(Clone)]
 pub struct Globals {
     pub date: BlockDate,
     pub chain_length: ChainLength,
@@ -187,7 +187,7 @@ impl<'a> std::iter::FromIterator<Entry<'a>> for Result<Ledger, Error> {
         let mut updates = update::UpdateState::new();
         let mut multisig_accounts = vec![];
         let mut multisig_declarations = vec![];
-        let delegation = PoolsState::new();
+        let mut stake_pools = vec![];
         let mut globals = None;
         let mut pots = Pots::zero();
         let mut leaders_log = LeadersParticipationRecord::new();
@@ -228,10 +228,7 @@ impl<'a> std::iter::FromIterator<Entry<'a>> for Result<Ledger, Error> {
                     multisig_declarations.push((id.clone(), decl.clone()));
                 }
                 Entry::StakePool((pool_id, pool_state)) => {
-                    delegation
-                        .stake_pools
-                        .insert(pool_id.clone(), pool_state.clone())
-                        .unwrap();
+                    stake_pools.push((pool_id.clone(), pool_state.clone()));
                 }
                 Entry::Pot(ent) => pots.set_from_entry(&ent),
                 Entry::LeaderParticipation((pool_id, pool_participation)) => leaders_log
@@ -249,7 +246,7 @@ impl<'a> std::iter::FromIterator<Entry<'a>> for Result<Ledger, Error> {
             settings: setting::Settings::new().apply(&config_params)?,
             updates,
             multisig: multisig::Ledger::restore(multisig_accounts, multisig_declarations),
-            delegation,
+            delegation: stake_pools.into_iter().collect(),
             static_params: Arc::new(globals.static_params),
             date: globals.date,
             chain_length: globals.chain_length,
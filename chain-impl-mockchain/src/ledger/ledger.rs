@@ -3,6 +3,7 @@
 
 use super::check::{self, TxVerifyError};
 use super::pots::Pots;
+use super::receipt::{BlockReceipt, FragmentReceipt};
 use super::reward_info::{EpochRewardsInfo, RewardsInfoParameters};
 use crate::block::{ConsensusVersion, LeadersParticipationRecord};
 use crate::certificate::PoolId;
@@ -257,6 +258,16 @@ pub enum Error {
     PoolUpdateKeysUpdateNotAllowed,
     #[error("Update not yet allowed")]
     UpdateNotAllowedYet,
+    #[error("Transaction is not valid before {valid_from}, but the block is at {block_date}")]
+    TransactionNotYetValid {
+        valid_from: BlockDate,
+        block_date: BlockDate,
+    },
+    #[error("Transaction expired after {valid_until}, but the block is at {block_date}")]
+    TransactionExpired {
+        valid_until: BlockDate,
+        block_date: BlockDate,
+    },
 }
 
 impl LedgerParameters {
@@ -647,6 +658,50 @@ impl Ledger {
         ledger_params: &LedgerParameters,
         contents: &Contents,
         metadata: &HeaderContentEvalContext,
+    ) -> Result<Self, Error> {
+        self.apply_block_internal(ledger_params, contents, metadata, None)
+    }
+
+    /// Same as `apply_block`, also returning the receipt recording
+    /// the effects of each fragment of the block
+    pub fn apply_block_with_receipt(
+        &self,
+        ledger_params: &LedgerParameters,
+        contents: &Contents,
+        metadata: &HeaderContentEvalContext,
+    ) -> Result<(Self, BlockReceipt), Error> {
+        let mut receipt = BlockReceipt::new();
+        let new_ledger =
+            self.apply_block_internal(ledger_params, contents, metadata, Some(&mut receipt))?;
+        Ok((new_ledger, receipt))
+    }
+
+    /// Same as `apply_block_with_receipt` for the first block of an epoch:
+    /// the rewards of the previous epoch are distributed with
+    /// `distribute_rewards` before applying the block, and recorded
+    /// in the receipt
+    pub fn apply_block_with_rewards_receipt(
+        &self,
+        ledger_params: &LedgerParameters,
+        contents: &Contents,
+        metadata: &HeaderContentEvalContext,
+        distribution: &StakeDistribution,
+        rewards_info_params: RewardsInfoParameters,
+    ) -> Result<(Self, BlockReceipt), Error> {
+        let (ledger, rewards) =
+            self.distribute_rewards(distribution, ledger_params, rewards_info_params)?;
+        let (new_ledger, mut receipt) =
+            ledger.apply_block_with_receipt(ledger_params, contents, metadata)?;
+        receipt.rewards = Some(rewards);
+        Ok((new_ledger, receipt))
+    }
+
+    fn apply_block_internal(
+        &self,
+        ledger_params: &LedgerParameters,
+        contents: &Contents,
+        metadata: &HeaderContentEvalContext,
+        mut receipt: Option<&mut BlockReceipt>,
     ) -> Result<Self, Error> {
         let mut new_ledger = self.clone();
 
@@ -701,6 +756,11 @@ impl Ledger {
 
         // Apply all the fragments
         for content in contents.iter() {
+            if let Some(receipt) = receipt.as_mut() {
+                receipt
+                    .fragments
+                    .push(FragmentReceipt::new(&new_ledger, ledger_params, content));
+            }
             new_ledger = new_ledger.apply_fragment(ledger_params, content, metadata.block_date)?;
         }
 
@@ -741,18 +801,21 @@ impl Ledger {
             Fragment::OldUtxoDeclaration(_) => return Err(Error::Block0OnlyFragmentReceived),
             Fragment::Transaction(tx) => {
                 let tx = tx.as_slice();
+                check::valid_transaction_date(&tx, block_date)?;
                 let (new_ledger_, _fee) =
                     new_ledger.apply_transaction(&fragment_id, &tx, &ledger_params)?;
                 new_ledger = new_ledger_;
             }
             Fragment::OwnerStakeDelegation(tx) => {
                 let tx = tx.as_slice();
+                check::valid_transaction_date(&tx, block_date)?;
                 let (new_ledger_, _fee) =
                     new_ledger.apply_owner_stake_delegation(&tx, &ledger_params)?;
                 new_ledger = new_ledger_;
             }
             Fragment::StakeDelegation(tx) => {
                 let tx = tx.as_slice();
+                check::valid_transaction_date(&tx, block_date)?;
                 let payload = tx.payload().into_payload();
                 let payload_auth = tx.payload_auth().into_payload_auth();
                 let verified = match payload_auth {
@@ -780,6 +843,7 @@ impl Ledger {
             }
             Fragment::PoolRegistration(tx) => {
                 let tx = tx.as_slice();
+                check::valid_transaction_date(&tx, block_date)?;
                 let (new_ledger_, _fee) =
                     new_ledger.apply_transaction(&fragment_id, &tx, &ledger_params)?;
                 new_ledger = new_ledger_.apply_pool_registration_signcheck(
@@ -790,6 +854,7 @@ impl Ledger {
             }
             Fragment::PoolRetirement(tx) => {
                 let tx = tx.as_slice();
+                check::valid_transaction_date(&tx, block_date)?;
 
                 let (new_ledger_, _fee) =
                     new_ledger.apply_transaction(&fragment_id, &tx, &ledger_params)?;
@@ -801,6 +866,7 @@ impl Ledger {
             }
             Fragment::PoolUpdate(tx) => {
                 let tx = tx.as_slice();
+                check::valid_transaction_date(&tx, block_date)?;
 
                 let (new_ledger_, _fee) =
                     new_ledger.apply_transaction(&fragment_id, &tx, &ledger_params)?;
@@ -1402,7 +1468,7 @@ fn input_multi_account_verify<'a>(
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
 
     use super::*;
     use crate::{
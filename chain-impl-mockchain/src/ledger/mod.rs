@@ -1,14 +1,22 @@
 pub mod check;
+mod commitment;
+pub(crate) mod diff;
 mod info;
 pub mod iter;
 pub mod ledger;
 mod pots;
+mod receipt;
 mod reward_info;
+mod snapshot;
 
+pub use commitment::{ComponentRoots, StateProof};
+pub use diff::{Change, DiffError, EntryDiff, LedgerDiff, MapDiff};
 pub use iter::*;
 pub use ledger::*;
 pub use pots::Pots;
+pub use receipt::{BlockReceipt, FragmentReceipt};
 pub use reward_info::{EpochRewardsInfo, RewardsInfoParameters};
+pub use snapshot::SnapshotError;
 
 cfg_if! {
    if #[cfg(test)] {
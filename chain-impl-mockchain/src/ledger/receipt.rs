@@ -0,0 +1,179 @@
+use super::ledger::{Ledger, LedgerParameters};
+use super::reward_info::EpochRewardsInfo;
+use crate::certificate::Certificate;
+use crate::fee::FeeAlgorithm;
+use crate::fragment::{Fragment, FragmentId};
+use crate::legacy::OldAddress;
+use crate::multisig;
+use crate::transaction::{
+    AccountIdentifier, InputEnum, Output, Payload, TransactionSlice, UtxoPointer, Witness,
+};
+use crate::value::{Value, ValueError};
+use chain_addr::{Address, Kind};
+
+/// The effects of a fragment applied by a block.
+#[derive(Debug, Clone)]
+pub struct FragmentReceipt {
+    pub fragment_id: FragmentId,
+    /// Fees charged, added to the fees pot
+    pub fee: Value,
+    /// UTxOs spent by the fragment
+    pub utxos_consumed: Vec<(UtxoPointer, Output<Address>)>,
+    /// Legacy UTxOs declared in the genesis block and spent by the fragment
+    pub old_utxos_consumed: Vec<(UtxoPointer, Output<OldAddress>)>,
+    /// UTxOs created by the fragment
+    pub utxos_created: Vec<(UtxoPointer, Output<Address>)>,
+    /// Value withdrawn from accounts, in the order of the inputs
+    pub accounts_debited: Vec<(AccountIdentifier, Value)>,
+    /// Value deposited to accounts, in the order of the outputs
+    pub accounts_credited: Vec<(AccountIdentifier, Value)>,
+    /// Certificate applied by the fragment, if any
+    pub certificate: Option<Certificate>,
+}
+
+/// The effects of a block applied to the ledger.
+#[derive(Debug, Clone, Default)]
+pub struct BlockReceipt {
+    /// Rewards distributed at the epoch transition preceding the block.
+    ///
+    /// Only set by `apply_block_with_rewards_receipt`, which distributes
+    /// the rewards before applying the first block of an epoch.
+    /// `apply_block_with_receipt` leaves this empty.
+    pub rewards: Option<EpochRewardsInfo>,
+    /// Receipts of the fragments of the block, in the block order
+    pub fragments: Vec<FragmentReceipt>,
+}
+
+impl BlockReceipt {
+    pub fn new() -> Self {
+        BlockReceipt {
+            rewards: None,
+            fragments: Vec::new(),
+        }
+    }
+
+    /// Total fees charged by the fragments of the block
+    pub fn total_fees(&self) -> Result<Value, ValueError> {
+        Value::sum(self.fragments.iter().map(|receipt| receipt.fee))
+    }
+}
+
+impl FragmentReceipt {
+    /// Records the effects of the fragment, which is expected to be valid
+    /// when applied to `ledger`.
+    pub(super) fn new(
+        ledger: &Ledger,
+        ledger_params: &LedgerParameters,
+        fragment: &Fragment,
+    ) -> Self {
+        let mut receipt = FragmentReceipt {
+            fragment_id: fragment.hash(),
+            fee: Value::zero(),
+            utxos_consumed: Vec::new(),
+            old_utxos_consumed: Vec::new(),
+            utxos_created: Vec::new(),
+            accounts_debited: Vec::new(),
+            accounts_credited: Vec::new(),
+            certificate: None,
+        };
+        match fragment {
+            Fragment::Initial(_)
+            | Fragment::OldUtxoDeclaration(_)
+            | Fragment::UpdateProposal(_)
+            | Fragment::UpdateVote(_) => {}
+            Fragment::Transaction(tx) => {
+                receipt.record_transaction(ledger, ledger_params, &tx.as_slice());
+            }
+            Fragment::OwnerStakeDelegation(tx) => {
+                receipt.record_certificate(ledger, ledger_params, &tx.as_slice())
+            }
+            Fragment::StakeDelegation(tx) => {
+                receipt.record_certificate(ledger, ledger_params, &tx.as_slice())
+            }
+            Fragment::PoolRegistration(tx) => {
+                receipt.record_certificate(ledger, ledger_params, &tx.as_slice())
+            }
+            Fragment::PoolRetirement(tx) => {
+                receipt.record_certificate(ledger, ledger_params, &tx.as_slice())
+            }
+            Fragment::PoolUpdate(tx) => {
+                receipt.record_certificate(ledger, ledger_params, &tx.as_slice())
+            }
+        }
+        receipt
+    }
+
+    fn record_certificate<'a, Extra>(
+        &mut self,
+        ledger: &Ledger,
+        ledger_params: &LedgerParameters,
+        tx: &TransactionSlice<'a, Extra>,
+    ) where
+        Extra: Payload,
+        Certificate: From<Extra>,
+    {
+        self.record_transaction(ledger, ledger_params, tx);
+        self.certificate = Some(tx.payload().into_payload().into());
+    }
+
+    fn record_transaction<'a, Extra: Payload>(
+        &mut self,
+        ledger: &Ledger,
+        ledger_params: &LedgerParameters,
+        tx: &TransactionSlice<'a, Extra>,
+    ) {
+        self.fee = ledger_params.fees.calculate_tx(tx);
+
+        for (input, witness) in tx.inputs_and_witnesses().iter() {
+            match input.to_enum() {
+                InputEnum::UtxoInput(utxo) => {
+                    // the witness tells which of the UTxO ledgers is spent from
+                    if let Witness::OldUtxo(..) = witness {
+                        if let Some(entry) = ledger
+                            .oldutxos
+                            .get(&utxo.transaction_id, &utxo.output_index)
+                        {
+                            self.old_utxos_consumed.push((utxo, entry.output.clone()));
+                        }
+                    } else if let Some(output) =
+                        ledger.utxo_out(utxo.transaction_id, utxo.output_index)
+                    {
+                        self.utxos_consumed.push((utxo, output.clone()));
+                    }
+                }
+                InputEnum::AccountInput(account_id, value) => {
+                    // the witness tells the kind of account
+                    let account = match witness {
+                        Witness::Account(_) => account_id
+                            .to_single_account()
+                            .map(AccountIdentifier::Single),
+                        Witness::Multisig(_) => {
+                            Some(AccountIdentifier::Multi(account_id.to_multi_account()))
+                        }
+                        Witness::Utxo(_) | Witness::OldUtxo(..) => None,
+                    };
+                    if let Some(account) = account {
+                        self.accounts_debited.push((account, value));
+                    }
+                }
+            }
+        }
+
+        for (index, output) in tx.outputs().iter().enumerate() {
+            match output.address.kind() {
+                Kind::Single(_) | Kind::Group(_, _) => {
+                    let utxo = UtxoPointer::new(self.fragment_id, index as u8, output.value);
+                    self.utxos_created.push((utxo, output.clone()));
+                }
+                Kind::Account(identifier) => {
+                    let account = AccountIdentifier::Single(identifier.clone().into());
+                    self.accounts_credited.push((account, output.value));
+                }
+                Kind::Multisig(identifier) => {
+                    let account = AccountIdentifier::Multi(multisig::Identifier::from(*identifier));
+                    self.accounts_credited.push((account, output.value));
+                }
+            }
+        }
+    }
+}
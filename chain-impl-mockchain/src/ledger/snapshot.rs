@@ -0,0 +1,440 @@
+//! Binary snapshot of the full state of a ledger
+//!
+//! A snapshot allows a node to restart from a known ledger state instead of
+//! replaying all the blocks from block0. The format is:
+//!
+//! * the magic bytes `MAGIC`
+//! * the version of the format, as a u16
+//! * every entry of the ledger iterator, each prefixed by a tag byte
+//! * the end tag
+//! * the blake2b256 checksum of all the preceding bytes
+
+use super::iter::{Entry, Globals};
+use super::ledger::{Error, Ledger, LedgerStaticParameters};
+use super::pots;
+use crate::accounting::account::AccountState;
+use crate::certificate::PoolId;
+use crate::config::{Block0Date, ConfigParam};
+use crate::fragment::FragmentId;
+use crate::header::{BlockDate, ChainLength};
+use crate::key::Hash;
+use crate::leadership::bft::LeaderId;
+use crate::legacy::OldAddress;
+use crate::stake::PoolState;
+use crate::transaction::Output;
+use crate::update::{UpdateProposal, UpdateProposalId, UpdateProposalState};
+use crate::value::Value;
+use crate::{account, multisig, utxo};
+use chain_addr::{Address, Discrimination};
+use chain_core::mempack::{ReadBuf, ReadError, Readable};
+use chain_core::property::Serialize as _;
+use chain_time::{Epoch, TimeEra};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use thiserror::Error;
+use typed_bytes::ByteBuilder;
+
+const MAGIC: &[u8; 8] = b"LEDGSNAP";
+// version 2: the entries are serialized like the leaves of the state tree
+const VERSION: u16 = 2;
+const CHECKSUM_SIZE: usize = 32;
+
+const TAG_END: u8 = 0;
+const TAG_GLOBALS: u8 = 1;
+const TAG_POT: u8 = 2;
+const TAG_UTXO: u8 = 3;
+const TAG_OLD_UTXO: u8 = 4;
+const TAG_ACCOUNT: u8 = 5;
+const TAG_CONFIG_PARAM: u8 = 6;
+const TAG_UPDATE_PROPOSAL: u8 = 7;
+const TAG_MULTISIG_ACCOUNT: u8 = 8;
+const TAG_MULTISIG_DECLARATION: u8 = 9;
+const TAG_STAKE_POOL: u8 = 10;
+const TAG_LEADER_PARTICIPATION: u8 = 11;
+
+const POT_FEES: u8 = 0;
+const POT_TREASURY: u8 = 1;
+const POT_REWARDS: u8 = 2;
+
+const DISCRIMINATION_PRODUCTION: u8 = 0;
+const DISCRIMINATION_TEST: u8 = 1;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Cannot read the snapshot")]
+    Io(#[from] io::Error),
+    #[error("Not a ledger snapshot")]
+    InvalidMagic,
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedVersion(u16),
+    #[error("Snapshot checksum does not match its content")]
+    ChecksumMismatch,
+    #[error("Malformed snapshot")]
+    Malformed(#[from] ReadError),
+    #[error("Snapshot does not contain a valid ledger")]
+    Ledger(#[from] Error),
+}
+
+impl Ledger {
+    /// Write a snapshot of the ledger, which can be restored with
+    /// `Ledger::from_snapshot`
+    pub fn serialize_snapshot<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        let bb = ByteBuilder::<Ledger>::new().bytes(MAGIC).u16(VERSION);
+        let bytes = self
+            .iter()
+            .fold(bb, serialize_entry)
+            .u8(TAG_END)
+            .finalize_as_vec();
+        let checksum = Hash::hash_bytes(&bytes);
+
+        writer.write_all(&bytes)?;
+        writer.write_all(checksum.as_ref())
+    }
+
+    /// Restore a ledger from a snapshot written by `Ledger::serialize_snapshot`
+    pub fn from_snapshot<R: Read>(mut reader: R) -> Result<Ledger, SnapshotError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let mut buf = ReadBuf::from(&bytes);
+        if buf.get_slice(MAGIC.len())? != MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let version = buf.get_u16()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let header_size = buf.position();
+        if bytes.len() < header_size + CHECKSUM_SIZE {
+            return Err(ReadError::NotEnoughBytes(bytes.len() - header_size, CHECKSUM_SIZE).into());
+        }
+        let (content, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        if Hash::hash_bytes(content).as_ref() != checksum {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+
+        let mut buf = ReadBuf::from(&content[header_size..]);
+        let mut entries = Vec::new();
+        loop {
+            match buf.get_u8()? {
+                TAG_END => break,
+                tag => entries.push(read_entry(tag, &mut buf)?),
+            }
+        }
+        buf.expect_end()?;
+
+        let ledger: Result<Ledger, Error> = entries.iter().map(OwnedEntry::as_entry).collect();
+        Ok(ledger?)
+    }
+}
+
+fn serialize_entry(bb: ByteBuilder<Ledger>, entry: Entry) -> ByteBuilder<Ledger> {
+    match entry {
+        Entry::Globals(globals) => serialize_globals(bb.u8(TAG_GLOBALS), &globals),
+        Entry::Pot(entry) => {
+            let kind = match entry.entry_type() {
+                pots::EntryType::Fees => POT_FEES,
+                pots::EntryType::Treasury => POT_TREASURY,
+                pots::EntryType::Rewards => POT_REWARDS,
+            };
+            bb.u8(TAG_POT).u8(kind).u64(entry.value().0)
+        }
+        Entry::Utxo(entry) => bb
+            .u8(TAG_UTXO)
+            .bytes(entry.fragment_id.as_ref())
+            .u8(entry.output_index)
+            .sub(|bb| entry.output.serialize_in(bb)),
+        Entry::OldUtxo(entry) => bb
+            .u8(TAG_OLD_UTXO)
+            .bytes(entry.fragment_id.as_ref())
+            .u8(entry.output_index)
+            .sub(|bb| entry.output.serialize_in(bb)),
+        Entry::Account((id, state)) => bb
+            .u8(TAG_ACCOUNT)
+            .bytes(&id.serialize_as_vec().unwrap())
+            .sub(|bb| state.serialize_in(bb)),
+        Entry::ConfigParam(param) => bb
+            .u8(TAG_CONFIG_PARAM)
+            .bytes(&param.serialize_as_vec().unwrap()),
+        Entry::UpdateProposal((id, state)) => {
+            // sort the votes so the same ledger always gives the same snapshot
+            let mut votes: Vec<&LeaderId> = state.votes.iter().collect();
+            votes.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+            bb.u8(TAG_UPDATE_PROPOSAL)
+                .bytes(id.as_ref())
+                .bytes(&state.proposal.serialize_as_vec().unwrap())
+                .u32(state.proposal_date.epoch)
+                .u32(state.proposal_date.slot_id)
+                .iter16(votes.into_iter(), |bb, vote| bb.bytes(vote.as_ref()))
+        }
+        Entry::MultisigAccount((id, state)) => bb
+            .u8(TAG_MULTISIG_ACCOUNT)
+            .bytes(id.as_ref())
+            .sub(|bb| state.serialize_in(bb)),
+        Entry::MultisigDeclaration((id, declaration)) => bb
+            .u8(TAG_MULTISIG_DECLARATION)
+            .bytes(id.as_ref())
+            .sub(|bb| declaration.serialize_in(bb)),
+        Entry::StakePool((pool_id, state)) => bb
+            .u8(TAG_STAKE_POOL)
+            .bytes(pool_id.as_ref())
+            .sub(|bb| state.serialize_in(bb)),
+        Entry::LeaderParticipation((pool_id, participation)) => bb
+            .u8(TAG_LEADER_PARTICIPATION)
+            .bytes(pool_id.as_ref())
+            .u32(*participation),
+    }
+}
+
+fn serialize_globals(bb: ByteBuilder<Ledger>, globals: &Globals) -> ByteBuilder<Ledger> {
+    let params = &globals.static_params;
+    let discrimination = match params.discrimination {
+        Discrimination::Production => DISCRIMINATION_PRODUCTION,
+        Discrimination::Test => DISCRIMINATION_TEST,
+    };
+    bb.u32(globals.date.epoch)
+        .u32(globals.date.slot_id)
+        .u32(globals.chain_length.into())
+        .bytes(params.block0_initial_hash.as_ref())
+        .u64(params.block0_start_time.0)
+        .u8(discrimination)
+        .u32(params.kes_update_speed)
+        .u64(globals.era.slot_start().into())
+        .u32(globals.era.epoch_start().0)
+        .u32(globals.era.slots_per_epoch())
+}
+
+/// Owned version of `Entry`, holding what the entries read from a snapshot
+/// refer to
+enum OwnedEntry {
+    Globals(Globals),
+    Pot(pots::Entry),
+    Utxo(FragmentId, u8, Output<Address>),
+    OldUtxo(FragmentId, u8, Output<OldAddress>),
+    Account(account::Identifier, AccountState<()>),
+    ConfigParam(ConfigParam),
+    UpdateProposal(UpdateProposalId, UpdateProposalState),
+    MultisigAccount(multisig::Identifier, AccountState<()>),
+    MultisigDeclaration(multisig::Identifier, multisig::Declaration),
+    StakePool(PoolId, PoolState),
+    LeaderParticipation(PoolId, u32),
+}
+
+impl OwnedEntry {
+    fn as_entry(&self) -> Entry<'_> {
+        match self {
+            OwnedEntry::Globals(globals) => Entry::Globals(globals.clone()),
+            OwnedEntry::Pot(entry) => Entry::Pot(*entry),
+            OwnedEntry::Utxo(fragment_id, output_index, output) => Entry::Utxo(utxo::Entry {
+                fragment_id: *fragment_id,
+                output_index: *output_index,
+                output,
+            }),
+            OwnedEntry::OldUtxo(fragment_id, output_index, output) => Entry::OldUtxo(utxo::Entry {
+                fragment_id: *fragment_id,
+                output_index: *output_index,
+                output,
+            }),
+            OwnedEntry::Account(id, state) => Entry::Account((id, state)),
+            OwnedEntry::ConfigParam(param) => Entry::ConfigParam(param.clone()),
+            OwnedEntry::UpdateProposal(id, state) => Entry::UpdateProposal((id, state)),
+            OwnedEntry::MultisigAccount(id, state) => Entry::MultisigAccount((id, state)),
+            OwnedEntry::MultisigDeclaration(id, declaration) => {
+                Entry::MultisigDeclaration((id, declaration))
+            }
+            OwnedEntry::StakePool(pool_id, state) => Entry::StakePool((pool_id, state)),
+            OwnedEntry::LeaderParticipation(pool_id, participation) => {
+                Entry::LeaderParticipation((pool_id, participation))
+            }
+        }
+    }
+}
+
+fn read_entry<'a>(tag: u8, buf: &mut ReadBuf<'a>) -> Result<OwnedEntry, ReadError> {
+    match tag {
+        TAG_GLOBALS => read_globals(buf).map(OwnedEntry::Globals),
+        TAG_POT => {
+            let kind = buf.get_u8()?;
+            let value = Value::read(buf)?;
+            let entry = match kind {
+                POT_FEES => pots::Entry::Fees(value),
+                POT_TREASURY => pots::Entry::Treasury(value),
+                POT_REWARDS => pots::Entry::Rewards(value),
+                kind => return Err(ReadError::UnknownTag(kind as u32)),
+            };
+            Ok(OwnedEntry::Pot(entry))
+        }
+        TAG_UTXO => {
+            let fragment_id = FragmentId::read(buf)?;
+            let output_index = buf.get_u8()?;
+            let output = Output::read(buf)?;
+            Ok(OwnedEntry::Utxo(fragment_id, output_index, output))
+        }
+        TAG_OLD_UTXO => {
+            let fragment_id = FragmentId::read(buf)?;
+            let output_index = buf.get_u8()?;
+            let address_size = buf.get_u16()? as usize;
+            let address = OldAddress::try_from(buf.get_slice(address_size)?)
+                .map_err(|_| ReadError::StructureInvalid("invalid legacy address".to_string()))?;
+            let value = Value::read(buf)?;
+            Ok(OwnedEntry::OldUtxo(
+                fragment_id,
+                output_index,
+                Output { address, value },
+            ))
+        }
+        TAG_ACCOUNT => {
+            let id = account::Identifier::read(buf)?;
+            let state = AccountState::read(buf)?;
+            Ok(OwnedEntry::Account(id, state))
+        }
+        TAG_CONFIG_PARAM => ConfigParam::read(buf).map(OwnedEntry::ConfigParam),
+        TAG_UPDATE_PROPOSAL => {
+            let id = UpdateProposalId::read(buf)?;
+            let proposal = UpdateProposal::read(buf)?;
+            let proposal_date = BlockDate {
+                epoch: buf.get_u32()?,
+                slot_id: buf.get_u32()?,
+            };
+            let nb_votes = buf.get_u16()?;
+            let mut votes = std::collections::HashSet::with_capacity(nb_votes as usize);
+            for _ in 0..nb_votes {
+                votes.insert(LeaderId::read(buf)?);
+            }
+            let state = UpdateProposalState {
+                proposal,
+                proposal_date,
+                votes,
+            };
+            Ok(OwnedEntry::UpdateProposal(id, state))
+        }
+        TAG_MULTISIG_ACCOUNT => {
+            let id = <[u8; 32]>::read(buf)?.into();
+            let state = AccountState::read(buf)?;
+            Ok(OwnedEntry::MultisigAccount(id, state))
+        }
+        TAG_MULTISIG_DECLARATION => {
+            let id = <[u8; 32]>::read(buf)?.into();
+            let declaration = multisig::Declaration::read(buf)?;
+            Ok(OwnedEntry::MultisigDeclaration(id, declaration))
+        }
+        TAG_STAKE_POOL => {
+            let pool_id = <[u8; 32]>::read(buf)?.into();
+            let state = PoolState::read(buf)?;
+            Ok(OwnedEntry::StakePool(pool_id, state))
+        }
+        TAG_LEADER_PARTICIPATION => {
+            let pool_id = <[u8; 32]>::read(buf)?.into();
+            let participation = buf.get_u32()?;
+            Ok(OwnedEntry::LeaderParticipation(pool_id, participation))
+        }
+        tag => Err(ReadError::UnknownTag(tag as u32)),
+    }
+}
+
+fn read_globals<'a>(buf: &mut ReadBuf<'a>) -> Result<Globals, ReadError> {
+    let date = BlockDate {
+        epoch: buf.get_u32()?,
+        slot_id: buf.get_u32()?,
+    };
+    let chain_length = ChainLength::from(buf.get_u32()?);
+    let block0_initial_hash = Hash::read(buf)?;
+    let block0_start_time = Block0Date(buf.get_u64()?);
+    let discrimination = match buf.get_u8()? {
+        DISCRIMINATION_PRODUCTION => Discrimination::Production,
+        DISCRIMINATION_TEST => Discrimination::Test,
+        tag => return Err(ReadError::UnknownTag(tag as u32)),
+    };
+    let kes_update_speed = buf.get_u32()?;
+    let slot_start = buf.get_u64()?.into();
+    let epoch_start = Epoch(buf.get_u32()?);
+    let slots_per_epoch = buf.get_u32()?;
+
+    Ok(Globals {
+        date,
+        chain_length,
+        static_params: LedgerStaticParameters {
+            block0_initial_hash,
+            block0_start_time,
+            discrimination,
+            kes_update_speed,
+        },
+        era: TimeEra::new(slot_start, epoch_start, slots_per_epoch),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::ledger::tests::ArbitraryEmptyLedger;
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+
+    fn snapshot(ledger: &Ledger) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ledger.serialize_snapshot(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[quickcheck]
+    pub fn snapshot_roundtrip(ledger: ArbitraryEmptyLedger) -> TestResult {
+        let ledger: Ledger = ledger.into();
+        match Ledger::from_snapshot(&snapshot(&ledger)[..]) {
+            Ok(restored) if restored == ledger => TestResult::passed(),
+            Ok(_) => TestResult::error("restored ledger is different"),
+            Err(err) => TestResult::error(format!("cannot restore the snapshot: {}", err)),
+        }
+    }
+
+    #[quickcheck]
+    pub fn snapshot_is_deterministic(ledger: ArbitraryEmptyLedger) -> bool {
+        let ledger: Ledger = ledger.into();
+        snapshot(&ledger) == snapshot(&ledger)
+    }
+
+    #[quickcheck]
+    pub fn corrupted_snapshot_is_rejected(ledger: ArbitraryEmptyLedger, index: usize) -> bool {
+        let ledger: Ledger = ledger.into();
+        let mut bytes = snapshot(&ledger);
+        // leave the header alone, it is checked before the checksum
+        let header_size = MAGIC.len() + 2;
+        let index = header_size + index % (bytes.len() - header_size);
+        bytes[index] ^= 0x01;
+
+        matches!(
+            Ledger::from_snapshot(&bytes[..]),
+            Err(SnapshotError::ChecksumMismatch)
+        )
+    }
+
+    #[quickcheck]
+    pub fn truncated_snapshot_is_rejected(ledger: ArbitraryEmptyLedger, size: usize) -> bool {
+        let ledger: Ledger = ledger.into();
+        let bytes = snapshot(&ledger);
+        let size = size % bytes.len();
+
+        Ledger::from_snapshot(&bytes[..size]).is_err()
+    }
+
+    #[quickcheck]
+    pub fn unknown_version_is_rejected(ledger: ArbitraryEmptyLedger) -> bool {
+        let ledger: Ledger = ledger.into();
+        let mut bytes = snapshot(&ledger);
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_be_bytes());
+
+        matches!(
+            Ledger::from_snapshot(&bytes[..]),
+            Err(SnapshotError::UnsupportedVersion(version)) if version == VERSION + 1
+        )
+    }
+
+    #[test]
+    pub fn invalid_magic_is_rejected() {
+        let bytes = b"NOTASNAPSHOT, JUST SOME BYTES".to_vec();
+        match Ledger::from_snapshot(&bytes[..]) {
+            Err(SnapshotError::InvalidMagic) => (),
+            _ => panic!("the snapshot should be rejected"),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+#![cfg(test)]
+
+use crate::{
+    config::ConfigParam,
+    fragment::ConfigParams,
+    ledger::{DiffError, EntryDiff},
+    multisig::{DeclElement, Declaration},
+    testing::{
+        builders::{
+            update_builder::{ProposalBuilder, SignedProposalBuilder},
+            TestTxBuilder,
+        },
+        data::{AddressData, AddressDataValue},
+        ledger::{ConfigBuilder, LedgerBuilder},
+        scenario::{prepare_scenario, wallet},
+        TestGen,
+    },
+    value::Value,
+};
+use chain_addr::Discrimination;
+
+#[test]
+pub fn diff_of_block_can_be_applied_and_reverted() {
+    let (mut ledger, controller) = prepare_scenario()
+        .with_initials(vec![
+            wallet("Alice").with(1_000).owns("stake_pool"),
+            wallet("Bob").with(1_000),
+            wallet("Clarice").with(1_000),
+        ])
+        .build()
+        .unwrap();
+
+    let stake_pool = controller.stake_pool("stake_pool").unwrap();
+    let alice = controller.wallet("Alice").unwrap();
+    let bob = controller.wallet("Bob").unwrap();
+    let clarice = controller.wallet("Clarice").unwrap();
+
+    let fragment_factory = controller.fragment_factory();
+    let transaction = fragment_factory.transaction(&bob, &clarice, &mut ledger, 100);
+    let delegation = fragment_factory.delegation(&alice, &stake_pool);
+    let block = ledger.forge_block_with_fragments(&stake_pool, vec![transaction, delegation]);
+
+    let old_ledger = ledger.ledger.clone();
+    ledger.apply_block(block).unwrap();
+    let new_ledger = ledger.ledger;
+
+    let diff = old_ledger.diff(&new_ledger);
+    assert!(!diff.is_empty());
+    assert!(old_ledger.diff(&old_ledger).is_empty());
+    assert_eq!(diff.accounts.len(), 3);
+    assert!(diff.utxos.is_empty());
+    assert!(diff.pools.is_empty());
+    assert!(diff.pots.is_some());
+    assert!(diff.chain_length.is_some());
+    assert!(diff.leaders_log.is_some());
+
+    let applied = old_ledger.apply_diff(&diff).unwrap();
+    assert!(applied == new_ledger);
+    assert_eq!(applied.state_root(), new_ledger.state_root());
+
+    let reverted = new_ledger.revert_diff(&diff).unwrap();
+    assert!(reverted == old_ledger);
+    assert_eq!(reverted.state_root(), old_ledger.state_root());
+
+    assert!(new_ledger.apply_diff(&diff).is_err());
+    assert!(old_ledger.revert_diff(&diff).is_err());
+}
+
+#[test]
+pub fn diff_of_utxos() {
+    let faucet = AddressDataValue::utxo(Discrimination::Test, Value(100));
+    let other = AddressDataValue::utxo(Discrimination::Test, Value(200));
+    let receiver = AddressDataValue::utxo(Discrimination::Test, Value(0));
+    let mut test_ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .initial_funds(&vec![faucet.clone(), other])
+        .build()
+        .unwrap();
+    let old_ledger = test_ledger.ledger.clone();
+
+    let fragment = TestTxBuilder::new(&test_ledger.block0_hash)
+        .move_funds(&mut test_ledger, &faucet, &receiver, &faucet.value)
+        .get_fragment();
+    let fragment_id = fragment.hash();
+    test_ledger.apply_transaction(fragment).unwrap();
+    let new_ledger = test_ledger.ledger;
+
+    // one output of the initial fragment is spent, and one output is created
+    let diff = old_ledger.diff(&new_ledger);
+    assert_eq!(diff.utxos.len(), 2);
+    for (tid, entry) in diff.utxos.iter() {
+        match entry {
+            EntryDiff::Added(outputs) => {
+                assert_eq!(*tid, fragment_id);
+                assert_eq!(outputs.len(), 1);
+                assert_eq!(outputs[0].1.address, receiver.address());
+            }
+            EntryDiff::Changed { old, new } => {
+                assert_eq!(old.len(), 2);
+                assert_eq!(new.len(), 1);
+            }
+            EntryDiff::Removed(_) => panic!("unexpected removed outputs"),
+        }
+    }
+
+    // spent outputs are compared by value, not by their storage
+    let applied = old_ledger.apply_diff(&diff).unwrap();
+    assert!(applied.diff(&new_ledger).is_empty());
+    assert_eq!(applied.state_root(), new_ledger.state_root());
+
+    let reverted = new_ledger.revert_diff(&diff).unwrap();
+    assert!(reverted.diff(&old_ledger).is_empty());
+    assert_eq!(reverted.state_root(), old_ledger.state_root());
+
+    // the old state of the changed outputs is not in an unrelated ledger
+    let unrelated = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .initial_funds(&vec![AddressDataValue::utxo(
+            Discrimination::Test,
+            Value(100),
+        )])
+        .build()
+        .unwrap()
+        .ledger;
+    assert_eq!(
+        unrelated.apply_diff(&diff).err(),
+        Some(DiffError::EntryNotFound)
+    );
+
+    assert_eq!(
+        new_ledger.apply_diff(&diff).err(),
+        Some(DiffError::EntryNotMatching)
+    );
+}
+
+#[test]
+pub fn diff_of_multisig_declarations() {
+    let (ledger, _) = prepare_scenario()
+        .with_initials(vec![wallet("Alice").with(1_000)])
+        .build()
+        .unwrap();
+    let old_ledger = ledger.ledger;
+
+    let owners = (0..2)
+        .map(|_| {
+            let owner = AddressData::account(Discrimination::Test);
+            DeclElement::from_publickey(&owner.public_key())
+        })
+        .collect();
+    let declaration = Declaration {
+        threshold: 1,
+        owners,
+    };
+    let mut new_ledger = old_ledger.clone();
+    new_ledger.multisig = new_ledger.multisig.add_account(&declaration).unwrap();
+
+    let diff = old_ledger.diff(&new_ledger);
+    assert_eq!(diff.multisig_accounts.len(), 1);
+    assert_eq!(
+        diff.multisig_declarations.iter().collect::<Vec<_>>(),
+        vec![&(
+            declaration.to_identifier(),
+            EntryDiff::Added(declaration.clone())
+        )]
+    );
+
+    let applied = old_ledger.apply_diff(&diff).unwrap();
+    assert!(applied.diff(&new_ledger).is_empty());
+    let reverted = new_ledger.revert_diff(&diff).unwrap();
+    assert!(reverted.diff(&old_ledger).is_empty());
+    assert_eq!(
+        new_ledger.apply_diff(&diff).err(),
+        Some(DiffError::EntryExists)
+    );
+}
+
+#[test]
+pub fn diff_of_pools() {
+    let (mut ledger, controller) = prepare_scenario()
+        .with_initials(vec![wallet("Alice").with(1_000).owns("stake_pool")])
+        .build()
+        .unwrap();
+    let stake_pool = controller.stake_pool("stake_pool").unwrap();
+    let alice = controller.wallet("Alice").unwrap();
+
+    let old_ledger = ledger.ledger.clone();
+    let retirement = controller
+        .fragment_factory()
+        .stake_pool_retire(&[&alice], &stake_pool);
+    ledger.apply_fragment(&retirement, ledger.date()).unwrap();
+    let new_ledger = ledger.ledger;
+
+    let diff = old_ledger.diff(&new_ledger);
+    assert_eq!(diff.pools.len(), 1);
+    let (pool_id, entry) = diff.pools.iter().next().unwrap();
+    assert_eq!(*pool_id, stake_pool.id());
+    assert!(entry.old_value().is_some());
+
+    let applied = old_ledger.apply_diff(&diff).unwrap();
+    assert!(applied.diff(&new_ledger).is_empty());
+    assert_eq!(
+        applied.delegation().state_root(),
+        new_ledger.delegation().state_root()
+    );
+    let reverted = new_ledger.revert_diff(&diff).unwrap();
+    assert!(reverted.diff(&old_ledger).is_empty());
+    assert!(new_ledger.apply_diff(&diff).is_err());
+}
+
+#[test]
+pub fn diff_of_settings_and_update_proposals() {
+    let (ledger, _) = prepare_scenario()
+        .with_initials(vec![wallet("Alice").with(1_000)])
+        .build()
+        .unwrap();
+    let old_ledger = ledger.ledger;
+
+    // a new BFT leader proposes an update of the settings
+    let leader = TestGen::leader_pair();
+    let mut changes = ConfigParams::new();
+    changes.push(ConfigParam::AddBftLeader(leader.id()));
+    let mut new_ledger = old_ledger.clone();
+    new_ledger.settings = new_ledger.settings.apply(&changes).unwrap();
+    let proposal = SignedProposalBuilder::new()
+        .with_proposal_update(
+            ProposalBuilder::new()
+                .with_proposal_change(ConfigParam::SlotsPerEpoch(100))
+                .build(),
+        )
+        .with_proposer_id(leader.id())
+        .build();
+    new_ledger.updates = old_ledger
+        .updates
+        .clone()
+        .apply_proposal(
+            TestGen::hash(),
+            &proposal,
+            &new_ledger.settings,
+            new_ledger.date(),
+        )
+        .unwrap();
+
+    let diff = old_ledger.diff(&new_ledger);
+    assert!(diff.settings.is_some());
+    assert!(diff.updates.is_some());
+    assert!(diff.accounts.is_empty());
+    assert!(diff.pots.is_none());
+
+    let applied = old_ledger.apply_diff(&diff).unwrap();
+    assert!(applied.diff(&new_ledger).is_empty());
+    let reverted = new_ledger.revert_diff(&diff).unwrap();
+    assert!(reverted.diff(&old_ledger).is_empty());
+    assert_eq!(
+        new_ledger.apply_diff(&diff).err(),
+        Some(DiffError::StateNotMatching)
+    );
+}
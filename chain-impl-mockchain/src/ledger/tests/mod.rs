@@ -2,8 +2,21 @@
 mod macros;
 pub mod apply_block_tests;
 pub mod certificate_tests;
+pub mod diff_tests;
 pub mod discrimination_tests;
 pub mod initial_funds_tests;
 pub mod ledger_tests;
+pub mod receipt_tests;
+pub mod snapshot_tests;
+pub mod state_root_tests;
 pub mod transaction_tests;
 pub mod update_tests;
+
+use crate::ledger::Ledger;
+
+/// Serialize the ledger to a snapshot and read it back.
+fn restore(ledger: &Ledger) -> Ledger {
+    let mut bytes = Vec::new();
+    ledger.serialize_snapshot(&mut bytes).unwrap();
+    Ledger::from_snapshot(&bytes[..]).unwrap()
+}
@@ -0,0 +1,206 @@
+#![cfg(test)]
+
+use crate::{
+    certificate::Certificate,
+    fee::FeeAlgorithm,
+    fragment::Fragment,
+    ledger::RewardsInfoParameters,
+    legacy::UtxoDeclaration,
+    testing::{
+        builders::StakePoolBuilder,
+        data::AddressData,
+        ledger::{ConfigBuilder, LedgerBuilder},
+        scenario::{prepare_scenario, stake_pool, wallet},
+    },
+    transaction::{
+        AccountIdentifier, Input, NoExtra, Output, TxBuilder, UtxoPointer, ValidityInterval,
+        Witness,
+    },
+    value::Value,
+};
+use cardano_legacy_address::ExtendedAddr;
+use chain_addr::Discrimination;
+use chain_crypto::{Ed25519, SecretKey};
+use ed25519_bip32::{XPub, XPUB_SIZE};
+use rand_core::OsRng;
+
+#[test]
+pub fn apply_block_with_receipt_records_fragments() {
+    let (mut ledger, controller) = prepare_scenario()
+        .with_initials(vec![
+            wallet("Alice").with(1_000).owns("stake_pool"),
+            wallet("Bob").with(1_000),
+            wallet("Clarice").with(1_000),
+        ])
+        .build()
+        .unwrap();
+
+    let stake_pool = controller.stake_pool("stake_pool").unwrap();
+    let alice = controller.wallet("Alice").unwrap();
+    let bob = controller.wallet("Bob").unwrap();
+    let clarice = controller.wallet("Clarice").unwrap();
+
+    let fragment_factory = controller.fragment_factory();
+    let transaction = fragment_factory.transaction(&bob, &clarice, &mut ledger, 100);
+    let delegation = fragment_factory.delegation(&alice, &stake_pool);
+    let block = ledger
+        .forge_block_with_fragments(&stake_pool, vec![transaction.clone(), delegation.clone()]);
+
+    let parameters = ledger.ledger.get_ledger_parameters();
+    let metadata = block.header.to_content_eval_context();
+    let (new_ledger, receipt) = ledger
+        .ledger
+        .apply_block_with_receipt(&parameters, &block.contents, &metadata)
+        .unwrap();
+    let expected_ledger = ledger
+        .ledger
+        .apply_block(&parameters, &block.contents, &metadata)
+        .unwrap();
+    assert!(new_ledger == expected_ledger);
+    assert!(receipt.rewards.is_none());
+    assert_eq!(receipt.fragments.len(), 2);
+
+    let fee = ledger.fee().calculate(None, 1, 1);
+    let transaction_receipt = &receipt.fragments[0];
+    assert_eq!(transaction_receipt.fragment_id, transaction.hash());
+    assert_eq!(transaction_receipt.fee, fee);
+    assert!(transaction_receipt.utxos_consumed.is_empty());
+    assert!(transaction_receipt.utxos_created.is_empty());
+    assert_eq!(
+        transaction_receipt.accounts_debited,
+        vec![(
+            AccountIdentifier::Single(bob.as_account().to_id()),
+            Value(100)
+        )]
+    );
+    assert_eq!(
+        transaction_receipt.accounts_credited,
+        vec![(
+            AccountIdentifier::Single(clarice.as_account().to_id()),
+            (Value(100) - fee).unwrap()
+        )]
+    );
+    assert!(transaction_receipt.certificate.is_none());
+
+    let delegation_receipt = &receipt.fragments[1];
+    assert_eq!(delegation_receipt.fragment_id, delegation.hash());
+    match delegation_receipt.certificate {
+        Some(Certificate::StakeDelegation(_)) => {}
+        ref certificate => panic!("unexpected certificate {:?}", certificate),
+    }
+
+    assert_eq!(
+        receipt.total_fees().unwrap(),
+        (transaction_receipt.fee + delegation_receipt.fee).unwrap()
+    );
+}
+
+#[test]
+pub fn apply_block_with_rewards_receipt_records_rewards() {
+    let (mut ledger, controller) = prepare_scenario()
+        .with_config(
+            ConfigBuilder::new(0)
+                .with_rewards(Value(100))
+                .with_treasury(Value(100)),
+        )
+        .with_initials(vec![wallet("Alice").with(1_000).owns("stake_pool")])
+        .with_stake_pools(vec![stake_pool("stake_pool")
+            .with_reward_account(true)
+            .tax_ratio(1, 1)])
+        .build()
+        .unwrap();
+    let stake_pool = controller.stake_pool("stake_pool").unwrap();
+
+    assert!(ledger.produce_empty_block(&stake_pool).is_ok());
+    ledger.forward_date();
+    let block = ledger.forge_empty_block(&stake_pool);
+
+    let parameters = ledger.ledger.get_ledger_parameters();
+    let distribution = ledger.ledger.get_stake_distribution();
+    let metadata = block.header.to_content_eval_context();
+    let (new_ledger, receipt) = ledger
+        .ledger
+        .apply_block_with_rewards_receipt(
+            &parameters,
+            &block.contents,
+            &metadata,
+            &distribution,
+            RewardsInfoParameters::report_all(),
+        )
+        .unwrap();
+    let (rewarded_ledger, expected_rewards) = ledger
+        .ledger
+        .distribute_rewards(
+            &distribution,
+            &parameters,
+            RewardsInfoParameters::report_all(),
+        )
+        .unwrap();
+    let expected_ledger = rewarded_ledger
+        .apply_block(&parameters, &block.contents, &metadata)
+        .unwrap();
+    assert!(new_ledger == expected_ledger);
+    assert!(receipt.fragments.is_empty());
+
+    let rewards = receipt.rewards.unwrap();
+    assert!(rewards.drawn > Value::zero());
+    assert_eq!(rewards.drawn, expected_rewards.drawn);
+    assert_eq!(rewards.treasury, expected_rewards.treasury);
+    assert_eq!(rewards.stake_pools, expected_rewards.stake_pools);
+    assert!(rewards.stake_pools.contains_key(&stake_pool.id()));
+}
+
+#[test]
+pub fn apply_block_with_receipt_records_old_utxos() {
+    let key = SecretKey::<Ed25519>::generate(OsRng);
+    let chain_code = [7; 32];
+    let mut xpub = [0; XPUB_SIZE];
+    xpub[..32].copy_from_slice(key.to_public().as_ref());
+    xpub[32..].copy_from_slice(&chain_code);
+    let old_address = ExtendedAddr::new_simple(&XPub::from_bytes(xpub), None).to_address();
+    let declaration = Fragment::OldUtxoDeclaration(UtxoDeclaration {
+        addrs: vec![(old_address.clone(), Value(100))],
+    });
+    let ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .fragment(declaration.clone())
+        .build()
+        .unwrap();
+
+    let utxo = UtxoPointer::new(declaration.hash(), 0, Value(100));
+    let receiver = AddressData::utxo(Discrimination::Test);
+    let fee = ledger.fee().calculate(None, 1, 1);
+    let outputs = vec![Output {
+        address: receiver.address.clone(),
+        value: (Value(100) - fee).unwrap(),
+    }];
+    let tx_builder = TxBuilder::new()
+        .set_payload(&NoExtra)
+        .set_validity(ValidityInterval::unbounded())
+        .set_ios(&[Input::from_utxo(utxo)], &outputs);
+    let witness = Witness::new_old_utxo(
+        &ledger.block0_hash,
+        &tx_builder.get_auth_data_for_witness().hash(),
+        |data| (key.to_public(), key.sign(data)),
+        &chain_code,
+    );
+    let transaction =
+        Fragment::Transaction(tx_builder.set_witnesses(&[witness]).set_payload_auth(&()));
+    let block = ledger
+        .forge_block_with_fragments(&StakePoolBuilder::new().build(), vec![transaction.clone()]);
+
+    let parameters = ledger.ledger.get_ledger_parameters();
+    let metadata = block.header.to_content_eval_context();
+    let (_, receipt) = ledger
+        .ledger
+        .apply_block_with_receipt(&parameters, &block.contents, &metadata)
+        .unwrap();
+    let transaction_receipt = &receipt.fragments[0];
+    assert_eq!(transaction_receipt.fragment_id, transaction.hash());
+    assert!(transaction_receipt.utxos_consumed.is_empty());
+    assert_eq!(transaction_receipt.old_utxos_consumed.len(), 1);
+    let (consumed, output) = &transaction_receipt.old_utxos_consumed[0];
+    assert_eq!(*consumed, utxo);
+    assert_eq!(output.address, old_address);
+    assert_eq!(output.value, Value(100));
+    assert_eq!(transaction_receipt.utxos_created.len(), 1);
+}
@@ -0,0 +1,96 @@
+#![cfg(test)]
+
+use super::restore;
+use crate::{
+    fragment::Fragment,
+    key::Hash,
+    multisig::{DeclElement, Declaration},
+    testing::{
+        builders::OldAddressBuilder,
+        data::AddressDataValue,
+        ledger::{ConfigBuilder, LedgerBuilder},
+        scenario::{prepare_scenario, wallet},
+    },
+    value::Value,
+};
+use quickcheck::TestResult;
+use quickcheck_macros::quickcheck;
+
+#[quickcheck]
+pub fn snapshot_of_ledger_with_initial_funds(funds: Vec<AddressDataValue>) -> TestResult {
+    let ledger = match LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .initial_funds(&funds)
+        .build()
+    {
+        Ok(ledger) => ledger.ledger,
+        Err(_) => return TestResult::discard(),
+    };
+
+    TestResult::from_bool(restore(&ledger) == ledger)
+}
+
+#[test]
+pub fn snapshot_of_ledger_with_old_utxos() {
+    let ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .fragment(Fragment::OldUtxoDeclaration(
+            OldAddressBuilder::build_utxo_declaration(Some(5)),
+        ))
+        .build()
+        .unwrap();
+
+    assert!(restore(&ledger.ledger) == ledger.ledger);
+}
+
+#[test]
+pub fn snapshot_of_ledger_with_stake_pools_and_delegation() {
+    let (mut ledger, controller) = prepare_scenario()
+        .with_config(
+            ConfigBuilder::new(0)
+                .with_rewards(Value(1_000))
+                .with_treasury(Value(100)),
+        )
+        .with_initials(vec![
+            wallet("Alice").with(1_000).owns("alice_stake_pool"),
+            wallet("Bob").with(1_000).owns("bob_stake_pool"),
+            wallet("Clarice").with(1_000),
+        ])
+        .build()
+        .unwrap();
+
+    let alice_stake_pool = controller.stake_pool("alice_stake_pool").unwrap();
+    let bob_stake_pool = controller.stake_pool("bob_stake_pool").unwrap();
+    let clarice = controller.wallet("Clarice").unwrap();
+
+    controller
+        .delegates_to_many(
+            &clarice,
+            &[(&alice_stake_pool, 1), (&bob_stake_pool, 1)],
+            &mut ledger,
+        )
+        .unwrap();
+    ledger.increase_leader_log(&alice_stake_pool.id());
+
+    let restored = restore(&ledger.ledger);
+    assert!(restored == ledger.ledger);
+    assert_eq!(restored.leaders_log.total(), 1);
+    assert_eq!(restored.delegation.stake_pools.size(), 2);
+}
+
+#[test]
+pub fn snapshot_of_ledger_with_multisig_account() {
+    let mut ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .build()
+        .unwrap()
+        .ledger;
+    let declaration = Declaration {
+        threshold: 2,
+        owners: vec![
+            DeclElement::Owner(Hash::hash_bytes(&[1])),
+            DeclElement::Owner(Hash::hash_bytes(&[2])),
+            DeclElement::Owner(Hash::hash_bytes(&[3])),
+        ],
+    };
+    ledger.multisig = ledger.multisig.add_account(&declaration).unwrap();
+
+    assert!(restore(&ledger) == ledger);
+}
@@ -0,0 +1,79 @@
+#![cfg(test)]
+
+use super::restore;
+use crate::{
+    ledger::StateProof,
+    testing::{
+        data::AddressData,
+        ledger::{ConfigBuilder, LedgerBuilder},
+        TestTxBuilder,
+    },
+    value::Value,
+};
+use chain_addr::Discrimination;
+use chain_core::mempack::{ReadBuf, Readable};
+use typed_bytes::ByteBuilder;
+
+#[test]
+pub fn state_root_follows_transactions() {
+    let mut test_ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .faucet_value(Value(1000))
+        .build()
+        .expect("cannot build test ledger");
+    let initial_root = test_ledger.ledger.state_root();
+    assert_eq!(restore(&test_ledger.ledger).state_root(), initial_root);
+
+    let receiver = AddressData::utxo(Discrimination::Test);
+    let fragment = TestTxBuilder::new(&test_ledger.block0_hash)
+        .move_from_faucet(&mut test_ledger, &receiver.address, &Value(100))
+        .get_fragment();
+    test_ledger.apply_transaction(fragment).unwrap();
+
+    let root = test_ledger.ledger.state_root();
+    assert_ne!(root, initial_root);
+    assert_eq!(restore(&test_ledger.ledger).state_root(), root);
+}
+
+#[test]
+pub fn account_and_utxo_proofs() {
+    let mut test_ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .faucet_value(Value(1000))
+        .build()
+        .expect("cannot build test ledger");
+    let receiver = AddressData::utxo(Discrimination::Test);
+    let fragment = TestTxBuilder::new(&test_ledger.block0_hash)
+        .move_from_faucet(&mut test_ledger, &receiver.address, &Value(100))
+        .get_fragment();
+    test_ledger.apply_transaction(fragment).unwrap();
+
+    let ledger = &test_ledger.ledger;
+    let root = ledger.state_root();
+
+    let faucet = test_ledger.faucets[0].to_id();
+    let state = ledger.accounts().get_state(&faucet).unwrap().clone();
+    let proof = ledger.account_proof(&faucet).unwrap();
+    assert!(proof.verify_account(&root, &faucet, &state));
+    assert!(!proof.verify_account(&root, &faucet, &state.add(Value(1)).unwrap()));
+    assert!(!proof.verify_account(&crate::key::Hash::zero_hash(), &faucet, &state));
+
+    let entry = ledger.utxos().next().unwrap();
+    let proof = ledger
+        .utxo_proof(&entry.fragment_id, entry.output_index)
+        .unwrap();
+    assert!(proof.verify_utxo(&root, &entry.fragment_id, entry.output_index, entry.output));
+    assert!(!proof.verify_utxo(
+        &root,
+        &entry.fragment_id,
+        entry.output_index + 1,
+        entry.output
+    ));
+    assert!(ledger
+        .utxo_proof(&entry.fragment_id, entry.output_index + 1)
+        .is_none());
+
+    let bytes = proof.serialize_in(ByteBuilder::new()).finalize_as_vec();
+    let mut buf = ReadBuf::from(&bytes);
+    let decoded = StateProof::read(&mut buf).unwrap();
+    buf.expect_end().unwrap();
+    assert_eq!(decoded, proof);
+}
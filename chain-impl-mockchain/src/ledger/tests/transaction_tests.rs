@@ -2,6 +2,7 @@
 
 use crate::{
     accounting::account::LedgerError::NonExistent,
+    date::BlockDate,
     ledger::{
         self,
         check::TxVerifyError,
@@ -63,7 +64,7 @@ pub fn duplicated_account_transaction() {
         Err(err) => panic!("first transaction should be succesful but {}", err),
         Ok(_) => {
             assert_err_match!(
-                &ledger::Error::AccountInvalidSignature{..},
+                &ledger::Error::AccountInvalidSignature { .. },
                 test_ledger.apply_transaction(fragment2)
             );
         }
@@ -130,3 +131,80 @@ pub fn repeated_account_transaction() {
         .get_fragment();
     assert!(test_ledger.apply_transaction(fragment).is_err());
 }
+
+fn block_date(epoch: u32, slot_id: u32) -> BlockDate {
+    BlockDate { epoch, slot_id }
+}
+
+#[test]
+pub fn transaction_within_validity_interval() {
+    let faucet = AddressDataValue::account(Discrimination::Test, Value(200));
+    let receiver = AddressDataValue::account(Discrimination::Test, Value(0));
+
+    let mut test_ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .faucet(&faucet)
+        .build()
+        .expect("cannot build test ledger");
+
+    let validity = ValidityInterval::new(Some(block_date(0, 10)), Some(block_date(1, 5)));
+    let fragment = TestTxBuilder::new(&test_ledger.block0_hash)
+        .with_validity(validity)
+        .move_all_funds(&mut test_ledger, &faucet, &receiver)
+        .get_fragment();
+
+    assert!(test_ledger
+        .clone()
+        .apply_fragment(&fragment, block_date(0, 10))
+        .is_ok());
+    assert!(test_ledger
+        .apply_fragment(&fragment, block_date(1, 5))
+        .is_ok());
+}
+
+#[test]
+pub fn transaction_before_validity_interval() {
+    let faucet = AddressDataValue::account(Discrimination::Test, Value(200));
+    let receiver = AddressDataValue::account(Discrimination::Test, Value(0));
+
+    let mut test_ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .faucet(&faucet)
+        .build()
+        .expect("cannot build test ledger");
+
+    let fragment = TestTxBuilder::new(&test_ledger.block0_hash)
+        .with_validity(ValidityInterval::new(Some(block_date(1, 0)), None))
+        .move_all_funds(&mut test_ledger, &faucet, &receiver)
+        .get_fragment();
+
+    assert_err!(
+        ledger::Error::TransactionNotYetValid {
+            valid_from: block_date(1, 0),
+            block_date: block_date(0, 99),
+        },
+        test_ledger.apply_fragment(&fragment, block_date(0, 99))
+    );
+}
+
+#[test]
+pub fn transaction_after_validity_interval() {
+    let faucet = AddressDataValue::account(Discrimination::Test, Value(200));
+    let receiver = AddressDataValue::account(Discrimination::Test, Value(0));
+
+    let mut test_ledger = LedgerBuilder::from_config(ConfigBuilder::new(0))
+        .faucet(&faucet)
+        .build()
+        .expect("cannot build test ledger");
+
+    let fragment = TestTxBuilder::new(&test_ledger.block0_hash)
+        .with_validity(ValidityInterval::until(block_date(0, 5)))
+        .move_all_funds(&mut test_ledger, &faucet, &receiver)
+        .get_fragment();
+
+    assert_err!(
+        ledger::Error::TransactionExpired {
+            valid_until: block_date(0, 5),
+            block_date: block_date(0, 6),
+        },
+        test_ledger.apply_fragment(&fragment, block_date(0, 6))
+    );
+}
@@ -19,6 +19,7 @@ pub mod header;
 pub mod key;
 pub mod leadership;
 pub mod ledger;
+pub mod merkle;
 pub mod multisig;
 pub mod multiverse;
 pub mod rewards;
@@ -0,0 +1,383 @@
+//! Sparse Merkle tree committing to the content of the ledger
+//!
+//! Every element is a leaf at the position given by the 256 bits of the hash
+//! of its key. Only the branches where the paths of the leaves diverge are
+//! kept, so the shape of the tree only depends on the set of keys and not on
+//! the order in which they have been inserted.
+//!
+//! Like the rest of the ledger the tree is immutable: updating it creates a
+//! new tree sharing all the untouched nodes with the previous one, so it can
+//! be kept up to date at each ledger operation.
+
+use crate::key::Hash;
+use chain_core::mempack::{ReadBuf, ReadError, Readable};
+use std::sync::Arc;
+use typed_bytes::ByteBuilder;
+
+const LEAF_PREFIX: u8 = 0;
+const BRANCH_PREFIX: u8 = 1;
+
+/// Maximum number of branches between the root and a leaf
+const MAX_DEPTH: usize = 256;
+
+/// Hash committing to an element of the ledger, used as the key or the value
+/// of the leaves of a `MerkleTree`
+pub trait Commitment {
+    fn commitment(&self) -> Hash;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MerkleTree {
+    root: Option<Arc<Node>>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        path: Hash,
+        hash: Hash,
+    },
+    /// The paths of all the leaves below a branch share the same `bit` first
+    /// bits, they are on the left if their next bit is 0, on the right otherwise
+    Branch {
+        bit: u8,
+        path: Hash,
+        left: Arc<Node>,
+        right: Arc<Node>,
+        hash: Hash,
+    },
+}
+
+fn bit_at(path: &Hash, bit: u8) -> bool {
+    let byte = path.as_ref()[bit as usize / 8];
+    (byte >> (7 - bit % 8)) & 1 == 1
+}
+
+fn first_different_bit(a: &Hash, b: &Hash) -> Option<u8> {
+    a.as_ref()
+        .iter()
+        .zip(b.as_ref())
+        .enumerate()
+        .find(|(_, (a, b))| a != b)
+        .map(|(i, (a, b))| (i * 8) as u8 + (a ^ b).leading_zeros() as u8)
+}
+
+fn leaf_hash(path: &Hash, value: &Hash) -> Hash {
+    let mut bytes = Vec::with_capacity(65);
+    bytes.push(LEAF_PREFIX);
+    bytes.extend_from_slice(path.as_ref());
+    bytes.extend_from_slice(value.as_ref());
+    Hash::hash_bytes(&bytes)
+}
+
+fn branch_hash(bit: u8, left: &Hash, right: &Hash) -> Hash {
+    let mut bytes = Vec::with_capacity(66);
+    bytes.push(BRANCH_PREFIX);
+    bytes.push(bit);
+    bytes.extend_from_slice(left.as_ref());
+    bytes.extend_from_slice(right.as_ref());
+    Hash::hash_bytes(&bytes)
+}
+
+impl Node {
+    fn leaf(path: Hash, value: &Hash) -> Arc<Node> {
+        let hash = leaf_hash(&path, value);
+        Arc::new(Node::Leaf { path, hash })
+    }
+
+    fn branch(bit: u8, left: Arc<Node>, right: Arc<Node>) -> Arc<Node> {
+        let hash = branch_hash(bit, left.hash(), right.hash());
+        let path = *left.path();
+        Arc::new(Node::Branch {
+            bit,
+            path,
+            left,
+            right,
+            hash,
+        })
+    }
+
+    /// join two subtrees whose paths diverge at the given bit
+    fn fork(bit: u8, node: Arc<Node>, other: Arc<Node>) -> Arc<Node> {
+        if bit_at(other.path(), bit) {
+            Node::branch(bit, node, other)
+        } else {
+            Node::branch(bit, other, node)
+        }
+    }
+
+    fn hash(&self) -> &Hash {
+        match self {
+            Node::Leaf { hash, .. } => hash,
+            Node::Branch { hash, .. } => hash,
+        }
+    }
+
+    /// path of one of the leaves of this subtree
+    fn path(&self) -> &Hash {
+        match self {
+            Node::Leaf { path, .. } => path,
+            Node::Branch { path, .. } => path,
+        }
+    }
+}
+
+fn insert(node: &Arc<Node>, path: Hash, value: &Hash) -> Arc<Node> {
+    match node.as_ref() {
+        Node::Leaf { path: leaf_path, .. } => match first_different_bit(leaf_path, &path) {
+            None => Node::leaf(path, value),
+            Some(bit) => Node::fork(bit, node.clone(), Node::leaf(path, value)),
+        },
+        Node::Branch {
+            bit,
+            path: branch_path,
+            left,
+            right,
+            ..
+        } => match first_different_bit(branch_path, &path) {
+            Some(diverge) if diverge < *bit => {
+                Node::fork(diverge, node.clone(), Node::leaf(path, value))
+            }
+            _ if bit_at(&path, *bit) => Node::branch(*bit, left.clone(), insert(right, path, value)),
+            _ => Node::branch(*bit, insert(left, path, value), right.clone()),
+        },
+    }
+}
+
+fn remove(node: &Arc<Node>, path: &Hash) -> Option<Arc<Node>> {
+    match node.as_ref() {
+        Node::Leaf { path: leaf_path, .. } if leaf_path == path => None,
+        Node::Leaf { .. } => Some(node.clone()),
+        Node::Branch {
+            bit, left, right, ..
+        } => {
+            let (child, sibling) = if bit_at(path, *bit) {
+                (right, left)
+            } else {
+                (left, right)
+            };
+            match remove(child, path) {
+                None => Some(sibling.clone()),
+                Some(new_child) if Arc::ptr_eq(&new_child, child) => Some(node.clone()),
+                Some(new_child) if bit_at(path, *bit) => {
+                    Some(Node::branch(*bit, left.clone(), new_child))
+                }
+                Some(new_child) => Some(Node::branch(*bit, new_child, right.clone())),
+            }
+        }
+    }
+}
+
+impl MerkleTree {
+    pub fn new() -> Self {
+        MerkleTree { root: None }
+    }
+
+    /// Hash of the root of the tree, the zero hash if the tree is empty
+    pub fn root(&self) -> Hash {
+        self.root
+            .as_ref()
+            .map_or_else(Hash::zero_hash, |root| *root.hash())
+    }
+
+    /// Set the value of the leaf at the given path, adding it if needed
+    pub fn insert(&self, path: Hash, value: &Hash) -> Self {
+        let root = match &self.root {
+            None => Node::leaf(path, value),
+            Some(root) => insert(root, path, value),
+        };
+        MerkleTree { root: Some(root) }
+    }
+
+    /// Remove the leaf at the given path, if there is one
+    pub fn remove(&self, path: &Hash) -> Self {
+        MerkleTree {
+            root: self.root.as_ref().and_then(|root| remove(root, path)),
+        }
+    }
+
+    /// Proof that there is a leaf at the given path in this tree
+    pub fn proof(&self, path: &Hash) -> Option<MerkleProof> {
+        let mut node = self.root.as_ref()?;
+        let mut siblings = Vec::new();
+        loop {
+            match node.as_ref() {
+                Node::Leaf { path: leaf_path, .. } if leaf_path == path => break,
+                Node::Leaf { .. } => return None,
+                Node::Branch {
+                    bit, left, right, ..
+                } => {
+                    let (child, sibling) = if bit_at(path, *bit) {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    siblings.push((*bit, *sibling.hash()));
+                    node = child;
+                }
+            }
+        }
+        siblings.reverse();
+        Some(MerkleProof { siblings })
+    }
+}
+
+impl PartialEq for MerkleTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.root() == other.root()
+    }
+}
+
+impl Eq for MerkleTree {}
+
+impl<'a, P: Commitment + 'a, V: Commitment + 'a> std::iter::FromIterator<(&'a P, &'a V)>
+    for MerkleTree
+{
+    fn from_iter<I: IntoIterator<Item = (&'a P, &'a V)>>(iter: I) -> Self {
+        iter.into_iter().fold(MerkleTree::new(), |tree, (path, value)| {
+            tree.insert(path.commitment(), &value.commitment())
+        })
+    }
+}
+
+/// Hashes of the siblings of the branches between a leaf and the root of a
+/// `MerkleTree`, from the leaf up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    siblings: Vec<(u8, Hash)>,
+}
+
+impl MerkleProof {
+    /// Root of the tree in which the leaf would be
+    pub fn root(&self, path: &Hash, value: &Hash) -> Hash {
+        self.siblings
+            .iter()
+            .fold(leaf_hash(path, value), |hash, (bit, sibling)| {
+                if bit_at(path, *bit) {
+                    branch_hash(*bit, sibling, &hash)
+                } else {
+                    branch_hash(*bit, &hash, sibling)
+                }
+            })
+    }
+
+    /// Check the leaf with the given path and value is in the tree with the given root
+    pub fn verify(&self, root: &Hash, path: &Hash, value: &Hash) -> bool {
+        self.root(path, value) == *root
+    }
+
+    pub fn serialize_in(&self, bb: ByteBuilder<Self>) -> ByteBuilder<Self> {
+        bb.iter16(self.siblings.iter(), |bb, (bit, sibling)| {
+            bb.u8(*bit).bytes(sibling.as_ref())
+        })
+    }
+}
+
+impl Readable for MerkleProof {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        let nb_siblings = buf.get_u16()? as usize;
+        if nb_siblings > MAX_DEPTH {
+            return Err(ReadError::SizeTooBig(nb_siblings, MAX_DEPTH));
+        }
+        let mut siblings = Vec::with_capacity(nb_siblings);
+        for _ in 0..nb_siblings {
+            let bit = buf.get_u8()?;
+            siblings.push((bit, Hash::read(buf)?));
+        }
+        Ok(MerkleProof { siblings })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeMap;
+
+    fn path(key: u32) -> Hash {
+        Hash::hash_bytes(&key.to_be_bytes())
+    }
+
+    fn value(value: u64) -> Hash {
+        Hash::hash_bytes(&value.to_be_bytes())
+    }
+
+    fn build<'a, I: Iterator<Item = (&'a u32, &'a u64)>>(entries: I) -> MerkleTree {
+        entries.fold(MerkleTree::new(), |tree, (k, v)| tree.insert(path(*k), &value(*v)))
+    }
+
+    #[test]
+    pub fn empty_tree() {
+        let tree = MerkleTree::new();
+        assert_eq!(tree.root(), Hash::zero_hash());
+        assert!(tree.proof(&path(0)).is_none());
+        assert_eq!(tree.remove(&path(0)), tree);
+    }
+
+    #[test]
+    pub fn bits() {
+        let mut bytes = [0u8; 32];
+        bytes[1] = 0b0010_0000;
+        let a = Hash::from_bytes(bytes);
+        let b = Hash::zero_hash();
+        assert!(bit_at(&a, 10));
+        assert!(!bit_at(&a, 9));
+        assert!(!bit_at(&a, 11));
+        assert_eq!(first_different_bit(&a, &b), Some(10));
+        assert_eq!(first_different_bit(&a, &a), None);
+    }
+
+    #[quickcheck]
+    pub fn root_does_not_depend_on_insertion_order(entries: BTreeMap<u32, u64>) -> bool {
+        build(entries.iter()) == build(entries.iter().rev())
+    }
+
+    #[quickcheck]
+    pub fn insert_replaces_value(entries: BTreeMap<u32, u64>, key: u32, v1: u64, v2: u64) -> bool {
+        let tree = build(entries.iter());
+        tree.insert(path(key), &value(v1)).insert(path(key), &value(v2))
+            == tree.insert(path(key), &value(v2))
+    }
+
+    #[quickcheck]
+    pub fn remove_is_inverse_of_insert(entries: BTreeMap<u32, u64>, key: u32, v: u64) -> TestResult {
+        if entries.contains_key(&key) {
+            return TestResult::discard();
+        }
+        let tree = build(entries.iter());
+        TestResult::from_bool(tree.insert(path(key), &value(v)).remove(&path(key)) == tree)
+    }
+
+    #[quickcheck]
+    pub fn proofs_verify(entries: BTreeMap<u32, u64>) -> bool {
+        let tree = build(entries.iter());
+        let root = tree.root();
+        entries.iter().all(|(k, v)| {
+            let proof = tree.proof(&path(*k)).unwrap();
+            proof.verify(&root, &path(*k), &value(*v))
+                && !proof.verify(&root, &path(*k), &value(v.wrapping_add(1)))
+        })
+    }
+
+    #[quickcheck]
+    pub fn no_proof_for_missing_leaf(entries: BTreeMap<u32, u64>, key: u32) -> TestResult {
+        if entries.contains_key(&key) {
+            return TestResult::discard();
+        }
+        TestResult::from_bool(build(entries.iter()).proof(&path(key)).is_none())
+    }
+
+    #[quickcheck]
+    pub fn proof_serialization(entries: BTreeMap<u32, u64>) -> TestResult {
+        let key = match entries.keys().next() {
+            None => return TestResult::discard(),
+            Some(key) => path(*key),
+        };
+        let proof = build(entries.iter()).proof(&key).unwrap();
+        let bytes = proof.serialize_in(ByteBuilder::new()).finalize_as_vec();
+        let mut buf = ReadBuf::from(&bytes);
+        let decoded = MerkleProof::read(&mut buf).unwrap();
+        TestResult::from_bool(buf.expect_end().is_ok() && decoded == proof)
+    }
+}
@@ -1,5 +1,8 @@
+use crate::merkle::Commitment;
 use crate::{account, key};
+use chain_core::mempack::{ReadBuf, ReadError, Readable};
 use chain_crypto::{PublicKey, Signature};
+use typed_bytes::ByteBuilder;
 
 use super::index::{Index, TreeIndex, LEVEL_MAXLIMIT};
 pub use crate::transaction::WitnessMultisigData;
@@ -27,6 +30,12 @@ impl From<Identifier> for [u8; 32] {
     }
 }
 
+impl Commitment for Identifier {
+    fn commitment(&self) -> key::Hash {
+        key::Hash::hash_bytes(self.as_ref())
+    }
+}
+
 #[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum DeclarationError {
     #[error("Invalid threshold")]
@@ -128,5 +137,38 @@ impl Declaration {
     }
 }
 
+const DECL_ELEMENT_OWNER: u8 = 0;
+const DECL_ELEMENT_SUB: u8 = 1;
+
+impl Declaration {
+    pub fn serialize_in(&self, bb: ByteBuilder<Self>) -> ByteBuilder<Self> {
+        bb.u8(self.threshold)
+            .iter8(self.owners.iter(), |bb, owner| match owner {
+                DeclElement::Owner(hash) => bb.u8(DECL_ELEMENT_OWNER).bytes(hash.as_ref()),
+                DeclElement::Sub(decl) => bb.u8(DECL_ELEMENT_SUB).sub(|bb| decl.serialize_in(bb)),
+            })
+    }
+}
+
+impl Readable for Declaration {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        let threshold = buf.get_u8()?;
+        let nb_owners = buf.get_u8()? as usize;
+        if nb_owners > LEVEL_MAXLIMIT {
+            return Err(ReadError::SizeTooBig(nb_owners, LEVEL_MAXLIMIT));
+        }
+        let mut owners = Vec::with_capacity(nb_owners);
+        for _ in 0..nb_owners {
+            let owner = match buf.get_u8()? {
+                DECL_ELEMENT_OWNER => DeclElement::Owner(key::Hash::read(buf)?),
+                DECL_ELEMENT_SUB => DeclElement::Sub(Declaration::read(buf)?),
+                tag => return Err(ReadError::UnknownTag(tag as u32)),
+            };
+            owners.push(owner);
+        }
+        Ok(Declaration { threshold, owners })
+    }
+}
+
 pub type Pk = PublicKey<account::AccountAlg>;
 pub type Sig = Signature<WitnessMultisigData, account::AccountAlg>;
@@ -4,6 +4,7 @@ use thiserror::Error;
 
 use super::declaration::{Declaration, DeclarationError, Identifier};
 use crate::accounting::account::{self, DelegationType, Iter, SpendingCounter};
+use crate::ledger::diff::{hamt_apply_entry, hamt_diff, DiffError, MapDiff};
 use crate::value::{Value, ValueError};
 
 #[derive(Clone, PartialEq, Eq)]
@@ -91,6 +92,39 @@ impl Ledger {
         })
     }
 
+    /// Accounts and declarations changed from this ledger to the `other` ledger
+    pub(crate) fn diff(
+        &self,
+        other: &Self,
+    ) -> (
+        MapDiff<Identifier, account::AccountState<()>>,
+        MapDiff<Identifier, Declaration>,
+    ) {
+        (
+            self.accounts.diff(&other.accounts),
+            hamt_diff(&self.declarations, &other.declarations),
+        )
+    }
+
+    /// Apply the changes of the accounts and declarations, checking
+    /// the current states
+    pub(crate) fn apply_diff(
+        &self,
+        accounts: &MapDiff<Identifier, account::AccountState<()>>,
+        declarations: &MapDiff<Identifier, Declaration>,
+    ) -> Result<Self, DiffError> {
+        let accounts = self.accounts.apply_diff(accounts)?;
+        let declarations = declarations
+            .iter()
+            .try_fold(self.declarations.clone(), |decls, (identifier, entry)| {
+                hamt_apply_entry(&decls, identifier, entry)
+            })?;
+        Ok(Self {
+            accounts,
+            declarations,
+        })
+    }
+
     pub fn add_value(&self, identifier: &Identifier, value: Value) -> Result<Self, LedgerError> {
         let new_accounts = self.accounts.add_value(identifier, value)?;
         Ok(Self {
@@ -103,6 +137,11 @@ impl Ledger {
         self.accounts.iter()
     }
 
+    /// root of the merkle tree of the multisig accounts
+    pub fn accounts_state_root(&self) -> crate::key::Hash {
+        self.accounts.state_root()
+    }
+
     pub fn iter_declarations<'a>(&'a self) -> HamtIter<'a, Identifier, Declaration> {
         self.declarations.iter()
     }
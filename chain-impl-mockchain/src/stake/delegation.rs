@@ -1,15 +1,21 @@
 use crate::certificate::{PoolId, PoolRegistration, PoolRegistrationHash};
 use crate::header::Epoch;
+use crate::key::Hash;
+use crate::ledger::diff::{hamt_apply_entry, hamt_diff, DiffError, MapDiff};
+use crate::merkle::{Commitment, MerkleProof, MerkleTree};
 use crate::value::Value;
+use chain_core::mempack::{ReadBuf, ReadError, Readable};
 use imhamt::Hamt;
 use std::collections::hash_map::DefaultHasher;
 use std::fmt::{self, Debug};
 use std::sync::Arc;
+use typed_bytes::ByteBuilder;
 
 /// A structure that keeps track of stake keys and stake pools.
 #[derive(Clone, PartialEq, Eq)]
 pub struct PoolsState {
     pub(crate) stake_pools: Hamt<DefaultHasher, PoolId, PoolState>,
+    state_tree: MerkleTree,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -52,6 +58,40 @@ impl PoolState {
     pub fn current_pool_registration_hash(&self) -> PoolRegistrationHash {
         self.registration.to_id()
     }
+
+    pub fn serialize_in(&self, bb: ByteBuilder<Self>) -> ByteBuilder<Self> {
+        bb.u32(self.last_rewards.epoch)
+            .u64(self.last_rewards.value_taxed.0)
+            .u64(self.last_rewards.value_for_stakers.0)
+            .sub(|bb| self.registration.serialize_in(bb))
+    }
+}
+
+impl Readable for PoolState {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        let last_rewards = PoolLastRewards {
+            epoch: buf.get_u32()?,
+            value_taxed: Value::read(buf)?,
+            value_for_stakers: Value::read(buf)?,
+        };
+        let registration = PoolRegistration::read(buf)?;
+        Ok(PoolState {
+            last_rewards,
+            registration: Arc::new(registration),
+        })
+    }
+}
+
+impl Commitment for PoolState {
+    fn commitment(&self) -> Hash {
+        Hash::hash_bytes(&self.serialize_in(ByteBuilder::new()).finalize_as_vec())
+    }
+}
+
+impl Commitment for PoolId {
+    fn commitment(&self) -> Hash {
+        Hash::hash_bytes(self.as_ref())
+    }
 }
 
 impl Debug for PoolsState {
@@ -90,9 +130,52 @@ impl PoolsState {
     pub fn new() -> Self {
         PoolsState {
             stake_pools: Hamt::new(),
+            state_tree: MerkleTree::new(),
+        }
+    }
+
+    /// update the state tree for a pool which has been changed in the given pools
+    fn with_stake_pools(
+        &self,
+        stake_pools: Hamt<DefaultHasher, PoolId, PoolState>,
+        pool_id: &PoolId,
+    ) -> Self {
+        let state_tree = match stake_pools.lookup(pool_id) {
+            Some(state) => self
+                .state_tree
+                .insert(pool_id.commitment(), &state.commitment()),
+            None => self.state_tree.remove(&pool_id.commitment()),
+        };
+        PoolsState {
+            stake_pools,
+            state_tree,
         }
     }
 
+    /// Stake pools changed from this state to the `other` state
+    pub(crate) fn diff(&self, other: &Self) -> MapDiff<PoolId, PoolState> {
+        hamt_diff(&self.stake_pools, &other.stake_pools)
+    }
+
+    /// Apply the changes of the stake pools, checking the current states
+    pub(crate) fn apply_diff(&self, diff: &MapDiff<PoolId, PoolState>) -> Result<Self, DiffError> {
+        diff.iter()
+            .try_fold(self.clone(), |state, (pool_id, entry)| {
+                let stake_pools = hamt_apply_entry(&state.stake_pools, pool_id, entry)?;
+                Ok(state.with_stake_pools(stake_pools, pool_id))
+            })
+    }
+
+    /// root of the merkle tree of all the registered stake pools
+    pub fn state_root(&self) -> Hash {
+        self.state_tree.root()
+    }
+
+    /// Proof that the stake pool is registered, if it is
+    pub fn proof(&self, pool_id: &PoolId) -> Option<MerkleProof> {
+        self.state_tree.proof(&pool_id.commitment())
+    }
+
     pub fn lookup(&self, id: &PoolId) -> Option<&PoolState> {
         self.stake_pools.lookup(id)
     }
@@ -122,11 +205,12 @@ impl PoolsState {
         pool_id: &PoolId,
         pool_state: PoolState,
     ) -> Result<(), PoolError> {
-        self.stake_pools = self
+        let stake_pools = self
             .stake_pools
             .replace(pool_id, pool_state)
             .map(|r| r.0)
             .map_err(|_| PoolError::NotFound(pool_id.clone()))?;
+        *self = self.with_stake_pools(stake_pools, pool_id);
         Ok(())
     }
 
@@ -149,7 +233,7 @@ impl PoolsState {
             value_taxed,
             value_for_stakers,
         };
-        self.stake_pools = self
+        let stake_pools = self
             .stake_pools
             .replace_with(pool_id, |st| {
                 let mut st = st.clone();
@@ -157,6 +241,7 @@ impl PoolsState {
                 st
             })
             .map_err(|_| PoolError::NotFound(pool_id.clone()))?;
+        *self = self.with_stake_pools(stake_pools, pool_id);
         Ok(())
     }
 
@@ -165,19 +250,27 @@ impl PoolsState {
         let new_pools = self
             .stake_pools
             .insert(id.clone(), PoolState::new(owner))
-            .map_err(|_| PoolError::AlreadyExists(id))?;
-        Ok(PoolsState {
-            stake_pools: new_pools,
-        })
+            .map_err(|_| PoolError::AlreadyExists(id.clone()))?;
+        Ok(self.with_stake_pools(new_pools, &id))
     }
 
     pub fn deregister_stake_pool(&self, pool_id: &PoolId) -> Result<Self, PoolError> {
-        Ok(PoolsState {
-            stake_pools: self
-                .stake_pools
-                .remove(pool_id)
-                .map_err(|_| PoolError::NotFound(pool_id.clone()))?,
-        })
+        let new_pools = self
+            .stake_pools
+            .remove(pool_id)
+            .map_err(|_| PoolError::NotFound(pool_id.clone()))?;
+        Ok(self.with_stake_pools(new_pools, pool_id))
+    }
+}
+
+impl std::iter::FromIterator<(PoolId, PoolState)> for PoolsState {
+    fn from_iter<I: IntoIterator<Item = (PoolId, PoolState)>>(iter: I) -> Self {
+        let stake_pools: Hamt<DefaultHasher, PoolId, PoolState> = iter.into_iter().collect();
+        let state_tree = stake_pools.iter().collect();
+        PoolsState {
+            stake_pools,
+            state_tree,
+        }
     }
 }
 
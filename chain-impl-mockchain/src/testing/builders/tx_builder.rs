@@ -7,7 +7,7 @@ use crate::{
     },
     transaction::{
         Input, NoExtra, Output, OutputsSlice, Transaction, TransactionSignDataHash,
-        TransactionSlice, TxBuilder, Witness, WitnessesSlice,
+        TransactionSlice, TxBuilder, ValidityInterval, Witness, WitnessesSlice,
     },
     value::Value,
 };
@@ -15,6 +15,7 @@ use chain_addr::Address;
 
 pub struct TestTxBuilder {
     block0_hash: HeaderId,
+    validity: ValidityInterval,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -60,9 +61,15 @@ impl TestTxBuilder {
     pub fn new(block0_hash: &HeaderId) -> Self {
         Self {
             block0_hash: block0_hash.clone(),
+            validity: ValidityInterval::unbounded(),
         }
     }
 
+    pub fn with_validity(mut self, validity: ValidityInterval) -> Self {
+        self.validity = validity;
+        self
+    }
+
     pub fn move_from_faucet(
         &self,
         test_ledger: &mut TestLedger,
@@ -94,6 +101,7 @@ impl TestTxBuilder {
         }];
         let tx_builder = TxBuilder::new()
             .set_payload(&NoExtra)
+            .set_validity(self.validity)
             .set_ios(&inputs, &outputs);
 
         let witness =
@@ -128,6 +136,7 @@ impl TestTxBuilder {
         )];
         let tx_builder = TxBuilder::new()
             .set_payload(&NoExtra)
+            .set_validity(self.validity)
             .set_ios(&inputs, &destination);
 
         let witness =
@@ -189,6 +198,7 @@ impl TestTxBuilder {
             .collect();
         let tx_builder = TxBuilder::new()
             .set_payload(&NoExtra)
+            .set_validity(self.validity)
             .set_ios(&inputs, &destinations);
 
         let witnesses: Vec<Witness> = sources
@@ -4,6 +4,7 @@ use super::transaction::{
     Transaction, TransactionAuthData, TransactionBindingAuthData, TransactionStruct,
 };
 use super::transfer::Output;
+use super::validity::ValidityInterval;
 use super::witness::Witness;
 use chain_addr::Address;
 use std::marker::PhantomData;
@@ -49,6 +50,7 @@ impl TxBuilder {
                 sz: 0,
                 nb_inputs: 0,
                 nb_outputs: 0,
+                validity: ValidityInterval::unbounded(),
                 inputs: 0,
                 outputs: 0,
                 witnesses: 0,
@@ -85,6 +87,16 @@ impl TxBuilderState<SetPayload> {
 }
 
 impl<P> TxBuilderState<SetIOs<P>> {
+    /// Set the interval of block dates in which this transaction is valid
+    ///
+    /// By default a transaction is valid at any block date. The interval
+    /// is serialized along with the inputs and outputs, and thus is part
+    /// of the data signed by the witnesses.
+    pub fn set_validity(mut self, validity: ValidityInterval) -> Self {
+        self.tstruct.validity = validity;
+        self
+    }
+
     /// Set the inputs and outputs of this transaction
     ///
     /// This cannot accept more than 255 inputs, 255 outputs, since
@@ -109,6 +121,8 @@ impl<P> TxBuilderState<SetIOs<P>> {
         self.tstruct.nb_inputs = nb_inputs;
         self.tstruct.nb_outputs = nb_outputs;
 
+        self.tstruct.validity.serialize_in(&mut self.data);
+
         self.tstruct.inputs = self.current_pos();
 
         for i in inputs {
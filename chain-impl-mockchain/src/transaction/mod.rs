@@ -6,6 +6,7 @@ mod payload;
 mod transaction;
 mod transfer;
 mod utxo;
+mod validity;
 mod witness;
 
 #[cfg(any(test, feature = "property-test-api"))]
@@ -23,6 +24,7 @@ pub use payload::{NoExtra, Payload, PayloadAuthData, PayloadAuthSlice, PayloadDa
 pub use transaction::*;
 pub use transfer::*;
 pub use utxo::*;
+pub use validity::ValidityInterval;
 pub use witness::*;
 
 impl<Extra: Payload> property::Serialize for Transaction<Extra> {
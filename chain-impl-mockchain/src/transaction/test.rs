@@ -1,6 +1,7 @@
 use super::{
     element::SingleAccountBindingSignature, AccountBindingSignature, AccountIdentifier, Input,
-    NoExtra, Payload, Transaction, TxBuilder, UnspecifiedAccountIdentifier, UtxoPointer, Witness,
+    NoExtra, Payload, Transaction, TxBuilder, UnspecifiedAccountIdentifier, UtxoPointer,
+    ValidityInterval, Witness,
 };
 use crate::certificate::OwnerStakeDelegation;
 use crate::key::{EitherEd25519SecretKey, SpendingSignature};
@@ -124,6 +125,15 @@ impl Arbitrary for Input {
     }
 }
 
+impl Arbitrary for ValidityInterval {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        ValidityInterval {
+            valid_from: Arbitrary::arbitrary(g),
+            valid_until: Arbitrary::arbitrary(g),
+        }
+    }
+}
+
 impl Arbitrary for NoExtra {
     fn arbitrary<G: Gen>(_: &mut G) -> Self {
         Self
@@ -137,6 +147,7 @@ where
     fn arbitrary<G: Gen>(g: &mut G) -> Self {
         let payload: Extra = Arbitrary::arbitrary(g);
         let payload_auth: Extra::Auth = Arbitrary::arbitrary(g);
+        let validity: ValidityInterval = Arbitrary::arbitrary(g);
 
         let num_inputs = u8::arbitrary(g) as usize;
         let num_outputs = u8::arbitrary(g) as usize;
@@ -153,6 +164,7 @@ where
 
         TxBuilder::new()
             .set_payload(&payload)
+            .set_validity(validity)
             .set_ios(&inputs, &outputs)
             .set_witnesses(&witnesses)
             .set_payload_auth(&payload_auth)
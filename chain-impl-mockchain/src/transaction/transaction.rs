@@ -3,6 +3,7 @@ use super::element::{Balance, BalanceError, TransactionSignDataHash};
 use super::input::{Input, INPUT_SIZE};
 use super::payload::{Payload, PayloadAuthSlice, PayloadSlice};
 use super::transfer::Output;
+use super::validity::ValidityInterval;
 use super::witness::Witness;
 use crate::value::{Value, ValueError};
 use chain_addr::Address;
@@ -34,6 +35,7 @@ impl<P> Debug for Transaction<P> {
             .field("payload", &tx.payload().0)
             .field("nb_inputs", &tx.nb_inputs())
             .field("nb_outputs", &tx.nb_outputs())
+            .field("validity", &tx.validity())
             .field("nb_witnesses", &tx.nb_witnesses())
             .field("total_input_value", &self.total_input())
             .field("total_output_value", &self.total_output())
@@ -224,6 +226,7 @@ impl<'a> InputsWitnessesSlice<'a> {
 pub enum TransactionStructError {
     CannotReadNbInputs,
     CannotReadNbOutputs,
+    ValidityInvalid,
     PayloadInvalid,
     InputsInvalid,
     OutputsInvalid,
@@ -238,6 +241,7 @@ pub(super) struct TransactionStruct {
     pub(super) sz: usize,
     pub(super) nb_inputs: u8,
     pub(super) nb_outputs: u8,
+    pub(super) validity: ValidityInterval,
     pub(super) inputs: usize,
     pub(super) outputs: usize,
     pub(super) witnesses: usize,
@@ -262,6 +266,10 @@ fn get_spine<'a, P: Payload>(slice: &'a [u8]) -> Result<TransactionStruct, Trans
         .get_u8()
         .map_err(|_| TransactionStructError::CannotReadNbOutputs)?;
 
+    // read validity interval
+    let validity =
+        ValidityInterval::read(&mut rb).map_err(|_| TransactionStructError::ValidityInvalid)?;
+
     let inputs_pos = rb.position();
     rb.skip_bytes(nb_inputs as usize * INPUT_SIZE)
         .map_err(|_| TransactionStructError::InputsInvalid)?;
@@ -293,6 +301,7 @@ fn get_spine<'a, P: Payload>(slice: &'a [u8]) -> Result<TransactionStruct, Trans
         sz,
         nb_inputs,
         nb_outputs,
+        validity,
         inputs: inputs_pos,
         outputs: outputs_pos,
         witnesses: witnesses_pos,
@@ -368,6 +377,10 @@ impl<P> Transaction<P> {
         self.tstruct.nb_outputs
     }
 
+    pub fn validity(&self) -> ValidityInterval {
+        self.tstruct.validity
+    }
+
     pub fn total_input(&self) -> Result<Value, ValueError> {
         Value::sum(self.as_slice().inputs().iter().map(|input| input.value()))
     }
@@ -458,6 +471,11 @@ impl<'a, P> TransactionSlice<'a, P> {
         self.tstruct.nb_inputs
     }
 
+    /// The interval of block dates in which this transaction can be applied
+    pub fn validity(&self) -> ValidityInterval {
+        self.tstruct.validity
+    }
+
     pub fn inputs(&self) -> InputsSlice<'a> {
         InputsSlice(
             self.tstruct.nb_inputs,
@@ -1,6 +1,9 @@
+use crate::key::Hash;
 use crate::legacy::OldAddress;
+use crate::merkle::Commitment;
 use crate::value::*;
 use chain_core::mempack::{ReadBuf, ReadError, Readable};
+use typed_bytes::ByteBuilder;
 
 /// Information how tokens are spent.
 /// A value of tokens is sent to the address.
@@ -24,6 +27,33 @@ impl<Address: Readable> Readable for Output<Address> {
     }
 }
 
+impl Output<chain_addr::Address> {
+    pub fn serialize_in(&self, bb: ByteBuilder<Self>) -> ByteBuilder<Self> {
+        bb.bytes(&self.address.to_bytes()).u64(self.value.0)
+    }
+}
+
+impl Output<OldAddress> {
+    pub fn serialize_in(&self, bb: ByteBuilder<Self>) -> ByteBuilder<Self> {
+        let address = self.address.as_ref();
+        bb.u16(address.len() as u16)
+            .bytes(address)
+            .u64(self.value.0)
+    }
+}
+
+impl Commitment for Output<chain_addr::Address> {
+    fn commitment(&self) -> Hash {
+        Hash::hash_bytes(&self.serialize_in(ByteBuilder::new()).finalize_as_vec())
+    }
+}
+
+impl Commitment for Output<OldAddress> {
+    fn commitment(&self) -> Hash {
+        Hash::hash_bytes(&self.serialize_in(ByteBuilder::new()).finalize_as_vec())
+    }
+}
+
 impl std::fmt::Display for Output<chain_addr::Address> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}.{}", self.address.base32(), self.value)
@@ -0,0 +1,136 @@
+use crate::date::BlockDate;
+use chain_core::mempack::{ReadBuf, ReadError, Readable};
+
+const VALID_FROM_FLAG: u8 = 0b01;
+const VALID_UNTIL_FLAG: u8 = 0b10;
+
+/// Interval of block dates in which a transaction is allowed to be applied
+/// to the ledger. Both bounds are inclusive, and an absent bound is
+/// unrestricted.
+///
+/// The interval is part of the transaction authenticated data, so it is
+/// covered by the `TransactionSignDataHash` signed by the witnesses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ValidityInterval {
+    pub valid_from: Option<BlockDate>,
+    pub valid_until: Option<BlockDate>,
+}
+
+impl Default for ValidityInterval {
+    fn default() -> Self {
+        ValidityInterval::unbounded()
+    }
+}
+
+impl ValidityInterval {
+    /// A validity interval with no restriction, valid at any block date
+    pub fn unbounded() -> Self {
+        ValidityInterval {
+            valid_from: None,
+            valid_until: None,
+        }
+    }
+
+    pub fn new(valid_from: Option<BlockDate>, valid_until: Option<BlockDate>) -> Self {
+        ValidityInterval {
+            valid_from,
+            valid_until,
+        }
+    }
+
+    /// A validity interval only bounded by an expiry date
+    pub fn until(valid_until: BlockDate) -> Self {
+        ValidityInterval::new(None, Some(valid_until))
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.valid_from.is_none() && self.valid_until.is_none()
+    }
+
+    /// Check if the given block date is before the start of the interval
+    pub fn is_before(&self, date: BlockDate) -> bool {
+        self.valid_from.is_some_and(|from| date < from)
+    }
+
+    /// Check if the given block date is after the end of the interval
+    pub fn is_expired(&self, date: BlockDate) -> bool {
+        self.valid_until.is_some_and(|until| date > until)
+    }
+
+    pub fn contains(&self, date: BlockDate) -> bool {
+        !self.is_before(date) && !self.is_expired(date)
+    }
+
+    pub(super) fn serialize_in(&self, data: &mut Vec<u8>) {
+        let mut flags = 0;
+        if self.valid_from.is_some() {
+            flags |= VALID_FROM_FLAG;
+        }
+        if self.valid_until.is_some() {
+            flags |= VALID_UNTIL_FLAG;
+        }
+        data.push(flags);
+        for date in self.valid_from.iter().chain(self.valid_until.iter()) {
+            data.extend_from_slice(&date.epoch.to_be_bytes());
+            data.extend_from_slice(&date.slot_id.to_be_bytes());
+        }
+    }
+}
+
+fn read_block_date<'a>(buf: &mut ReadBuf<'a>) -> Result<BlockDate, ReadError> {
+    let epoch = buf.get_u32()?;
+    let slot_id = buf.get_u32()?;
+    Ok(BlockDate { epoch, slot_id })
+}
+
+impl Readable for ValidityInterval {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        let flags = buf.get_u8()?;
+        if flags & !(VALID_FROM_FLAG | VALID_UNTIL_FLAG) != 0 {
+            return Err(ReadError::UnknownTag(flags as u32));
+        }
+        let valid_from = if flags & VALID_FROM_FLAG != 0 {
+            Some(read_block_date(buf)?)
+        } else {
+            None
+        };
+        let valid_until = if flags & VALID_UNTIL_FLAG != 0 {
+            Some(read_block_date(buf)?)
+        } else {
+            None
+        };
+        Ok(ValidityInterval {
+            valid_from,
+            valid_until,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    #[quickcheck]
+    fn validity_interval_serialization_bijection(validity: ValidityInterval) -> bool {
+        let mut data = Vec::new();
+        validity.serialize_in(&mut data);
+        let mut buf = ReadBuf::from(&data);
+        let decoded = ValidityInterval::read(&mut buf).unwrap();
+        buf.expect_end().unwrap();
+        decoded == validity
+    }
+
+    #[quickcheck]
+    fn validity_interval_contains(validity: ValidityInterval, date: BlockDate) -> bool {
+        let after_from = validity.valid_from.is_none_or(|from| from <= date);
+        let before_until = validity.valid_until.is_none_or(|until| date <= until);
+        validity.contains(date) == (after_from && before_until)
+    }
+
+    #[test]
+    fn validity_interval_unknown_flags() {
+        let data = [0b100u8];
+        assert!(ValidityInterval::read(&mut ReadBuf::from(&data)).is_err());
+    }
+}
@@ -5,6 +5,9 @@
 //!
 
 use crate::fragment::FragmentId;
+use crate::key::Hash;
+use crate::ledger::diff::{hamt_apply_entry_by, hamt_diff, DiffError, EntryDiff, MapDiff};
+use crate::merkle::{Commitment, MerkleProof, MerkleTree};
 use crate::transaction::{Output, TransactionIndex};
 use chain_addr::Address;
 use sparse_array::{FastSparseArray, FastSparseArrayBuilder, FastSparseArrayIter};
@@ -62,6 +65,13 @@ impl<OutAddress: Clone> TransactionUnspents<OutAddress> {
         TransactionUnspents(sa.build())
     }
 
+    pub fn to_outputs(&self) -> Vec<(TransactionIndex, Output<OutAddress>)> {
+        self.0
+            .iter()
+            .map(|(index, output)| (index, output.clone()))
+            .collect()
+    }
+
     pub fn remove_input(
         &self,
         index: TransactionIndex,
@@ -77,7 +87,18 @@ impl<OutAddress: Clone> TransactionUnspents<OutAddress> {
 
 /// Ledger of UTXO
 #[derive(Clone, PartialEq, Eq)]
-pub struct Ledger<OutAddress>(Hamt<DefaultHasher, FragmentId, TransactionUnspents<OutAddress>>);
+pub struct Ledger<OutAddress> {
+    utxos: Hamt<DefaultHasher, FragmentId, TransactionUnspents<OutAddress>>,
+    state_tree: MerkleTree,
+}
+
+/// Path of an output in the state tree of the UTXO ledger
+pub(crate) fn output_path(tid: &FragmentId, index: TransactionIndex) -> Hash {
+    let mut bytes = Vec::with_capacity(33);
+    bytes.extend_from_slice(tid.as_ref());
+    bytes.push(index);
+    Hash::hash_bytes(&bytes)
+}
 
 pub struct Iter<'a, V> {
     hamt_iter: HamtIter<'a, FragmentId, TransactionUnspents<V>>,
@@ -107,14 +128,14 @@ pub struct Entry<'a, OutputAddress> {
 impl<OutAddress> Ledger<OutAddress> {
     pub fn iter<'a>(&'a self) -> Iter<'a, OutAddress> {
         Iter {
-            hamt_iter: self.0.iter(),
+            hamt_iter: self.utxos.iter(),
             unspents_iter: None,
         }
     }
 
     pub fn values<'a>(&'a self) -> Values<'a, OutAddress> {
         Values {
-            hamt_iter: self.0.iter(),
+            hamt_iter: self.utxos.iter(),
             unspents_iter: None,
         }
     }
@@ -124,7 +145,7 @@ impl<OutAddress> Ledger<OutAddress> {
         tid: &FragmentId,
         index: &TransactionIndex,
     ) -> Option<Entry<'a, OutAddress>> {
-        self.0
+        self.utxos
             .lookup(tid)
             .and_then(|unspent| unspent.0.get(*index))
             .map(|output| Entry {
@@ -135,7 +156,7 @@ impl<OutAddress> Ledger<OutAddress> {
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.utxos.is_empty()
     }
 }
 
@@ -183,10 +204,92 @@ impl<'a, V> Iterator for Iter<'a, V> {
     }
 }
 
-impl<OutAddress: Clone> Ledger<OutAddress> {
+impl<OutAddress: Clone> Ledger<OutAddress>
+where
+    Output<OutAddress>: Commitment,
+{
     /// Create a new empty UTXO Ledger
     pub fn new() -> Self {
-        Ledger(Hamt::new())
+        Ledger {
+            utxos: Hamt::new(),
+            state_tree: MerkleTree::new(),
+        }
+    }
+
+    /// update the state tree for the outputs of a transaction which have
+    /// been changed in the given utxos
+    fn with_utxos(
+        &self,
+        utxos: Hamt<DefaultHasher, FragmentId, TransactionUnspents<OutAddress>>,
+        tid: &FragmentId,
+    ) -> Self {
+        let old = self.utxos.lookup(tid).map(|unspents| &unspents.0);
+        let new = utxos.lookup(tid).map(|unspents| &unspents.0);
+
+        let removed = old
+            .into_iter()
+            .flat_map(|unspents| unspents.iter())
+            .filter(|(index, _)| !new.is_some_and(|new| new.contains_key(*index)));
+        let added = new
+            .into_iter()
+            .flat_map(|unspents| unspents.iter())
+            .filter(|(index, _)| !old.is_some_and(|old| old.contains_key(*index)));
+
+        let state_tree = removed.fold(self.state_tree.clone(), |tree, (index, _)| {
+            tree.remove(&output_path(tid, index))
+        });
+        let state_tree = added.fold(state_tree, |tree, (index, output)| {
+            tree.insert(output_path(tid, index), &output.commitment())
+        });
+
+        Ledger { utxos, state_tree }
+    }
+
+    /// Outputs changed from this ledger to the `other` ledger, by transaction
+    pub(crate) fn diff(
+        &self,
+        other: &Self,
+    ) -> MapDiff<FragmentId, Vec<(TransactionIndex, Output<OutAddress>)>>
+    where
+        OutAddress: PartialEq,
+    {
+        // spent outputs are kept in the sparse arrays until they are shrunk,
+        // so the unspent outputs are compared rather than the arrays
+        let mut diff = hamt_diff(&self.utxos, &other.utxos).map_values(|u| u.to_outputs());
+        diff.retain(|_, entry| match entry {
+            EntryDiff::Changed { old, new } => old != new,
+            _ => true,
+        });
+        diff
+    }
+
+    /// Apply the changes of the outputs, checking the current outputs
+    pub(crate) fn apply_diff(
+        &self,
+        diff: &MapDiff<FragmentId, Vec<(TransactionIndex, Output<OutAddress>)>>,
+    ) -> Result<Self, DiffError>
+    where
+        OutAddress: PartialEq,
+    {
+        diff.iter().try_fold(self.clone(), |ledger, (tid, entry)| {
+            let entry = entry
+                .clone()
+                .map(|outs| TransactionUnspents::from_outputs(&outs));
+            let utxos = hamt_apply_entry_by(&ledger.utxos, tid, &entry, |current, expected| {
+                current.to_outputs() == expected.to_outputs()
+            })?;
+            Ok(ledger.with_utxos(utxos, tid))
+        })
+    }
+
+    /// root of the merkle tree of all the unspent outputs
+    pub fn state_root(&self) -> Hash {
+        self.state_tree.root()
+    }
+
+    /// Proof that the output is unspent, if it is
+    pub fn proof(&self, tid: &FragmentId, index: TransactionIndex) -> Option<MerkleProof> {
+        self.state_tree.proof(&output_path(tid, index))
     }
 
     /// Add new outputs associated with a specific transaction
@@ -200,8 +303,8 @@ impl<OutAddress: Clone> Ledger<OutAddress> {
         assert!(outs.len() > 0);
         assert!(outs.len() < 255);
         let b = TransactionUnspents::from_outputs(outs);
-        let next = self.0.insert(tid.clone(), b)?;
-        Ok(Ledger(next))
+        let next = self.utxos.insert(*tid, b)?;
+        Ok(self.with_utxos(next, tid))
     }
 
     /// Spend a specific index from the transaction
@@ -211,16 +314,17 @@ impl<OutAddress: Clone> Ledger<OutAddress> {
         tid: &FragmentId,
         index: TransactionIndex,
     ) -> Result<(Self, Output<OutAddress>), Error> {
-        let (treemap, output) = match self.0.lookup(tid) {
+        let (treemap, output) = match self.utxos.lookup(tid) {
             None => Err(Error::TransactionNotFound),
             Some(out) => out.remove_input(index),
         }?;
 
-        if treemap.0.is_empty() {
-            Ok((Ledger(self.0.remove(tid)?), output))
+        let next = if treemap.0.is_empty() {
+            self.utxos.remove(tid)?
         } else {
-            Ok((Ledger(self.0.replace(tid, treemap)?.0), output))
-        }
+            self.utxos.replace(tid, treemap)?.0
+        };
+        Ok((self.with_utxos(next, tid), output))
     }
 
     pub fn remove_multiple(
@@ -228,7 +332,7 @@ impl<OutAddress: Clone> Ledger<OutAddress> {
         tid: &FragmentId,
         indices: &[TransactionIndex],
     ) -> Result<(Self, Vec<Output<OutAddress>>), Error> {
-        let (treemap, outputs) = match self.0.lookup(tid) {
+        let (treemap, outputs) = match self.utxos.lookup(tid) {
             None => Err(Error::TransactionNotFound),
             Some(out) => {
                 let mut treemap = out.clone();
@@ -242,17 +346,20 @@ impl<OutAddress: Clone> Ledger<OutAddress> {
             }
         }?;
 
-        if treemap.0.is_empty() {
-            Ok((Ledger(self.0.remove(tid)?), outputs))
+        let next = if treemap.0.is_empty() {
+            self.utxos.remove(tid)?
         } else {
-            Ok((Ledger(self.0.replace(tid, treemap)?.0), outputs))
-        }
+            self.utxos.replace(tid, treemap)?.0
+        };
+        Ok((self.with_utxos(next, tid), outputs))
     }
 }
 
 impl<OutAddress: Clone>
     std::iter::FromIterator<(FragmentId, Vec<(TransactionIndex, Output<OutAddress>)>)>
     for Ledger<OutAddress>
+where
+    Output<OutAddress>: Commitment,
 {
     fn from_iter<
         I: IntoIterator<Item = (FragmentId, Vec<(TransactionIndex, Output<OutAddress>)>)>,
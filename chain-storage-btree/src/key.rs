@@ -0,0 +1,71 @@
+use btree::Storeable;
+use chain_core::property::{BlockId, Deserialize};
+
+/// Kind of entry stored under a key, so blocks and their info can share the same index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyKind {
+    Block = 0,
+    BlockInfo = 1,
+}
+
+/// Index key: the kind of entry followed by the serialized block id, padded with zeros to the
+/// key buffer size of the index (which needs to be a multiple of 8)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct StoreKey(Box<[u8]>);
+
+impl StoreKey {
+    pub(crate) fn new<Id: BlockId>(kind: KeyKind, id: &Id, key_size: usize) -> Self {
+        let id = id
+            .serialize_as_vec()
+            .expect("block id serialization failed");
+        assert!(
+            id.len() < key_size,
+            "block id doesn't fit in the index keys"
+        );
+
+        let mut bytes = vec![0u8; key_size];
+        bytes[0] = kind as u8;
+        bytes[1..=id.len()].copy_from_slice(&id);
+        StoreKey(bytes.into_boxed_slice())
+    }
+
+    /// smallest key of the given kind, to start a scan over all the entries of that kind
+    pub(crate) fn first(kind: KeyKind, key_size: usize) -> Self {
+        let mut bytes = vec![0u8; key_size];
+        bytes[0] = kind as u8;
+        StoreKey(bytes.into_boxed_slice())
+    }
+
+    pub(crate) fn is_kind(&self, kind: KeyKind) -> bool {
+        self.0[0] == kind as u8
+    }
+
+    pub(crate) fn id<Id: BlockId>(&self) -> Result<Id, <Id as Deserialize>::Error> {
+        Id::deserialize(&self.0[1..])
+    }
+
+    /// size of the keys needed to store the ids of the given type
+    pub(crate) fn size_for<Id: BlockId>() -> usize {
+        let id_size = Id::zero()
+            .serialize_as_vec()
+            .expect("block id serialization failed")
+            .len();
+
+        // one byte for the kind, rounded up to the next multiple of 8
+        (id_size + 1).div_ceil(8) * 8
+    }
+}
+
+impl<'a> Storeable<'a> for StoreKey {
+    type Error = std::io::Error;
+    type Output = Self;
+
+    fn write(&self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        buf[..self.0.len()].copy_from_slice(&self.0);
+        Ok(())
+    }
+
+    fn read(buf: &'a [u8]) -> Result<Self::Output, Self::Error> {
+        Ok(StoreKey(buf.into()))
+    }
+}
@@ -0,0 +1,389 @@
+mod key;
+
+use btree::{BTreeStore, BTreeStoreError};
+use chain_core::packer::Codec;
+use chain_core::property::{Block, BlockId};
+use chain_storage::{
+    error::Error,
+    store::{BackLink, BlockInfo, BlockStore},
+};
+use key::{KeyKind, StoreKey};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const PAGE_SIZE: u16 = 4096;
+const TAGS_FILE: &str = "tags";
+const TAGS_TMP_FILE: &str = "tags.tmp";
+const CREATED_FILE: &str = "created";
+
+fn backend_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
+    Error::BackendError(Box::new(err))
+}
+
+/// `BlockStore` backed by an append only `BTreeStore`. Blocks and their `BlockInfo` are
+/// appended to the flatfile and indexed by block id. Tags are few and small, so they are kept
+/// in memory and persisted in a separate file that is rewritten on every update.
+pub struct BTreeBlockStore<B>
+where
+    B: Block,
+{
+    store: BTreeStore<StoreKey>,
+    key_size: usize,
+    tags: HashMap<String, B::Id>,
+    path: PathBuf,
+}
+
+impl<B> BTreeBlockStore<B>
+where
+    B: Block,
+{
+    /// open the store in the given directory, creating it if it doesn't exist
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let key_size = StoreKey::size_for::<B::Id>();
+
+        let (store, tags) = if path.join(CREATED_FILE).is_file() {
+            let store = BTreeStore::open(&path).map_err(backend_error)?;
+            let tags = read_tags(&fs::read(path.join(TAGS_FILE)).map_err(backend_error)?)?;
+            (store, tags)
+        } else {
+            // a store whose creation was interrupted never held any block, so it is
+            // created again from scratch
+            let store = BTreeStore::new(&path, key_size.try_into().unwrap(), PAGE_SIZE)
+                .map_err(backend_error)?;
+            let tags = HashMap::new();
+            write_tags::<B::Id>(&path, &tags)?;
+            // the marker is written last, once all the files of the store are durable
+            fs::File::create(path.join(CREATED_FILE))
+                .and_then(|file| file.sync_all())
+                .map_err(backend_error)?;
+            (store, tags)
+        };
+
+        Ok(BTreeBlockStore {
+            store,
+            key_size,
+            tags,
+            path,
+        })
+    }
+
+    fn key(&self, kind: KeyKind, block_hash: &B::Id) -> StoreKey {
+        StoreKey::new(kind, block_hash, self.key_size)
+    }
+
+    fn is_tagged(&self, block_hash: &B::Id) -> bool {
+        self.tags.values().any(|tagged| tagged == block_hash)
+    }
+}
+
+impl<B> BlockStore for BTreeBlockStore<B>
+where
+    B: Block,
+{
+    type Block = B;
+
+    fn put_block_internal(&mut self, block: &B, block_info: BlockInfo<B::Id>) -> Result<(), Error> {
+        if self.block_exists(&block_info.block_hash)? {
+            return Err(Error::BlockAlreadyPresent);
+        }
+
+        let block_bytes = block.serialize_as_vec().unwrap();
+        let info_bytes = serialize_block_info(&block_info)?;
+
+        // both entries are inserted in the same transaction
+        self.store
+            .insert_many(vec![
+                (
+                    self.key(KeyKind::Block, &block_info.block_hash),
+                    block_bytes,
+                ),
+                (
+                    self.key(KeyKind::BlockInfo, &block_info.block_hash),
+                    info_bytes,
+                ),
+            ])
+            .map_err(|err| match err {
+                BTreeStoreError::DuplicatedKey => Error::BlockAlreadyPresent,
+                err => backend_error(err),
+            })
+    }
+
+    fn get_block(&self, block_hash: &B::Id) -> Result<(B, BlockInfo<B::Id>), Error> {
+        let block = self
+            .store
+            .get(&self.key(KeyKind::Block, block_hash))
+            .map_err(backend_error)?
+            .ok_or(Error::BlockNotFound)?;
+
+        let block = B::deserialize(&block[..]).map_err(backend_error)?;
+        let info = self.get_block_info(block_hash)?;
+
+        Ok((block, info))
+    }
+
+    fn get_block_info(&self, block_hash: &B::Id) -> Result<BlockInfo<B::Id>, Error> {
+        let info = self
+            .store
+            .get(&self.key(KeyKind::BlockInfo, block_hash))
+            .map_err(backend_error)?
+            .ok_or(Error::BlockNotFound)?;
+
+        deserialize_block_info(block_hash.clone(), &info)
+    }
+
+    fn remove_block(&mut self, block_hash: &B::Id) -> Result<(), Error> {
+        if self.is_tagged(block_hash) {
+            return Err(Error::BlockIsTagged);
+        }
+
+        let keys = [
+            self.key(KeyKind::Block, block_hash),
+            self.key(KeyKind::BlockInfo, block_hash),
+        ];
+
+        self.store
+            .delete_many(keys.iter())
+            .map_err(|err| match err {
+                BTreeStoreError::KeyNotFound => Error::BlockNotFound,
+                err => backend_error(err),
+            })
+    }
+
+    fn prune_below(&mut self, depth: u64) -> Result<u64, Error> {
+        let mut keys = vec![];
+
+        let infos = self
+            .store
+            .prefix(StoreKey::first(KeyKind::BlockInfo, self.key_size), |key| {
+                key.is_kind(KeyKind::BlockInfo)
+            });
+
+        for entry in infos {
+            let (key, info) = entry.map_err(backend_error)?;
+            let block_hash: B::Id = key.id().map_err(backend_error)?;
+            let info = deserialize_block_info(block_hash.clone(), &info)?;
+
+            if info.depth < depth && !self.is_tagged(&block_hash) {
+                keys.push(self.key(KeyKind::Block, &block_hash));
+                keys.push(key);
+            }
+        }
+
+        // the blobs are not reclaimed until the store is compacted
+        self.store.delete_many(keys.iter()).map_err(backend_error)?;
+
+        Ok((keys.len() / 2) as u64)
+    }
+
+    fn put_tag(&mut self, tag_name: &str, block_hash: &B::Id) -> Result<(), Error> {
+        if !self.block_exists(block_hash)? {
+            return Err(Error::BlockNotFound);
+        }
+
+        let mut tags = self.tags.clone();
+        tags.insert(tag_name.to_owned(), block_hash.clone());
+        write_tags::<B::Id>(&self.path, &tags)?;
+
+        self.tags = tags;
+        Ok(())
+    }
+
+    fn get_tag(&self, tag_name: &str) -> Result<Option<B::Id>, Error> {
+        Ok(self.tags.get(tag_name).cloned())
+    }
+}
+
+fn serialize_block_info<Id: BlockId>(block_info: &BlockInfo<Id>) -> Result<Vec<u8>, Error> {
+    let mut codec = Codec::new(vec![]);
+    codec.put_u64(block_info.depth).map_err(backend_error)?;
+    codec
+        .put_u8(block_info.back_links.len().try_into().unwrap())
+        .map_err(backend_error)?;
+    for back_link in block_info.back_links.iter() {
+        codec.put_u64(back_link.distance).map_err(backend_error)?;
+        back_link.block_hash.serialize(&mut codec).unwrap();
+    }
+    Ok(codec.into_inner())
+}
+
+fn deserialize_block_info<Id: BlockId>(
+    block_hash: Id,
+    bytes: &[u8],
+) -> Result<BlockInfo<Id>, Error> {
+    let mut codec = Codec::new(bytes);
+    let depth = codec.get_u64().map_err(backend_error)?;
+    let nb_back_links = codec.get_u8().map_err(backend_error)?;
+
+    let mut back_links = Vec::with_capacity(nb_back_links.into());
+    for _ in 0..nb_back_links {
+        let distance = codec.get_u64().map_err(backend_error)?;
+        let block_hash = Id::deserialize(&mut codec).map_err(backend_error)?;
+        back_links.push(BackLink {
+            distance,
+            block_hash,
+        });
+    }
+
+    Ok(BlockInfo {
+        block_hash,
+        depth,
+        back_links,
+    })
+}
+
+fn read_tags<Id: BlockId>(bytes: &[u8]) -> Result<HashMap<String, Id>, Error> {
+    let mut codec = Codec::new(bytes);
+    let nb_tags = codec.get_u32().map_err(backend_error)?;
+
+    let mut tags = HashMap::new();
+    for _ in 0..nb_tags {
+        let name_len = codec.get_u32().map_err(backend_error)?;
+        let name = codec
+            .get_bytes(name_len.try_into().unwrap())
+            .map_err(backend_error)?;
+        let name = String::from_utf8(name).map_err(backend_error)?;
+        let block_hash = Id::deserialize(&mut codec).map_err(backend_error)?;
+        tags.insert(name, block_hash);
+    }
+
+    Ok(tags)
+}
+
+/// write the tags to a temporary file and then move it over the old one, so a crash in the
+/// middle doesn't leave a partially written file
+fn write_tags<Id: BlockId>(path: &Path, tags: &HashMap<String, Id>) -> Result<(), Error> {
+    let mut codec = Codec::new(vec![]);
+    codec
+        .put_u32(tags.len().try_into().unwrap())
+        .map_err(backend_error)?;
+    for (name, block_hash) in tags.iter() {
+        codec
+            .put_u32(name.len().try_into().unwrap())
+            .map_err(backend_error)?;
+        codec.put_bytes(name.as_bytes()).map_err(backend_error)?;
+        block_hash.serialize(&mut codec).unwrap();
+    }
+
+    let tmp_path = path.join(TAGS_TMP_FILE);
+    let mut file = fs::File::create(&tmp_path).map_err(backend_error)?;
+    file.write_all(&codec.into_inner()).map_err(backend_error)?;
+    file.sync_all().map_err(backend_error)?;
+
+    fs::rename(&tmp_path, path.join(TAGS_FILE)).map_err(backend_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain_storage::store::testing::Block;
+    use rand_core::OsRng;
+
+    #[test]
+    pub fn put_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+        chain_storage::store::testing::test_put_get(&mut store);
+    }
+
+    #[test]
+    pub fn nth_ancestor() {
+        let mut rng = OsRng;
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+        chain_storage::store::testing::test_nth_ancestor(&mut rng, &mut store);
+    }
+
+    #[test]
+    pub fn iterate_range() {
+        let mut rng = OsRng;
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+        chain_storage::store::testing::test_iterate_range(&mut rng, &mut store);
+    }
+
+    #[test]
+    pub fn get_block_by_chain_length() {
+        let mut rng = OsRng;
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+        chain_storage::store::testing::test_get_block_by_chain_length(&mut rng, &mut store);
+    }
+
+    #[test]
+    pub fn remove_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+        chain_storage::store::testing::test_remove_block(&mut store);
+    }
+
+    #[test]
+    pub fn prune_below() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+        chain_storage::store::testing::test_prune_below(&mut store);
+    }
+
+    #[test]
+    pub fn interrupted_creation() {
+        let dir = tempfile::tempdir().unwrap();
+        let genesis_block = Block::genesis(None);
+
+        // the process stopped after creating the btree, before the tags and the marker
+        BTreeStore::<StoreKey>::new(
+            dir.path(),
+            StoreKey::size_for::<<Block as chain_core::property::Block>::Id>()
+                .try_into()
+                .unwrap(),
+            PAGE_SIZE,
+        )
+        .unwrap();
+
+        {
+            let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+            assert_eq!(store.get_tag("tip").unwrap(), None);
+            store.put_block(&genesis_block).unwrap();
+        }
+
+        let store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+        assert_eq!(
+            store.get_block(&genesis_block.id()).unwrap().0,
+            genesis_block
+        );
+    }
+
+    #[test]
+    pub fn reopen() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let genesis_block = Block::genesis(Some(vec![1, 2, 3].into_boxed_slice()));
+        let child = genesis_block.make_child(None);
+
+        {
+            let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+            store.put_block(&genesis_block).unwrap();
+            store.put_block(&child).unwrap();
+            store.put_tag("tip", &child.id()).unwrap();
+        }
+
+        let mut store = BTreeBlockStore::<Block>::new(dir.path()).unwrap();
+
+        assert_eq!(store.get_tag("tip").unwrap(), Some(child.id()));
+        let (block, info) = store.get_block(&child.id()).unwrap();
+        assert_eq!(block, child);
+        assert_eq!(info.depth, 2);
+        assert_eq!(info.parent_id(), genesis_block.id());
+        assert_eq!(
+            store.get_block(&genesis_block.id()).unwrap().0,
+            genesis_block
+        );
+
+        match store.put_block(&child) {
+            Err(Error::BlockAlreadyPresent) => (),
+            _ => panic!("the block should be already present"),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rand_core::{OsRng, RngCore};
+
+use chain_core::property::Block as _;
+use chain_storage::store::{testing::Block, BlockStore};
+use chain_storage_sqlite::{IndexMode, SQLiteBlockStore};
+
+const BLOCK_DATA_LENGTH: usize = 1024;
+const NB_BLOCKS: usize = 10_000;
+
+const INDEX_MODES: [(&str, IndexMode); 2] = [
+    ("in_memory", IndexMode::InMemory),
+    ("lazy", IndexMode::Lazy { cache_size: 1024 }),
+];
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut rng = OsRng;
+    let mut block_data = [0; BLOCK_DATA_LENGTH];
+
+    rng.fill_bytes(&mut block_data);
+    let genesis_block = Block::genesis(Some(Box::new(block_data)));
+
+    let mut blocks = vec![genesis_block];
+    while blocks.len() < NB_BLOCKS {
+        let last_block = blocks.get(rng.next_u32() as usize % blocks.len()).unwrap();
+        rng.fill_bytes(&mut block_data);
+        let block = last_block.make_child(Some(Box::new(block_data)));
+        blocks.push(block);
+    }
+
+    let tempdir = tempfile::TempDir::new().unwrap();
+    let path = {
+        let mut path = tempdir.path().to_path_buf();
+        path.push("test.sqlite");
+        path
+    };
+    SQLiteBlockStore::<Block>::new(&path)
+        .put_blocks(&blocks)
+        .unwrap();
+
+    for (name, index_mode) in INDEX_MODES.iter() {
+        c.bench_function(&format!("open_{}", name), |b| {
+            b.iter(|| SQLiteBlockStore::<Block>::with_index_mode(&path, *index_mode))
+        });
+
+        let store = SQLiteBlockStore::<Block>::with_index_mode(&path, *index_mode);
+
+        c.bench_function(&format!("get_block_{}", name), |b| {
+            b.iter_batched(
+                || {
+                    blocks
+                        .get(rng.next_u32() as usize % blocks.len())
+                        .unwrap()
+                        .id()
+                },
+                |block_id| store.get_block(&block_id).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -1,20 +1,75 @@
+use lru_cache::LruCache;
 use std::collections::HashMap;
+use std::sync::Mutex;
 
-pub(crate) struct DbIndex<K, V>(HashMap<K, V>);
+/// Result of looking a key up in a `DbIndex`.
+pub(crate) enum Lookup<V> {
+    Found(V),
+    /// The key is not in the table.
+    Absent,
+    /// The key is not in the cache, the table has to be queried.
+    Unknown,
+}
+
+/// Row ids of a table by key. The index holds either every row of the
+/// table, or only the most recently used ones.
+pub(crate) enum DbIndex<K, V>
+where
+    K: std::hash::Hash + std::cmp::Eq,
+{
+    Complete(HashMap<K, V>),
+    Cached(Mutex<LruCache<K, V>>),
+}
 
 impl<K, V> DbIndex<K, V>
 where
     K: std::hash::Hash + std::cmp::Eq,
+    V: Clone,
 {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        DbIndex::Complete(HashMap::new())
     }
 
-    pub fn get(&self, key: &K) -> Option<&V> {
-        self.0.get(key)
+    pub fn cached(capacity: usize) -> Self {
+        DbIndex::Cached(Mutex::new(LruCache::new(capacity)))
+    }
+
+    pub fn get(&self, key: &K) -> Lookup<V> {
+        match self {
+            DbIndex::Complete(map) => match map.get(key) {
+                Some(value) => Lookup::Found(value.clone()),
+                None => Lookup::Absent,
+            },
+            DbIndex::Cached(cache) => match cache.lock().unwrap().get_mut(key) {
+                Some(value) => Lookup::Found(value.clone()),
+                None => Lookup::Unknown,
+            },
+        }
     }
 
     pub fn add(&mut self, key: K, value: V) {
-        self.0.insert(key, value);
+        match self {
+            DbIndex::Complete(map) => {
+                map.insert(key, value);
+            }
+            DbIndex::Cached(cache) => {
+                cache.get_mut().unwrap().insert(key, value);
+            }
+        }
+    }
+
+    /// Remember a value that was looked up in the table. Complete
+    /// indexes already have it.
+    pub fn cache(&self, key: K, value: V) {
+        if let DbIndex::Cached(cache) = self {
+            cache.lock().unwrap().insert(key, value);
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        match self {
+            DbIndex::Complete(map) => map.remove(key),
+            DbIndex::Cached(cache) => cache.get_mut().unwrap().remove(key),
+        }
     }
 }
@@ -3,10 +3,10 @@ mod index;
 use chain_core::property::{Block, BlockId, Serialize};
 use chain_storage::{
     error::Error,
-    store::{BackLink, BlockInfo, BlockStore},
+    store::{compute_block_info, BackLink, BlockInfo, BlockStore},
 };
-use index::DbIndex;
-use rusqlite::types::Value;
+use index::{DbIndex, Lookup};
+use rusqlite::{types::Value, Connection, OptionalExtension};
 use std::{
     path::Path,
     sync::{Arc, RwLock},
@@ -21,6 +21,14 @@ enum IndexError {
     BlockExists,
     #[error("could not find block")]
     BlockNotFound,
+    #[error("cannot read from sqlite: {0}")]
+    SQLiteError(rusqlite::Error),
+}
+
+impl std::convert::From<rusqlite::Error> for IndexError {
+    fn from(e: rusqlite::Error) -> Self {
+        IndexError::SQLiteError(e)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -60,55 +68,155 @@ where
         }
     }
 
-    pub fn get_block(&self, key: &B::Id) -> Option<&RowId> {
-        self.blocks_index.get(key)
+    pub fn cached(cache_size: usize) -> Self {
+        Self {
+            blocks_index: DbIndex::cached(cache_size),
+            block_info_index: DbIndex::cached(cache_size),
+            tags_index: DbIndex::cached(cache_size),
+        }
     }
 
-    pub fn add_block_check(&self, block_id: &B::Id) -> IndexResult {
-        if self.blocks_index.get(block_id).is_some()
-            || self.block_info_index.get(block_id).is_some()
+    pub fn get_block(&self, conn: &Connection, key: &B::Id) -> Result<Option<RowId>, IndexError> {
+        lookup(
+            &self.blocks_index,
+            conn,
+            "select rowid from Blocks where hash = ?",
+            key,
+            || Value::Blob(key.serialize_as_vec().unwrap()),
+        )
+    }
+
+    pub fn add_block_check(&self, conn: &Connection, block_id: &B::Id) -> IndexResult {
+        if self.get_block(conn, block_id)?.is_some()
+            || self.get_block_info(conn, block_id)?.is_some()
         {
             return Err(IndexError::BlockExists);
         }
         Ok(())
     }
 
-    pub fn add_block(&mut self, block_id: B::Id, row_id: RowId) -> IndexResult {
-        self.add_block_check(&block_id)?;
+    pub fn add_block(&mut self, conn: &Connection, block_id: B::Id, row_id: RowId) -> IndexResult {
+        self.add_block_check(conn, &block_id)?;
         self.blocks_index.add(block_id, row_id);
         Ok(())
     }
 
-    pub fn get_block_info(&self, key: &B::Id) -> Option<&RowId> {
-        self.block_info_index.get(key)
+    pub fn get_block_info(
+        &self,
+        conn: &Connection,
+        key: &B::Id,
+    ) -> Result<Option<RowId>, IndexError> {
+        lookup(
+            &self.block_info_index,
+            conn,
+            "select rowid from BlockInfo where hash = ?",
+            key,
+            || Value::Blob(key.serialize_as_vec().unwrap()),
+        )
     }
 
-    pub fn add_block_info(&mut self, block_id: B::Id, row_id: RowId) -> IndexResult {
-        if self.get_block(&block_id).is_none() {
+    pub fn add_block_info(
+        &mut self,
+        conn: &Connection,
+        block_id: B::Id,
+        row_id: RowId,
+    ) -> IndexResult {
+        if self.get_block(conn, &block_id)?.is_none() {
             return Err(IndexError::BlockNotFound);
         }
         self.block_info_index.add(block_id, row_id);
         Ok(())
     }
 
-    pub fn get_tag(&self, tag: &String) -> Option<&RowId> {
-        self.tags_index.get(tag)
+    /// Add the rows of a block that was just inserted, after checking
+    /// with `add_block_check` that it wasn't there already.
+    pub fn add_inserted_block(
+        &mut self,
+        block_id: B::Id,
+        block_row_id: RowId,
+        block_info_row_id: RowId,
+    ) {
+        self.blocks_index.add(block_id.clone(), block_row_id);
+        self.block_info_index.add(block_id, block_info_row_id);
+    }
+
+    pub fn remove_block(&mut self, block_id: &B::Id) {
+        self.blocks_index.remove(block_id);
+        self.block_info_index.remove(block_id);
+    }
+
+    pub fn get_tag(&self, conn: &Connection, tag: &String) -> Result<Option<RowId>, IndexError> {
+        lookup(
+            &self.tags_index,
+            conn,
+            "select rowid from Tags where name = ?",
+            tag,
+            || Value::Text(tag.clone()),
+        )
     }
 
-    pub fn add_tag_check(&self, block_id: &B::Id) -> IndexResult {
-        if self.get_block(block_id).is_none() {
+    pub fn add_tag_check(&self, conn: &Connection, block_id: &B::Id) -> IndexResult {
+        if self.get_block(conn, block_id)?.is_none() {
             return Err(IndexError::BlockNotFound);
         }
         Ok(())
     }
 
-    pub fn add_tag(&mut self, tag: String, block_id: &B::Id, row_id: RowId) -> IndexResult {
-        self.add_tag_check(block_id)?;
+    pub fn add_tag(
+        &mut self,
+        conn: &Connection,
+        tag: String,
+        block_id: &B::Id,
+        row_id: RowId,
+    ) -> IndexResult {
+        self.add_tag_check(conn, block_id)?;
         self.tags_index.add(tag, row_id);
         Ok(())
     }
 }
 
+/// Get the row id of `key`, querying the table if the index doesn't
+/// know about it.
+fn lookup<K, F>(
+    index: &DbIndex<K, RowId>,
+    conn: &Connection,
+    query: &str,
+    key: &K,
+    param: F,
+) -> Result<Option<RowId>, IndexError>
+where
+    K: std::hash::Hash + std::cmp::Eq + Clone,
+    F: FnOnce() -> Value,
+{
+    match index.get(key) {
+        Lookup::Found(row_id) => Ok(Some(row_id)),
+        Lookup::Absent => Ok(None),
+        Lookup::Unknown => {
+            let row_id = conn
+                .prepare_cached(query)?
+                .query_row([param()], |row| row.get(0))
+                .optional()?;
+            if let Some(row_id) = row_id {
+                index.cache(key.clone(), row_id);
+            }
+            Ok(row_id)
+        }
+    }
+}
+
+/// How the store finds the rows of the blocks, block infos and tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexMode {
+    /// Load the row ids of the whole store in memory when opening it.
+    /// Lookups don't touch the database, but opening takes time and
+    /// memory proportional to the length of the chain.
+    InMemory,
+    /// Look the row ids up through the SQLite indexes, keeping the most
+    /// recently used ones in a cache of `cache_size` entries per table.
+    /// Opening takes constant time and memory.
+    Lazy { cache_size: usize },
+}
+
 #[derive(Clone)]
 pub struct SQLiteBlockStore<B>
 where
@@ -124,6 +232,10 @@ where
     B: Block,
 {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_index_mode(path, IndexMode::InMemory)
+    }
+
+    pub fn with_index_mode<P: AsRef<Path>>(path: P, index_mode: IndexMode) -> Self {
         let manager = r2d2_sqlite::SqliteConnectionManager::file(path);
         let pool = r2d2::Pool::new(manager).unwrap();
 
@@ -152,6 +264,10 @@ where
                     hash blob not null
                   );
 
+                  create index if not exists BlocksHash on Blocks (hash);
+                  create index if not exists BlockInfoHash on BlockInfo (hash);
+                  create index if not exists TagsName on Tags (name);
+
                   commit;
                 "#,
             )
@@ -167,65 +283,250 @@ where
             .execute_batch("pragma journal_mode = WAL")
             .unwrap();
 
-        let mut index = ChainStorageIndex::new();
-        connection
-            .prepare("select rowid, hash from Blocks")
-            .unwrap()
-            .query_and_then(rusqlite::NO_PARAMS, |row| {
-                let row_id = row.get(0);
-                let hash = blob_to_hash(row.get(1));
+        let index = match index_mode {
+            IndexMode::InMemory => load_index(&connection),
+            IndexMode::Lazy { cache_size } => ChainStorageIndex::cached(cache_size),
+        };
 
-                index
-                    .add_block(hash, row_id)
-                    .map_err(IndexCreationError::IndexError)
-            })
-            .unwrap()
-            .try_for_each(std::convert::identity)
-            .unwrap();
+        SQLiteBlockStore {
+            pool,
+            dummy: std::marker::PhantomData,
+            index: Arc::new(RwLock::new(index)),
+        }
+    }
 
-        connection
-            .prepare("select rowid, hash from BlockInfo")
-            .unwrap()
-            .query_and_then(rusqlite::NO_PARAMS, |row| {
-                let row_id = row.get(0);
-                let hash = blob_to_hash(row.get(1));
+    /// Write a sequence of blocks in a single transaction. The parent of
+    /// each block must either be in the store already or come earlier in
+    /// the sequence.
+    ///
+    /// If any of the blocks can't be written, none of them are: the
+    /// transaction is rolled back and the index is restored.
+    pub fn put_blocks<'a, I>(&mut self, blocks: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a B>,
+        B: 'a,
+    {
+        let mut index = self.index.write().unwrap();
 
-                index
-                    .add_block_info(hash, row_id)
-                    .map_err(IndexCreationError::IndexError)
-            })
-            .unwrap()
-            .try_for_each(std::convert::identity)
-            .unwrap();
+        let mut conn = self
+            .pool
+            .get()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
 
-        connection
-            .prepare("select rowid, hash, name from Tags")
-            .unwrap()
-            .query_and_then(rusqlite::NO_PARAMS, |row| {
-                let row_id = row.get(0);
-                let hash = blob_to_hash(row.get(1));
-                let name = row.get(2);
+        let tx = conn
+            .transaction()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
 
-                index
-                    .add_tag(name, &hash, row_id)
-                    .map_err(IndexCreationError::IndexError)
+        let mut written = Vec::new();
+
+        let result = blocks
+            .into_iter()
+            .try_for_each(|block| {
+                if index
+                    .get_block(&tx, &block.id())
+                    .map_err(|err| Error::BackendError(Box::new(err)))?
+                    .is_some()
+                {
+                    return Err(Error::BlockAlreadyPresent);
+                }
+
+                let block_info = compute_block_info(block, |block_hash| {
+                    read_block_info(&tx, &index, block_hash)
+                })?;
+                let block_hash = block_info.block_hash.clone();
+
+                insert_block(&tx, &mut index, block, block_info)?;
+                written.push(block_hash);
+                Ok(())
             })
-            .unwrap()
-            .try_for_each(std::convert::identity)
-            .unwrap();
+            .and_then(|()| {
+                tx.commit()
+                    .map_err(|err| Error::BackendError(Box::new(err)))
+            });
 
-        SQLiteBlockStore {
-            pool,
-            dummy: std::marker::PhantomData,
-            index: Arc::new(RwLock::new(index)),
+        if result.is_err() {
+            for block_hash in written.iter() {
+                index.remove_block(block_hash);
+            }
         }
+
+        result
     }
 }
 
+/// Load the row ids of every block, block info and tag in the store.
+fn load_index<B: Block>(connection: &Connection) -> ChainStorageIndex<B> {
+    let mut index = ChainStorageIndex::new();
+    connection
+        .prepare("select rowid, hash from Blocks")
+        .unwrap()
+        .query_and_then(rusqlite::NO_PARAMS, |row| {
+            let row_id = row.get(0);
+            let hash = blob_to_hash(row.get(1));
+
+            index
+                .add_block(connection, hash, row_id)
+                .map_err(IndexCreationError::IndexError)
+        })
+        .unwrap()
+        .try_for_each(std::convert::identity)
+        .unwrap();
+
+    connection
+        .prepare("select rowid, hash from BlockInfo")
+        .unwrap()
+        .query_and_then(rusqlite::NO_PARAMS, |row| {
+            let row_id = row.get(0);
+            let hash = blob_to_hash(row.get(1));
+
+            index
+                .add_block_info(connection, hash, row_id)
+                .map_err(IndexCreationError::IndexError)
+        })
+        .unwrap()
+        .try_for_each(std::convert::identity)
+        .unwrap();
+
+    connection
+        .prepare("select rowid, hash, name from Tags")
+        .unwrap()
+        .query_and_then(rusqlite::NO_PARAMS, |row| {
+            let row_id = row.get(0);
+            let hash = blob_to_hash(row.get(1));
+            let name = row.get(2);
+
+            index
+                .add_tag(connection, name, &hash, row_id)
+                .map_err(IndexCreationError::IndexError)
+        })
+        .unwrap()
+        .try_for_each(std::convert::identity)
+        .unwrap();
+
+    index
+}
+
 fn blob_to_hash<Id: BlockId>(blob: Vec<u8>) -> Id {
     Id::deserialize(&blob[..]).unwrap()
 }
 
+/// Insert the block and its info, and add them to the index once both
+/// rows are written. Committing is left to the caller.
+fn insert_block<B: Block>(
+    conn: &Connection,
+    index: &mut ChainStorageIndex<B>,
+    block: &B,
+    block_info: BlockInfo<B::Id>,
+) -> Result<(), Error> {
+    index
+        .add_block_check(conn, &block_info.block_hash)
+        .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+    let worked = conn
+        .prepare_cached("insert into Blocks (hash, block) values(?, ?)")
+        .map_err(|err| Error::BackendError(Box::new(err)))?
+        .execute(&[
+            &block_info.block_hash.serialize_as_vec().unwrap()[..],
+            &block.serialize_as_vec().unwrap()[..],
+        ])
+        .map(|_| true)
+        .or_else(|err| match err {
+            rusqlite::Error::SqliteFailure(error, _) => {
+                if error.code == rusqlite::ErrorCode::ConstraintViolation {
+                    Ok(false)
+                } else {
+                    Err(err)
+                }
+            }
+            _ => Err(err),
+        })
+        .map_err(|err| Error::BackendError(Box::new(err)))?;
+    if !worked {
+        return Err(Error::BlockAlreadyPresent);
+    }
+
+    let block_row_id: RowId = conn
+        .prepare_cached("select last_insert_rowid()")
+        .map_err(|err| Error::BackendError(Box::new(err)))?
+        .query_row(rusqlite::NO_PARAMS, |row| row.get(0))
+        .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+    let parent = block_info
+        .back_links
+        .iter()
+        .find(|x| x.distance == 1)
+        .unwrap();
+
+    let (fast_distance, fast_hash) = match block_info.back_links.iter().find(|x| x.distance != 1) {
+        Some(fast_link) => (
+            Value::Integer(fast_link.distance as i64),
+            Value::Blob(fast_link.block_hash.serialize_as_vec().unwrap()),
+        ),
+        None => (Value::Null, Value::Null),
+    };
+
+    conn
+        .prepare_cached("insert into BlockInfo (hash, depth, parent, fast_distance, fast_hash) values(?, ?, ?, ?, ?)")
+        .map_err(|err| Error::BackendError(Box::new(err)))?
+        .execute(&[
+            Value::Blob(block_info.block_hash.serialize_as_vec().unwrap()),
+            Value::Integer(block_info.depth as i64),
+            Value::Blob(parent.block_hash.serialize_as_vec().unwrap()),
+            fast_distance,
+            fast_hash,
+        ])
+        .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+    let block_info_row_id: RowId = conn
+        .prepare_cached("select last_insert_rowid()")
+        .map_err(|err| Error::BackendError(Box::new(err)))?
+        .query_row(rusqlite::NO_PARAMS, |row| row.get(0))
+        .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+    index.add_inserted_block(block_info.block_hash, block_row_id, block_info_row_id);
+
+    Ok(())
+}
+
+fn read_block_info<B: Block>(
+    conn: &Connection,
+    index: &ChainStorageIndex<B>,
+    block_hash: &B::Id,
+) -> Result<BlockInfo<B::Id>, Error> {
+    let row_id = index
+        .get_block_info(conn, block_hash)
+        .map_err(|err| Error::BackendError(Box::new(err)))?
+        .ok_or(Error::BlockNotFound)?;
+
+    conn.prepare_cached(
+        "select depth, parent, fast_distance, fast_hash from BlockInfo where rowid = ?",
+    )
+    .map_err(|err| Error::BackendError(Box::new(err)))?
+    .query_row([row_id], |row| {
+        let mut back_links = vec![BackLink {
+            distance: 1,
+            block_hash: blob_to_hash(row.get(1)),
+        }];
+
+        let fast_distance: Option<i64> = row.get(2);
+        if let Some(fast_distance) = fast_distance {
+            back_links.push(BackLink {
+                distance: fast_distance as u64,
+                block_hash: blob_to_hash(row.get(3)),
+            });
+        }
+
+        let depth: i64 = row.get(0);
+
+        BlockInfo {
+            block_hash: block_hash.clone(),
+            depth: depth as u64,
+            back_links,
+        }
+    })
+    .map_err(|err| Error::BackendError(Box::new(err)))
+}
+
 impl<B> BlockStore for SQLiteBlockStore<B>
 where
     B: Block,
@@ -235,10 +536,6 @@ where
     fn put_block_internal(&mut self, block: &B, block_info: BlockInfo<B::Id>) -> Result<(), Error> {
         let mut index = self.index.write().unwrap();
 
-        index
-            .add_block_check(&block_info.block_hash)
-            .map_err(|err| Error::BackendError(Box::new(err)))?;
-
         let mut conn = self
             .pool
             .get()
@@ -248,90 +545,29 @@ where
             .transaction()
             .map_err(|err| Error::BackendError(Box::new(err)))?;
 
-        let worked = tx
-            .prepare_cached("insert into Blocks (hash, block) values(?, ?)")
-            .map_err(|err| Error::BackendError(Box::new(err)))?
-            .execute(&[
-                &block_info.block_hash.serialize_as_vec().unwrap()[..],
-                &block.serialize_as_vec().unwrap()[..],
-            ])
-            .map(|_| true)
-            .or_else(|err| match err {
-                rusqlite::Error::SqliteFailure(error, _) => {
-                    if error.code == rusqlite::ErrorCode::ConstraintViolation {
-                        Ok(false)
-                    } else {
-                        Err(err)
-                    }
-                }
-                _ => Err(err),
-            })
-            .map_err(|err| Error::BackendError(Box::new(err)))?;
-        if !worked {
-            return Err(Error::BlockAlreadyPresent);
-        }
-
-        let block_row_id: RowId = tx
-            .prepare_cached("select last_insert_rowid()")
-            .map_err(|err| Error::BackendError(Box::new(err)))?
-            .query_row(rusqlite::NO_PARAMS, |row| row.get(0))
-            .map_err(|err| Error::BackendError(Box::new(err)))?;
-
-        let parent = block_info
-            .back_links
-            .iter()
-            .find(|x| x.distance == 1)
-            .unwrap();
+        let block_hash = block_info.block_hash.clone();
+        insert_block(&tx, &mut index, block, block_info)?;
 
-        let (fast_distance, fast_hash) =
-            match block_info.back_links.iter().find(|x| x.distance != 1) {
-                Some(fast_link) => (
-                    Value::Integer(fast_link.distance as i64),
-                    Value::Blob(fast_link.block_hash.serialize_as_vec().unwrap()),
-                ),
-                None => (Value::Null, Value::Null),
-            };
-
-        tx
-            .prepare_cached("insert into BlockInfo (hash, depth, parent, fast_distance, fast_hash) values(?, ?, ?, ?, ?)")
-            .map_err(|err| Error::BackendError(Box::new(err)))?
-            .execute(&[
-                Value::Blob(block_info.block_hash.serialize_as_vec().unwrap()),
-                Value::Integer(block_info.depth as i64),
-                Value::Blob(parent.block_hash.serialize_as_vec().unwrap()),
-                fast_distance,
-                fast_hash,
-            ])
-            .map_err(|err| Error::BackendError(Box::new(err)))?;
-
-        let block_info_row_id: RowId = tx
-            .prepare_cached("select last_insert_rowid()")
-            .map_err(|err| Error::BackendError(Box::new(err)))?
-            .query_row(rusqlite::NO_PARAMS, |row| row.get(0))
-            .map_err(|err| Error::BackendError(Box::new(err)))?;
-
-        tx.commit()
-            .map_err(|err| Error::BackendError(Box::new(err)))?;
-
-        index
-            .add_block(block_info.block_hash.clone(), block_row_id)
-            .unwrap();
-        index
-            .add_block_info(block_info.block_hash.clone(), block_info_row_id)
-            .unwrap();
-
-        Ok(())
+        tx.commit().map_err(|err| {
+            index.remove_block(&block_hash);
+            Error::BackendError(Box::new(err))
+        })
     }
 
     fn get_block(&self, block_hash: &B::Id) -> Result<(B, BlockInfo<B::Id>), Error> {
         let index = self.index.read().unwrap();
 
-        let row_id = index.get_block(block_hash).ok_or(Error::BlockNotFound)?;
-
-        let blk = self
+        let conn = self
             .pool
             .get()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        let row_id = index
+            .get_block(&conn, block_hash)
             .map_err(|err| Error::BackendError(Box::new(err)))?
+            .ok_or(Error::BlockNotFound)?;
+
+        let blk = conn
             .prepare_cached("select block from Blocks where rowid = ?")
             .map_err(|err| Error::BackendError(Box::new(err)))?
             .query_row(&[row_id], |row| {
@@ -340,7 +576,7 @@ where
             })
             .map_err(|err| Error::BackendError(Box::new(err)))?;
 
-        let info = self.get_block_info(block_hash)?;
+        let info = read_block_info(&conn, &index, block_hash)?;
 
         Ok((blk, info))
     }
@@ -348,40 +584,115 @@ where
     fn get_block_info(&self, block_hash: &B::Id) -> Result<BlockInfo<B::Id>, Error> {
         let index = self.index.read().unwrap();
 
-        let row_id = index
-            .get_block_info(block_hash)
-            .ok_or(Error::BlockNotFound)?;
+        let conn = self
+            .pool
+            .get()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
 
-        self.pool
+        read_block_info(&conn, &index, block_hash)
+    }
+
+    fn remove_block(&mut self, block_hash: &B::Id) -> Result<(), Error> {
+        let mut index = self.index.write().unwrap();
+
+        let mut conn = self
+            .pool
             .get()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        let tx = conn
+            .transaction()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        let block_row_id = index
+            .get_block(&tx, block_hash)
+            .map_err(|err| Error::BackendError(Box::new(err)))?
+            .ok_or(Error::BlockNotFound)?;
+        let block_info_row_id = index
+            .get_block_info(&tx, block_hash)
+            .map_err(|err| Error::BackendError(Box::new(err)))?
+            .ok_or(Error::BlockNotFound)?;
+
+        let nb_tags: i64 = tx
+            .prepare_cached("select count(*) from Tags where hash = ?")
+            .map_err(|err| Error::BackendError(Box::new(err)))?
+            .query_row(&[&block_hash.serialize_as_vec().unwrap()[..]], |row| {
+                row.get(0)
+            })
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+        if nb_tags > 0 {
+            return Err(Error::BlockIsTagged);
+        }
+
+        tx.prepare_cached("delete from Blocks where rowid = ?")
+            .map_err(|err| Error::BackendError(Box::new(err)))?
+            .execute([block_row_id])
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        tx.prepare_cached("delete from BlockInfo where rowid = ?")
             .map_err(|err| Error::BackendError(Box::new(err)))?
+            .execute([block_info_row_id])
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        tx.commit()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        index.remove_block(block_hash);
+
+        Ok(())
+    }
+
+    fn prune_below(&mut self, depth: u64) -> Result<u64, Error> {
+        let mut index = self.index.write().unwrap();
+
+        let mut conn = self
+            .pool
+            .get()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        let tx = conn
+            .transaction()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        let pruned: Vec<B::Id> = tx
             .prepare_cached(
-                "select depth, parent, fast_distance, fast_hash from BlockInfo where rowid = ?",
+                "select hash from BlockInfo where depth < ? and hash not in (select hash from Tags)",
             )
             .map_err(|err| Error::BackendError(Box::new(err)))?
-            .query_row(&[row_id], |row| {
-                let mut back_links = vec![BackLink {
-                    distance: 1,
-                    block_hash: blob_to_hash(row.get(1)),
-                }];
-
-                let fast_distance: Option<i64> = row.get(2);
-                if let Some(fast_distance) = fast_distance {
-                    back_links.push(BackLink {
-                        distance: fast_distance as u64,
-                        block_hash: blob_to_hash(row.get(3)),
-                    });
-                }
+            .query_map([depth as i64], |row| blob_to_hash(row.get(0)))
+            .map_err(|err| Error::BackendError(Box::new(err)))?
+            .collect::<Result<_, _>>()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
 
-                let depth: i64 = row.get(0);
+        for block_hash in pruned.iter() {
+            let block_row_id = index
+                .get_block(&tx, block_hash)
+                .map_err(|err| Error::BackendError(Box::new(err)))?
+                .unwrap();
+            let block_info_row_id = index
+                .get_block_info(&tx, block_hash)
+                .map_err(|err| Error::BackendError(Box::new(err)))?
+                .unwrap();
 
-                BlockInfo {
-                    block_hash: block_hash.clone(),
-                    depth: depth as u64,
-                    back_links,
-                }
-            })
-            .map_err(|err| Error::BackendError(Box::new(err)))
+            tx.prepare_cached("delete from Blocks where rowid = ?")
+                .map_err(|err| Error::BackendError(Box::new(err)))?
+                .execute([block_row_id])
+                .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+            tx.prepare_cached("delete from BlockInfo where rowid = ?")
+                .map_err(|err| Error::BackendError(Box::new(err)))?
+                .execute([block_info_row_id])
+                .map_err(|err| Error::BackendError(Box::new(err)))?;
+        }
+
+        tx.commit()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        for block_hash in pruned.iter() {
+            index.remove_block(block_hash);
+        }
+
+        Ok(pruned.len() as u64)
     }
 
     fn put_tag(&mut self, tag_name: &str, block_hash: &B::Id) -> Result<(), Error> {
@@ -392,17 +703,24 @@ where
             .get()
             .map_err(|err| Error::BackendError(Box::new(err)))?;
 
-        match index.get_tag(&tag_name.to_owned()) {
+        match index
+            .get_tag(&conn, &tag_name.to_owned())
+            .map_err(|err| Error::BackendError(Box::new(err)))?
+        {
             Some(row_id) => conn
                 .prepare_cached("replace into Tags (rowid, name, hash) values(?, ?, ?)")
                 .map_err(|err| Error::BackendError(Box::new(err)))?
                 .execute(&[
-                    Value::Integer(*row_id as i64),
+                    Value::Integer(row_id as i64),
                     Value::Text(tag_name.to_string()),
                     Value::Blob(block_hash.serialize_as_vec().unwrap()),
                 ]),
             None => {
-                if index.get_block(block_hash).is_none() {
+                if index
+                    .get_block(&conn, block_hash)
+                    .map_err(|err| Error::BackendError(Box::new(err)))?
+                    .is_none()
+                {
                     return Err(Error::BlockNotFound);
                 }
 
@@ -420,7 +738,7 @@ where
             .map_err(|err| Error::BackendError(Box::new(err)))?
             .query_row(rusqlite::NO_PARAMS, |row| {
                 index
-                    .add_tag(tag_name.to_owned(), block_hash, row.get(0))
+                    .add_tag(&conn, tag_name.to_owned(), block_hash, row.get(0))
                     .map_err(|err| Error::BackendError(Box::new(err)))
             })
             .map_err(|err| Error::BackendError(Box::new(err)))??;
@@ -430,22 +748,27 @@ where
 
     fn get_tag(&self, tag_name: &str) -> Result<Option<B::Id>, Error> {
         let index = self.index.read().unwrap();
-        let row_id = match index.get_tag(&tag_name.to_owned()) {
-            Some(v) => v,
-            None => return Ok(None),
-        };
 
-        match self
+        let conn = self
             .pool
             .get()
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        let row_id = match index
+            .get_tag(&conn, &tag_name.to_owned())
             .map_err(|err| Error::BackendError(Box::new(err)))?
+        {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let block_hash = conn
             .prepare_cached("select hash from Tags where rowid = ?")
             .map_err(|err| Error::BackendError(Box::new(err)))?
             .query_row(&[row_id], |row| blob_to_hash(row.get(0)))
-        {
-            Ok(s) => Ok(Some(s)),
-            Err(err) => Err(Error::BackendError(Box::new(err))),
-        }
+            .map_err(|err| Error::BackendError(Box::new(err)))?;
+
+        Ok(Some(block_hash))
     }
 }
 
@@ -474,4 +797,151 @@ mod tests {
         let mut store = SQLiteBlockStore::<Block>::new(":memory:");
         chain_storage::store::testing::test_iterate_range(&mut rng, &mut store);
     }
+
+    #[test]
+    pub fn get_block_by_chain_length() {
+        let mut rng = OsRng;
+        let mut store = SQLiteBlockStore::<Block>::new(":memory:");
+        chain_storage::store::testing::test_get_block_by_chain_length(&mut rng, &mut store);
+    }
+
+    #[test]
+    pub fn remove_block() {
+        let mut store = SQLiteBlockStore::<Block>::new(":memory:");
+        chain_storage::store::testing::test_remove_block(&mut store);
+    }
+
+    #[test]
+    pub fn prune_below() {
+        let mut store = SQLiteBlockStore::<Block>::new(":memory:");
+        chain_storage::store::testing::test_prune_below(&mut store);
+    }
+
+    const LAZY: IndexMode = IndexMode::Lazy { cache_size: 16 };
+
+    #[test]
+    pub fn lazy_put_get() {
+        let mut store = SQLiteBlockStore::<Block>::with_index_mode(":memory:", LAZY);
+        chain_storage::store::testing::test_put_get(&mut store);
+    }
+
+    #[test]
+    pub fn lazy_nth_ancestor() {
+        let mut rng = OsRng;
+        let mut store = SQLiteBlockStore::<Block>::with_index_mode(":memory:", LAZY);
+        chain_storage::store::testing::test_nth_ancestor(&mut rng, &mut store);
+    }
+
+    #[test]
+    pub fn lazy_remove_block() {
+        let mut store = SQLiteBlockStore::<Block>::with_index_mode(":memory:", LAZY);
+        chain_storage::store::testing::test_remove_block(&mut store);
+    }
+
+    #[test]
+    pub fn lazy_prune_below() {
+        let mut store = SQLiteBlockStore::<Block>::with_index_mode(":memory:", LAZY);
+        chain_storage::store::testing::test_prune_below(&mut store);
+    }
+
+    #[test]
+    pub fn reopen_with_other_index_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.sqlite");
+        let blocks = linear_chain(100);
+
+        {
+            let mut store = SQLiteBlockStore::<Block>::new(&path);
+            store.put_blocks(&blocks[..50]).unwrap();
+            store.put_tag("tip", &blocks[49].id()).unwrap();
+        }
+
+        {
+            let mut store = SQLiteBlockStore::<Block>::with_index_mode(&path, LAZY);
+            assert_eq!(store.get_tag("tip").unwrap(), Some(blocks[49].id()));
+            store.put_blocks(&blocks[50..]).unwrap();
+            store.put_tag("tip", &blocks[99].id()).unwrap();
+            match store.put_block(&blocks[10]) {
+                Err(Error::BlockAlreadyPresent) => (),
+                _ => panic!("the block should be already present"),
+            }
+        }
+
+        for index_mode in [IndexMode::InMemory, LAZY].iter() {
+            let store = SQLiteBlockStore::<Block>::with_index_mode(&path, *index_mode);
+            assert_eq!(store.get_tag("tip").unwrap(), Some(blocks[99].id()));
+            for (depth, block) in blocks.iter().enumerate() {
+                let (stored, info) = store.get_block(&block.id()).unwrap();
+                assert_eq!(&stored, block);
+                assert_eq!(info.depth, depth as u64 + 1);
+            }
+        }
+    }
+
+    fn linear_chain(length: usize) -> Vec<Block> {
+        let mut blocks = vec![Block::genesis(None)];
+        while blocks.len() < length {
+            let child = blocks.last().unwrap().make_child(None);
+            blocks.push(child);
+        }
+        blocks
+    }
+
+    #[test]
+    pub fn put_blocks() {
+        let mut store = SQLiteBlockStore::<Block>::new(":memory:");
+        let blocks = linear_chain(100);
+
+        store.put_blocks(&blocks[..50]).unwrap();
+        store.put_blocks(&blocks[50..]).unwrap();
+
+        let tip = blocks.last().unwrap().id();
+        for (distance, block) in blocks.iter().rev().enumerate() {
+            let info = store.get_nth_ancestor(&tip, distance as u64).unwrap();
+            assert_eq!(info.block_hash, block.id());
+            assert_eq!(info.depth, 100 - distance as u64);
+            assert_eq!(&store.get_block(&block.id()).unwrap().0, block);
+        }
+    }
+
+    #[test]
+    pub fn put_blocks_is_atomic() {
+        let mut store = SQLiteBlockStore::<Block>::new(":memory:");
+        let blocks = linear_chain(20);
+        store.put_blocks(&blocks[..10]).unwrap();
+
+        // the last block of the batch has no parent in the store
+        let orphan = Block::genesis(None).make_child(None);
+        let batch = blocks[10..].iter().chain(std::iter::once(&orphan));
+        match store.put_blocks(batch) {
+            Err(Error::MissingParent) => (),
+            _ => panic!("the orphan block should have been rejected"),
+        }
+
+        // the last block of the batch is already in the store
+        let batch = blocks[10..].iter().chain(std::iter::once(&blocks[0]));
+        match store.put_blocks(batch) {
+            Err(Error::BlockAlreadyPresent) => (),
+            _ => panic!("the duplicated block should have been rejected"),
+        }
+
+        for block in blocks[10..].iter() {
+            assert!(!store.block_exists(&block.id()).unwrap());
+        }
+        assert_eq!(
+            store
+                .pool
+                .get()
+                .unwrap()
+                .query_row("select count(*) from Blocks", rusqlite::NO_PARAMS, |row| {
+                    row.get::<_, i64>(0)
+                })
+                .unwrap(),
+            10
+        );
+
+        store.put_blocks(&blocks[10..]).unwrap();
+        let tip_info = store.get_block_info(&blocks[19].id()).unwrap();
+        assert_eq!(tip_info.depth, 20);
+    }
 }
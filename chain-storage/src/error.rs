@@ -8,6 +8,7 @@ pub enum Error {
     Block0InFuture,
     BlockAlreadyPresent,
     MissingParent,
+    BlockIsTagged,
 }
 
 impl fmt::Display for Error {
@@ -19,6 +20,7 @@ impl fmt::Display for Error {
             Error::Block0InFuture => write!(f, "block0 is in the future"),
             Error::BlockAlreadyPresent => write!(f, "Block already present in DB"),
             Error::MissingParent => write!(f, "the parent block is missing for the required write"),
+            Error::BlockIsTagged => write!(f, "the block is tagged and cannot be removed"),
         }
     }
 }
@@ -21,6 +21,10 @@ where
             tags: HashMap::new(),
         }
     }
+
+    fn is_tagged(&self, block_hash: &B::Id) -> bool {
+        self.tags.values().any(|tagged| tagged == block_hash)
+    }
 }
 
 impl<B> BlockStore for MemoryBlockStore<B>
@@ -53,6 +57,28 @@ where
         }
     }
 
+    fn remove_block(&mut self, block_hash: &B::Id) -> Result<(), Error> {
+        if self.is_tagged(block_hash) {
+            return Err(Error::BlockIsTagged);
+        }
+
+        match self.blocks.remove(block_hash) {
+            None => Err(Error::BlockNotFound),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn prune_below(&mut self, depth: u64) -> Result<u64, Error> {
+        let tags = &self.tags;
+        let len = self.blocks.len();
+
+        self.blocks.retain(|block_hash, (_, block_info)| {
+            block_info.depth >= depth || tags.values().any(|tagged| tagged == block_hash)
+        });
+
+        Ok((len - self.blocks.len()) as u64)
+    }
+
     fn put_tag(&mut self, tag_name: &str, block_hash: &B::Id) -> Result<(), Error> {
         match self.blocks.get(block_hash) {
             None => Err(Error::BlockNotFound),
@@ -97,4 +123,23 @@ mod tests {
         let mut store = MemoryBlockStore::<Block>::new();
         crate::store::testing::test_iterate_range(&mut rng, &mut store);
     }
+
+    #[test]
+    pub fn get_block_by_chain_length() {
+        let mut rng = OsRng;
+        let mut store = MemoryBlockStore::<Block>::new();
+        crate::store::testing::test_get_block_by_chain_length(&mut rng, &mut store);
+    }
+
+    #[test]
+    pub fn remove_block() {
+        let mut store = MemoryBlockStore::<Block>::new();
+        crate::store::testing::test_remove_block(&mut store);
+    }
+
+    #[test]
+    pub fn prune_below() {
+        let mut store = MemoryBlockStore::<Block>::new();
+        crate::store::testing::test_prune_below(&mut store);
+    }
 }
@@ -18,6 +18,9 @@ pub struct BlockInfo<Id: BlockId> {
     pub back_links: Vec<BackLink<Id>>,
 }
 
+/// A block along with its info, as returned by the lookups of a `BlockStore`.
+pub type BlockWithInfo<B> = (B, BlockInfo<<B as Block>::Id>);
+
 impl<Id: BlockId> BlockInfo<Id> {
     pub fn parent_id(&self) -> Id {
         self.back_links
@@ -48,51 +51,13 @@ pub trait BlockStore {
     /// get_nth_ancestor(), and calls put_block_internal() to do the
     /// actual write.
     fn put_block(&mut self, block: &Self::Block) -> Result<(), Error> {
-        let block_hash = block.id();
-
-        if self.block_exists(&block_hash)? {
+        if self.block_exists(&block.id())? {
             return Err(Error::BlockAlreadyPresent);
         }
 
-        let parent_hash = block.parent_id();
-
-        // Always include a link to the parent.
-        let mut back_links = vec![BackLink {
-            distance: 1,
-            block_hash: parent_hash.clone(),
-        }];
-
-        let depth = if parent_hash == <Self::Block as Block>::Id::zero() {
-            1
-        } else {
-            let parent_info = self.get_block_info(&parent_hash).map_err(|e| match e {
-                Error::BlockNotFound => Error::MissingParent,
-                e => e,
-            })?;
-            assert!(parent_info.depth > 0);
-            let depth = 1 + parent_info.depth;
-            let fast_link = compute_fast_link(depth);
-            //println!("from {} -> {}", depth, fast_link);
-            let distance = depth - fast_link;
-            if distance != 1 && fast_link > 0 {
-                let far_block_info = self.get_nth_ancestor(&parent_hash, depth - 1 - fast_link)?;
-                back_links.push(BackLink {
-                    distance,
-                    block_hash: far_block_info.block_hash,
-                })
-            }
-
-            depth
-        };
+        let block_info = compute_block_info(block, |block_hash| self.get_block_info(block_hash))?;
 
-        self.put_block_internal(
-            block,
-            BlockInfo {
-                block_hash: block_hash.clone(),
-                depth,
-                back_links,
-            },
-        )
+        self.put_block_internal(block, block_info)
     }
 
     /// Write a block and associated info to the store.
@@ -114,6 +79,21 @@ pub trait BlockStore {
         block_hash: &<Self::Block as Block>::Id,
     ) -> Result<BlockInfo<<Self::Block as Block>::Id>, Error>;
 
+    /// Remove a block and its info from the store. Tagged blocks can't
+    /// be removed.
+    ///
+    /// The descendants of the block are kept, but their ancestors can't
+    /// be reached through it anymore.
+    fn remove_block(&mut self, block_hash: &<Self::Block as Block>::Id) -> Result<(), Error>;
+
+    /// Remove all the blocks with a depth lower than `depth`, except
+    /// the tagged ones. Returns the number of blocks removed.
+    ///
+    /// The blocks from `depth` on can still be reached from their
+    /// descendants, as the back links never skip over the block being
+    /// looked for.
+    fn prune_below(&mut self, depth: u64) -> Result<u64, Error>;
+
     /// Check whether a block exists.
     fn block_exists(&self, block_hash: &<Self::Block as Block>::Id) -> Result<bool, Error> {
         match self.get_block_info(block_hash) {
@@ -142,6 +122,23 @@ pub trait BlockStore {
         for_path_to_nth_ancestor(self, block_hash, distance, |_| {})
     }
 
+    /// Fetch the block with the given chain length (depth) in the
+    /// chain ending at `tip`.
+    fn get_block_by_chain_length(
+        &self,
+        tip: &<Self::Block as Block>::Id,
+        chain_length: u64,
+    ) -> Result<BlockWithInfo<Self::Block>, Error> {
+        let tip_info = self.get_block_info(tip)?;
+
+        if chain_length == 0 || chain_length > tip_info.depth {
+            return Err(Error::BlockNotFound);
+        }
+
+        let info = self.get_nth_ancestor(tip, tip_info.depth - chain_length)?;
+        self.get_block(&info.block_hash)
+    }
+
     /// Determine whether block 'ancestor' is an ancestor of block 'descendent'
     ///
     /// Returned values:
@@ -211,6 +208,14 @@ impl<T: ?Sized + BlockStore> BlockStore for Box<T> {
         (**self).get_block_info(block_hash)
     }
 
+    fn remove_block(&mut self, block_hash: &<Self::Block as Block>::Id) -> Result<(), Error> {
+        (**self).remove_block(block_hash)
+    }
+
+    fn prune_below(&mut self, depth: u64) -> Result<u64, Error> {
+        (**self).prune_below(depth)
+    }
+
     fn block_exists(&self, block_hash: &<Self::Block as Block>::Id) -> Result<bool, Error> {
         (**self).block_exists(block_hash)
     }
@@ -254,6 +259,70 @@ where
     }
 }
 
+/// Compute the BlockInfo of a block that is about to be written, with
+/// back_links set to ensure O(lg n) seek time in get_nth_ancestor().
+/// The parent of the block must exist (unless it's the zero hash).
+///
+/// The ancestors are looked up with `get_block_info`, so stores
+/// writing several blocks at once can resolve the ones that are not
+/// committed yet.
+pub fn compute_block_info<B, G>(block: &B, get_block_info: G) -> Result<BlockInfo<B::Id>, Error>
+where
+    B: Block,
+    G: Fn(&B::Id) -> Result<BlockInfo<B::Id>, Error>,
+{
+    let parent_hash = block.parent_id();
+
+    // Always include a link to the parent.
+    let mut back_links = vec![BackLink {
+        distance: 1,
+        block_hash: parent_hash.clone(),
+    }];
+
+    let depth = if parent_hash == B::Id::zero() {
+        1
+    } else {
+        let parent_info = get_block_info(&parent_hash).map_err(|e| match e {
+            Error::BlockNotFound => Error::MissingParent,
+            e => e,
+        })?;
+        assert!(parent_info.depth > 0);
+        let depth = 1 + parent_info.depth;
+        let fast_link = compute_fast_link(depth);
+        //println!("from {} -> {}", depth, fast_link);
+        let distance = depth - fast_link;
+        if distance != 1 && fast_link > 0 {
+            let mut lowest = None;
+            let far_block_info = match path_to_nth_ancestor(
+                &get_block_info,
+                &parent_hash,
+                depth - 1 - fast_link,
+                |info| lowest = Some(info.clone()),
+            ) {
+                Ok(info) => Some(info),
+                // the target has been pruned: link to the lowest ancestor
+                // reached instead, if it is not the parent
+                Err(Error::BlockNotFound) => lowest.filter(|info| info.depth + 1 < depth),
+                Err(e) => return Err(e),
+            };
+            if let Some(far_block_info) = far_block_info {
+                back_links.push(BackLink {
+                    distance: depth - far_block_info.depth,
+                    block_hash: far_block_info.block_hash,
+                })
+            }
+        }
+
+        depth
+    };
+
+    Ok(BlockInfo {
+        block_hash: block.id(),
+        depth,
+        back_links,
+    })
+}
+
 /// Like `BlockStore::get_nth_ancestor`, but calls the closure 'callback' with
 /// each intermediate block encountered while travelling from
 /// 'block_hash' to its n'th ancestor.
@@ -264,13 +333,32 @@ pub fn for_path_to_nth_ancestor<S, F>(
     store: &S,
     block_hash: &<S::Block as Block>::Id,
     distance: u64,
-    mut callback: F,
+    callback: F,
 ) -> Result<BlockInfo<<S::Block as Block>::Id>, Error>
 where
     S: ?Sized + BlockStore,
     F: FnMut(&BlockInfo<<S::Block as Block>::Id>),
 {
-    let mut cur_block_info = store.get_block_info(block_hash)?;
+    path_to_nth_ancestor(
+        |block_hash| store.get_block_info(block_hash),
+        block_hash,
+        distance,
+        callback,
+    )
+}
+
+fn path_to_nth_ancestor<Id, G, F>(
+    get_block_info: G,
+    block_hash: &Id,
+    distance: u64,
+    mut callback: F,
+) -> Result<BlockInfo<Id>, Error>
+where
+    Id: BlockId,
+    G: Fn(&Id) -> Result<BlockInfo<Id>, Error>,
+    F: FnMut(&BlockInfo<Id>),
+{
+    let mut cur_block_info = get_block_info(block_hash)?;
 
     if distance >= cur_block_info.depth {
         // FIXME: return error
@@ -296,7 +384,7 @@ where
             .unwrap()
             .clone();
         callback(&cur_block_info);
-        cur_block_info = store.get_block_info(&best_link.block_hash)?;
+        cur_block_info = get_block_info(&best_link.block_hash)?;
     }
 
     assert_eq!(target, cur_block_info.depth);
@@ -367,6 +455,7 @@ fn compute_fast_link(depth: u64) -> u64 {
 #[cfg(any(feature = "test-api", test))]
 pub mod testing {
     use super::*;
+    use chain_core::mempack::{ReadBuf, ReadError};
     use chain_core::packer::*;
     use chain_core::property::{Block as _, BlockDate as _, BlockId as _};
     use rand_core::RngCore;
@@ -408,6 +497,12 @@ pub mod testing {
         }
     }
 
+    impl chain_core::mempack::Readable for BlockId {
+        fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+            Ok(Self(buf.get_u64()?))
+        }
+    }
+
     #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Copy)]
     pub struct BlockDate(u32, u32);
 
@@ -417,6 +512,28 @@ pub mod testing {
         }
     }
 
+    impl std::fmt::Display for BlockDate {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}.{}", self.0, self.1)
+        }
+    }
+
+    impl std::str::FromStr for BlockDate {
+        type Err = std::io::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, s);
+            let mut parts = s.splitn(2, '.');
+            let mut next = || -> Result<u32, Self::Err> {
+                parts
+                    .next()
+                    .and_then(|part| part.parse().ok())
+                    .ok_or_else(invalid)
+            };
+            Ok(Self(next()?, next()?))
+        }
+    }
+
     #[derive(Debug, Clone, Eq, PartialEq)]
     pub struct Block {
         id: BlockId,
@@ -446,6 +563,12 @@ pub mod testing {
                 data: data.unwrap_or_default(),
             }
         }
+
+        /// same as the `Block` and `Header` methods, without having to
+        /// pick one of the traits
+        pub fn id(&self) -> BlockId {
+            self.id
+        }
     }
 
     #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Copy)]
@@ -484,6 +607,43 @@ pub mod testing {
         }
     }
 
+    // the test blocks carry no content worth leaving out of the header,
+    // so a block is its own header
+    impl chain_core::property::Header for Block {
+        type Id = BlockId;
+        type Date = BlockDate;
+        type ChainLength = ChainLength;
+        type Version = u8;
+
+        fn id(&self) -> Self::Id {
+            self.id
+        }
+
+        fn parent_id(&self) -> Self::Id {
+            self.parent
+        }
+
+        fn date(&self) -> Self::Date {
+            self.date
+        }
+
+        fn version(&self) -> Self::Version {
+            0
+        }
+
+        fn chain_length(&self) -> Self::ChainLength {
+            self.chain_length
+        }
+    }
+
+    impl chain_core::property::HasHeader for Block {
+        type Header = Block;
+
+        fn header(&self) -> Self::Header {
+            self.clone()
+        }
+    }
+
     impl chain_core::property::Serialize for Block {
         type Error = std::io::Error;
 
@@ -518,6 +678,21 @@ pub mod testing {
         }
     }
 
+    impl chain_core::mempack::Readable for Block {
+        fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+            Ok(Self {
+                id: BlockId(buf.get_u64()?),
+                parent: BlockId(buf.get_u64()?),
+                date: BlockDate(buf.get_u32()?, buf.get_u32()?),
+                chain_length: ChainLength(buf.get_u64()?),
+                data: {
+                    let length = buf.get_u64()?;
+                    buf.get_slice(length as usize)?.into()
+                },
+            })
+        }
+    }
+
     pub fn pick_from_vector<'a, A, R: RngCore>(rng: &mut R, v: &'a Vec<A>) -> &'a A {
         let s = rng.next_u32() as usize;
         // this doesn't need to be uniform
@@ -548,6 +723,27 @@ pub mod testing {
         blocks
     }
 
+    /// Append `length` blocks to `parent` (or to a new genesis block,
+    /// which counts as one of them).
+    pub fn generate_linear_chain<Store: BlockStore<Block = Block>>(
+        store: &mut Store,
+        parent: Option<&Block>,
+        length: usize,
+    ) -> Vec<Block> {
+        let mut blocks: Vec<Block> = vec![];
+
+        for _ in 0..length {
+            let block = match blocks.last().or(parent) {
+                Some(parent) => parent.make_child(None),
+                None => Block::genesis(None),
+            };
+            store.put_block(&block).unwrap();
+            blocks.push(block);
+        }
+
+        blocks
+    }
+
     pub fn test_put_get<Store: BlockStore<Block = Block>>(store: &mut Store) {
         assert!(store.get_tag("tip").unwrap().is_none());
 
@@ -642,4 +838,125 @@ pub mod testing {
             }
         }
     }
+
+    pub fn test_get_block_by_chain_length<R: RngCore, Store: BlockStore<Block = Block>>(
+        rng: &mut R,
+        store: &mut Store,
+    ) {
+        let blocks = generate_chain(rng, store);
+
+        for _ in 0..1000 {
+            let tip = pick_from_vector(rng, &blocks);
+            let tip_length = tip.chain_length().0;
+            let chain_length = 1 + rng.next_u64() % tip_length;
+
+            let (block, block_info) = store
+                .get_block_by_chain_length(&tip.id(), chain_length)
+                .unwrap();
+            assert_eq!(block.chain_length().0, chain_length);
+            assert_eq!(block_info.depth, chain_length);
+            assert_eq!(
+                store.is_ancestor(&block.id(), &tip.id()).unwrap(),
+                Some(tip_length - chain_length)
+            );
+
+            for chain_length in &[0, tip_length + 1] {
+                match store.get_block_by_chain_length(&tip.id(), *chain_length) {
+                    Err(Error::BlockNotFound) => {}
+                    _ => panic!("there is no block with chain length {}", chain_length),
+                }
+            }
+        }
+    }
+
+    pub fn test_remove_block<Store: BlockStore<Block = Block>>(store: &mut Store) {
+        let blocks = generate_linear_chain(store, None, 10);
+        let tip = blocks.last().unwrap();
+        store.put_tag("tip", &tip.id()).unwrap();
+
+        let removed = &blocks[4];
+        store.remove_block(&removed.id()).unwrap();
+        assert!(!store.block_exists(&removed.id()).unwrap());
+        match store.get_block(&removed.id()) {
+            Err(Error::BlockNotFound) => {}
+            _ => panic!("the block should be removed"),
+        }
+        match store.remove_block(&removed.id()) {
+            Err(Error::BlockNotFound) => {}
+            _ => panic!("the block was already removed"),
+        }
+
+        // the rest of the chain is kept
+        for block in blocks.iter().filter(|block| block.id() != removed.id()) {
+            assert_eq!(&store.get_block(&block.id()).unwrap().0, block);
+        }
+        assert_eq!(
+            store.get_block_by_chain_length(&tip.id(), 6).unwrap().0,
+            blocks[5]
+        );
+
+        match store.remove_block(&tip.id()) {
+            Err(Error::BlockIsTagged) => {}
+            _ => panic!("tagged blocks can't be removed"),
+        }
+        assert!(store.block_exists(&tip.id()).unwrap());
+
+        // the block can be added back, as its parent is still there
+        store.put_block(removed).unwrap();
+        assert_eq!(&store.get_block(&removed.id()).unwrap().0, removed);
+    }
+
+    pub fn test_prune_below<Store: BlockStore<Block = Block>>(store: &mut Store) {
+        let main_chain = generate_linear_chain(store, None, 100);
+        let fork = generate_linear_chain(store, Some(&main_chain[29]), 5);
+
+        let tip = main_chain.last().unwrap();
+        let checkpoint = &main_chain[9];
+        store.put_tag("tip", &tip.id()).unwrap();
+        store.put_tag("checkpoint", &checkpoint.id()).unwrap();
+
+        // all the blocks below 50 but the tagged one, including the fork
+        assert_eq!(store.prune_below(50).unwrap(), 48 + 5);
+
+        for block in main_chain.iter().chain(fork.iter()) {
+            let expected = block.chain_length().0 >= 50 || block.id() == checkpoint.id();
+            assert_eq!(store.block_exists(&block.id()).unwrap(), expected);
+        }
+
+        for chain_length in 50..=100 {
+            let (block, _) = store
+                .get_block_by_chain_length(&tip.id(), chain_length)
+                .unwrap();
+            assert_eq!(block, main_chain[chain_length as usize - 1]);
+        }
+
+        match store.get_block_by_chain_length(&tip.id(), 49) {
+            Err(Error::BlockNotFound) => {}
+            _ => panic!("the block should be pruned"),
+        }
+
+        assert_eq!(&store.get_block(&checkpoint.id()).unwrap().0, checkpoint);
+        assert_eq!(store.prune_below(50).unwrap(), 0);
+
+        // blocks whose fast link would point below the pruned depth can still be added
+        let extension = generate_linear_chain(store, Some(tip), 40);
+        let new_tip = extension.last().unwrap();
+        for chain_length in 50..=140 {
+            let (block, _) = store
+                .get_block_by_chain_length(&new_tip.id(), chain_length)
+                .unwrap();
+            let expected = main_chain
+                .iter()
+                .chain(extension.iter())
+                .nth(chain_length as usize - 1);
+            assert_eq!(Some(&block), expected);
+        }
+        assert_eq!(
+            store
+                .get_nth_ancestor(&new_tip.id(), 90)
+                .unwrap()
+                .block_hash,
+            main_chain[49].id()
+        );
+    }
 }
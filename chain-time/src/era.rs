@@ -48,6 +48,16 @@ impl TimeEra {
         self.slots_per_epoch
     }
 
+    /// retrieve the epoch at which this era starts
+    pub fn epoch_start(&self) -> Epoch {
+        self.epoch_start
+    }
+
+    /// retrieve the slot at which this era starts
+    pub fn slot_start(&self) -> Slot {
+        self.slot_start
+    }
+
     /// Try to return the epoch/inner-epoch-slot associated.
     ///
     /// If the slot in parameter is before the beginning of this era, then
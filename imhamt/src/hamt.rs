@@ -1,9 +1,9 @@
 use super::hash::{Hash, HashedKey, Hasher};
 use super::node::{
-    insert_rec, lookup_one, remove_eq_rec, remove_rec, replace_rec, replace_with_rec, size_rec,
-    update_rec, Entry, LookupRet, Node, NodeIter,
+    diff_rec, insert_rec, lookup_one, merge_rec, remove_eq_rec, remove_rec, replace_rec,
+    replace_with_rec, size_rec, update_rec, Entry, LookupRet, Node, NodeIter,
 };
-pub use super::operation::{InsertError, RemoveError, ReplaceError, UpdateError};
+pub use super::operation::{DiffEntry, InsertError, RemoveError, ReplaceError, UpdateError};
 use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::mem::swap;
@@ -198,6 +198,48 @@ impl<H: Hasher + Default, K: Hash + Eq, V> Hamt<H, K, V> {
     }
 }
 
+impl<H: Hasher + Default, K: Hash + Eq, V: PartialEq> Hamt<H, K, V> {
+    /// Differences of the entries between this HAMT and the `other` HAMT
+    ///
+    /// Entries only in `other` are added, and entries only in `self` are
+    /// removed. Subtrees shared by both HAMTs, e.g. when `other` has been
+    /// derived from `self`, are skipped, so the cost is proportional to
+    /// the changes rather than to the size of the HAMTs.
+    pub fn diff<'a>(&'a self, other: &'a Self) -> Vec<DiffEntry<'a, K, V>> {
+        let mut out = Vec::new();
+        diff_rec(&self.root, &other.root, &mut out);
+        out
+    }
+}
+
+impl<H: Hasher + Default, K: Eq + Hash + Clone, V: Clone> Hamt<H, K, V> {
+    /// Union of this HAMT and the `other` HAMT
+    ///
+    /// The value of a key present in both HAMTs is the result of the
+    /// closure F applied to the value in `self` and the value in `other`.
+    /// Subtrees present in only one HAMT are shared with the result.
+    pub fn union_with<F>(&self, other: &Self, f: F) -> Self
+    where
+        F: Fn(&V, &V) -> V,
+    {
+        Hamt {
+            root: merge_rec(&self.root, &other.root, 0, false, &f),
+            hasher: PhantomData,
+        }
+    }
+
+    /// Union of this HAMT and the `other` HAMT, where the values of
+    /// `other` take precedence
+    ///
+    /// Subtrees shared by both HAMTs are kept without being visited.
+    pub fn merge(&self, other: &Self) -> Self {
+        Hamt {
+            root: merge_rec(&self.root, &other.root, 0, true, &|_, v: &V| v.clone()),
+            hasher: PhantomData,
+        }
+    }
+}
+
 impl<'a, K, V> Iterator for HamtIter<'a, K, V> {
     type Item = (&'a K, &'a V);
 
@@ -253,18 +295,8 @@ impl<H: Default + Hasher, K: Eq + Hash, V: PartialEq> PartialEq for Hamt<H, K, V
         if self.is_empty() != other.is_empty() {
             return false;
         }
-        // then compare key and values
-        // TODO : optimise by comparing nodes directly
-        for (k, v) in self.iter() {
-            if let Some(v2) = other.lookup(k) {
-                if v != v2 {
-                    return false;
-                }
-            } else {
-                return false;
-            }
-        }
-        true
+        // then compare the entries of the subtrees which are not shared
+        self.diff(other).is_empty()
     }
 }
 
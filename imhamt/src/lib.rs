@@ -438,6 +438,119 @@ mod tests {
         let after_iter = BTreeMap::from_iter(h.iter().map(|(k, v)| (k.clone(), v.clone())));
         reference == after_iter
     }
+
+    #[test]
+    fn diff_of_derived() {
+        let mut h: Hamt<DefaultHasher, u32, u32> = Hamt::new();
+        for k in 0..10_000 {
+            h = h.insert(k, k).unwrap();
+        }
+        assert!(h.diff(&h).is_empty());
+
+        let (h2, _) = h.replace(&10, 11).unwrap();
+        let h2 = h2.remove(&20).unwrap().insert(10_000, 0).unwrap();
+        let mut diff = h.diff(&h2);
+        diff.sort_by_key(|entry| match entry {
+            DiffEntry::Added(k, _) | DiffEntry::Removed(k, _) | DiffEntry::Changed(k, _, _) => **k,
+        });
+        assert_eq!(
+            diff,
+            vec![
+                DiffEntry::Changed(&10, &10, &11),
+                DiffEntry::Removed(&20, &20),
+                DiffEntry::Added(&10_000, &0),
+            ]
+        );
+        assert!(h != h2);
+    }
+
+    fn apply_changes(
+        h: &Hamt<DefaultHasher, String, u32>,
+        reference: &BTreeMap<String, u32>,
+        changes: &[(String, Option<u32>)],
+    ) -> (Hamt<DefaultHasher, String, u32>, BTreeMap<String, u32>) {
+        let mut h = h.clone();
+        let mut reference = reference.clone();
+        for (k, v) in changes {
+            match v {
+                Some(v) => {
+                    reference.insert(k.clone(), *v);
+                    h = h.insert_or_update_simple(k.clone(), *v, |_| Some(*v));
+                }
+                None => {
+                    if reference.remove(k).is_some() {
+                        h = h.remove(k).unwrap();
+                    }
+                }
+            }
+        }
+        (h, reference)
+    }
+
+    #[quickcheck]
+    fn diff_equivalent(xs: Plan<String, u32>, changes: Vec<(String, Option<u32>)>) -> bool {
+        let (h1, reference1) = arbitrary_hamt_and_btree(xs, next_u32, |v| v.wrapping_mul(2));
+        let (h2, reference2) = apply_changes(&h1, &reference1, &changes);
+
+        let mut expected = BTreeMap::new();
+        for (k, v) in reference1.iter() {
+            match reference2.get(k) {
+                None => {
+                    expected.insert(k.clone(), (Some(*v), None));
+                }
+                Some(v2) if v2 != v => {
+                    expected.insert(k.clone(), (Some(*v), Some(*v2)));
+                }
+                Some(_) => {}
+            }
+        }
+        for (k, v) in reference2.iter() {
+            if !reference1.contains_key(k) {
+                expected.insert(k.clone(), (None, Some(*v)));
+            }
+        }
+
+        let mut diff = BTreeMap::new();
+        for entry in h1.diff(&h2) {
+            let (k, change) = match entry {
+                DiffEntry::Added(k, v) => (k, (None, Some(*v))),
+                DiffEntry::Removed(k, v) => (k, (Some(*v), None)),
+                DiffEntry::Changed(k, v1, v2) => (k, (Some(*v1), Some(*v2))),
+            };
+            if diff.insert(k.clone(), change).is_some() {
+                return false;
+            }
+        }
+        diff == expected && (h1 == h2) == expected.is_empty()
+    }
+
+    #[quickcheck]
+    fn merge_equivalent(xs: Plan<String, u32>, changes: Vec<(String, Option<u32>)>) -> bool {
+        let (h1, reference1) = arbitrary_hamt_and_btree(xs, next_u32, |v| v.wrapping_mul(2));
+        let (h2, reference2) = apply_changes(&h1, &reference1, &changes);
+
+        let mut reference = reference1;
+        reference.extend(reference2);
+        property_btreemap_eq(&reference, &h1.merge(&h2))
+    }
+
+    #[quickcheck]
+    fn union_with_equivalent(xs: Vec<(String, u32)>, ys: Vec<(String, u32)>) -> bool {
+        let h1: Hamt<DefaultHasher, String, u32> = xs.iter().cloned().collect();
+        let h2: Hamt<DefaultHasher, String, u32> = ys.iter().cloned().collect();
+        let reference1: BTreeMap<_, _> = h1.iter().map(|(k, v)| (k.clone(), *v)).collect();
+
+        let mut reference = reference1;
+        for (k, v) in h2.iter() {
+            let v = match reference.get(k) {
+                Some(v1) => v1.wrapping_add(*v),
+                None => *v,
+            };
+            reference.insert(k.clone(), v);
+        }
+        let union = h1.union_with(&h2, |v1, v2| v1.wrapping_add(*v2));
+        property_btreemap_eq(&reference, &union) && union.size() == reference.len()
+    }
 }
 
 #[cfg(test)]
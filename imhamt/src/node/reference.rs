@@ -1,5 +1,5 @@
 use super::super::bitmap::{ArrayIndex, SmallBitmap};
-use super::super::hash::{HashedKey, LevelIndex};
+use super::super::hash::{HashedKey, LevelIndex, SIZE};
 use super::super::helper;
 use super::super::operation::*;
 use super::super::sharedref::SharedRef;
@@ -538,6 +538,173 @@ where
     }
 }
 
+// recursively lookup a key, starting from a node at the given level
+pub fn lookup_rec<'a, K: PartialEq, V>(
+    node: &'a Node<K, V>,
+    h: &HashedKey,
+    lvl: usize,
+    k: &K,
+) -> Option<&'a V> {
+    match lookup_one(node, h, lvl, k) {
+        LookupRet::NotFound => None,
+        LookupRet::Found(v) => Some(v),
+        LookupRet::ContinueIn(subnode) => lookup_rec(subnode, h, lvl + 1, k),
+    }
+}
+
+// collect all the key values under an entry, with their hash
+fn entry_items<'a, K, V>(entry: &'a Entry<K, V>, out: &mut Vec<(HashedKey, &'a K, &'a V)>) {
+    match entry {
+        Entry::Leaf(h, k, v) => out.push((*h, k, v)),
+        Entry::LeafMany(h, col) => out.extend(col.iter().map(|(k, v)| (*h, k, v))),
+        Entry::SubNode(sub) => {
+            for c in sub.iter() {
+                entry_items(c, out)
+            }
+        }
+    }
+}
+
+// differences between two entries at the same position, by comparing
+// all their key values
+fn diff_entries<'a, K: PartialEq, V: PartialEq>(
+    old: &'a Entry<K, V>,
+    new: &'a Entry<K, V>,
+    out: &mut Vec<DiffEntry<'a, K, V>>,
+) {
+    let mut old_items = Vec::new();
+    let mut new_items = Vec::new();
+    entry_items(old, &mut old_items);
+    entry_items(new, &mut new_items);
+
+    for (h, k, v) in old_items.iter() {
+        match new_items.iter().find(|(nh, nk, _)| nh == h && nk == k) {
+            None => out.push(DiffEntry::Removed(k, v)),
+            Some((_, _, nv)) => {
+                if v != nv {
+                    out.push(DiffEntry::Changed(k, v, nv))
+                }
+            }
+        }
+    }
+    for (h, k, v) in new_items.iter() {
+        if !old_items.iter().any(|(oh, ok, _)| oh == h && ok == k) {
+            out.push(DiffEntry::Added(k, v))
+        }
+    }
+}
+
+// recursively collect the differences between two nodes at the same level.
+//
+// the children shared by both nodes are skipped without being visited,
+// so the cost is proportional to the number of children that differ.
+pub fn diff_rec<'a, K: PartialEq, V: PartialEq>(
+    old: &'a Node<K, V>,
+    new: &'a Node<K, V>,
+    out: &mut Vec<DiffEntry<'a, K, V>>,
+) {
+    for i in 0..SIZE {
+        let level_hash = LevelIndex(i);
+        let old_idx = old.bitmap.get_index_sparse(level_hash);
+        let new_idx = new.bitmap.get_index_sparse(level_hash);
+        match (old_idx.is_not_found(), new_idx.is_not_found()) {
+            (true, true) => {}
+            (false, true) => {
+                let mut items = Vec::new();
+                entry_items(old.get_child(old_idx), &mut items);
+                out.extend(items.into_iter().map(|(_, k, v)| DiffEntry::Removed(k, v)))
+            }
+            (true, false) => {
+                let mut items = Vec::new();
+                entry_items(new.get_child(new_idx), &mut items);
+                out.extend(items.into_iter().map(|(_, k, v)| DiffEntry::Added(k, v)))
+            }
+            (false, false) => {
+                let old_child = old.get_child(old_idx);
+                let new_child = new.get_child(new_idx);
+                if SharedRef::ptr_eq(old_child, new_child) {
+                    continue;
+                }
+                match (old_child.as_ref(), new_child.as_ref()) {
+                    (Entry::SubNode(old_sub), Entry::SubNode(new_sub)) => {
+                        diff_rec(old_sub, new_sub, out)
+                    }
+                    (old_entry, new_entry) => diff_entries(old_entry, new_entry, out),
+                }
+            }
+        }
+    }
+}
+
+// recursively merge two nodes at the same level, combining with f the values
+// of the keys present in both nodes.
+//
+// the children present in only one node are shared with the result. if
+// `skip_shared` is set, the children shared by both nodes are kept as they
+// are, which is only valid when f returns one of two equal values.
+pub fn merge_rec<K: Clone + PartialEq, V: Clone, F>(
+    a: &Node<K, V>,
+    b: &Node<K, V>,
+    lvl: usize,
+    skip_shared: bool,
+    f: &F,
+) -> Node<K, V>
+where
+    F: Fn(&V, &V) -> V,
+{
+    let mut bitmap = SmallBitmap::new();
+    let mut children = Vec::with_capacity(std::cmp::max(a.children.len(), b.children.len()));
+    for i in 0..SIZE {
+        let level_hash = LevelIndex(i);
+        let a_idx = a.bitmap.get_index_sparse(level_hash);
+        let b_idx = b.bitmap.get_index_sparse(level_hash);
+        let child = match (a_idx.is_not_found(), b_idx.is_not_found()) {
+            (true, true) => continue,
+            (false, true) => SharedRef::clone(a.get_child(a_idx)),
+            (true, false) => SharedRef::clone(b.get_child(b_idx)),
+            (false, false) => {
+                let a_child = a.get_child(a_idx);
+                let b_child = b.get_child(b_idx);
+                if skip_shared && SharedRef::ptr_eq(a_child, b_child) {
+                    SharedRef::clone(a_child)
+                } else {
+                    match (a_child.as_ref(), b_child.as_ref()) {
+                        (Entry::SubNode(a_sub), Entry::SubNode(b_sub)) => SharedRef::new(
+                            Entry::SubNode(merge_rec(a_sub, b_sub, lvl + 1, skip_shared, f)),
+                        ),
+                        (_, b_entry) => {
+                            // add the key values of b in a node holding only
+                            // the child of a, at the same level
+                            let mut node = Node::singleton(level_hash, SharedRef::clone(a_child));
+                            let mut items = Vec::new();
+                            entry_items(b_entry, &mut items);
+                            for (h, k, v) in items {
+                                let newv = lookup_rec(&node, &h, lvl, k).map(|av| f(av, v));
+                                node = match newv {
+                                    Some(newv) => {
+                                        replace_rec(&node, h, lvl, k, newv)
+                                            .expect("key is present in the node")
+                                            .0
+                                    }
+                                    None => insert_rec(&node, h, lvl, k.clone(), v.clone())
+                                        .expect("key is not present in the node"),
+                                };
+                            }
+                            SharedRef::clone(node.get_child(ArrayIndex::create(0)))
+                        }
+                    }
+                }
+            }
+        };
+        bitmap = bitmap.set_index(level_hash);
+        children.push(child);
+    }
+    Node {
+        bitmap,
+        children: children.into(),
+    }
+}
+
 pub fn size_rec<K, V>(node: &Node<K, V>) -> usize {
     let mut sum = 0;
     for c in node.children.iter() {
@@ -19,3 +19,14 @@ pub enum UpdateError<T> {
 pub enum ReplaceError {
     KeyNotFound,
 }
+
+/// Difference of an entry between two versions of a HAMT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffEntry<'a, K, V> {
+    /// Entry only present in the new version
+    Added(&'a K, &'a V),
+    /// Entry only present in the old version
+    Removed(&'a K, &'a V),
+    /// Entry present in both versions, with the old and the new value
+    Changed(&'a K, &'a V, &'a V),
+}
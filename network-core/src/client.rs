@@ -5,6 +5,8 @@ mod fragment;
 mod gossip;
 mod p2p;
 
+pub mod sync;
+
 pub use block::{BlockService, HandshakeError};
 pub use fragment::FragmentService;
 pub use gossip::GossipService;
@@ -147,6 +147,9 @@ pub enum HandshakeError {
     /// The protocol version reported by the server is not supported.
     /// Carries the reported version in a human-readable form.
     UnsupportedVersion(Box<str>),
+    /// Authentication of the server node, or of this node by the server,
+    /// has failed.
+    Authentication(Box<str>),
     /// Error occurred with the protocol request.
     Rpc(Error),
 }
@@ -157,6 +160,9 @@ impl fmt::Display for HandshakeError {
             HandshakeError::UnsupportedVersion(v) => {
                 write!(f, "unsupported protocol version {}", v)
             }
+            HandshakeError::Authentication(msg) => {
+                write!(f, "node authentication failed: {}", msg)
+            }
             HandshakeError::Rpc(e) => write!(f, "{}", e),
         }
     }
@@ -1,6 +1,6 @@
 use super::p2p::P2pService;
 use crate::error::Error;
-use chain_core::property::Fragment;
+use chain_core::property::{BlockId, Fragment};
 
 use futures::prelude::*;
 
@@ -10,6 +10,10 @@ pub trait FragmentService: P2pService {
     /// The data type to represent fragments constituting a block.
     type Fragment: Fragment;
 
+    /// The block identifier type, which is the same as that of
+    /// the block service of the client.
+    type BlockId: BlockId;
+
     /// The type of an asynchronous stream that provides blocks in
     /// response to method `get_blocks`.
     type GetFragmentsStream: Stream<Item = Self::Fragment, Error = Error>;
@@ -26,6 +30,24 @@ pub trait FragmentService: P2pService {
         ids: &[<Self::Fragment as Fragment>::Id],
     ) -> Self::GetFragmentsFuture;
 
+    /// Retrieves fragments of the identified block by their positions
+    /// in the block, in the order of the requested positions.
+    ///
+    /// This is used to fetch the fragments that are missing from
+    /// a block reconstructed from a compact block announcement;
+    /// see the `compact` module.
+    ///
+    /// The request fails immediately if the implementation does not
+    /// support it; the default implementation returns an error with
+    /// `Code::Unimplemented`.
+    fn get_block_fragments(
+        &mut self,
+        _block_id: &Self::BlockId,
+        _indices: &[u32],
+    ) -> Result<Self::GetFragmentsFuture, Error> {
+        Err(Error::unimplemented())
+    }
+
     /// The type of asynchronous futures returned by method `content_subscription`.
     ///
     /// The future resolves to a stream of fragments sent by the remote node
@@ -0,0 +1,1160 @@
+//! Header-first chain synchronization.
+//!
+//! `ChainSync` drives the synchronization of a local block store with
+//! the chains of a set of peers, using the client `BlockService`
+//! interface of the peer connections:
+//!
+//! 1. The tips of all peers are requested, and the peers with chains
+//!    longer than the local chain are ranked by their chain length.
+//! 2. Headers are pulled from the best peer, passing checkpoints
+//!    sampled from the local chain with exponentially increasing distance
+//!    from the tip, so that the peer can find the common ancestor.
+//!    The headers are checked to form a chain connected to a block
+//!    in the local store and ending at the tip announced by the peer,
+//!    and validated with an optional application callback.
+//!    If the peer fails, the next best peer is tried.
+//! 3. Block bodies are fetched in batches with `get_blocks`, in parallel
+//!    from all peers that have the chain, and stored in the chain order.
+//!    A batch that fails is retried with other peers, up to the configured
+//!    number of attempts.
+
+use super::BlockService;
+use crate::error::{Code, Error};
+
+use chain_core::property::{Block, ChainLength as _, HasHeader, Header};
+use chain_storage::{error::Error as StorageError, store::BlockStore};
+
+use futures::{future, prelude::*, stream, try_ready};
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, VecDeque},
+    error, fmt, mem,
+};
+
+type BlockId<P> = <<P as BlockService>::Block as Block>::Id;
+type ChainLength<P> = <<P as BlockService>::Block as Block>::ChainLength;
+type BlockHeader<P> = <<P as BlockService>::Block as HasHeader>::Header;
+type CollectBlocks<P> = stream::Collect<<P as BlockService>::GetBlocksStream>;
+type FetchFuture<P> = future::AndThen<
+    <P as BlockService>::GetBlocksFuture,
+    CollectBlocks<P>,
+    fn(<P as BlockService>::GetBlocksStream) -> CollectBlocks<P>,
+>;
+type Fetch<P> = Option<(Batch<BlockId<P>>, FetchFuture<P>)>;
+type HeaderValidator<H> = Box<dyn FnMut(&H) -> Result<(), Error> + Send>;
+
+/// Parameters of the chain synchronization.
+#[derive(Clone, Debug)]
+pub struct SyncConfig {
+    /// Maximum number of blocks requested from a peer in one
+    /// `get_blocks` call.
+    pub batch_size: usize,
+    /// Maximum number of attempts to fetch a batch of blocks,
+    /// across all peers.
+    pub max_attempts: u32,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            batch_size: 100,
+            max_attempts: 3,
+        }
+    }
+}
+
+/// Builder of the chain synchronization process.
+pub struct ChainSync<S, P>
+where
+    P: BlockService,
+{
+    store: S,
+    peers: Vec<P>,
+    config: SyncConfig,
+    validator: Option<HeaderValidator<BlockHeader<P>>>,
+}
+
+impl<S, P> ChainSync<S, P>
+where
+    S: BlockStore<Block = P::Block>,
+    P: BlockService,
+    BlockHeader<P>: Header<Id = BlockId<P>, ChainLength = ChainLength<P>>,
+{
+    /// Sets up synchronization of the block store with the chains
+    /// of the given peers.
+    ///
+    /// The peer connections should have completed the handshake.
+    pub fn new(store: S, peers: Vec<P>) -> Self {
+        ChainSync {
+            store,
+            peers,
+            config: SyncConfig::default(),
+            validator: None,
+        }
+    }
+
+    pub fn config(&mut self, config: SyncConfig) -> &mut Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the application callback to validate the headers received
+    /// from peers, in the chain order.
+    ///
+    /// Failing the validation makes the synchronization abandon
+    /// the headers received from the peer and try the next best peer.
+    pub fn validate_headers<F>(&mut self, validator: F) -> &mut Self
+    where
+        F: FnMut(&BlockHeader<P>) -> Result<(), Error> + Send + 'static,
+    {
+        self.validator = Some(Box::new(validator));
+        self
+    }
+
+    /// Starts synchronizing the chain ending at the given block
+    /// in the local store.
+    ///
+    /// The returned future resolves to the outcome of the synchronization,
+    /// giving back the store and the peer connections. If the
+    /// synchronization fails, they are given back with the error.
+    pub fn run(self, local_tip: BlockId<P>) -> SyncFuture<S, P> {
+        let mut ctx = Context {
+            store: self.store,
+            peers: self.peers,
+            config: self.config,
+            validator: self.validator,
+            tip: local_tip,
+            blocks_stored: 0,
+        };
+        let state = match ctx.store.get_block(&ctx.tip) {
+            Ok((block, _)) => State::Tips(TipsPhase::new(&mut ctx.peers, block.chain_length())),
+            Err(e) => State::Failed(e.into()),
+        };
+        SyncFuture {
+            ctx: Some(ctx),
+            state,
+        }
+    }
+}
+
+/// Returns identifiers of blocks sampled from the chain ending at `tip`,
+/// to be used as the starting points in a `pull_headers` request.
+///
+/// The checkpoints start with the tip and follow with ancestors
+/// at exponentially increasing distances, ending with the genesis block.
+pub fn checkpoints<S>(
+    store: &S,
+    tip: &<S::Block as Block>::Id,
+) -> Result<Vec<<S::Block as Block>::Id>, StorageError>
+where
+    S: ?Sized + BlockStore,
+{
+    let genesis_distance = store.get_block_info(tip)?.depth - 1;
+    let mut ids = Vec::new();
+    let mut distance = 0;
+    let mut step = 1;
+    while distance < genesis_distance {
+        ids.push(store.get_nth_ancestor(tip, distance)?.block_hash);
+        distance += step;
+        step *= 2;
+    }
+    ids.push(store.get_nth_ancestor(tip, genesis_distance)?.block_hash);
+    Ok(ids)
+}
+
+/// The result of a successful synchronization.
+pub struct SyncOutcome<S, P>
+where
+    P: BlockService,
+{
+    /// The block store.
+    pub store: S,
+    /// The peer connections.
+    pub peers: Vec<P>,
+    /// The identifier of the new tip of the synchronized chain, or of the
+    /// original local tip if no peer has a longer chain.
+    pub tip: BlockId<P>,
+    /// The number of blocks added to the store.
+    pub blocks_stored: u64,
+}
+
+/// The result of a failed synchronization.
+///
+/// The blocks stored before the failure are kept in the store, and
+/// the synchronization can be resumed from the returned tip.
+pub struct SyncFailure<S, P>
+where
+    P: BlockService,
+{
+    /// The cause of the failure.
+    pub error: SyncError,
+    /// The block store.
+    pub store: S,
+    /// The peer connections.
+    pub peers: Vec<P>,
+    /// The identifier of the last block stored in the chain order, or of
+    /// the original local tip if no blocks have been stored.
+    pub tip: BlockId<P>,
+    /// The number of blocks added to the store.
+    pub blocks_stored: u64,
+}
+
+impl<S, P> fmt::Debug for SyncFailure<S, P>
+where
+    P: BlockService,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncFailure")
+            .field("error", &self.error)
+            .field("tip", &self.tip)
+            .field("blocks_stored", &self.blocks_stored)
+            .finish()
+    }
+}
+
+impl<S, P> fmt::Display for SyncFailure<S, P>
+where
+    P: BlockService,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<S, P> error::Error for SyncFailure<S, P>
+where
+    P: BlockService,
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// An error that can occur in the chain synchronization.
+#[derive(Debug)]
+pub enum SyncError {
+    /// Error occurred with the local block store.
+    Storage(StorageError),
+    /// The peers have failed to provide the chain data.
+    /// Carries the last error reported by a peer or detected in validation.
+    Network(Error),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Storage(e) => write!(f, "block storage error: {}", e),
+            SyncError::Network(e) => write!(f, "failed to retrieve the chain from peers: {}", e),
+        }
+    }
+}
+
+impl error::Error for SyncError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SyncError::Storage(e) => Some(e),
+            SyncError::Network(e) => Some(e),
+        }
+    }
+}
+
+impl From<StorageError> for SyncError {
+    fn from(err: StorageError) -> Self {
+        SyncError::Storage(err)
+    }
+}
+
+/// Future driving the chain synchronization.
+#[must_use = "futures do nothing unless polled"]
+pub struct SyncFuture<S, P>
+where
+    P: BlockService,
+{
+    ctx: Option<Context<S, P>>,
+    state: State<P>,
+}
+
+struct Context<S, P>
+where
+    P: BlockService,
+{
+    store: S,
+    peers: Vec<P>,
+    config: SyncConfig,
+    validator: Option<HeaderValidator<BlockHeader<P>>>,
+    tip: BlockId<P>,
+    blocks_stored: u64,
+}
+
+enum State<P>
+where
+    P: BlockService,
+{
+    Tips(TipsPhase<P>),
+    Headers(HeadersPhase<P>),
+    Blocks(BlocksPhase<P>),
+    Failed(SyncError),
+    Finished,
+}
+
+// The state to transition to, or `None` if the synchronization is complete.
+type Transition<P> = Option<State<P>>;
+
+impl<S, P> Future for SyncFuture<S, P>
+where
+    S: BlockStore<Block = P::Block>,
+    P: BlockService,
+    BlockHeader<P>: Header<Id = BlockId<P>, ChainLength = ChainLength<P>>,
+{
+    type Item = SyncOutcome<S, P>;
+    type Error = SyncFailure<S, P>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let ctx = self.ctx.as_mut().expect("polled a finished future");
+            let next = match &mut self.state {
+                State::Tips(phase) => phase.poll(ctx),
+                State::Headers(phase) => phase.poll(ctx),
+                State::Blocks(phase) => phase.poll(ctx),
+                State::Failed(_) => Ok(Async::Ready(None)),
+                State::Finished => panic!("polled a finished future"),
+            };
+            let next = match next {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(next)) => Ok(next),
+                Err(e) => Err(e),
+            };
+            match (mem::replace(&mut self.state, State::Finished), next) {
+                (State::Failed(error), _) | (_, Err(error)) => {
+                    let ctx = self.ctx.take().unwrap();
+                    return Err(SyncFailure {
+                        error,
+                        store: ctx.store,
+                        peers: ctx.peers,
+                        tip: ctx.tip,
+                        blocks_stored: ctx.blocks_stored,
+                    });
+                }
+                (_, Ok(Some(state))) => self.state = state,
+                (_, Ok(None)) => {
+                    let ctx = self.ctx.take().unwrap();
+                    return Ok(Async::Ready(SyncOutcome {
+                        store: ctx.store,
+                        peers: ctx.peers,
+                        tip: ctx.tip,
+                        blocks_stored: ctx.blocks_stored,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+// Requests the tips of all peers.
+struct TipsPhase<P>
+where
+    P: BlockService,
+{
+    local_length: ChainLength<P>,
+    requests: Vec<Option<P::TipFuture>>,
+    tips: Vec<Option<BlockHeader<P>>>,
+}
+
+impl<P> TipsPhase<P>
+where
+    P: BlockService,
+    BlockHeader<P>: Header<Id = BlockId<P>, ChainLength = ChainLength<P>>,
+{
+    fn new(peers: &mut [P], local_length: ChainLength<P>) -> Self {
+        TipsPhase {
+            local_length,
+            requests: peers.iter_mut().map(|peer| Some(peer.tip())).collect(),
+            tips: peers.iter().map(|_| None).collect(),
+        }
+    }
+
+    fn poll<S>(&mut self, ctx: &mut Context<S, P>) -> Poll<Transition<P>, SyncError>
+    where
+        S: BlockStore<Block = P::Block>,
+    {
+        let mut pending = false;
+        for (request, tip) in self.requests.iter_mut().zip(self.tips.iter_mut()) {
+            if let Some(future) = request {
+                match future.poll() {
+                    Ok(Async::NotReady) => pending = true,
+                    Ok(Async::Ready(header)) => {
+                        *tip = Some(header);
+                        *request = None;
+                    }
+                    // Peers failing to report the tip are not used.
+                    Err(_) => *request = None,
+                }
+            }
+        }
+        if pending {
+            return Ok(Async::NotReady);
+        }
+        let mut candidates: Vec<(usize, BlockHeader<P>)> = mem::take(&mut self.tips)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, tip)| tip.map(|tip| (i, tip)))
+            .filter(|(_, tip)| tip.chain_length() > self.local_length)
+            .collect();
+        if candidates.is_empty() {
+            return Ok(Async::Ready(None));
+        }
+        candidates.sort_by_key(|(_, tip)| Reverse(tip.chain_length()));
+        let checkpoints = checkpoints(&ctx.store, &ctx.tip)?;
+        let phase = HeadersPhase::new(ctx, candidates, checkpoints);
+        Ok(Async::Ready(Some(State::Headers(phase))))
+    }
+}
+
+// Pulls headers from the best peer, falling back to the next best
+// peers on failure.
+struct HeadersPhase<P>
+where
+    P: BlockService,
+{
+    // Peers with longer chains and their tips, the best peer first.
+    candidates: Vec<(usize, BlockHeader<P>)>,
+    current: usize,
+    checkpoints: Vec<BlockId<P>>,
+    pull: PullHeaders<P>,
+    headers: Vec<BlockHeader<P>>,
+}
+
+enum PullHeaders<P>
+where
+    P: BlockService,
+{
+    Requesting(P::PullHeadersFuture),
+    Streaming(P::PullHeadersStream),
+}
+
+impl<P> HeadersPhase<P>
+where
+    P: BlockService,
+    BlockHeader<P>: Header<Id = BlockId<P>, ChainLength = ChainLength<P>>,
+{
+    fn new<S>(
+        ctx: &mut Context<S, P>,
+        candidates: Vec<(usize, BlockHeader<P>)>,
+        checkpoints: Vec<BlockId<P>>,
+    ) -> Self {
+        let pull = Self::request(ctx, &candidates[0], &checkpoints);
+        HeadersPhase {
+            candidates,
+            current: 0,
+            checkpoints,
+            pull,
+            headers: Vec::new(),
+        }
+    }
+
+    fn request<S>(
+        ctx: &mut Context<S, P>,
+        candidate: &(usize, BlockHeader<P>),
+        checkpoints: &[BlockId<P>],
+    ) -> PullHeaders<P> {
+        let (peer, tip) = candidate;
+        PullHeaders::Requesting(ctx.peers[*peer].pull_headers(checkpoints, &tip.id()))
+    }
+
+    fn poll<S>(&mut self, ctx: &mut Context<S, P>) -> Poll<Transition<P>, SyncError>
+    where
+        S: BlockStore<Block = P::Block>,
+    {
+        loop {
+            match self.poll_headers(ctx) {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => break,
+                Err(PeerFailure::Storage(e)) => return Err(e.into()),
+                Err(PeerFailure::Network(e)) => {
+                    self.current += 1;
+                    if self.current == self.candidates.len() {
+                        return Err(SyncError::Network(e));
+                    }
+                    self.headers.clear();
+                    self.pull =
+                        Self::request(ctx, &self.candidates[self.current], &self.checkpoints);
+                }
+            }
+        }
+        let headers = mem::take(&mut self.headers);
+        // The headers end at the tip announced by the peer.
+        let target_length = self.candidates[self.current].1.chain_length();
+        // Bodies are fetched from all peers with chains at least as long
+        // as the chain to synchronize, starting with the one that
+        // has provided the headers. Peers that have failed to provide
+        // the headers are not used.
+        let mut peers = vec![self.candidates[self.current].0];
+        peers.extend(
+            self.candidates[self.current + 1..]
+                .iter()
+                .filter(|(_, tip)| tip.chain_length() >= target_length)
+                .map(|(i, _)| *i),
+        );
+        let ids = headers.iter().map(|header| header.id()).collect();
+        let phase = BlocksPhase::new(ids, peers, &ctx.config);
+        Ok(Async::Ready(Some(State::Blocks(phase))))
+    }
+
+    fn poll_headers<S>(&mut self, ctx: &mut Context<S, P>) -> Poll<(), PeerFailure>
+    where
+        S: BlockStore<Block = P::Block>,
+    {
+        loop {
+            let stream = match &mut self.pull {
+                PullHeaders::Requesting(future) => {
+                    let stream = try_ready!(future.poll());
+                    self.pull = PullHeaders::Streaming(stream);
+                    continue;
+                }
+                PullHeaders::Streaming(stream) => stream,
+            };
+            match try_ready!(stream.poll()) {
+                Some(header) => {
+                    self.check_header(ctx, &header)?;
+                    self.headers.push(header);
+                }
+                None => {
+                    // A peer that has announced a longer chain must
+                    // provide the headers up to its tip.
+                    let (_, tip) = &self.candidates[self.current];
+                    return match self.headers.last() {
+                        Some(last) if last.id() == tip.id() => Ok(Async::Ready(())),
+                        _ => Err(
+                            invalid_data("received headers do not end at the announced tip").into(),
+                        ),
+                    };
+                }
+            }
+        }
+    }
+
+    fn check_header<S>(
+        &self,
+        ctx: &mut Context<S, P>,
+        header: &BlockHeader<P>,
+    ) -> Result<(), PeerFailure>
+    where
+        S: BlockStore<Block = P::Block>,
+    {
+        let (_, tip) = &self.candidates[self.current];
+        // With the chain lengths checked to increase by one from
+        // the local parent, this caps the number of headers at
+        // the distance from the parent to the announced tip.
+        if header.chain_length() > tip.chain_length() {
+            return Err(invalid_data("received headers extend past the announced tip").into());
+        }
+        let parent_length = match self.headers.last() {
+            Some(prev) => {
+                if header.parent_id() != prev.id() {
+                    return Err(invalid_data("received headers do not form a chain").into());
+                }
+                prev.chain_length()
+            }
+            None => match ctx.store.get_block(&header.parent_id()) {
+                Ok((parent, _)) => parent.chain_length(),
+                Err(StorageError::BlockNotFound) => {
+                    return Err(
+                        invalid_data("received headers do not connect to the local chain").into(),
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            },
+        };
+        if header.chain_length() != parent_length.next() {
+            return Err(invalid_data("received headers do not form a chain").into());
+        }
+        if let Some(validator) = &mut ctx.validator {
+            validator(header)?;
+        }
+        Ok(())
+    }
+}
+
+// Fetches block bodies in parallel from multiple peers.
+struct BlocksPhase<P>
+where
+    P: BlockService,
+{
+    queue: BatchQueue<BlockId<P>, P::Block>,
+    // Peers used for fetching and their requests in progress.
+    fetches: Vec<(usize, Fetch<P>)>,
+}
+
+impl<P> BlocksPhase<P>
+where
+    P: BlockService,
+{
+    fn new(ids: Vec<BlockId<P>>, peers: Vec<usize>, config: &SyncConfig) -> Self {
+        BlocksPhase {
+            queue: BatchQueue::new(ids, config.batch_size, config.max_attempts),
+            fetches: peers.into_iter().map(|peer| (peer, None)).collect(),
+        }
+    }
+
+    fn poll<S>(&mut self, ctx: &mut Context<S, P>) -> Poll<Transition<P>, SyncError>
+    where
+        S: BlockStore<Block = P::Block>,
+    {
+        loop {
+            let mut progress = false;
+            for (peer, fetch) in self.fetches.iter_mut() {
+                if fetch.is_none() {
+                    if let Some(batch) = self.queue.next_batch(*peer) {
+                        let future = ctx.peers[*peer]
+                            .get_blocks(&batch.ids)
+                            .and_then(Stream::collect as fn(_) -> _);
+                        *fetch = Some((batch, future));
+                    }
+                }
+                let result = match fetch {
+                    Some((_, future)) => match future.poll() {
+                        Ok(Async::NotReady) => continue,
+                        Ok(Async::Ready(blocks)) => Ok(blocks),
+                        Err(e) => Err(e),
+                    },
+                    None => continue,
+                };
+                progress = true;
+                let (batch, _) = fetch.take().unwrap();
+                let result = result.and_then(|blocks| {
+                    if blocks
+                        .iter()
+                        .map(|block| block.id())
+                        .eq(batch.ids.iter().cloned())
+                    {
+                        Ok(blocks)
+                    } else {
+                        Err(invalid_data("received blocks do not match the request"))
+                    }
+                });
+                match result {
+                    Ok(blocks) => self.queue.complete(batch.index, blocks),
+                    Err(e) => {
+                        if !self.queue.fail(batch, *peer) {
+                            return Err(SyncError::Network(e));
+                        }
+                    }
+                }
+            }
+            while let Some(blocks) = self.queue.pop_ready() {
+                for block in blocks {
+                    match ctx.store.put_block(&block) {
+                        Ok(()) => ctx.blocks_stored += 1,
+                        Err(StorageError::BlockAlreadyPresent) => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                    ctx.tip = block.id();
+                }
+            }
+            if self.queue.is_done() {
+                return Ok(Async::Ready(None));
+            }
+            if !progress {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+enum PeerFailure {
+    Network(Error),
+    Storage(StorageError),
+}
+
+impl From<Error> for PeerFailure {
+    fn from(err: Error) -> Self {
+        PeerFailure::Network(err)
+    }
+}
+
+impl From<StorageError> for PeerFailure {
+    fn from(err: StorageError) -> Self {
+        PeerFailure::Storage(err)
+    }
+}
+
+fn invalid_data(msg: &'static str) -> Error {
+    Error::new(Code::InvalidArgument, msg)
+}
+
+// A batch of block identifiers to fetch.
+struct Batch<Id> {
+    index: usize,
+    ids: Vec<Id>,
+    failures: u32,
+    failed_peers: Vec<usize>,
+}
+
+// Schedules batches of blocks for fetching and reorders the fetched
+// batches in the chain order.
+struct BatchQueue<Id, B> {
+    pending: VecDeque<Batch<Id>>,
+    completed: BTreeMap<usize, Vec<B>>,
+    next_index: usize,
+    num_batches: usize,
+    max_attempts: u32,
+}
+
+impl<Id, B> BatchQueue<Id, B> {
+    fn new(ids: Vec<Id>, batch_size: usize, max_attempts: u32) -> Self {
+        let mut pending = VecDeque::new();
+        let mut ids = ids.into_iter().peekable();
+        while ids.peek().is_some() {
+            let batch = Batch {
+                index: pending.len(),
+                ids: ids.by_ref().take(batch_size.max(1)).collect(),
+                failures: 0,
+                failed_peers: Vec::new(),
+            };
+            pending.push_back(batch);
+        }
+        BatchQueue {
+            num_batches: pending.len(),
+            pending,
+            completed: BTreeMap::new(),
+            next_index: 0,
+            max_attempts,
+        }
+    }
+
+    // Takes the next batch to fetch from the peer, preferring the batches
+    // that have not failed with this peer.
+    fn next_batch(&mut self, peer: usize) -> Option<Batch<Id>> {
+        let pos = self
+            .pending
+            .iter()
+            .position(|batch| !batch.failed_peers.contains(&peer))
+            .unwrap_or(0);
+        self.pending.remove(pos)
+    }
+
+    fn complete(&mut self, index: usize, blocks: Vec<B>) {
+        self.completed.insert(index, blocks);
+    }
+
+    // Returns the failed batch to the queue. Returns false if the batch
+    // has run out of attempts.
+    fn fail(&mut self, mut batch: Batch<Id>, peer: usize) -> bool {
+        batch.failures += 1;
+        if batch.failures >= self.max_attempts {
+            return false;
+        }
+        batch.failed_peers.push(peer);
+        self.pending.push_front(batch);
+        true
+    }
+
+    // Returns the next batch of fetched blocks in the chain order,
+    // if it is available.
+    fn pop_ready(&mut self) -> Option<Vec<B>> {
+        let blocks = self.completed.remove(&self.next_index)?;
+        self.next_index += 1;
+        Some(blocks)
+    }
+
+    fn is_done(&self) -> bool {
+        self.next_index == self.num_batches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{HandshakeError, P2pService};
+    use crate::subscription::BlockEvent;
+    use crate::testing::TestNodeId;
+    use chain_storage::{
+        memory::MemoryBlockStore,
+        store::testing::{self, Block},
+    };
+
+    use futures::future::FutureResult;
+    use futures::stream::IterOk;
+
+    use std::sync::{Arc, Mutex};
+    use std::vec;
+
+    type TestBlockId = <Block as chain_core::property::Block>::Id;
+    type Blocks = IterOk<vec::IntoIter<Block>, Error>;
+    type SyncResult = Result<
+        SyncOutcome<MemoryBlockStore<Block>, MockPeer>,
+        SyncFailure<MemoryBlockStore<Block>, MockPeer>,
+    >;
+
+    // Client connection to a peer serving the given chain.
+    #[derive(Default)]
+    struct MockPeer {
+        chain: Vec<Block>,
+        // Fails the `tip` request if set.
+        fail_tip: bool,
+        // Headers sent in response to `pull_headers` instead of
+        // the chain following the checkpoints.
+        headers: Option<Vec<Block>>,
+        // Number of `get_blocks` requests to fail.
+        failing_fetches: usize,
+        // Number of blocks at the start of the chain that are served
+        // by `get_blocks`, if not all.
+        served_blocks: Option<usize>,
+        // Identifiers requested with `get_blocks`.
+        fetched: Vec<TestBlockId>,
+    }
+
+    impl MockPeer {
+        fn new(chain: &[Block]) -> Self {
+            MockPeer {
+                chain: chain.to_vec(),
+                ..Default::default()
+            }
+        }
+
+        fn position(&self, id: &TestBlockId) -> Option<usize> {
+            self.chain.iter().position(|block| block.id() == *id)
+        }
+    }
+
+    fn unimplemented<T, E: From<Error>>() -> FutureResult<T, E> {
+        future::err(Error::unimplemented().into())
+    }
+
+    impl P2pService for MockPeer {
+        type NodeId = TestNodeId;
+    }
+
+    impl BlockService for MockPeer {
+        type Block = Block;
+        type HandshakeFuture = FutureResult<TestBlockId, HandshakeError>;
+        type TipFuture = FutureResult<Block, Error>;
+        type PullBlocksStream = Blocks;
+        type PullBlocksToTipFuture = FutureResult<Blocks, Error>;
+        type PullHeadersStream = Blocks;
+        type PullHeadersFuture = FutureResult<Blocks, Error>;
+        type GetBlocksStream = Blocks;
+        type GetBlocksFuture = FutureResult<Blocks, Error>;
+        type PushHeadersFuture = FutureResult<(), Error>;
+        type UploadBlocksFuture = FutureResult<(), Error>;
+        type BlockSubscriptionFuture = FutureResult<(Self::BlockSubscription, TestNodeId), Error>;
+        type BlockSubscription = stream::Empty<BlockEvent<Block>, Error>;
+
+        fn handshake(&mut self) -> Self::HandshakeFuture {
+            future::ok(self.chain[0].id())
+        }
+
+        fn tip(&mut self) -> Self::TipFuture {
+            if self.fail_tip {
+                return future::err(Error::new(Code::Unavailable, "tip is not available"));
+            }
+            future::ok(self.chain.last().unwrap().clone())
+        }
+
+        fn pull_blocks_to_tip(&mut self, _: &[TestBlockId]) -> Self::PullBlocksToTipFuture {
+            unimplemented()
+        }
+
+        fn pull_headers(
+            &mut self,
+            from: &[TestBlockId],
+            to: &TestBlockId,
+        ) -> Self::PullHeadersFuture {
+            if let Some(headers) = &self.headers {
+                return future::ok(stream::iter_ok(headers.clone()));
+            }
+            let start = from.iter().filter_map(|id| self.position(id)).max();
+            match (start, self.position(to)) {
+                (Some(start), Some(end)) => {
+                    future::ok(stream::iter_ok(self.chain[start + 1..=end].to_vec()))
+                }
+                _ => future::err(Error::new(Code::NotFound, "block not found")),
+            }
+        }
+
+        fn get_blocks(&mut self, ids: &[TestBlockId]) -> Self::GetBlocksFuture {
+            self.fetched.extend_from_slice(ids);
+            if self.failing_fetches > 0 {
+                self.failing_fetches -= 1;
+                return future::err(Error::new(Code::Unavailable, "fetch failed"));
+            }
+            let served = self.served_blocks.unwrap_or(self.chain.len());
+            let blocks = ids
+                .iter()
+                .filter_map(|id| self.position(id).filter(|&i| i < served))
+                .map(|i| self.chain[i].clone())
+                .collect::<Vec<_>>();
+            future::ok(stream::iter_ok(blocks))
+        }
+
+        fn push_headers<S>(&mut self, _: S) -> Self::PushHeadersFuture {
+            unimplemented()
+        }
+
+        fn upload_blocks<S>(&mut self, _: S) -> Self::UploadBlocksFuture {
+            unimplemented()
+        }
+
+        fn block_subscription<S>(&mut self, _: S) -> Self::BlockSubscriptionFuture {
+            unimplemented()
+        }
+    }
+
+    // Appends `length` blocks to the chain.
+    fn extend(chain: &[Block], length: usize) -> Vec<Block> {
+        let mut chain = chain.to_vec();
+        for _ in 0..length {
+            let block = chain.last().unwrap().make_child(None);
+            chain.push(block);
+        }
+        chain
+    }
+
+    // Creates a store with a chain of the given length,
+    // returning the store and the chain.
+    fn local_store(length: usize) -> (MemoryBlockStore<Block>, Vec<Block>) {
+        let mut store = MemoryBlockStore::new();
+        let chain = testing::generate_linear_chain(&mut store, None, length);
+        (store, chain)
+    }
+
+    fn config(batch_size: usize, max_attempts: u32) -> SyncConfig {
+        SyncConfig {
+            batch_size,
+            max_attempts,
+        }
+    }
+
+    fn ids(blocks: &[Block]) -> Vec<TestBlockId> {
+        blocks.iter().map(|block| block.id()).collect()
+    }
+
+    // The failure gives back the store and the peers by value.
+    #[allow(clippy::result_large_err)]
+    fn sync(store: MemoryBlockStore<Block>, peers: Vec<MockPeer>, local_tip: &Block) -> SyncResult {
+        let mut sync = ChainSync::new(store, peers);
+        sync.config(config(2, 3));
+        sync.run(local_tip.id()).wait()
+    }
+
+    fn network_error(res: SyncResult) -> Error {
+        match res {
+            Err(SyncFailure {
+                error: SyncError::Network(e),
+                ..
+            }) => e,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("synchronization should have failed"),
+        }
+    }
+
+    #[test]
+    fn checkpoints_are_exponentially_spaced() {
+        let mut store = MemoryBlockStore::new();
+        let blocks = testing::generate_linear_chain(&mut store, None, 20);
+        let tip = blocks.last().unwrap().id();
+        let ids = checkpoints(&store, &tip).unwrap();
+        let expected: Vec<_> = [19, 18, 16, 12, 4, 0]
+            .iter()
+            .map(|&i| blocks[i].id())
+            .collect();
+        assert_eq!(ids, expected);
+
+        let genesis = blocks[0].id();
+        assert_eq!(checkpoints(&store, &genesis).unwrap(), vec![genesis]);
+    }
+
+    #[test]
+    fn batches_are_reordered() {
+        let mut queue = BatchQueue::new((0..5).collect(), 2, 3);
+        let first = queue.next_batch(0).unwrap();
+        let second = queue.next_batch(1).unwrap();
+        assert_eq!(first.ids, vec![0, 1]);
+        assert_eq!(second.ids, vec![2, 3]);
+        queue.complete(second.index, vec!["b2", "b3"]);
+        assert!(queue.pop_ready().is_none());
+        queue.complete(first.index, vec!["b0", "b1"]);
+        assert_eq!(queue.pop_ready(), Some(vec!["b0", "b1"]));
+        assert_eq!(queue.pop_ready(), Some(vec!["b2", "b3"]));
+        assert!(!queue.is_done());
+        let last = queue.next_batch(0).unwrap();
+        queue.complete(last.index, vec!["b4"]);
+        assert_eq!(queue.pop_ready(), Some(vec!["b4"]));
+        assert!(queue.is_done());
+    }
+
+    #[test]
+    fn failed_batches_are_retried_with_other_peers() {
+        let mut queue: BatchQueue<u32, ()> = BatchQueue::new((0..4).collect(), 2, 2);
+        let batch = queue.next_batch(0).unwrap();
+        assert!(queue.fail(batch, 0));
+        let batch = queue.next_batch(0).unwrap();
+        assert_eq!(batch.index, 1);
+        let batch = queue.next_batch(1).unwrap();
+        assert_eq!(batch.index, 0);
+        assert!(!queue.fail(batch, 1));
+    }
+
+    #[test]
+    fn blocks_are_fetched_from_peers_with_the_chain() {
+        let (store, local) = local_store(3);
+        let chain = extend(&local, 7);
+        let mut failing_tip = MockPeer::new(&chain);
+        failing_tip.fail_tip = true;
+        let peers = vec![
+            MockPeer::new(&chain[..6]),
+            MockPeer::new(&chain),
+            MockPeer::new(&local[..2]),
+            failing_tip,
+            MockPeer::new(&chain),
+        ];
+        let outcome = sync(store, peers, local.last().unwrap()).unwrap();
+        assert_eq!(outcome.tip, chain[9].id());
+        assert_eq!(outcome.blocks_stored, 7);
+        for block in &chain {
+            assert!(outcome.store.block_exists(&block.id()).unwrap());
+        }
+
+        // the bodies are fetched in parallel only from the peers
+        // that have announced the whole chain
+        for i in [0, 2, 3].iter() {
+            assert!(outcome.peers[*i].fetched.is_empty());
+        }
+        let fetched1 = &outcome.peers[1].fetched;
+        let fetched4 = &outcome.peers[4].fetched;
+        assert!(!fetched1.is_empty() && !fetched4.is_empty());
+        assert_eq!(fetched1.len() + fetched4.len(), 7);
+        for id in ids(&chain[3..]) {
+            assert!(fetched1.contains(&id) || fetched4.contains(&id));
+        }
+    }
+
+    #[test]
+    fn nothing_to_sync_without_longer_chains() {
+        let (store, local) = local_store(5);
+        let peers = vec![MockPeer::new(&local), MockPeer::new(&local[..3])];
+        let outcome = sync(store, peers, local.last().unwrap()).unwrap();
+        assert_eq!(outcome.tip, local[4].id());
+        assert_eq!(outcome.blocks_stored, 0);
+        assert!(outcome.peers.iter().all(|peer| peer.fetched.is_empty()));
+    }
+
+    #[test]
+    fn invalid_headers_make_the_next_peer_tried() {
+        type Headers = fn(&[Block], &[Block]) -> Vec<Block>;
+        let cases: Vec<(&str, Headers)> = vec![
+            ("no headers", |_, _| Vec::new()),
+            ("short of the tip", |local, chain| {
+                chain[local.len()..chain.len() - 1].to_vec()
+            }),
+            ("not a chain", |local, chain| {
+                let mut headers = chain[local.len()..].to_vec();
+                headers.remove(1);
+                headers
+            }),
+            ("not connected", |local, chain| {
+                chain[local.len() + 1..].to_vec()
+            }),
+            ("past the tip", |local, chain| {
+                extend(chain, 1)[local.len()..].to_vec()
+            }),
+            ("another tip", |local, chain| {
+                extend(local, chain.len() - local.len())[local.len()..].to_vec()
+            }),
+        ];
+        for (case, headers) in cases {
+            let (store, local) = local_store(3);
+            let chain = extend(&local, 5);
+            let fork = extend(&local, 4);
+            let mut best = MockPeer::new(&chain);
+            best.headers = Some(headers(&local, &chain));
+            let peers = vec![best, MockPeer::new(&fork)];
+            let outcome = sync(store, peers, local.last().unwrap())
+                .unwrap_or_else(|e| panic!("{}: {}", case, e));
+            assert_eq!(outcome.tip, fork[6].id(), "{}", case);
+            assert_eq!(outcome.blocks_stored, 4, "{}", case);
+            assert!(outcome.peers[0].fetched.is_empty(), "{}", case);
+        }
+    }
+
+    #[test]
+    fn sync_fails_when_no_peer_provides_headers() {
+        let (store, local) = local_store(3);
+        let mut peer = MockPeer::new(&extend(&local, 2));
+        peer.headers = Some(Vec::new());
+        let err = network_error(sync(store, vec![peer], local.last().unwrap()));
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn failed_fetches_are_retried_with_other_peers() {
+        let (store, local) = local_store(2);
+        let chain = extend(&local, 4);
+        let mut flaky = MockPeer::new(&chain);
+        flaky.failing_fetches = 1;
+        let outcome = sync(store, vec![flaky, MockPeer::new(&chain)], &local[1]).unwrap();
+        assert_eq!(outcome.tip, chain[5].id());
+        assert_eq!(outcome.blocks_stored, 4);
+
+        let (store, local) = local_store(2);
+        let mut failing = MockPeer::new(&extend(&local, 4));
+        failing.failing_fetches = usize::MAX;
+        let err = network_error(sync(store, vec![failing], &local[1]));
+        assert_eq!(err.code(), Code::Unavailable);
+    }
+
+    #[test]
+    fn failure_gives_back_the_stored_blocks() {
+        let (store, local) = local_store(2);
+        let chain = extend(&local, 4);
+        let mut peer = MockPeer::new(&chain);
+        peer.served_blocks = Some(4);
+        let failure = match sync(store, vec![peer], &local[1]) {
+            Err(failure) => failure,
+            Ok(_) => panic!("synchronization should have failed"),
+        };
+        match &failure.error {
+            SyncError::Network(e) => assert_eq!(e.code(), Code::InvalidArgument),
+            e => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(failure.tip, chain[3].id());
+        assert_eq!(failure.blocks_stored, 2);
+        assert_eq!(failure.peers.len(), 1);
+        for (i, block) in chain.iter().enumerate() {
+            assert_eq!(failure.store.block_exists(&block.id()).unwrap(), i < 4);
+        }
+    }
+
+    #[test]
+    fn headers_rejected_by_validator_are_abandoned() {
+        let (store, local) = local_store(3);
+        let chain = extend(&local, 5);
+        let fork = extend(&local, 3);
+        let rejected = chain[5].id();
+        let validated = Arc::new(Mutex::new(Vec::new()));
+
+        let mut sync = ChainSync::new(store, vec![MockPeer::new(&chain), MockPeer::new(&fork)]);
+        let log = validated.clone();
+        sync.validate_headers(move |header| {
+            log.lock().unwrap().push(header.id());
+            if header.id() == rejected {
+                Err(Error::new(Code::PermissionDenied, "rejected"))
+            } else {
+                Ok(())
+            }
+        });
+        let outcome = sync.run(local[2].id()).wait().unwrap();
+        assert_eq!(outcome.tip, fork[5].id());
+        assert_eq!(outcome.blocks_stored, 3);
+        let mut expected = ids(&chain[3..=5]);
+        expected.extend(ids(&fork[3..]));
+        assert_eq!(*validated.lock().unwrap(), expected);
+
+        let (store, local) = local_store(3);
+        let mut sync = ChainSync::new(store, vec![MockPeer::new(&extend(&local, 1))]);
+        sync.validate_headers(|_| Err(Error::new(Code::PermissionDenied, "rejected")));
+        let err = network_error(sync.run(local[2].id()).wait());
+        assert_eq!(err.code(), Code::PermissionDenied);
+    }
+}
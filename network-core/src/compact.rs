@@ -0,0 +1,342 @@
+//! Compact block relay.
+//!
+//! A compact block announcement carries the header of a new block and
+//! short identifiers of the block's fragments, in the order the fragments
+//! appear in the block. Peers usually have received most of the fragments
+//! of a new block beforehand via the fragment subscription, so the
+//! receiver can reconstruct the block from its own fragment pool with
+//! `PartialBlock::reconstruct` and then request only the fragments it is
+//! missing, by their positions in the block, with the
+//! `get_block_fragments` method of the client `FragmentService`.
+//!
+//! Short identifiers are truncated SipHash-2-4 digests of the serialized
+//! fragment identifiers. The hash is keyed with a salt chosen by the
+//! announcing node for each compact block, so that collisions cannot be
+//! engineered to affect all relays of a block in the network.
+
+use crate::error::{Code, Error};
+
+use chain_core::property::{Fragment, HasFragments, HasHeader, Serialize};
+
+use siphasher::sip::SipHasher24;
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    hash::Hasher,
+    io,
+};
+
+/// Size of a short fragment identifier in bytes.
+pub const SHORT_ID_SIZE: usize = 6;
+
+/// Short identifier of a fragment in a compact block.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShortFragmentId([u8; SHORT_ID_SIZE]);
+
+impl ShortFragmentId {
+    /// Computes the short identifier for the fragment identifier,
+    /// keyed with the salt of the compact block.
+    pub fn compute<Id>(salt: u64, id: &Id) -> Result<Self, Error>
+    where
+        Id: Serialize,
+    {
+        let mut hasher = SipHasher24::new_with_keys(salt, 0);
+        id.serialize(HashWriter(&mut hasher))
+            .map_err(|e| Error::new(Code::Internal, e.to_string()))?;
+        let digest = hasher.finish().to_le_bytes();
+        let mut bytes = [0; SHORT_ID_SIZE];
+        bytes.copy_from_slice(&digest[..SHORT_ID_SIZE]);
+        Ok(ShortFragmentId(bytes))
+    }
+
+    pub fn from_bytes(bytes: [u8; SHORT_ID_SIZE]) -> Self {
+        ShortFragmentId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; SHORT_ID_SIZE] {
+        &self.0
+    }
+}
+
+// Feeds serialized data into a hasher.
+struct HashWriter<'a, H>(&'a mut H);
+
+impl<'a, H: Hasher> io::Write for HashWriter<'a, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A block announcement carrying the block header and short identifiers
+/// of the block's fragments.
+#[derive(Clone, Debug)]
+pub struct CompactBlock<H> {
+    header: H,
+    salt: u64,
+    short_ids: Vec<ShortFragmentId>,
+}
+
+impl<H> CompactBlock<H> {
+    pub fn new(header: H, salt: u64, short_ids: Vec<ShortFragmentId>) -> Self {
+        CompactBlock {
+            header,
+            salt,
+            short_ids,
+        }
+    }
+
+    /// Creates a compact representation of the block, computing the
+    /// short identifiers of the fragments with the given salt.
+    ///
+    /// The salt should be picked at random by the announcing node.
+    pub fn from_block<'a, B>(block: &'a B, salt: u64) -> Result<Self, Error>
+    where
+        B: HasHeader<Header = H>,
+        &'a B: HasFragments<'a>,
+    {
+        let short_ids = block
+            .fragments()
+            .map(|fragment| ShortFragmentId::compute(salt, &fragment.id()))
+            .collect::<Result<_, _>>()?;
+        Ok(CompactBlock::new(block.header(), salt, short_ids))
+    }
+
+    pub fn header(&self) -> &H {
+        &self.header
+    }
+
+    pub fn salt(&self) -> u64 {
+        self.salt
+    }
+
+    /// Short identifiers of the fragments in the order of
+    /// their appearance in the block.
+    pub fn short_ids(&self) -> &[ShortFragmentId] {
+        &self.short_ids
+    }
+
+    pub fn into_header(self) -> H {
+        self.header
+    }
+}
+
+/// A block being reconstructed from a compact block announcement.
+#[derive(Clone, Debug)]
+pub struct PartialBlock<H, F> {
+    compact: CompactBlock<H>,
+    fragments: Vec<Option<F>>,
+}
+
+impl<H, F> PartialBlock<H, F>
+where
+    F: Fragment,
+{
+    /// Fills in the fragments of the compact block with the matching
+    /// fragments from the pool.
+    ///
+    /// Fragments whose short identifiers are ambiguous among the fragments
+    /// in the pool are left missing, to be retrieved from the peer.
+    pub fn reconstruct<I>(compact: CompactBlock<H>, pool: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = F>,
+    {
+        let wanted: HashSet<_> = compact.short_ids.iter().collect();
+        let mut candidates = HashMap::new();
+        for fragment in pool {
+            let short_id = ShortFragmentId::compute(compact.salt, &fragment.id())?;
+            if !wanted.contains(&short_id) {
+                continue;
+            }
+            match candidates.entry(short_id) {
+                Entry::Vacant(entry) => {
+                    entry.insert(Some(fragment));
+                }
+                Entry::Occupied(mut entry) => {
+                    let is_same = match entry.get() {
+                        Some(other) => other.id() == fragment.id(),
+                        None => false,
+                    };
+                    if !is_same {
+                        entry.insert(None);
+                    }
+                }
+            }
+        }
+        let fragments = compact
+            .short_ids
+            .iter()
+            .map(|short_id| candidates.get_mut(short_id).and_then(Option::take))
+            .collect();
+        Ok(PartialBlock { compact, fragments })
+    }
+
+    pub fn header(&self) -> &H {
+        self.compact.header()
+    }
+
+    /// Returns the positions of the fragments that are missing from
+    /// the block, in ascending order.
+    pub fn missing_indices(&self) -> Vec<u32> {
+        self.fragments
+            .iter()
+            .enumerate()
+            .filter(|(_, fragment)| fragment.is_none())
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.fragments.iter().all(Option::is_some)
+    }
+
+    /// Puts a fragment retrieved from the peer at the given position
+    /// in the block.
+    ///
+    /// Returns an error with code `InvalidArgument` if the position is
+    /// out of range or the fragment does not match the short identifier
+    /// at the position.
+    pub fn fill(&mut self, index: u32, fragment: F) -> Result<(), Error> {
+        let short_id = match self.compact.short_ids.get(index as usize) {
+            Some(short_id) => short_id,
+            None => {
+                return Err(Error::new(
+                    Code::InvalidArgument,
+                    "fragment index is out of range of the compact block",
+                ))
+            }
+        };
+        if ShortFragmentId::compute(self.compact.salt, &fragment.id())? != *short_id {
+            return Err(Error::new(
+                Code::InvalidArgument,
+                "fragment does not match the short identifier in the compact block",
+            ));
+        }
+        self.fragments[index as usize] = Some(fragment);
+        Ok(())
+    }
+
+    /// Returns the header and the fragments of the block in order if
+    /// the block is complete, otherwise returns the partial block back.
+    pub fn into_parts(self) -> Result<(H, Vec<F>), Self> {
+        if !self.is_complete() {
+            return Err(self);
+        }
+        let fragments = self.fragments.into_iter().map(Option::unwrap).collect();
+        Ok((self.compact.header, fragments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain_core::property::{Deserialize, FragmentId};
+    use std::io::{BufRead, Write};
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    struct TestId(u32);
+
+    impl Serialize for TestId {
+        type Error = io::Error;
+
+        fn serialize<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+            writer.write_all(&self.0.to_be_bytes())
+        }
+    }
+
+    impl Deserialize for TestId {
+        type Error = io::Error;
+
+        fn deserialize<R: BufRead>(mut reader: R) -> Result<Self, io::Error> {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(TestId(u32::from_be_bytes(bytes)))
+        }
+    }
+
+    impl FragmentId for TestId {}
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TestFragment(u32);
+
+    impl Serialize for TestFragment {
+        type Error = io::Error;
+
+        fn serialize<W: Write>(&self, writer: W) -> Result<(), io::Error> {
+            TestId(self.0).serialize(writer)
+        }
+    }
+
+    impl Deserialize for TestFragment {
+        type Error = io::Error;
+
+        fn deserialize<R: BufRead>(reader: R) -> Result<Self, io::Error> {
+            TestId::deserialize(reader).map(|id| TestFragment(id.0))
+        }
+    }
+
+    impl Fragment for TestFragment {
+        type Id = TestId;
+
+        fn id(&self) -> TestId {
+            TestId(self.0)
+        }
+    }
+
+    const SALT: u64 = 0x0123_4567_89ab_cdef;
+
+    fn compact_block(fragments: &[u32]) -> CompactBlock<&'static str> {
+        let short_ids = fragments
+            .iter()
+            .map(|&n| ShortFragmentId::compute(SALT, &TestId(n)).unwrap())
+            .collect();
+        CompactBlock::new("header", SALT, short_ids)
+    }
+
+    #[test]
+    fn short_ids_depend_on_salt() {
+        let id = TestId(42);
+        let a = ShortFragmentId::compute(1, &id).unwrap();
+        let b = ShortFragmentId::compute(2, &id).unwrap();
+        assert_eq!(a, ShortFragmentId::compute(1, &id).unwrap());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn reconstruct_and_fill_missing() {
+        let compact = compact_block(&[1, 2, 3, 4]);
+        let pool = vec![TestFragment(4), TestFragment(2), TestFragment(10)];
+        let mut block = PartialBlock::reconstruct(compact, pool).unwrap();
+        assert_eq!(block.missing_indices(), vec![0, 2]);
+        assert!(!block.is_complete());
+        block.fill(0, TestFragment(1)).unwrap();
+        block.fill(2, TestFragment(3)).unwrap();
+        let (header, fragments) = block.into_parts().unwrap();
+        assert_eq!(header, "header");
+        assert_eq!(
+            fragments,
+            vec![
+                TestFragment(1),
+                TestFragment(2),
+                TestFragment(3),
+                TestFragment(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_rejects_mismatched_fragment() {
+        let compact = compact_block(&[1, 2]);
+        let mut block = PartialBlock::reconstruct(compact, vec![]).unwrap();
+        let err = block.fill(0, TestFragment(2)).unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        let err = block.fill(2, TestFragment(1)).unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        assert_eq!(block.missing_indices(), vec![0, 1]);
+        assert!(block.into_parts().is_err());
+    }
+}
@@ -15,6 +15,8 @@ pub enum Code {
     Unimplemented,
     Internal,
     Unavailable,
+    PermissionDenied,
+    ResourceExhausted,
 }
 
 /// Represents errors that can be returned by the node protocol implementation.
@@ -62,6 +64,8 @@ impl fmt::Display for Error {
             Code::Unimplemented => "not implemented",
             Code::Internal => "internal processing error",
             Code::Unavailable => "the service is unavailable",
+            Code::PermissionDenied => "the operation is not permitted",
+            Code::ResourceExhausted => "resource limits have been exceeded",
         };
         write!(f, "{} ({})", msg, self.source)
     }
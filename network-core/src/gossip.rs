@@ -1,3 +1,7 @@
+pub mod address_book;
+pub mod keys;
+pub mod scoring;
+
 use chain_core::property;
 
 use std::{
@@ -9,6 +13,33 @@ use std::{
 /// Marker trait for the type representing a node ID.
 pub trait NodeId: Clone + property::Serialize + property::Deserialize {}
 
+/// Identity of a network peer, verified by the protocol implementation
+/// with a signature made by the node key of the peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerIdentity<Id> {
+    node_id: Id,
+    public_key: Box<[u8]>,
+}
+
+impl<Id> PeerIdentity<Id> {
+    pub fn new(node_id: Id, public_key: &[u8]) -> Self {
+        PeerIdentity {
+            node_id,
+            public_key: public_key.into(),
+        }
+    }
+
+    /// Returns the node identifier the peer has authenticated with.
+    pub fn node_id(&self) -> &Id {
+        &self.node_id
+    }
+
+    /// Returns the serialized public key of the peer's node key.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
 /// Abstract trait for data types representing gossip about network nodes.
 pub trait Node {
     /// Type that represents the node identifier in the gossip message.
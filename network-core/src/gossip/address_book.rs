@@ -0,0 +1,528 @@
+//! Book of network peer addresses.
+//!
+//! `AddressBook` collects the addresses of network peers learned from
+//! gossip and from connections made by the node, keeping track of when
+//! each address was last seen in gossip and when a connection to it last
+//! succeeded. Addresses that are not routable on the public Internet are
+//! filtered out, unless the book is configured to allow private addresses,
+//! for example on a test network.
+//!
+//! The book supplies candidate peers to connect to and to share
+//! in `peers` responses, preferring the addresses that have been connected
+//! to most recently. It can be saved to a file and loaded back, so that
+//! the node does not need to rely on the trusted peers alone when it
+//! restarts.
+//!
+//! As with `PeerScores`, the methods take the current time as
+//! a parameter. System time is used so that the timestamps remain
+//! meaningful when the book is persisted.
+
+use super::{Gossip, Node, Peer, PeersResponse};
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Parameters of the address book.
+#[derive(Clone, Debug)]
+pub struct AddressBookConfig {
+    /// The maximum number of addresses kept in the book.
+    pub max_addresses: usize,
+    /// Whether addresses in private and loopback ranges are accepted.
+    pub allow_private: bool,
+    /// The time after which an address that has not been seen
+    /// or connected to is removed by pruning.
+    pub max_age: Duration,
+    /// The number of consecutive failed connection attempts
+    /// after which an address is removed.
+    pub max_failures: u32,
+}
+
+impl Default for AddressBookConfig {
+    fn default() -> Self {
+        AddressBookConfig {
+            max_addresses: 2000,
+            allow_private: false,
+            max_age: Duration::from_secs(14 * 24 * 60 * 60),
+            max_failures: 5,
+        }
+    }
+}
+
+/// Information on a peer address recorded in the book.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressInfo {
+    last_seen: SystemTime,
+    last_connected: Option<SystemTime>,
+    failures: u32,
+}
+
+impl AddressInfo {
+    fn new(now: SystemTime) -> Self {
+        AddressInfo {
+            last_seen: now,
+            last_connected: None,
+            failures: 0,
+        }
+    }
+
+    /// Returns the time the address was last seen in gossip
+    /// or connected to.
+    pub fn last_seen(&self) -> SystemTime {
+        self.last_seen
+    }
+
+    /// Returns the time of the last successful connection to the address.
+    pub fn last_connected(&self) -> Option<SystemTime> {
+        self.last_connected
+    }
+
+    /// Returns the number of failed connection attempts since the last
+    /// successful connection.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    // Ordering key of the candidate addresses; lesser is better.
+    fn rank(&self) -> (u32, Reverse<Option<SystemTime>>, Reverse<SystemTime>) {
+        (
+            self.failures,
+            Reverse(self.last_connected),
+            Reverse(self.last_seen),
+        )
+    }
+}
+
+/// Book of network peer addresses.
+#[derive(Clone, Debug)]
+pub struct AddressBook {
+    config: AddressBookConfig,
+    entries: HashMap<SocketAddr, AddressInfo>,
+}
+
+impl AddressBook {
+    pub fn new(config: AddressBookConfig) -> Self {
+        AddressBook {
+            config,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &AddressBookConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&AddressInfo> {
+        self.entries.get(addr)
+    }
+
+    /// Returns true if the address is accepted into the book
+    /// with the configured filtering.
+    pub fn is_acceptable(&self, addr: &SocketAddr) -> bool {
+        if self.config.allow_private {
+            is_valid(addr)
+        } else {
+            is_routable(addr)
+        }
+    }
+
+    /// Records that the address has been seen at the given time.
+    ///
+    /// Returns true if the address has been added to the book. When the
+    /// book is full, the least promising address is evicted to make room.
+    pub fn insert(&mut self, addr: SocketAddr, now: SystemTime) -> bool {
+        if !self.is_acceptable(&addr) {
+            return false;
+        }
+        if let Some(info) = self.entries.get_mut(&addr) {
+            info.last_seen = info.last_seen.max(now);
+            return false;
+        }
+        if self.config.max_addresses == 0 {
+            return false;
+        }
+        if self.entries.len() >= self.config.max_addresses {
+            self.evict();
+        }
+        self.entries.insert(addr, AddressInfo::new(now));
+        true
+    }
+
+    /// Adds the addresses of the nodes in a gossip message.
+    ///
+    /// Returns the number of addresses added to the book.
+    pub fn merge_gossip<T: Node>(&mut self, gossip: &Gossip<T>, now: SystemTime) -> usize {
+        gossip
+            .nodes()
+            .iter()
+            .filter_map(Node::address)
+            .filter(|addr| self.insert(*addr, now))
+            .count()
+    }
+
+    /// Records a successful connection to the address,
+    /// adding it to the book if necessary.
+    pub fn record_connected(&mut self, addr: SocketAddr, now: SystemTime) {
+        self.insert(addr, now);
+        if let Some(info) = self.entries.get_mut(&addr) {
+            info.last_connected = Some(now);
+            info.failures = 0;
+        }
+    }
+
+    /// Records a failed connection attempt to the address.
+    ///
+    /// The address is removed after the configured number of consecutive
+    /// failures.
+    pub fn record_failure(&mut self, addr: &SocketAddr) {
+        let max_failures = self.config.max_failures;
+        if let Some(info) = self.entries.get_mut(addr) {
+            info.failures += 1;
+            if info.failures >= max_failures {
+                self.entries.remove(addr);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, addr: &SocketAddr) -> Option<AddressInfo> {
+        self.entries.remove(addr)
+    }
+
+    /// Returns up to `limit` addresses, ordered from the most promising:
+    /// fewer failed attempts first, then the most recently connected,
+    /// then the most recently seen.
+    pub fn candidates(&self, limit: usize) -> Vec<SocketAddr> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(addr, info)| (info.rank(), **addr));
+        entries
+            .into_iter()
+            .take(limit)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Returns a response to a `peers` request with up to `limit`
+    /// candidate addresses.
+    pub fn peers(&self, limit: usize) -> PeersResponse {
+        let peers = self
+            .candidates(limit)
+            .into_iter()
+            .map(|addr| Peer { addr })
+            .collect();
+        PeersResponse { peers }
+    }
+
+    /// Removes the addresses that have not been seen or connected to
+    /// within the configured maximum age.
+    pub fn prune(&mut self, now: SystemTime) {
+        let max_age = self.config.max_age;
+        self.entries.retain(|_, info| {
+            let last = info
+                .last_connected
+                .map_or(info.last_seen, |t| t.max(info.last_seen));
+            match now.duration_since(last) {
+                Ok(age) => age <= max_age,
+                Err(_) => true,
+            }
+        });
+    }
+
+    fn evict(&mut self) {
+        let worst = self
+            .entries
+            .iter()
+            .max_by_key(|(addr, info)| (info.rank(), **addr))
+            .map(|(addr, _)| *addr);
+        if let Some(addr) = worst {
+            self.entries.remove(&addr);
+        }
+    }
+
+    /// Writes the addresses in the book in a line-oriented text format.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(addr, info)| (info.rank(), **addr));
+        for (addr, info) in entries {
+            write!(writer, "{} {} ", addr, unix_secs(info.last_seen))?;
+            match info.last_connected {
+                Some(time) => write!(writer, "{}", unix_secs(time))?,
+                None => write!(writer, "-")?,
+            }
+            writeln!(writer, " {}", info.failures)?;
+        }
+        writer.flush()
+    }
+
+    /// Reads addresses in the format produced by `write_to` into
+    /// a new address book.
+    ///
+    /// Addresses that are not acceptable with the given configuration
+    /// are skipped. Empty lines and lines starting with `#` are ignored.
+    pub fn read_from<R: BufRead>(config: AddressBookConfig, reader: R) -> io::Result<Self> {
+        let mut book = AddressBook::new(config);
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (addr, info) = parse_entry(line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed address book entry: {}", line),
+                )
+            })?;
+            if book.insert(addr, info.last_seen) {
+                book.entries.insert(addr, info);
+            }
+        }
+        Ok(book)
+    }
+
+    /// Saves the address book to a file.
+    ///
+    /// The content is written to a temporary file next to the destination,
+    /// which is then renamed to replace the destination file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        let file = fs::File::create(&tmp_path)?;
+        self.write_to(BufWriter::new(&file))?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Loads an address book from a file saved with `save`.
+    pub fn load<P: AsRef<Path>>(config: AddressBookConfig, path: P) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        AddressBook::read_from(config, BufReader::new(file))
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn parse_entry(line: &str) -> Option<(SocketAddr, AddressInfo)> {
+    let mut fields = line.split_whitespace();
+    let addr = fields.next()?.parse().ok()?;
+    let last_seen = UNIX_EPOCH + Duration::from_secs(fields.next()?.parse().ok()?);
+    let last_connected = match fields.next()? {
+        "-" => None,
+        secs => Some(UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?)),
+    };
+    let failures = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    let info = AddressInfo {
+        last_seen,
+        last_connected,
+        failures,
+    };
+    Some((addr, info))
+}
+
+/// Returns true if the address can be used to connect to a peer:
+/// the port is not zero and the IP address is neither unspecified
+/// nor a multicast or broadcast address.
+pub fn is_valid(addr: &SocketAddr) -> bool {
+    if addr.port() == 0 {
+        return false;
+    }
+    match addr.ip() {
+        IpAddr::V4(ip) => !(ip.is_unspecified() || ip.is_multicast() || ip.is_broadcast()),
+        IpAddr::V6(ip) => !(ip.is_unspecified() || ip.is_multicast()),
+    }
+}
+
+/// Returns true if the address is valid and routable on the public
+/// Internet, excluding private, loopback, link-local, shared,
+/// documentation and reserved address ranges.
+pub fn is_routable(addr: &SocketAddr) -> bool {
+    if !is_valid(addr) {
+        return false;
+    }
+    match addr.ip() {
+        IpAddr::V4(ip) => is_routable_v4(ip),
+        IpAddr::V6(ip) => is_routable_v6(ip),
+    }
+}
+
+fn is_routable_v4(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_documentation()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        // 0.0.0.0/8, "this network"
+        || octets[0] == 0
+        // 100.64.0.0/10, shared address space
+        || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+        // 198.18.0.0/15, benchmarking
+        || (octets[0] == 198 && octets[1] & 0xfe == 18)
+        // 240.0.0.0/4, reserved
+        || octets[0] >= 240)
+}
+
+fn is_routable_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    if segments[..5] == [0; 5] && segments[5] == 0xffff {
+        // IPv4-mapped address
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        return is_routable_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // fc00::/7, unique local
+        || segments[0] & 0xfe00 == 0xfc00
+        // fe80::/10, link-local
+        || segments[0] & 0xffc0 == 0xfe80
+        // 2001:db8::/32, documentation
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn unroutable_addresses_are_filtered() {
+        for a in &[
+            "8.8.8.8:3000",
+            "[2a00:1450:4001::1]:3000",
+            "[::ffff:8.8.8.8]:3000",
+        ] {
+            assert!(is_routable(&addr(a)), "{} should be routable", a);
+        }
+        for a in &[
+            "8.8.8.8:0",
+            "0.0.0.0:3000",
+            "10.1.2.3:3000",
+            "127.0.0.1:3000",
+            "169.254.1.1:3000",
+            "100.64.0.1:3000",
+            "192.168.1.1:3000",
+            "224.0.0.1:3000",
+            "255.255.255.255:3000",
+            "[::1]:3000",
+            "[fd00::1]:3000",
+            "[fe80::1]:3000",
+            "[2001:db8::1]:3000",
+            "[::ffff:10.0.0.1]:3000",
+        ] {
+            assert!(!is_routable(&addr(a)), "{} should not be routable", a);
+        }
+
+        let mut book = AddressBook::new(AddressBookConfig::default());
+        assert!(!book.insert(addr("192.168.1.1:3000"), at(1)));
+        let mut book = AddressBook::new(AddressBookConfig {
+            allow_private: true,
+            ..Default::default()
+        });
+        assert!(book.insert(addr("192.168.1.1:3000"), at(1)));
+        assert!(!book.insert(addr("0.0.0.0:3000"), at(1)));
+    }
+
+    #[test]
+    fn candidates_prefer_connected_addresses() {
+        let mut book = AddressBook::new(AddressBookConfig::default());
+        book.insert(addr("1.1.1.1:3000"), at(10));
+        book.insert(addr("2.2.2.2:3000"), at(20));
+        book.insert(addr("3.3.3.3:3000"), at(5));
+        book.record_connected(addr("3.3.3.3:3000"), at(30));
+        book.insert(addr("4.4.4.4:3000"), at(40));
+        book.record_failure(&addr("4.4.4.4:3000"));
+        assert_eq!(
+            book.candidates(10),
+            vec![
+                addr("3.3.3.3:3000"),
+                addr("2.2.2.2:3000"),
+                addr("1.1.1.1:3000"),
+                addr("4.4.4.4:3000"),
+            ]
+        );
+        assert_eq!(book.peers(1).peers[0].addr, addr("3.3.3.3:3000"));
+    }
+
+    #[test]
+    fn failing_and_stale_addresses_are_removed() {
+        let mut book = AddressBook::new(AddressBookConfig {
+            max_failures: 2,
+            max_age: Duration::from_secs(100),
+            ..Default::default()
+        });
+        book.insert(addr("1.1.1.1:3000"), at(10));
+        book.record_failure(&addr("1.1.1.1:3000"));
+        assert_eq!(book.len(), 1);
+        book.record_failure(&addr("1.1.1.1:3000"));
+        assert!(book.is_empty());
+
+        book.insert(addr("2.2.2.2:3000"), at(10));
+        book.insert(addr("3.3.3.3:3000"), at(10));
+        book.record_connected(addr("3.3.3.3:3000"), at(100));
+        book.prune(at(150));
+        assert_eq!(book.candidates(10), vec![addr("3.3.3.3:3000")]);
+    }
+
+    #[test]
+    fn least_promising_address_is_evicted() {
+        let mut book = AddressBook::new(AddressBookConfig {
+            max_addresses: 2,
+            ..Default::default()
+        });
+        book.record_connected(addr("1.1.1.1:3000"), at(10));
+        book.insert(addr("2.2.2.2:3000"), at(20));
+        assert!(book.insert(addr("3.3.3.3:3000"), at(30)));
+        assert_eq!(book.len(), 2);
+        assert!(book.get(&addr("1.1.1.1:3000")).is_some());
+        assert!(book.get(&addr("2.2.2.2:3000")).is_none());
+    }
+
+    #[test]
+    fn book_round_trip() {
+        let mut book = AddressBook::new(AddressBookConfig::default());
+        book.insert(addr("1.1.1.1:3000"), at(10));
+        book.record_connected(addr("[2a00:1450:4001::1]:3100"), at(20));
+        book.insert(addr("2.2.2.2:3000"), at(30));
+        book.record_failure(&addr("2.2.2.2:3000"));
+
+        let mut buf = Vec::new();
+        book.write_to(&mut buf).unwrap();
+        let loaded = AddressBook::read_from(AddressBookConfig::default(), &buf[..]).unwrap();
+        assert_eq!(loaded.entries, book.entries);
+
+        let malformed = b"1.1.1.1:3000 10\n";
+        let err = AddressBook::read_from(AddressBookConfig::default(), &malformed[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,99 @@
+//! Pinned bindings of node identifiers to node keys.
+//!
+//! Authentication of a peer in the handshake proves that the peer
+//! possesses a node key, but the node identifier signed with the key
+//! is only claimed by the peer: any node can sign the identifier of
+//! another node with its own key. `PeerKeys` records the public key
+//! that is known to belong to each trusted node, so that a peer
+//! authenticating under the identifier of another node is rejected.
+//!
+//! `PeerKeys::check` returns an error suitable for rejecting requests,
+//! for example in `P2pService::accept_peer`. Protocol clients can use
+//! the bindings to verify the identity of the server node.
+
+use super::PeerIdentity;
+use crate::error::{Code, Error};
+
+use std::{collections::HashMap, hash::Hash};
+
+/// Public keys of the node keys of known peers,
+/// indexed by the node identifier.
+#[derive(Clone, Debug)]
+pub struct PeerKeys<Id> {
+    keys: HashMap<Id, Box<[u8]>>,
+}
+
+impl<Id> Default for PeerKeys<Id>
+where
+    Id: Eq + Hash,
+{
+    fn default() -> Self {
+        PeerKeys {
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl<Id> PeerKeys<Id>
+where
+    Id: Eq + Hash,
+{
+    /// Creates the bindings with no known peers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds the node identifier to the serialized public key,
+    /// returning the key previously bound to the identifier, if any.
+    pub fn pin(&mut self, node_id: Id, public_key: &[u8]) -> Option<Box<[u8]>> {
+        self.keys.insert(node_id, public_key.into())
+    }
+
+    /// Removes the binding of the node identifier, if any.
+    pub fn unpin(&mut self, node_id: &Id) -> Option<Box<[u8]>> {
+        self.keys.remove(node_id)
+    }
+
+    /// Returns the public key bound to the node identifier.
+    pub fn public_key(&self, node_id: &Id) -> Option<&[u8]> {
+        self.keys.get(node_id).map(|key| &key[..])
+    }
+
+    /// Returns an error with code `PermissionDenied` if the node
+    /// identifier of the peer is not known or is bound to another key.
+    pub fn check(&self, peer: &PeerIdentity<Id>) -> Result<(), Error> {
+        match self.public_key(peer.node_id()) {
+            Some(key) if key == peer.public_key() => Ok(()),
+            Some(_) => Err(Error::new(
+                Code::PermissionDenied,
+                "the node ID of the peer is bound to another key",
+            )),
+            None => Err(Error::new(
+                Code::PermissionDenied,
+                "the node ID of the peer is not known",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_pinned_keys_are_accepted() {
+        let mut keys = PeerKeys::new();
+        assert_eq!(keys.pin(1u32, b"key 1"), None);
+        assert!(keys.check(&PeerIdentity::new(1, b"key 1")).is_ok());
+
+        // another node claiming the ID of node 1
+        let err = keys.check(&PeerIdentity::new(1, b"key 2")).unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied);
+
+        let err = keys.check(&PeerIdentity::new(2, b"key 2")).unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied);
+
+        assert_eq!(keys.unpin(&1).as_deref(), Some(&b"key 1"[..]));
+        assert!(keys.check(&PeerIdentity::new(1, b"key 1")).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+//! Peer quality scoring and banning.
+//!
+//! `PeerScores` keeps track of a reputation score for each peer,
+//! updated from the events observed in the interaction with the peer.
+//! Good behavior is rewarded up to a maximum score, while misbehavior
+//! is penalized. The scores decay exponentially towards zero over time,
+//! so that old events gradually lose their influence.
+//!
+//! When the score of a peer falls to the ban threshold, the peer is
+//! banned for a configured period of time. Node implementations should
+//! consult the ban list before accepting subscriptions from a peer
+//! or connecting to it; `PeerScores::check` returns an error suitable
+//! for rejecting requests, for example in `P2pService::accept_peer`
+//! and `P2pService::accept_unauthenticated_peer`.
+//!
+//! The methods of `PeerScores` take the current time as a parameter,
+//! so the behavior is fully deterministic given the sequence of events.
+
+use crate::error::{Code, Error};
+
+use thiserror::Error;
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// An event observed in the interaction with a peer,
+/// affecting the score of the peer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// The peer has sent a valid block.
+    ValidBlock,
+    /// The peer has sent a valid fragment.
+    ValidFragment,
+    /// The peer has sent a block that failed validation.
+    InvalidBlock,
+    /// The peer has sent a fragment that failed validation.
+    InvalidFragment,
+    /// A request to the peer has timed out.
+    Timeout,
+    /// A protocol request to or from the peer has failed
+    /// with the given error code.
+    ProtocolError(Code),
+}
+
+impl PeerEvent {
+    /// Returns the event corresponding to a protocol error.
+    pub fn from_error(err: &Error) -> Self {
+        PeerEvent::ProtocolError(err.code())
+    }
+}
+
+/// Parameters of the peer scoring.
+///
+/// The score adjustments for events are added to the score of the peer,
+/// so penalties are negative.
+#[derive(Clone, Debug)]
+pub struct ScoringConfig {
+    /// Score adjustment for a valid block.
+    pub valid_block: f64,
+    /// Score adjustment for a valid fragment.
+    pub valid_fragment: f64,
+    /// Score adjustment for an invalid block.
+    pub invalid_block: f64,
+    /// Score adjustment for an invalid fragment.
+    pub invalid_fragment: f64,
+    /// Score adjustment for a timed out request or
+    /// an unavailable service.
+    pub timeout: f64,
+    /// Score adjustment for a malformed request or response.
+    pub malformed_message: f64,
+    /// Score adjustment for other protocol errors that indicate
+    /// misbehavior or malfunction of the peer.
+    pub protocol_error: f64,
+    /// The maximum score a peer can earn.
+    pub max_score: f64,
+    /// The score at or below which the peer is banned.
+    pub ban_threshold: f64,
+    /// The time in which the score of a peer decays by half.
+    pub half_life: Duration,
+    /// The duration of a ban.
+    pub ban_duration: Duration,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        ScoringConfig {
+            valid_block: 1.0,
+            valid_fragment: 0.1,
+            invalid_block: -50.0,
+            invalid_fragment: -10.0,
+            timeout: -5.0,
+            malformed_message: -20.0,
+            protocol_error: -2.0,
+            max_score: 100.0,
+            ban_threshold: -100.0,
+            half_life: Duration::from_secs(10 * 60),
+            ban_duration: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Error returned when the scoring parameters are not valid.
+#[derive(Debug, Error)]
+pub enum InvalidConfig {
+    #[error("the half-life of scores must not be zero")]
+    ZeroHalfLife,
+    #[error("scoring parameter `{0}` is not a finite number")]
+    NotFinite(&'static str),
+}
+
+impl ScoringConfig {
+    /// Checks that the scores computed with these parameters
+    /// are finite numbers.
+    pub fn validate(&self) -> Result<(), InvalidConfig> {
+        if self.half_life == Duration::from_secs(0) {
+            return Err(InvalidConfig::ZeroHalfLife);
+        }
+        let params = [
+            ("valid_block", self.valid_block),
+            ("valid_fragment", self.valid_fragment),
+            ("invalid_block", self.invalid_block),
+            ("invalid_fragment", self.invalid_fragment),
+            ("timeout", self.timeout),
+            ("malformed_message", self.malformed_message),
+            ("protocol_error", self.protocol_error),
+            ("max_score", self.max_score),
+            ("ban_threshold", self.ban_threshold),
+        ];
+        match params.iter().find(|(_, value)| !value.is_finite()) {
+            Some((name, _)) => Err(InvalidConfig::NotFinite(name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the score adjustment for the event.
+    pub fn adjustment(&self, event: PeerEvent) -> f64 {
+        match event {
+            PeerEvent::ValidBlock => self.valid_block,
+            PeerEvent::ValidFragment => self.valid_fragment,
+            PeerEvent::InvalidBlock => self.invalid_block,
+            PeerEvent::InvalidFragment => self.invalid_fragment,
+            PeerEvent::Timeout => self.timeout,
+            PeerEvent::ProtocolError(code) => match code {
+                Code::InvalidArgument => self.malformed_message,
+                Code::Unavailable => self.timeout,
+                Code::Unknown | Code::Internal | Code::FailedPrecondition => self.protocol_error,
+                // These codes do not indicate a fault of the peer.
+                Code::Canceled
+                | Code::Aborted
+                | Code::NotFound
+                | Code::Unimplemented
+                | Code::PermissionDenied
+                | Code::ResourceExhausted => 0.0,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Score {
+    value: f64,
+    updated: Instant,
+}
+
+impl Score {
+    fn decayed(&self, half_life: Duration, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated);
+        let half_lives = elapsed.as_secs_f64() / half_life.as_secs_f64();
+        self.value * 0.5f64.powf(half_lives)
+    }
+}
+
+// Scores with absolute values below this are considered to be zero
+// and are removed by pruning.
+const NEGLIGIBLE_SCORE: f64 = 0.01;
+
+/// Scores and the ban list of network peers.
+#[derive(Clone, Debug)]
+pub struct PeerScores<Id> {
+    config: ScoringConfig,
+    scores: HashMap<Id, Score>,
+    bans: HashMap<Id, Instant>,
+}
+
+impl<Id> PeerScores<Id>
+where
+    Id: Eq + Hash + Clone,
+{
+    /// Creates the scores with the given parameters, failing if
+    /// the parameters are not valid.
+    pub fn new(config: ScoringConfig) -> Result<Self, InvalidConfig> {
+        config.validate()?;
+        Ok(PeerScores {
+            config,
+            scores: HashMap::new(),
+            bans: HashMap::new(),
+        })
+    }
+
+    pub fn config(&self) -> &ScoringConfig {
+        &self.config
+    }
+
+    /// Returns the current score of the peer.
+    /// Peers with no recorded events have the score of zero.
+    pub fn score(&self, peer: &Id, now: Instant) -> f64 {
+        self.scores
+            .get(peer)
+            .map_or(0.0, |score| score.decayed(self.config.half_life, now))
+    }
+
+    /// Updates the score of the peer with the observed event.
+    ///
+    /// If the score falls to the ban threshold, the peer is banned and
+    /// its score is reset; the expiry time of the ban is returned.
+    /// Events recorded for a banned peer do not affect its score.
+    pub fn record(&mut self, peer: &Id, event: PeerEvent, now: Instant) -> Option<Instant> {
+        if self.is_banned(peer, now) {
+            return None;
+        }
+        let config = &self.config;
+        let value = self.score(peer, now) + config.adjustment(event);
+        if value <= config.ban_threshold {
+            self.scores.remove(peer);
+            let until = now + config.ban_duration;
+            self.bans.insert(peer.clone(), until);
+            Some(until)
+        } else {
+            let score = Score {
+                value: value.min(config.max_score),
+                updated: now,
+            };
+            self.scores.insert(peer.clone(), score);
+            None
+        }
+    }
+
+    /// Bans the peer until the specified time, regardless of its score.
+    pub fn ban(&mut self, peer: Id, until: Instant) {
+        self.scores.remove(&peer);
+        self.bans.insert(peer, until);
+    }
+
+    /// Lifts the ban on the peer, if any.
+    pub fn unban(&mut self, peer: &Id) {
+        self.bans.remove(peer);
+    }
+
+    /// Returns the expiry time of the peer's ban if the peer is banned.
+    pub fn banned_until(&self, peer: &Id, now: Instant) -> Option<Instant> {
+        self.bans.get(peer).cloned().filter(|&until| until > now)
+    }
+
+    pub fn is_banned(&self, peer: &Id, now: Instant) -> bool {
+        self.banned_until(peer, now).is_some()
+    }
+
+    /// Returns an error with code `PermissionDenied` if the peer
+    /// is banned.
+    pub fn check(&self, peer: &Id, now: Instant) -> Result<(), Error> {
+        if self.is_banned(peer, now) {
+            Err(Error::new(Code::PermissionDenied, "the peer is banned"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Removes the expired bans and the scores that have decayed
+    /// to negligible values.
+    pub fn prune(&mut self, now: Instant) {
+        self.bans.retain(|_, until| *until > now);
+        let half_life = self.config.half_life;
+        self.scores
+            .retain(|_, score| score.decayed(half_life, now).abs() >= NEGLIGIBLE_SCORE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores() -> PeerScores<u32> {
+        PeerScores::new(ScoringConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        let config = ScoringConfig {
+            half_life: Duration::from_secs(0),
+            ..ScoringConfig::default()
+        };
+        match PeerScores::<u32>::new(config) {
+            Err(InvalidConfig::ZeroHalfLife) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        let config = ScoringConfig {
+            invalid_block: f64::NEG_INFINITY,
+            ..ScoringConfig::default()
+        };
+        match PeerScores::<u32>::new(config) {
+            Err(InvalidConfig::NotFinite("invalid_block")) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        let config = ScoringConfig {
+            max_score: f64::NAN,
+            ..ScoringConfig::default()
+        };
+        assert!(PeerScores::<u32>::new(config).is_err());
+    }
+
+    #[test]
+    fn score_is_capped_and_decays() {
+        let mut scores = scores();
+        let start = Instant::now();
+        for _ in 0..200 {
+            scores.record(&1, PeerEvent::ValidBlock, start);
+        }
+        assert_eq!(scores.score(&1, start), 100.0);
+        let half_life = scores.config().half_life;
+        let score = scores.score(&1, start + half_life);
+        assert!((score - 50.0).abs() < 1e-6);
+        assert_eq!(scores.score(&2, start), 0.0);
+    }
+
+    #[test]
+    fn misbehaving_peer_is_banned() {
+        let mut scores = scores();
+        let start = Instant::now();
+        assert_eq!(scores.record(&1, PeerEvent::InvalidBlock, start), None);
+        let until = scores
+            .record(&1, PeerEvent::InvalidBlock, start)
+            .expect("peer should be banned");
+        assert!(scores.is_banned(&1, start));
+        assert_eq!(
+            scores.check(&1, start).unwrap_err().code(),
+            Code::PermissionDenied
+        );
+        assert!(scores.check(&2, start).is_ok());
+
+        assert!(!scores.is_banned(&1, until));
+        assert_eq!(scores.score(&1, until), 0.0);
+    }
+
+    #[test]
+    fn benign_errors_are_not_penalized() {
+        let mut scores = scores();
+        let now = Instant::now();
+        scores.record(&1, PeerEvent::ProtocolError(Code::NotFound), now);
+        assert_eq!(scores.score(&1, now), 0.0);
+        let err = Error::new(Code::InvalidArgument, "malformed block");
+        scores.record(&1, PeerEvent::from_error(&err), now);
+        assert_eq!(scores.score(&1, now), -20.0);
+    }
+
+    #[test]
+    fn prune_removes_expired_state() {
+        let mut scores = scores();
+        let start = Instant::now();
+        scores.record(&1, PeerEvent::ValidFragment, start);
+        scores.ban(2, start + Duration::from_secs(1));
+        let later = start + Duration::from_secs(24 * 60 * 60);
+        scores.prune(later);
+        assert!(scores.scores.is_empty());
+        assert!(scores.bans.is_empty());
+    }
+}
@@ -7,5 +7,10 @@ pub mod error;
 pub mod client;
 pub mod server;
 
+pub mod compact;
+
 pub mod gossip;
 pub mod subscription;
+
+#[cfg(any(feature = "test-api", test))]
+pub mod testing;
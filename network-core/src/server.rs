@@ -10,7 +10,8 @@ pub use block::BlockService;
 pub use fragment::FragmentService;
 pub use gossip::GossipService;
 
-use crate::gossip::NodeId;
+use crate::error::{Code, Error};
+use crate::gossip::{NodeId, PeerIdentity};
 
 /// Interface to application logic of the blockchain node server.
 ///
@@ -27,7 +28,7 @@ pub trait Node {
     type BlockService: BlockService;
 
     /// The implementation of the content service.
-    type FragmentService: FragmentService;
+    type FragmentService: FragmentService<BlockId = <Self::BlockService as BlockService>::BlockId>;
 
     /// The implementation of the gossip service.
     type GossipService: GossipService;
@@ -53,4 +54,39 @@ pub trait P2pService {
 
     /// Returns the identifier of this node.
     fn node_id(&self) -> Self::NodeId;
+
+    /// Checks whether a client peer that has authenticated with
+    /// its node key is accepted by this node.
+    ///
+    /// The protocol implementation calls this method on the service
+    /// handling the first subscription request of an authenticated peer,
+    /// and passes the node identifier of the peer to the subscription
+    /// methods only if the peer is accepted.
+    ///
+    /// The authentication proves that the peer possesses the node key,
+    /// but not that the node identifier it claims belongs to the key.
+    /// Implementations should check the binding of the identifier to
+    /// the key with `gossip::keys::PeerKeys`, and can use
+    /// `gossip::scoring::PeerScores` to reject banned peers.
+    /// The default implementation rejects all authenticated peers.
+    fn accept_peer(&mut self, peer: &PeerIdentity<Self::NodeId>) -> Result<(), Error> {
+        let _ = peer;
+        Err(Error::new(
+            Code::PermissionDenied,
+            "the node does not accept authenticated peers",
+        ))
+    }
+
+    /// Checks whether a client peer that has not authenticated is
+    /// accepted by this node under the node identifier it claims.
+    ///
+    /// The protocol implementation calls this method on the service
+    /// handling a subscription request when the connection does not
+    /// authenticate the peers, and passes the node identifier to the
+    /// subscription methods only if the peer is accepted.
+    /// The default implementation accepts any peer.
+    fn accept_unauthenticated_peer(&mut self, node_id: &Self::NodeId) -> Result<(), Error> {
+        let _ = node_id;
+        Ok(())
+    }
 }
@@ -170,5 +170,9 @@ pub trait BlockService: P2pService {
     /// to an object that serves as both an asynchronous stream used by this
     /// node to send block announcements and solicitations,
     /// and as an asynchrounous sink for incoming block announcements.
+    ///
+    /// Blocks may be announced with `BlockEvent::AnnounceCompact`;
+    /// the protocol implementation converts these to header announcements
+    /// for subscribers that have not negotiated compact block relay.
     fn block_subscription(&mut self, subscriber: Self::NodeId) -> Self::BlockSubscriptionFuture;
 }
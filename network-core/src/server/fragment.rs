@@ -3,7 +3,7 @@
 use super::{request_stream, P2pService};
 use crate::error::Error;
 
-use chain_core::property::{Fragment, FragmentId};
+use chain_core::property::{BlockId, Fragment, FragmentId};
 
 use futures::prelude::*;
 
@@ -17,6 +17,10 @@ pub trait FragmentService: P2pService {
     /// The fragment identifier type for the blockchain.
     type FragmentId: FragmentId;
 
+    /// The block identifier type, which is the same as that of
+    /// the block service of the node.
+    type BlockId: BlockId;
+
     /// The type of an asynchronous stream that provides fragments in
     /// response to `get_fragments`.
     type GetFragmentsStream: Stream<Item = Self::Fragment, Error = Error> + Send + 'static;
@@ -48,6 +52,22 @@ pub trait FragmentService: P2pService {
     /// Get all transactions by their id.
     fn get_fragments(&mut self, ids: &[Self::FragmentId]) -> Self::GetFragmentsFuture;
 
+    /// Retrieves fragments of the identified block by their positions
+    /// in the block.
+    ///
+    /// This is used by peers to complete reconstruction of compact blocks
+    /// announced by this node. The fragments in the returned stream
+    /// should be in the order of the requested positions.
+    ///
+    /// The default implementation fails with `Code::Unimplemented`.
+    fn get_block_fragments(
+        &mut self,
+        _block_id: &Self::BlockId,
+        _indices: &[u32],
+    ) -> Result<Self::GetFragmentsFuture, Error> {
+        Err(Error::unimplemented())
+    }
+
     /// Establishes a bidirectional subscription for exchanging new block
     /// fragments.
     ///
@@ -1,6 +1,8 @@
 use crate::error::Error;
 
-use futures::Future;
+use futures::prelude::*;
+use futures::stream::Fuse;
+use futures::try_ready;
 
 // derive
 use thiserror::Error;
@@ -46,3 +48,211 @@ pub trait MapResponse {
     /// and produce a response for the network peer.
     fn on_stream_termination(&mut self, res: Result<(), ProcessingError>) -> Self::ResponseFuture;
 }
+
+/// Forwarding of a client-streamed request into the sink provided
+/// by the server-side service.
+///
+/// The inbound stream is expected to produce items already converted
+/// for the sink, with errors tagged with their origin. Items are only
+/// taken from the inbound stream when the sink is ready to accept them,
+/// so back-pressure from the service propagates to the client stream.
+#[must_use = "futures do nothing unless polled"]
+pub struct Forward<In, S>
+where
+    S: Sink,
+{
+    inbound: Fuse<In>,
+    sink: Option<S>,
+    buffered: Option<S::SinkItem>,
+}
+
+impl<In: Stream, S: Sink> Forward<In, S> {
+    pub fn new(inbound: In, sink: S) -> Self {
+        Forward {
+            inbound: inbound.fuse(),
+            sink: Some(sink),
+            buffered: None,
+        }
+    }
+}
+
+impl<In, S: Sink> Forward<In, S> {
+    pub fn sink_mut(&mut self) -> &mut S {
+        self.sink
+            .as_mut()
+            .expect("attempted to poll request stream processing after completion")
+    }
+
+    pub fn break_up(&mut self) -> S {
+        self.sink
+            .take()
+            .expect("can't break up stream forwarding twice")
+    }
+}
+
+impl<In, S> Forward<In, S>
+where
+    In: Stream<Item = S::SinkItem, Error = ProcessingError>,
+    S: Sink<SinkError = Error>,
+{
+    fn try_send_item(&mut self, item: S::SinkItem) -> Poll<(), ProcessingError> {
+        match self
+            .sink_mut()
+            .start_send(item)
+            .map_err(ProcessingError::Sink)?
+        {
+            AsyncSink::Ready => Ok(Async::Ready(())),
+            AsyncSink::NotReady(item) => {
+                // The sink will notify the task when it can accept
+                // the item, until then the item is kept here.
+                debug_assert!(self.buffered.is_none());
+                self.buffered = Some(item);
+                Ok(Async::NotReady)
+            }
+        }
+    }
+
+    fn poll_step_internal(&mut self) -> Poll<Option<()>, ProcessingError> {
+        if let Some(item) = self.buffered.take() {
+            try_ready!(self.try_send_item(item));
+            Ok(None.into())
+        } else {
+            match self.inbound.poll()? {
+                Async::NotReady => {
+                    try_ready!(self
+                        .sink_mut()
+                        .poll_complete()
+                        .map_err(ProcessingError::Sink));
+                    Ok(Async::NotReady)
+                }
+                Async::Ready(Some(item)) => {
+                    try_ready!(self.try_send_item(item));
+                    Ok(None.into())
+                }
+                Async::Ready(None) => {
+                    try_ready!(self.sink_mut().close().map_err(ProcessingError::Sink));
+                    Ok(Some(()).into())
+                }
+            }
+        }
+    }
+}
+
+impl<In, S> Forward<In, S>
+where
+    In: Stream<Item = S::SinkItem, Error = ProcessingError>,
+    S: Sink<SinkError = Error>,
+    S: MapResponse,
+{
+    /// Makes a step in forwarding the request stream. When the request stream
+    /// has terminated, the sink is notified of the outcome and returned
+    /// along with the future of the response.
+    pub fn poll_step(&mut self) -> Async<Option<(S, S::ResponseFuture)>> {
+        let terminated = match self.poll_step_internal() {
+            Ok(Async::NotReady) => return Async::NotReady,
+            Ok(Async::Ready(None)) => return None.into(),
+            Ok(Async::Ready(Some(()))) => Ok(()),
+            Err(e) => Err(e),
+        };
+        let mut sink = self.sink.take().unwrap();
+        let shutdown = sink.on_stream_termination(terminated);
+        Some((sink, shutdown)).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::{self, Notify};
+    use futures::future::{self, FutureResult};
+    use futures::stream;
+    use futures::sync::mpsc;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct CountNotify(AtomicUsize);
+
+    impl Notify for CountNotify {
+        fn notify(&self, _: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Service sink feeding a bounded channel.
+    struct ChannelSink(mpsc::Sender<u32>);
+
+    impl Sink for ChannelSink {
+        type SinkItem = u32;
+        type SinkError = Error;
+
+        fn start_send(&mut self, item: u32) -> StartSend<u32, Error> {
+            self.0
+                .start_send(item)
+                .map_err(|e| Error::new(crate::error::Code::Aborted, e))
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), Error> {
+            self.0
+                .poll_complete()
+                .map_err(|e| Error::new(crate::error::Code::Aborted, e))
+        }
+
+        fn close(&mut self) -> Poll<(), Error> {
+            self.0
+                .close()
+                .map_err(|e| Error::new(crate::error::Code::Aborted, e))
+        }
+    }
+
+    impl MapResponse for ChannelSink {
+        type Response = ();
+        type ResponseFuture = FutureResult<(), Error>;
+
+        fn on_stream_termination(
+            &mut self,
+            res: Result<(), ProcessingError>,
+        ) -> Self::ResponseFuture {
+            future::result(res.map_err(ProcessingError::flatten))
+        }
+    }
+
+    #[test]
+    fn waits_for_full_bounded_channel() {
+        // The channel has room for one item per sender, so every item
+        // after the first has to wait for the receiver to take one.
+        let (tx, rx) = mpsc::channel(0);
+        let inbound = stream::iter_ok(0..4);
+        let mut forward = executor::spawn(Forward::new(inbound, ChannelSink(tx)));
+        let mut rx = executor::spawn(rx);
+        let notify = Arc::new(CountNotify::default());
+
+        let mut step = || {
+            forward.poll_fn_notify(&notify, 0, |forward| loop {
+                match forward.poll_step() {
+                    Async::NotReady => return Async::NotReady,
+                    Async::Ready(None) => continue,
+                    // drop the sink to close the channel
+                    Async::Ready(Some(_)) => return Async::Ready(()),
+                }
+            })
+        };
+        let mut recv = || rx.poll_stream_notify(&notify, 1).unwrap();
+        let woken = || notify.0.swap(0, Ordering::SeqCst) > 0;
+
+        assert_eq!(step(), Async::NotReady);
+        assert!(!woken());
+        for expected in 0..2 {
+            assert_eq!(recv(), Async::Ready(Some(expected)));
+            assert!(woken(), "forwarding task is not woken up");
+            assert_eq!(step(), Async::NotReady);
+        }
+        assert_eq!(recv(), Async::Ready(Some(2)));
+        assert!(woken(), "forwarding task is not woken up");
+        assert_eq!(step(), Async::Ready(()));
+        assert_eq!(recv(), Async::Ready(Some(3)));
+        assert_eq!(recv(), Async::Ready(None));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::compact::CompactBlock;
+
 use chain_core::property::{Block, HasHeader};
 
 use std::fmt::{self, Debug};
@@ -7,6 +9,9 @@ where
     B: Block + HasHeader,
 {
     Announce(B::Header),
+    /// Announcement of a block in the compact form, sent to peers that
+    /// have negotiated compact block relay.
+    AnnounceCompact(CompactBlock<B::Header>),
     Solicit(Vec<B::Id>),
     Missing(ChainPullRequest<B::Id>),
 }
@@ -30,6 +35,9 @@ where
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             BlockEvent::Announce(header) => f.debug_tuple("Announce").field(header).finish(),
+            BlockEvent::AnnounceCompact(compact) => {
+                f.debug_tuple("AnnounceCompact").field(compact).finish()
+            }
             BlockEvent::Solicit(ids) => f.debug_tuple("Solicit").field(ids).finish(),
             BlockEvent::Missing(req) => f.debug_tuple("Missing").field(req).finish(),
         }
@@ -0,0 +1,504 @@
+//! Mock implementations of the network services for testing
+//! the transports.
+
+use crate::compact::{CompactBlock, ShortFragmentId};
+use crate::error::{Code, Error};
+use crate::gossip::keys::PeerKeys;
+use crate::gossip::scoring::{PeerScores, ScoringConfig};
+use crate::gossip::{self, Gossip, Peer, PeerIdentity, PeersResponse};
+use crate::server::request_stream::{MapResponse, ProcessingError};
+use crate::server::{self, Node, P2pService};
+use crate::subscription::BlockEvent;
+
+use chain_core::mempack::{self, ReadBuf, ReadError};
+use chain_core::packer::Codec;
+use chain_core::property::{self, HasHeader as _};
+
+use futures::future::{self, FutureResult};
+use futures::prelude::*;
+use futures::stream::{self, IterOk};
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::vec;
+
+pub use chain_storage::store::testing::{Block, BlockDate, BlockId};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TestNodeId(pub u64);
+
+impl property::Serialize for TestNodeId {
+    type Error = io::Error;
+
+    fn serialize<W: io::Write>(&self, writer: W) -> Result<(), Self::Error> {
+        Codec::new(writer).put_u64(self.0)
+    }
+}
+
+impl property::Deserialize for TestNodeId {
+    type Error = io::Error;
+
+    fn deserialize<R: io::BufRead>(reader: R) -> Result<Self, Self::Error> {
+        Ok(TestNodeId(Codec::new(reader).get_u64()?))
+    }
+}
+
+impl gossip::NodeId for TestNodeId {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TestFragmentId(pub u64);
+
+impl property::Serialize for TestFragmentId {
+    type Error = io::Error;
+
+    fn serialize<W: io::Write>(&self, writer: W) -> Result<(), Self::Error> {
+        Codec::new(writer).put_u64(self.0)
+    }
+}
+
+impl property::Deserialize for TestFragmentId {
+    type Error = io::Error;
+
+    fn deserialize<R: io::BufRead>(reader: R) -> Result<Self, Self::Error> {
+        Ok(TestFragmentId(Codec::new(reader).get_u64()?))
+    }
+}
+
+impl property::FragmentId for TestFragmentId {}
+
+impl mempack::Readable for TestFragmentId {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        Ok(TestFragmentId(buf.get_u64()?))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestFragment(pub u64);
+
+impl property::Serialize for TestFragment {
+    type Error = io::Error;
+
+    fn serialize<W: io::Write>(&self, writer: W) -> Result<(), Self::Error> {
+        Codec::new(writer).put_u64(self.0)
+    }
+}
+
+impl property::Deserialize for TestFragment {
+    type Error = io::Error;
+
+    fn deserialize<R: io::BufRead>(reader: R) -> Result<Self, Self::Error> {
+        Ok(TestFragment(Codec::new(reader).get_u64()?))
+    }
+}
+
+impl mempack::Readable for TestFragment {
+    fn read<'a>(buf: &mut ReadBuf<'a>) -> Result<Self, ReadError> {
+        Ok(TestFragment(buf.get_u64()?))
+    }
+}
+
+impl property::Fragment for TestFragment {
+    type Id = TestFragmentId;
+
+    fn id(&self) -> TestFragmentId {
+        TestFragmentId(self.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TestGossipNode(pub TestNodeId);
+
+impl gossip::Node for TestGossipNode {
+    type Id = TestNodeId;
+
+    fn id(&self) -> TestNodeId {
+        self.0.clone()
+    }
+
+    fn address(&self) -> Option<std::net::SocketAddr> {
+        None
+    }
+}
+
+impl property::Serialize for TestGossipNode {
+    type Error = io::Error;
+
+    fn serialize<W: io::Write>(&self, writer: W) -> Result<(), Self::Error> {
+        self.0.serialize(writer)
+    }
+}
+
+impl property::Deserialize for TestGossipNode {
+    type Error = io::Error;
+
+    fn deserialize<R: io::BufRead>(reader: R) -> Result<Self, Self::Error> {
+        Ok(TestGossipNode(TestNodeId::deserialize(reader)?))
+    }
+}
+
+/// Items received by the node from a client stream, and whether
+/// the stream has terminated successfully.
+pub struct Received<T> {
+    pub items: Vec<T>,
+    pub terminated: Option<bool>,
+}
+
+pub type Log<T> = Arc<Mutex<Received<T>>>;
+
+pub fn new_log<T>() -> Log<T> {
+    Arc::new(Mutex::new(Received {
+        items: Vec::new(),
+        terminated: None,
+    }))
+}
+
+/// Service-side sink for client-streamed requests.
+pub struct Collector<T> {
+    pub log: Log<T>,
+}
+
+impl<T> Sink for Collector<T> {
+    type SinkItem = T;
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: T) -> StartSend<T, Error> {
+        self.log.lock().unwrap().items.push(item);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<T> MapResponse for Collector<T> {
+    type Response = ();
+    type ResponseFuture = FutureResult<(), Error>;
+
+    fn on_stream_termination(&mut self, res: Result<(), ProcessingError>) -> Self::ResponseFuture {
+        self.log.lock().unwrap().terminated = Some(res.is_ok());
+        future::ok(())
+    }
+}
+
+/// Service-side subscription sending a fixed sequence of items.
+pub struct MockSubscription<Out, In> {
+    pub outbound: VecDeque<Out>,
+    pub inbound: Collector<In>,
+}
+
+impl<Out, In> Stream for MockSubscription<Out, In> {
+    type Item = Out;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Out>, Error> {
+        Ok(self.outbound.pop_front().into())
+    }
+}
+
+impl<Out, In> Sink for MockSubscription<Out, In> {
+    type SinkItem = In;
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: In) -> StartSend<In, Error> {
+        self.inbound.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.inbound.poll_complete()
+    }
+}
+
+impl<Out, In> MapResponse for MockSubscription<Out, In> {
+    type Response = ();
+    type ResponseFuture = FutureResult<(), Error>;
+
+    fn on_stream_termination(&mut self, res: Result<(), ProcessingError>) -> Self::ResponseFuture {
+        self.inbound.on_stream_termination(res)
+    }
+}
+
+// Salt of the compact blocks announced by the mock node.
+const COMPACT_SALT: u64 = 0x5eed;
+
+pub type Blocks = IterOk<vec::IntoIter<Block>, Error>;
+pub type Fragments = IterOk<vec::IntoIter<TestFragment>, Error>;
+
+/// Node serving a linear chain of blocks.
+#[derive(Clone)]
+pub struct MockNode {
+    pub id: TestNodeId,
+    pub chain: Vec<Block>,
+    pub fragments: Vec<TestFragment>,
+    /// Fragments of the blocks in the chain. Blocks listed here
+    /// are announced in the compact form.
+    pub block_fragments: HashMap<BlockId, Vec<TestFragment>>,
+    /// Whether the node provides the block service.
+    pub serve_blocks: bool,
+    pub pushed_headers: Log<Block>,
+    pub uploaded_blocks: Log<Block>,
+    pub subscription_headers: Log<Block>,
+    pub subscribers: Arc<Mutex<Vec<TestNodeId>>>,
+    /// Peers banned here are not accepted as subscribers.
+    pub scores: Arc<Mutex<PeerScores<TestNodeId>>>,
+    /// Authenticated peers are only accepted with the keys pinned here.
+    pub peer_keys: Arc<Mutex<PeerKeys<TestNodeId>>>,
+}
+
+impl MockNode {
+    /// Creates a node with a chain of the given length and
+    /// a few fragments.
+    pub fn new(chain_length: usize) -> Self {
+        let mut chain = vec![Block::genesis(None)];
+        while chain.len() < chain_length {
+            let child = chain.last().unwrap().make_child(None);
+            chain.push(child);
+        }
+        MockNode {
+            id: TestNodeId(1),
+            chain,
+            fragments: (0..4).map(TestFragment).collect(),
+            block_fragments: HashMap::new(),
+            serve_blocks: true,
+            pushed_headers: new_log(),
+            uploaded_blocks: new_log(),
+            subscription_headers: new_log(),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            scores: Arc::new(Mutex::new(
+                PeerScores::new(ScoringConfig::default()).unwrap(),
+            )),
+            peer_keys: Arc::new(Mutex::new(PeerKeys::new())),
+        }
+    }
+
+    fn position(&self, id: &BlockId) -> Option<usize> {
+        self.chain.iter().position(|block| block.id() == *id)
+    }
+
+    /// blocks following the latest of the checkpoints, up to and including `to`
+    fn range(&self, from: &[BlockId], to: Option<&BlockId>) -> Result<Vec<Block>, Error> {
+        let not_found = || Error::new(Code::NotFound, "block not found");
+        let start = from
+            .iter()
+            .filter_map(|id| self.position(id))
+            .max()
+            .ok_or_else(not_found)?;
+        let end = match to {
+            Some(id) => self.position(id).ok_or_else(not_found)?,
+            None => self.chain.len() - 1,
+        };
+        Ok(self.chain[start + 1..=end].to_vec())
+    }
+
+    fn announcement(&self, block: &Block) -> Result<BlockEvent<Block>, Error> {
+        let fragments = match self.block_fragments.get(&block.id()) {
+            Some(fragments) => fragments,
+            None => return Ok(BlockEvent::Announce(block.header())),
+        };
+        let short_ids = fragments
+            .iter()
+            .map(|fragment| ShortFragmentId::compute(COMPACT_SALT, &TestFragmentId(fragment.0)))
+            .collect::<Result<_, _>>()?;
+        let compact = CompactBlock::new(block.header(), COMPACT_SALT, short_ids);
+        Ok(BlockEvent::AnnounceCompact(compact))
+    }
+
+    fn blocks(&self, ids: &[BlockId]) -> Vec<Block> {
+        ids.iter()
+            .filter_map(|id| self.position(id).map(|i| self.chain[i].clone()))
+            .collect()
+    }
+}
+
+impl Node for MockNode {
+    type BlockService = Self;
+    type FragmentService = Self;
+    type GossipService = Self;
+
+    fn block_service(&mut self) -> Option<&mut Self> {
+        if self.serve_blocks {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn fragment_service(&mut self) -> Option<&mut Self> {
+        Some(self)
+    }
+
+    fn gossip_service(&mut self) -> Option<&mut Self> {
+        Some(self)
+    }
+}
+
+impl P2pService for MockNode {
+    type NodeId = TestNodeId;
+
+    fn node_id(&self) -> TestNodeId {
+        self.id.clone()
+    }
+
+    fn accept_peer(&mut self, peer: &PeerIdentity<TestNodeId>) -> Result<(), Error> {
+        self.peer_keys.lock().unwrap().check(peer)?;
+        self.accept_unauthenticated_peer(peer.node_id())
+    }
+
+    fn accept_unauthenticated_peer(&mut self, node_id: &TestNodeId) -> Result<(), Error> {
+        self.scores.lock().unwrap().check(node_id, Instant::now())
+    }
+}
+
+impl server::BlockService for MockNode {
+    type BlockId = BlockId;
+    type BlockDate = BlockDate;
+    type Block = Block;
+    type Header = Block;
+    type TipFuture = FutureResult<Block, Error>;
+    type PullBlocksStream = Blocks;
+    type PullBlocksFuture = FutureResult<Blocks, Error>;
+    type PullBlocksToTipFuture = FutureResult<Blocks, Error>;
+    type GetBlocksStream = Blocks;
+    type GetBlocksFuture = FutureResult<Blocks, Error>;
+    type PullHeadersStream = Blocks;
+    type PullHeadersFuture = FutureResult<Blocks, Error>;
+    type GetHeadersStream = Blocks;
+    type GetHeadersFuture = FutureResult<Blocks, Error>;
+    type PushHeadersSink = Collector<Block>;
+    type UploadBlocksSink = Collector<Block>;
+    type BlockSubscription = MockSubscription<BlockEvent<Block>, Block>;
+    type BlockSubscriptionFuture = FutureResult<Self::BlockSubscription, Error>;
+
+    fn block0(&mut self) -> BlockId {
+        self.chain[0].id()
+    }
+
+    fn tip(&mut self) -> Self::TipFuture {
+        future::ok(self.chain.last().unwrap().header())
+    }
+
+    fn get_blocks(&mut self, ids: &[BlockId]) -> Self::GetBlocksFuture {
+        future::ok(stream::iter_ok(self.blocks(ids)))
+    }
+
+    fn get_headers(&mut self, ids: &[BlockId]) -> Self::GetHeadersFuture {
+        future::ok(stream::iter_ok(self.blocks(ids)))
+    }
+
+    fn pull_blocks(&mut self, from: &[BlockId], to: &BlockId) -> Self::PullBlocksFuture {
+        future::result(self.range(from, Some(to)).map(stream::iter_ok))
+    }
+
+    fn pull_blocks_to_tip(&mut self, from: &[BlockId]) -> Self::PullBlocksToTipFuture {
+        future::result(self.range(from, None).map(stream::iter_ok))
+    }
+
+    fn pull_headers(&mut self, from: &[BlockId], to: &BlockId) -> Self::PullHeadersFuture {
+        future::result(self.range(from, Some(to)).map(stream::iter_ok))
+    }
+
+    fn pull_headers_to_tip(&mut self, from: &[BlockId]) -> Self::PullHeadersFuture {
+        future::result(self.range(from, None).map(stream::iter_ok))
+    }
+
+    fn push_headers(&mut self) -> Self::PushHeadersSink {
+        Collector {
+            log: self.pushed_headers.clone(),
+        }
+    }
+
+    fn upload_blocks(&mut self) -> Self::UploadBlocksSink {
+        Collector {
+            log: self.uploaded_blocks.clone(),
+        }
+    }
+
+    fn block_subscription(&mut self, subscriber: TestNodeId) -> Self::BlockSubscriptionFuture {
+        self.subscribers.lock().unwrap().push(subscriber);
+        let outbound = self
+            .chain
+            .iter()
+            .map(|block| self.announcement(block))
+            .collect::<Result<_, _>>();
+        future::result(outbound.map(|outbound| MockSubscription {
+            outbound,
+            inbound: Collector {
+                log: self.subscription_headers.clone(),
+            },
+        }))
+    }
+}
+
+impl server::FragmentService for MockNode {
+    type Fragment = TestFragment;
+    type FragmentId = TestFragmentId;
+    type BlockId = BlockId;
+    type GetFragmentsStream = Fragments;
+    type GetFragmentsFuture = FutureResult<Fragments, Error>;
+    type FragmentSubscription = MockSubscription<TestFragment, TestFragment>;
+    type FragmentSubscriptionFuture = FutureResult<Self::FragmentSubscription, Error>;
+
+    fn get_fragments(&mut self, ids: &[TestFragmentId]) -> Self::GetFragmentsFuture {
+        let fragments = self
+            .fragments
+            .iter()
+            .filter(|fragment| ids.contains(&TestFragmentId(fragment.0)))
+            .cloned()
+            .collect::<Vec<_>>();
+        future::ok(stream::iter_ok(fragments))
+    }
+
+    fn get_block_fragments(
+        &mut self,
+        block_id: &BlockId,
+        indices: &[u32],
+    ) -> Result<Self::GetFragmentsFuture, Error> {
+        if self.position(block_id).is_none() {
+            return Err(Error::new(Code::NotFound, "block not found"));
+        }
+        let block_fragments = self
+            .block_fragments
+            .get(block_id)
+            .map_or(&[][..], |fragments| &fragments[..]);
+        let fragments = indices
+            .iter()
+            .map(|&i| {
+                block_fragments.get(i as usize).cloned().ok_or_else(|| {
+                    Error::new(Code::InvalidArgument, "fragment index is out of range")
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(future::ok(stream::iter_ok(fragments)))
+    }
+
+    fn fragment_subscription(&mut self, _: TestNodeId) -> Self::FragmentSubscriptionFuture {
+        future::ok(MockSubscription {
+            outbound: self.fragments.iter().cloned().collect(),
+            inbound: Collector { log: new_log() },
+        })
+    }
+}
+
+impl server::GossipService for MockNode {
+    type Node = TestGossipNode;
+    type GossipSubscription = MockSubscription<Gossip<TestGossipNode>, Gossip<TestGossipNode>>;
+    type GossipSubscriptionFuture = FutureResult<Self::GossipSubscription, Error>;
+    type PeersFuture = FutureResult<PeersResponse, Error>;
+
+    fn gossip_subscription(&mut self, _: TestNodeId) -> Self::GossipSubscriptionFuture {
+        future::ok(MockSubscription {
+            outbound: VecDeque::new(),
+            inbound: Collector { log: new_log() },
+        })
+    }
+
+    fn peers(&mut self) -> Self::PeersFuture {
+        future::ok(PeersResponse {
+            peers: vec![Peer {
+                addr: "127.0.0.1:8299".parse().unwrap(),
+            }],
+        })
+    }
+}
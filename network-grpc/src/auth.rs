@@ -0,0 +1,147 @@
+//! Challenge-response authentication of the nodes in the handshake.
+//!
+//! Each side of the connection can prove possession of its node key
+//! by signing a random nonce chosen by the other side, together with
+//! the serialized identifier of the node. The signed message is prefixed
+//! with the role of the signer, so that a signature made by a server
+//! cannot be replayed as a client authentication and vice versa.
+
+use chain_crypto::{Ed25519, PublicKey, SecretKey, Signature, Verification};
+use rand_core::{OsRng, RngCore};
+
+use std::{error, fmt};
+
+/// Secret key used by a node to authenticate itself to its peers.
+pub type NodeKey = SecretKey<Ed25519>;
+
+const NONCE_SIZE: usize = 32;
+
+const SERVER_ROLE: &[u8] = b"server";
+const CLIENT_ROLE: &[u8] = b"client";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Role {
+    Server,
+    Client,
+}
+
+impl Role {
+    fn tag(self) -> &'static [u8] {
+        match self {
+            Role::Server => SERVER_ROLE,
+            Role::Client => CLIENT_ROLE,
+        }
+    }
+}
+
+/// Error of the verification of a node signature.
+#[derive(Debug)]
+pub(crate) enum AuthError {
+    InvalidPublicKey,
+    InvalidSignature,
+    VerificationFailed,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::InvalidPublicKey => write!(f, "invalid node public key"),
+            AuthError::InvalidSignature => write!(f, "invalid node signature"),
+            AuthError::VerificationFailed => write!(f, "node signature verification failed"),
+        }
+    }
+}
+
+impl error::Error for AuthError {}
+
+pub(crate) fn generate_nonce() -> Vec<u8> {
+    let mut nonce = vec![0; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+fn signed_message(role: Role, nonce: &[u8], node_id: &[u8]) -> Vec<u8> {
+    let tag = role.tag();
+    let mut msg = Vec::with_capacity(tag.len() + nonce.len() + node_id.len());
+    msg.extend_from_slice(tag);
+    msg.extend_from_slice(nonce);
+    msg.extend_from_slice(node_id);
+    msg
+}
+
+pub(crate) fn sign(key: &NodeKey, role: Role, nonce: &[u8], node_id: &[u8]) -> Vec<u8> {
+    let msg = signed_message(role, nonce, node_id);
+    let signature: Signature<[u8], Ed25519> = key.sign_slice(&msg);
+    signature.as_ref().to_vec()
+}
+
+pub(crate) fn verify(
+    public_key: &[u8],
+    signature: &[u8],
+    role: Role,
+    nonce: &[u8],
+    node_id: &[u8],
+) -> Result<(), AuthError> {
+    let public_key =
+        PublicKey::<Ed25519>::from_binary(public_key).map_err(|_| AuthError::InvalidPublicKey)?;
+    let signature = Signature::<Vec<u8>, Ed25519>::from_binary(signature)
+        .map_err(|_| AuthError::InvalidSignature)?;
+    let msg = signed_message(role, nonce, node_id);
+    match signature.verify_slice(&public_key, &msg) {
+        Verification::Success => Ok(()),
+        Verification::Failed => Err(AuthError::VerificationFailed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_key() -> NodeKey {
+        NodeKey::generate(OsRng)
+    }
+
+    #[test]
+    fn signature_verifies() {
+        let key = node_key();
+        let nonce = generate_nonce();
+        let signature = sign(&key, Role::Client, &nonce, b"node");
+        let public_key = key.to_public();
+        assert!(verify(
+            public_key.as_ref(),
+            &signature,
+            Role::Client,
+            &nonce,
+            b"node"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn signature_is_bound_to_the_challenge() {
+        let key = node_key();
+        let nonce = generate_nonce();
+        let signature = sign(&key, Role::Server, &nonce, b"node");
+        let public_key = key.to_public();
+        let public_key = public_key.as_ref();
+        assert!(verify(public_key, &signature, Role::Client, &nonce, b"node").is_err());
+        assert!(verify(
+            public_key,
+            &signature,
+            Role::Server,
+            &generate_nonce(),
+            b"node"
+        )
+        .is_err());
+        assert!(verify(public_key, &signature, Role::Server, &nonce, b"other").is_err());
+        let other_key = node_key().to_public();
+        assert!(verify(
+            other_key.as_ref(),
+            &signature,
+            Role::Server,
+            &nonce,
+            b"node"
+        )
+        .is_err());
+    }
+}
@@ -9,23 +9,37 @@ pub mod unary;
 use client_streaming::RequestStream;
 
 use crate::{
-    convert::{encode_node_id, error_from_grpc, serialize_to_bytes, serialize_to_repeated_bytes},
+    auth::{self, NodeKey},
+    compression::{self, Compress, Compression},
+    convert::{
+        encode_node_auth, encode_node_id, error_from_grpc, serialize_to_bytes,
+        serialize_to_repeated_bytes,
+    },
     gen::{self, node::client as gen_client},
+    metrics::{self, Meter, MeteredBody},
 };
 
 use chain_core::property;
 use network_core::client::{BlockService, Client, FragmentService, GossipService, P2pService};
 use network_core::error as core_error;
-use network_core::gossip::{self, Gossip, PeersResponse};
+use network_core::gossip::{self, keys::PeerKeys, Gossip, PeerIdentity, PeersResponse};
 use network_core::subscription::BlockEvent;
 
 use futures::prelude::*;
 use tower_grpc::{BoxBody, Request};
 use tower_request_modifier::{self, RequestModifier};
 
+use std::hash::Hash;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
 pub use connect::{Connect, ConnectError, ConnectFuture};
 pub use handshake::HandshakeFuture;
 
+use handshake::{AuthState, Authentication, Negotiation};
+
 /// Traits setting additional bounds for blockchain entities
 /// that need to be satisfied for the protocol implementation.
 ///
@@ -99,9 +113,18 @@ pub trait ProtocolConfig {
     type FragmentId: chain_bounds::FragmentId;
     type Fragment: chain_bounds::Fragment + property::Fragment<Id = Self::FragmentId>;
     type Node: gossip::Node<Id = Self::NodeId> + property::Serialize + property::Deserialize;
-    type NodeId: gossip::NodeId + property::Serialize + property::Deserialize;
+    type NodeId: gossip::NodeId + property::Serialize + property::Deserialize + Eq + Hash;
 }
 
+// Transport service of a client connection.
+type Transport = Meter<RequestModifier<tower_hyper::client::Connection<BoxBody>, BoxBody>>;
+
+// Response future and body types of the transport service.
+type TransportFuture = metrics::ResponseFuture<
+    tower_hyper::client::ResponseFuture<hyper::client::conn::ResponseFuture>,
+>;
+type TransportBody = MeteredBody<tower_hyper::Body>;
+
 /// gRPC client for blockchain node.
 ///
 /// This type encapsulates the gRPC protocol client that can
@@ -110,14 +133,44 @@ pub struct Connection<P>
 where
     P: ProtocolConfig,
 {
-    service: gen_client::Node<RequestModifier<tower_hyper::client::Connection<BoxBody>, BoxBody>>,
+    service: gen_client::Node<Transport>,
     node_id: Option<<P::Node as gossip::Node>::Id>,
+    node_key: Option<Arc<NodeKey>>,
+    // Node keys of the trusted servers.
+    peer_keys: Arc<PeerKeys<P::NodeId>>,
+    auth: Arc<Mutex<AuthState<P::NodeId>>>,
+    // Whether compact block relay is requested in the handshake.
+    request_compact_blocks: bool,
+    // Set when compact block relay has been negotiated in the handshake.
+    compact_blocks: Arc<AtomicBool>,
+    // Compression algorithms offered to the server in the handshake.
+    accept_compression: Arc<[Compression]>,
+    // Compression negotiated for block content in the handshake.
+    compression: Arc<Mutex<Option<Compression>>>,
 }
 
 impl<P> Connection<P>
 where
     P: ProtocolConfig,
 {
+    /// Returns the identity of the server node, if it has been
+    /// authenticated in the handshake.
+    ///
+    /// The server node is authenticated when the connection has
+    /// been established with a node key.
+    pub fn peer_identity(&self) -> Option<PeerIdentity<P::NodeId>> {
+        self.auth.lock().unwrap().peer.clone()
+    }
+
+    /// Returns true if the server has agreed in the handshake to announce
+    /// blocks in the compact form on block subscriptions.
+    ///
+    /// Compact block relay is only requested if enabled with
+    /// `Connect::compact_blocks`.
+    pub fn compact_blocks(&self) -> bool {
+        self.compact_blocks.load(Ordering::Relaxed)
+    }
+
     fn new_subscription_request<R, Out>(&self, outbound: Out) -> Request<RequestStream<Out, R>>
     where
         Out: Stream + Send + 'static,
@@ -126,6 +179,11 @@ where
         let mut req = Request::new(rs);
         if let Some(ref id) = self.node_id {
             encode_node_id(id, req.metadata_mut()).unwrap();
+            let auth = self.auth.lock().unwrap();
+            if let (Some(key), Some(signature)) = (&self.node_key, &auth.signature) {
+                let public_key = key.to_public();
+                encode_node_auth(public_key.as_ref(), signature, req.metadata_mut());
+            }
         } else {
             // In the current server-side implementation, the request
             // will be rejected as invalid without the node ID.
@@ -141,7 +199,9 @@ where
     P: ProtocolConfig,
 {
     fn poll_ready(&mut self) -> Poll<(), core_error::Error> {
-        self.service.poll_ready().map_err(error_from_grpc)
+        self.service
+            .poll_ready::<BoxBody>()
+            .map_err(error_from_grpc)
     }
 }
 
@@ -158,7 +218,7 @@ where
 {
     type Block = P::Block;
 
-    type HandshakeFuture = HandshakeFuture<P::BlockId>;
+    type HandshakeFuture = HandshakeFuture<P>;
 
     type TipFuture = unary::ResponseFuture<P::Header, gen::node::TipResponse>;
 
@@ -181,9 +241,36 @@ where
     type UploadBlocksFuture = client_streaming::ResponseFuture<gen::node::UploadBlocksResponse>;
 
     fn handshake(&mut self) -> Self::HandshakeFuture {
-        let req = gen::node::HandshakeRequest {};
+        let auth = self.node_key.as_ref().map(|key| Authentication {
+            node_key: key.clone(),
+            node_id: self
+                .node_id
+                .as_ref()
+                .map(|id| serialize_to_bytes(id).unwrap()),
+            nonce: auth::generate_nonce(),
+            peer_keys: self.peer_keys.clone(),
+            state: self.auth.clone(),
+        });
+        let req = gen::node::HandshakeRequest {
+            nonce: auth
+                .as_ref()
+                .map(|auth| auth.nonce.clone())
+                .unwrap_or_default(),
+            compact_blocks: self.request_compact_blocks,
+            accept_compression: self
+                .accept_compression
+                .iter()
+                .map(|c| compression::into_proto(Some(*c)))
+                .collect(),
+        };
         let future = self.service.handshake(Request::new(req));
-        HandshakeFuture::new(future)
+        let negotiation = Negotiation {
+            request_compact_blocks: self.request_compact_blocks,
+            compact_blocks: self.compact_blocks.clone(),
+            accepted: self.accept_compression.clone(),
+            selected: self.compression.clone(),
+        };
+        HandshakeFuture::new(future, auth, negotiation)
     }
 
     fn tip(&mut self) -> Self::TipFuture {
@@ -228,7 +315,8 @@ where
     where
         S: Stream<Item = P::Block, Error = core_error::Error> + Send + 'static,
     {
-        let rs = RequestStream::new(blocks);
+        let compression = *self.compression.lock().unwrap();
+        let rs = Compress::new(RequestStream::new(blocks), compression);
         let req = Request::new(rs);
         let future = self.service.upload_blocks(req);
         client_streaming::ResponseFuture::new(future)
@@ -249,6 +337,7 @@ where
     P: ProtocolConfig,
 {
     type Fragment = P::Fragment;
+    type BlockId = P::BlockId;
 
     type GetFragmentsStream = server_streaming::ResponseStream<P::Fragment, gen::node::Fragment>;
     type GetFragmentsFuture = server_streaming::ResponseFuture<P::Fragment, gen::node::Fragment>;
@@ -259,11 +348,29 @@ where
 
     fn get_fragments(&mut self, ids: &[P::FragmentId]) -> Self::GetFragmentsFuture {
         let ids = serialize_to_repeated_bytes(ids).unwrap();
-        let req = gen::node::FragmentIds { ids };
+        let req = gen::node::FragmentIds {
+            ids,
+            ..Default::default()
+        };
         let future = self.service.get_fragments(Request::new(req));
         server_streaming::ResponseFuture::new(future)
     }
 
+    fn get_block_fragments(
+        &mut self,
+        block_id: &P::BlockId,
+        indices: &[u32],
+    ) -> Result<Self::GetFragmentsFuture, core_error::Error> {
+        let block_id = serialize_to_bytes(block_id).unwrap();
+        let req = gen::node::FragmentIds {
+            ids: Vec::new(),
+            block_id,
+            indices: indices.to_vec(),
+        };
+        let future = self.service.get_fragments(Request::new(req));
+        Ok(server_streaming::ResponseFuture::new(future))
+    }
+
     fn fragment_subscription<Out>(&mut self, outbound: Out) -> Self::FragmentSubscriptionFuture
     where
         Out: Stream<Item = P::Fragment, Error = core_error::Error> + Send + 'static,
@@ -1,3 +1,4 @@
+use super::{TransportBody, TransportFuture};
 use crate::convert::{error_from_grpc, error_into_grpc, IntoProtobuf};
 use network_core::error as core_error;
 
@@ -6,11 +7,8 @@ use tower_grpc::Status;
 
 use std::marker::PhantomData;
 
-type GrpcFuture<R> = tower_grpc::client::client_streaming::ResponseFuture<
-    R,
-    tower_hyper::client::ResponseFuture<hyper::client::conn::ResponseFuture>,
-    tower_hyper::Body,
->;
+type GrpcFuture<R> =
+    tower_grpc::client::client_streaming::ResponseFuture<R, TransportFuture, TransportBody>;
 
 pub struct ResponseFuture<R> {
     inner: GrpcFuture<R>,
@@ -1,7 +1,13 @@
 use super::{Connection, ProtocolConfig};
-use crate::gen::node::client as gen_client;
+use crate::{
+    auth::NodeKey,
+    compression::Compression,
+    gen::node::client as gen_client,
+    metrics::{Meter, Metrics, NoMetrics, Side},
+    tls::{self, ClientConfig, ClientSession, MaybeTlsStream, TlsStream},
+};
 
-use network_core::gossip;
+use network_core::gossip::{self, keys::PeerKeys};
 
 use futures::prelude::*;
 use futures::try_ready;
@@ -11,18 +17,33 @@ use hyper::client::connect::Connect as HyperConnect;
 use tower_grpc::BoxBody;
 use tower_hyper::client::ConnectExecutor;
 use tower_hyper::util::{Connector, Destination};
+use tower_service::Service;
 use tower_util::MakeService;
+use webpki::{DNSName, DNSNameRef, InvalidDNSNameError};
 
-use std::{error::Error, fmt, mem};
+use std::{
+    error::Error,
+    fmt, mem,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
 
 /// Builder-like API for establishing a protocol client connection.
 pub struct Connect<P, C, E>
 where
     P: ProtocolConfig,
 {
-    tower_connect: tower_hyper::client::Connect<Destination, BoxBody, Connector<C>, E>,
+    connector: C,
+    settings: tower_hyper::client::Builder,
+    executor: E,
     origin: Option<Origin>,
     node_id: Option<<P::Node as gossip::Node>::Id>,
+    node_key: Option<Arc<NodeKey>>,
+    peer_keys: Arc<PeerKeys<P::NodeId>>,
+    tls: Option<Arc<ClientConfig>>,
+    compact_blocks: bool,
+    compression: Arc<[Compression]>,
+    metrics: Arc<dyn Metrics>,
 }
 
 struct Origin {
@@ -38,15 +59,7 @@ where
 {
     /// create a new Connection utilizing the Global tokio `DefaultExecutor`.
     pub fn new(connector: C) -> Self {
-        let connector = Connector::new(connector);
-        let mut settings = tower_hyper::client::Builder::new();
-        settings.http2_only(true);
-        let tower_connect = tower_hyper::client::Connect::with_builder(connector, settings);
-        Connect {
-            tower_connect,
-            origin: None,
-            node_id: None,
-        }
+        Connect::with_executor(connector, tokio_executor::DefaultExecutor::current())
     }
 }
 
@@ -58,15 +71,20 @@ where
 {
     /// create a new Connection but using the the given `Executor`.
     pub fn with_executor(connector: C, executor: E) -> Self {
-        let connector = Connector::new(connector);
         let mut settings = tower_hyper::client::Builder::new();
         settings.http2_only(true);
-        let tower_connect =
-            tower_hyper::client::Connect::with_executor(connector, settings, executor);
         Connect {
-            tower_connect,
+            connector,
+            settings,
+            executor,
             origin: None,
             node_id: None,
+            node_key: None,
+            peer_keys: Arc::new(PeerKeys::new()),
+            tls: None,
+            compact_blocks: false,
+            compression: Arc::new([]),
+            metrics: Arc::new(NoMetrics),
         }
     }
 }
@@ -84,6 +102,74 @@ where
         self.node_id = Some(id);
         self
     }
+
+    /// Sets the key this node uses to authenticate itself to the server.
+    ///
+    /// With the node key set, the server is authenticated in the handshake
+    /// if it has a node key of its own; see `Connection::peer_identity`.
+    /// If the node ID is also set, the client node authenticates with it
+    /// to such a server.
+    pub fn node_key(&mut self, key: NodeKey) -> &mut Self {
+        self.node_key = Some(Arc::new(key));
+        self
+    }
+
+    /// Sets the node keys of the servers trusted by this node.
+    ///
+    /// A server authenticated in the handshake is only accepted if
+    /// the node ID it claims is bound to the key it has authenticated
+    /// with; otherwise, the handshake fails. By default, no keys are
+    /// pinned and the handshake fails with any server that has a node key.
+    pub fn peer_keys(&mut self, keys: PeerKeys<P::NodeId>) -> &mut Self {
+        self.peer_keys = Arc::new(keys);
+        self
+    }
+
+    /// Enables TLS on the connections, using the given configuration.
+    ///
+    /// The ALPN protocol list in the configuration is replaced with `h2`.
+    /// The server certificate is verified against the host name
+    /// of the request origin if set, otherwise against the host name
+    /// of the connection target.
+    /// The client certificate, if required by the server, is set up with
+    /// the configuration; see `tls::client_config`.
+    pub fn tls(&mut self, mut config: ClientConfig) -> &mut Self {
+        tls::set_alpn_h2(&mut config.alpn_protocols);
+        self.tls = Some(Arc::new(config));
+        self
+    }
+
+    /// Requests the server in the handshake to announce blocks in the
+    /// compact form on block subscriptions.
+    ///
+    /// The client application is expected to reconstruct announced blocks
+    /// from its fragment pool and retrieve the missing fragments with
+    /// `get_block_fragments`; see the `network_core::compact` module.
+    /// Whether the server has agreed is reported by
+    /// `Connection::compact_blocks` after the handshake.
+    pub fn compact_blocks(&mut self) -> &mut Self {
+        self.compact_blocks = true;
+        self
+    }
+
+    /// Offers the server in the handshake to compress block content
+    /// with one of the given algorithms, listed in the order of preference.
+    ///
+    /// If the server does not support any of the algorithms,
+    /// block content is transferred uncompressed.
+    pub fn compression(&mut self, algorithms: &[Compression]) -> &mut Self {
+        self.compression = algorithms.into();
+        self
+    }
+
+    /// Sets the receiver of the metrics of calls made on the connections.
+    ///
+    /// The calls are reported with the address of the connection target,
+    /// if the target host is specified as an IP address.
+    pub fn metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self
+    }
 }
 
 impl<P, C, E> Connect<P, C, E>
@@ -117,32 +203,80 @@ where
             .build()
             .map_err(|e| ConnectError(ErrorKind::InvalidOrigin(e)))
     }
+
+    fn tls_server_name(&self, target: &Destination) -> Result<DNSName, ConnectError<C::Error>> {
+        let host = match self.origin {
+            Some(ref origin) => origin.authority.host(),
+            None => target.host(),
+        };
+        DNSNameRef::try_from_ascii_str(host)
+            .map(|name| name.to_owned())
+            .map_err(|e| ConnectError(ErrorKind::InvalidServerName(e)))
+    }
 }
 
 impl<P, C, E> Connect<P, C, E>
 where
     P: ProtocolConfig,
-    C: HyperConnect + 'static,
+    C: HyperConnect + Clone + 'static,
     C::Transport: HttpConnection,
-    E: ConnectExecutor<C::Transport, BoxBody> + Clone,
+    E: ConnectExecutor<MaybeTlsStream<C::Transport>, BoxBody> + Clone,
 {
     pub fn connect(&mut self, target: Destination) -> ConnectFuture<P, C, E> {
         let origin_uri = match self.origin_uri(&target) {
             Ok(uri) => uri,
             Err(e) => return ConnectFuture::error(e),
         };
+        let tls = match &self.tls {
+            Some(config) => match self.tls_server_name(&target) {
+                Ok(server_name) => Some((config.clone(), server_name)),
+                Err(e) => return ConnectFuture::error(e),
+            },
+            None => None,
+        };
         let node_id = self.node_id.clone();
-        let inner = self.tower_connect.make_service(target);
+        let node_key = self.node_key.clone();
+        let peer_keys = self.peer_keys.clone();
+        let peer_addr = target_addr(&target);
+        let connector = TlsConnector {
+            inner: Connector::new(self.connector.clone()),
+            tls,
+        };
+        let mut tower_connect = tower_hyper::client::Connect::with_executor(
+            connector,
+            self.settings.clone(),
+            self.executor.clone(),
+        );
+        let inner = tower_connect.make_service(target);
         ConnectFuture {
             state: State::Connecting {
                 inner,
                 origin_uri,
                 node_id,
+                node_key,
+                peer_keys,
+                compact_blocks: self.compact_blocks,
+                compression: self.compression.clone(),
+                metrics: self.metrics.clone(),
+                peer_addr,
             },
         }
     }
 }
 
+// Returns the socket address of the connection target,
+// if the host is an IP address.
+fn target_addr(target: &Destination) -> Option<SocketAddr> {
+    let host = target.host();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let ip: IpAddr = host.parse().ok()?;
+    let port = target.port().unwrap_or_else(|| match target.scheme() {
+        "https" => 443,
+        _ => 80,
+    });
+    Some(SocketAddr::new(ip, port))
+}
+
 /// Completes with a protocol client Connection when it has been
 /// set up.
 pub struct ConnectFuture<P, C, E>
@@ -161,9 +295,15 @@ where
     C::Transport: HttpConnection,
 {
     Connecting {
-        inner: tower_hyper::client::ConnectFuture<Destination, BoxBody, Connector<C>, E>,
+        inner: tower_hyper::client::ConnectFuture<Destination, BoxBody, TlsConnector<C>, E>,
         origin_uri: Uri,
         node_id: Option<<P::Node as gossip::Node>::Id>,
+        node_key: Option<Arc<NodeKey>>,
+        peer_keys: Arc<PeerKeys<P::NodeId>>,
+        compact_blocks: bool,
+        compression: Arc<[Compression]>,
+        metrics: Arc<dyn Metrics>,
+        peer_addr: Option<SocketAddr>,
     },
     Error(ConnectError<C::Error>),
     Finished,
@@ -187,7 +327,7 @@ where
     P: ProtocolConfig,
     C: HyperConnect,
     C::Transport: HttpConnection,
-    E: ConnectExecutor<C::Transport, BoxBody>,
+    E: ConnectExecutor<MaybeTlsStream<C::Transport>, BoxBody>,
 {
     type Item = Connection<P>;
     type Error = ConnectError<C::Error>;
@@ -203,14 +343,28 @@ where
                 inner: _,
                 origin_uri,
                 node_id,
+                node_key,
+                peer_keys,
+                compact_blocks,
+                compression,
+                metrics,
+                peer_addr,
             } => {
                 let conn = tower_request_modifier::Builder::new()
                     .set_origin(origin_uri)
                     .build(conn_ready.unwrap())
                     .unwrap();
+                let conn = Meter::new(conn, metrics, Side::Client, peer_addr);
                 let conn = Connection {
                     service: gen_client::Node::new(conn),
                     node_id: node_id,
+                    node_key,
+                    peer_keys,
+                    auth: Arc::new(Mutex::new(Default::default())),
+                    request_compact_blocks: compact_blocks,
+                    compact_blocks: Default::default(),
+                    accept_compression: compression,
+                    compression: Arc::new(Mutex::new(None)),
                 };
                 return Ok(Async::Ready(conn));
             }
@@ -227,6 +381,7 @@ pub struct ConnectError<T>(ErrorKind<T>);
 enum ErrorKind<T> {
     Http(tower_hyper::client::ConnectError<T>),
     InvalidOrigin(http::Error),
+    InvalidServerName(InvalidDNSNameError),
 }
 
 impl<T> ConnectError<T> {
@@ -260,6 +415,7 @@ impl<T> fmt::Display for ConnectError<T> {
         match self.0 {
             ErrorKind::Http(_) => write!(f, "HTTP/2.0 connection error"),
             ErrorKind::InvalidOrigin(_) => write!(f, "invalid request origin"),
+            ErrorKind::InvalidServerName(_) => write!(f, "invalid TLS server name"),
         }
     }
 }
@@ -272,6 +428,7 @@ where
         match self.0 {
             ErrorKind::Http(ref e) => Some(e),
             ErrorKind::InvalidOrigin(ref e) => Some(e),
+            ErrorKind::InvalidServerName(ref e) => Some(e),
         }
     }
 }
@@ -281,3 +438,58 @@ impl<T> From<tower_hyper::client::ConnectError<T>> for ConnectError<T> {
         ConnectError(ErrorKind::Http(err))
     }
 }
+
+// Makes transport connections with the hyper connector, wrapping them
+// into TLS sessions if enabled.
+struct TlsConnector<C> {
+    inner: Connector<C>,
+    tls: Option<(Arc<ClientConfig>, DNSName)>,
+}
+
+impl<C> Service<Destination> for TlsConnector<C>
+where
+    C: HyperConnect,
+{
+    type Response = MaybeTlsStream<C::Transport>;
+    type Error = C::Error;
+    type Future = TlsConnectorFuture<C>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: Destination) -> Self::Future {
+        TlsConnectorFuture {
+            inner: self.inner.call(target),
+            tls: self.tls.clone(),
+        }
+    }
+}
+
+struct TlsConnectorFuture<C>
+where
+    C: HyperConnect,
+{
+    inner: tower_hyper::util::ConnectorFuture<C>,
+    tls: Option<(Arc<ClientConfig>, DNSName)>,
+}
+
+impl<C> Future for TlsConnectorFuture<C>
+where
+    C: HyperConnect,
+{
+    type Item = MaybeTlsStream<C::Transport>;
+    type Error = C::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let transport = try_ready!(self.inner.poll());
+        let stream = match self.tls.take() {
+            None => MaybeTlsStream::Plain(transport),
+            Some((config, server_name)) => {
+                let session = ClientSession::new(&config, server_name.as_ref());
+                MaybeTlsStream::Tls(Box::new(TlsStream::new(transport, session)))
+            }
+        };
+        Ok(Async::Ready(stream))
+    }
+}
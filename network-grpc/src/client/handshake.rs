@@ -1,36 +1,126 @@
-use crate::{convert, gen, PROTOCOL_VERSION};
-use chain_core::property;
+use super::{ProtocolConfig, TransportBody, TransportFuture};
+use crate::{
+    auth::{self, NodeKey, Role},
+    compression::{self, Compression},
+    convert, gen, PROTOCOL_VERSION,
+};
 use network_core::client::HandshakeError;
+use network_core::gossip::{keys::PeerKeys, PeerIdentity};
 
 use futures::prelude::*;
 
-use std::marker::PhantomData;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
 
 type ResponseFuture = tower_grpc::client::unary::ResponseFuture<
     gen::node::HandshakeResponse,
-    tower_hyper::client::ResponseFuture<hyper::client::conn::ResponseFuture>,
-    hyper::Body,
+    TransportFuture,
+    TransportBody,
 >;
 
-pub struct HandshakeFuture<Id> {
+/// Authentication state of a client connection,
+/// updated when the handshake completes.
+pub(super) struct AuthState<Id> {
+    // Verified identity of the server node.
+    pub peer: Option<PeerIdentity<Id>>,
+    // Signature of the server's challenge made with the client node key.
+    pub signature: Option<Vec<u8>>,
+}
+
+impl<Id> Default for AuthState<Id> {
+    fn default() -> Self {
+        AuthState {
+            peer: None,
+            signature: None,
+        }
+    }
+}
+
+// Client node credentials and the challenge sent to the server.
+pub(super) struct Authentication<P: ProtocolConfig> {
+    pub node_key: Arc<NodeKey>,
+    pub node_id: Option<Vec<u8>>,
+    pub nonce: Vec<u8>,
+    pub peer_keys: Arc<PeerKeys<P::NodeId>>,
+    pub state: Arc<Mutex<AuthState<P::NodeId>>>,
+}
+
+impl<P: ProtocolConfig> Authentication<P> {
+    fn process_response(&self, res: &gen::node::HandshakeResponse) -> Result<(), HandshakeError> {
+        // A server without a node key neither signs the challenge
+        // nor issues one; the connection is then left unauthenticated
+        // on both sides.
+        if res.signature.is_empty() {
+            return Ok(());
+        }
+        auth::verify(
+            &res.public_key,
+            &res.signature,
+            Role::Server,
+            &self.nonce,
+            &res.node_id,
+        )
+        .map_err(|e| HandshakeError::Authentication(e.to_string().into()))?;
+        let node_id = convert::deserialize_bytes(&res.node_id)?;
+        let peer = PeerIdentity::new(node_id, &res.public_key);
+        self.peer_keys
+            .check(&peer)
+            .map_err(|e| HandshakeError::Authentication(e.to_string().into()))?;
+        let mut state = self.state.lock().unwrap();
+        state.peer = Some(peer);
+        if let Some(id) = &self.node_id {
+            state.signature = Some(auth::sign(&self.node_key, Role::Client, &res.nonce, id));
+        }
+        Ok(())
+    }
+}
+
+// Connection features requested from the server and the shared state
+// receiving the ones agreed to in the handshake response.
+pub(super) struct Negotiation {
+    pub request_compact_blocks: bool,
+    pub compact_blocks: Arc<AtomicBool>,
+    pub accepted: Arc<[Compression]>,
+    pub selected: Arc<Mutex<Option<Compression>>>,
+}
+
+impl Negotiation {
+    fn process_response(&self, res: &gen::node::HandshakeResponse) {
+        // The server should not agree to what has not been requested.
+        let compact_blocks = self.request_compact_blocks && res.compact_blocks;
+        self.compact_blocks.store(compact_blocks, Ordering::Relaxed);
+        // Older servers leave the field unset, selecting no compression;
+        // an algorithm that has not been offered is disregarded as well.
+        let selected = compression::from_proto(res.compression)
+            .filter(|compression| self.accepted.contains(compression));
+        *self.selected.lock().unwrap() = selected;
+    }
+}
+
+pub struct HandshakeFuture<P: ProtocolConfig> {
     inner: ResponseFuture,
-    _phantom: PhantomData<Id>,
+    auth: Option<Authentication<P>>,
+    negotiation: Negotiation,
 }
 
-impl<Id> HandshakeFuture<Id> {
-    pub fn new(inner: ResponseFuture) -> Self {
+impl<P: ProtocolConfig> HandshakeFuture<P> {
+    pub(super) fn new(
+        inner: ResponseFuture,
+        auth: Option<Authentication<P>>,
+        negotiation: Negotiation,
+    ) -> Self {
         HandshakeFuture {
             inner,
-            _phantom: PhantomData,
+            auth,
+            negotiation,
         }
     }
 }
 
-impl<Id> Future for HandshakeFuture<Id>
-where
-    Id: property::BlockId + property::Deserialize,
-{
-    type Item = Id;
+impl<P: ProtocolConfig> Future for HandshakeFuture<P> {
+    type Item = P::BlockId;
     type Error = HandshakeError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -45,6 +135,10 @@ where
             ));
         }
         let block0_id = convert::deserialize_bytes(&res.block0)?;
+        if let Some(auth) = &self.auth {
+            auth.process_response(&res)?;
+        }
+        self.negotiation.process_response(&res);
         Ok(Async::Ready(block0_id))
     }
 }
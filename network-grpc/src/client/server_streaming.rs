@@ -1,3 +1,4 @@
+use super::{TransportBody, TransportFuture};
 use crate::convert::{error_from_grpc, FromProtobuf};
 use network_core::error as core_error;
 
@@ -6,10 +7,7 @@ use tower_grpc::Streaming;
 
 use std::marker::PhantomData;
 
-type GrpcFuture<R> = tower_grpc::client::server_streaming::ResponseFuture<
-    R,
-    tower_hyper::client::ResponseFuture<hyper::client::conn::ResponseFuture>,
->;
+type GrpcFuture<R> = tower_grpc::client::server_streaming::ResponseFuture<R, TransportFuture>;
 
 pub struct ResponseFuture<T, R> {
     inner: GrpcFuture<R>,
@@ -43,12 +41,12 @@ where
 }
 
 pub struct ResponseStream<T, R> {
-    inner: Streaming<R, tower_hyper::Body>,
+    inner: Streaming<R, TransportBody>,
     _phantom: PhantomData<T>,
 }
 
 impl<T, R> ResponseStream<T, R> {
-    pub(super) fn new(inner: Streaming<R, tower_hyper::Body>) -> Self {
+    pub(super) fn new(inner: Streaming<R, TransportBody>) -> Self {
         ResponseStream {
             inner,
             _phantom: PhantomData,
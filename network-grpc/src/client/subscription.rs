@@ -1,4 +1,5 @@
 use super::server_streaming::ResponseStream;
+use super::TransportFuture;
 use crate::convert::{decode_node_id, error_from_grpc};
 use chain_core::property;
 use network_core::error as core_error;
@@ -8,10 +9,7 @@ use futures::prelude::*;
 
 use std::marker::PhantomData;
 
-type GrpcFuture<R> = tower_grpc::client::streaming::ResponseFuture<
-    R,
-    tower_hyper::client::ResponseFuture<hyper::client::conn::ResponseFuture>,
->;
+type GrpcFuture<R> = tower_grpc::client::streaming::ResponseFuture<R, TransportFuture>;
 
 pub struct ResponseFuture<T, Id, R> {
     inner: GrpcFuture<R>,
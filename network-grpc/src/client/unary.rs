@@ -1,3 +1,4 @@
+use super::{TransportBody, TransportFuture};
 use crate::convert::{error_from_grpc, FromProtobuf};
 use network_core::error as core_error;
 
@@ -5,11 +6,7 @@ use futures::prelude::*;
 
 use std::marker::PhantomData;
 
-type GrpcFuture<R> = tower_grpc::client::unary::ResponseFuture<
-    R,
-    tower_hyper::client::ResponseFuture<hyper::client::conn::ResponseFuture>,
-    tower_hyper::Body,
->;
+type GrpcFuture<R> = tower_grpc::client::unary::ResponseFuture<R, TransportFuture, TransportBody>;
 
 pub struct ResponseFuture<T, R> {
     inner: GrpcFuture<R>,
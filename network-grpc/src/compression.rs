@@ -0,0 +1,243 @@
+//! Compression of block content transferred over the protocol.
+
+use crate::gen;
+
+use network_core::error as core_error;
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+use futures::prelude::*;
+use tower_grpc::{Response, Status};
+
+use std::io::{self, Read, Write};
+
+// Upper bound on the size of decompressed block content, protecting
+// against decompression bombs.
+const MAX_DECOMPRESSED_SIZE: u64 = 64 * 1024 * 1024;
+
+// Compression level used with zstd.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression algorithm for block content.
+///
+/// The client lists the compression algorithms it supports in
+/// the handshake, and the server selects the first of them that it has
+/// enabled. Block content sent on the connection in either direction may
+/// then be compressed with the selected algorithm; each block message
+/// indicates whether its content is compressed. If either peer does not
+/// support compression, blocks are transferred uncompressed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::stream::encode_all(data, ZSTD_LEVEL),
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let decoder: Box<dyn Read> = match self {
+            Compression::Gzip => Box::new(GzDecoder::new(data)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+        };
+        let mut buf = Vec::new();
+        decoder
+            .take(MAX_DECOMPRESSED_SIZE + 1)
+            .read_to_end(&mut buf)?;
+        if buf.len() as u64 > MAX_DECOMPRESSED_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decompressed content exceeds the size limit",
+            ));
+        }
+        Ok(buf)
+    }
+}
+
+pub fn into_proto(compression: Option<Compression>) -> i32 {
+    let value = match compression {
+        None => gen::node::Compression::Identity,
+        Some(Compression::Gzip) => gen::node::Compression::Gzip,
+        Some(Compression::Zstd) => gen::node::Compression::Zstd,
+    };
+    value as i32
+}
+
+// Returns None for identity and unknown values, the latter possibly
+// sent by peers supporting newer algorithms.
+pub fn from_proto(value: i32) -> Option<Compression> {
+    match gen::node::Compression::from_i32(value) {
+        Some(gen::node::Compression::Gzip) => Some(Compression::Gzip),
+        Some(gen::node::Compression::Zstd) => Some(Compression::Zstd),
+        Some(gen::node::Compression::Identity) | None => None,
+    }
+}
+
+/// Selects the first of the algorithms accepted by the client that is
+/// also enabled on the server.
+pub fn negotiate(accepted: &[i32], enabled: &[Compression]) -> Option<Compression> {
+    accepted
+        .iter()
+        .filter_map(|value| from_proto(*value))
+        .find(|compression| enabled.contains(compression))
+}
+
+/// Compresses the content of a block message.
+///
+/// The content is left uncompressed if compression does not reduce
+/// its size.
+pub fn compress_block(msg: &mut gen::node::Block, compression: Compression) -> Result<(), Status> {
+    debug_assert_eq!(msg.compression, into_proto(None));
+    let compressed = compression
+        .compress(&msg.content)
+        .map_err(|e| Status::new(tower_grpc::Code::Internal, e.to_string()))?;
+    if compressed.len() < msg.content.len() {
+        msg.content = compressed;
+        msg.compression = into_proto(Some(compression));
+    }
+    Ok(())
+}
+
+/// Returns the decompressed content of a block message.
+pub fn decompress_block(msg: gen::node::Block) -> Result<Vec<u8>, core_error::Error> {
+    if gen::node::Compression::from_i32(msg.compression).is_none() {
+        return Err(core_error::Error::new(
+            core_error::Code::InvalidArgument,
+            format!("unsupported block compression {}", msg.compression),
+        ));
+    }
+    match from_proto(msg.compression) {
+        None => Ok(msg.content),
+        Some(compression) => compression
+            .decompress(&msg.content)
+            .map_err(|e| core_error::Error::new(core_error::Code::InvalidArgument, e)),
+    }
+}
+
+/// Stream adapter compressing block messages with the given algorithm,
+/// if any.
+#[must_use = "streams do nothing unless polled"]
+pub struct Compress<S> {
+    inner: S,
+    compression: Option<Compression>,
+}
+
+impl<S> Compress<S> {
+    pub fn new(inner: S, compression: Option<Compression>) -> Self {
+        Compress { inner, compression }
+    }
+}
+
+impl<S> Stream for Compress<S>
+where
+    S: Stream<Item = gen::node::Block, Error = Status>,
+{
+    type Item = gen::node::Block;
+    type Error = Status;
+
+    fn poll(&mut self) -> Poll<Option<gen::node::Block>, Status> {
+        let mut item = try_ready!(self.inner.poll());
+        if let (Some(msg), Some(compression)) = (&mut item, self.compression) {
+            compress_block(msg, compression)?;
+        }
+        Ok(Async::Ready(item))
+    }
+}
+
+/// Resolves to a streamed response compressing block messages
+/// of the response stream produced by the inner future.
+#[must_use = "futures do nothing unless polled"]
+pub struct CompressResponse<F> {
+    inner: F,
+    compression: Option<Compression>,
+}
+
+impl<F> CompressResponse<F> {
+    pub fn new(inner: F, compression: Option<Compression>) -> Self {
+        CompressResponse { inner, compression }
+    }
+}
+
+impl<F, S> Future for CompressResponse<F>
+where
+    F: Future<Item = Response<S>, Error = Status>,
+{
+    type Item = Response<Compress<S>>;
+    type Error = Status;
+
+    fn poll(&mut self) -> Poll<Self::Item, Status> {
+        let res = try_ready!(self.inner.poll());
+        let compression = self.compression;
+        Ok(Async::Ready(
+            res.map(|stream| Compress::new(stream, compression)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(content: Vec<u8>) -> gen::node::Block {
+        gen::node::Block {
+            content,
+            compression: into_proto(None),
+        }
+    }
+
+    #[test]
+    fn block_content_round_trip() {
+        let content = b"block content ".repeat(100);
+        for &compression in &[Compression::Gzip, Compression::Zstd] {
+            let mut msg = block(content.clone());
+            compress_block(&mut msg, compression).unwrap();
+            assert_eq!(from_proto(msg.compression), Some(compression));
+            assert!(msg.content.len() < content.len());
+            assert_eq!(decompress_block(msg).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn incompressible_content_is_sent_as_is() {
+        let content = vec![0x5a];
+        let mut msg = block(content.clone());
+        compress_block(&mut msg, Compression::Zstd).unwrap();
+        assert_eq!(msg.compression, into_proto(None));
+        assert_eq!(decompress_block(msg).unwrap(), content);
+    }
+
+    #[test]
+    fn unknown_compression_is_rejected() {
+        let mut msg = block(vec![1, 2, 3]);
+        msg.compression = 100;
+        let err = decompress_block(msg).unwrap_err();
+        assert_eq!(err.code(), core_error::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn negotiation_follows_client_preference() {
+        let accepted = [
+            100,
+            into_proto(Some(Compression::Zstd)),
+            into_proto(Some(Compression::Gzip)),
+        ];
+        assert_eq!(
+            negotiate(&accepted, &[Compression::Gzip, Compression::Zstd]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            negotiate(&accepted, &[Compression::Gzip]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(negotiate(&[], &[Compression::Gzip]), None);
+        assert_eq!(negotiate(&accepted, &[]), None);
+    }
+}
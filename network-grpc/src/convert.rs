@@ -1,9 +1,11 @@
-use crate::gen;
+use crate::{compression, gen};
 
+use bytes::Bytes;
 use chain_core::{
     mempack::{self, ReadBuf},
     property,
 };
+use network_core::compact::{CompactBlock, ShortFragmentId, SHORT_ID_SIZE};
 use network_core::error as core_error;
 use network_core::gossip::{Gossip, Node, NodeId, Peer, PeersResponse};
 use network_core::subscription::{BlockEvent, ChainPullRequest};
@@ -16,6 +18,11 @@ use tower_grpc::{
 // Name of the binary metadata key used to pass the node ID in subscription requests.
 const NODE_ID_HEADER: &'static str = "node-id-bin";
 
+// Names of the binary metadata keys used to pass the public key and
+// the authentication signature of the client node in subscription requests.
+const NODE_KEY_HEADER: &str = "node-key-bin";
+const NODE_SIGNATURE_HEADER: &str = "node-sig-bin";
+
 pub fn error_into_grpc(err: core_error::Error) -> Status {
     use core_error::Code::*;
 
@@ -29,6 +36,8 @@ pub fn error_into_grpc(err: core_error::Error) -> Status {
         Unimplemented => Code::Unimplemented,
         Internal => Code::Internal,
         Unavailable => Code::Unavailable,
+        PermissionDenied => Code::PermissionDenied,
+        ResourceExhausted => Code::ResourceExhausted,
         // When a new case has to be added here, remember to
         // add the corresponding case in error_from_grpc below.
     };
@@ -49,6 +58,8 @@ pub fn error_from_grpc(e: Status) -> core_error::Error {
         Unimplemented => core_error::Code::Unimplemented,
         Internal => core_error::Code::Internal,
         Unavailable => core_error::Code::Unavailable,
+        PermissionDenied => core_error::Code::PermissionDenied,
+        ResourceExhausted => core_error::Code::ResourceExhausted,
         _ => core_error::Code::Unknown,
     };
 
@@ -108,7 +119,8 @@ where
     T: property::Block + mempack::Readable,
 {
     fn from_message(msg: gen::node::Block) -> Result<T, core_error::Error> {
-        let block = deserialize_bytes(&msg.content)?;
+        let content = compression::decompress_block(msg)?;
+        let block = deserialize_bytes(&content)?;
         Ok(block)
     }
 }
@@ -134,6 +146,30 @@ where
     }
 }
 
+impl<H> FromProtobuf<gen::node::CompactBlock> for CompactBlock<H>
+where
+    H: property::Header + mempack::Readable,
+{
+    fn from_message(msg: gen::node::CompactBlock) -> Result<Self, core_error::Error> {
+        let header = parse_bytes(&msg.header)?;
+        let chunks = msg.short_ids.chunks_exact(SHORT_ID_SIZE);
+        if !chunks.remainder().is_empty() {
+            return Err(core_error::Error::new(
+                core_error::Code::InvalidArgument,
+                "invalid length of compact block short identifiers",
+            ));
+        }
+        let short_ids = chunks
+            .map(|chunk| {
+                let mut bytes = [0; SHORT_ID_SIZE];
+                bytes.copy_from_slice(chunk);
+                ShortFragmentId::from_bytes(bytes)
+            })
+            .collect();
+        Ok(CompactBlock::new(header, msg.salt, short_ids))
+    }
+}
+
 impl<T> FromProtobuf<gen::node::BlockEvent> for BlockEvent<T>
 where
     T: property::Block + property::HasHeader,
@@ -148,6 +184,10 @@ where
                 let header = parse_bytes(&header.content)?;
                 BlockEvent::Announce(header)
             }
+            Some(Item::AnnounceCompact(compact)) => {
+                let compact = CompactBlock::from_message(compact)?;
+                BlockEvent::AnnounceCompact(compact)
+            }
             Some(Item::Solicit(ids)) => {
                 let ids = parse_repeated_bytes(&ids.ids)?;
                 BlockEvent::Solicit(ids)
@@ -264,7 +304,10 @@ where
 {
     fn into_message(self) -> Result<gen::node::Block, tower_grpc::Status> {
         let content = serialize_to_bytes(&self)?;
-        Ok(gen::node::Block { content })
+        Ok(gen::node::Block {
+            content,
+            compression: compression::into_proto(None),
+        })
     }
 }
 
@@ -301,6 +344,25 @@ where
     }
 }
 
+impl<H> IntoProtobuf<gen::node::CompactBlock> for CompactBlock<H>
+where
+    H: property::Header,
+{
+    fn into_message(self) -> Result<gen::node::CompactBlock, tower_grpc::Status> {
+        let mut short_ids = Vec::with_capacity(self.short_ids().len() * SHORT_ID_SIZE);
+        for short_id in self.short_ids() {
+            short_ids.extend_from_slice(short_id.as_bytes());
+        }
+        let salt = self.salt();
+        let header = serialize_to_bytes(&self.into_header())?;
+        Ok(gen::node::CompactBlock {
+            header,
+            salt,
+            short_ids,
+        })
+    }
+}
+
 impl<T> IntoProtobuf<gen::node::BlockEvent> for BlockEvent<T>
 where
     T: property::Block + property::HasHeader,
@@ -314,6 +376,10 @@ where
                 let content = serialize_to_bytes(&header)?;
                 Item::Announce(gen::node::Header { content })
             }
+            BlockEvent::AnnounceCompact(compact) => {
+                let compact = compact.into_message()?;
+                Item::AnnounceCompact(compact)
+            }
             BlockEvent::Solicit(ids) => {
                 let ids = serialize_to_repeated_bytes(&ids)?;
                 Item::Solicit(gen::node::BlockIds { ids })
@@ -384,6 +450,60 @@ where
     Ok(())
 }
 
+/// Authentication data of the client node passed in subscription requests.
+pub struct NodeAuth {
+    pub node_id: Bytes,
+    pub public_key: Bytes,
+    pub signature: Bytes,
+}
+
+fn get_bin_metadata(
+    metadata: &MetadataMap,
+    key: &'static str,
+) -> Result<Option<Bytes>, core_error::Error> {
+    match metadata.get_bin(key) {
+        None => Ok(None),
+        Some(val) => val.to_bytes().map(Some).map_err(|e| {
+            core_error::Error::new(
+                core_error::Code::InvalidArgument,
+                format!("invalid metadata value {}: {}", key, e),
+            )
+        }),
+    }
+}
+
+/// Decodes the client node authentication data from request metadata.
+/// Returns `None` if the client has not passed its public key.
+pub fn decode_node_auth(metadata: &MetadataMap) -> Result<Option<NodeAuth>, core_error::Error> {
+    let public_key = match get_bin_metadata(metadata, NODE_KEY_HEADER)? {
+        None => return Ok(None),
+        Some(val) => val,
+    };
+    let missing = |key| {
+        core_error::Error::new(
+            core_error::Code::InvalidArgument,
+            format!("missing metadata {}", key),
+        )
+    };
+    let node_id =
+        get_bin_metadata(metadata, NODE_ID_HEADER)?.ok_or_else(|| missing(NODE_ID_HEADER))?;
+    let signature = get_bin_metadata(metadata, NODE_SIGNATURE_HEADER)?
+        .ok_or_else(|| missing(NODE_SIGNATURE_HEADER))?;
+    Ok(Some(NodeAuth {
+        node_id,
+        public_key,
+        signature,
+    }))
+}
+
+pub fn encode_node_auth(public_key: &[u8], signature: &[u8], metadata: &mut MetadataMap) {
+    metadata.insert_bin(NODE_KEY_HEADER, BinaryMetadataValue::from_bytes(public_key));
+    metadata.insert_bin(
+        NODE_SIGNATURE_HEADER,
+        BinaryMetadataValue::from_bytes(signature),
+    );
+}
+
 impl IntoProtobuf<gen::node::PeersResponse> for PeersResponse {
     fn into_message(self) -> Result<gen::node::PeersResponse, tower_grpc::Status> {
         let peers = self.peers.iter().map(serialize_into_peer).collect();
@@ -455,3 +575,37 @@ fn serialize_into_peer(p: &Peer) -> gen::node::Peer {
     };
     gen::node::Peer { peer: Some(peer) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network_core::testing::{Block, MockNode};
+
+    fn compact_block() -> CompactBlock<Block> {
+        let header = MockNode::new(2).chain[1].clone();
+        let short_ids = vec![
+            ShortFragmentId::from_bytes([1, 2, 3, 4, 5, 6]),
+            ShortFragmentId::from_bytes([6; SHORT_ID_SIZE]),
+        ];
+        CompactBlock::new(header, 42, short_ids)
+    }
+
+    #[test]
+    fn compact_block_round_trip() {
+        let compact = compact_block();
+        let msg = compact.clone().into_message().unwrap();
+        assert_eq!(msg.short_ids.len(), 2 * SHORT_ID_SIZE);
+        let decoded = CompactBlock::<Block>::from_message(msg).unwrap();
+        assert_eq!(decoded.header(), compact.header());
+        assert_eq!(decoded.salt(), 42);
+        assert_eq!(decoded.short_ids(), compact.short_ids());
+    }
+
+    #[test]
+    fn compact_block_with_truncated_short_ids_is_rejected() {
+        let mut msg = compact_block().into_message().unwrap();
+        msg.short_ids.pop();
+        let err = CompactBlock::<Block>::from_message(msg).err().unwrap();
+        assert_eq!(err.code(), core_error::Code::InvalidArgument);
+    }
+}
@@ -12,10 +12,19 @@ mod gen {
     }
 }
 
+pub mod auth;
 pub mod client;
+mod compression;
 mod convert;
+pub mod metrics;
 pub mod server;
 mod service;
+pub mod tls;
+
+#[cfg(test)]
+mod tests;
+
+pub use compression::Compression;
 
 /// Version of the protocol implemented by this crate.
 ///
@@ -0,0 +1,327 @@
+//! Metrics of the protocol calls.
+//!
+//! The client and the server report the calls made on their connections
+//! to an implementation of the `Metrics` trait. Byte counts are
+//! those of the gRPC message frames, after compression, if any.
+
+mod meter;
+
+pub(crate) use meter::{Meter, MeteredBody, ResponseFuture, Side};
+
+use tower_grpc::Code;
+
+use std::{collections::HashMap, fmt, net::SocketAddr, sync::Mutex, time::Duration};
+
+/// Remote procedures of the node protocol.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Rpc {
+    Handshake,
+    Tip,
+    Peers,
+    GetBlocks,
+    GetHeaders,
+    GetFragments,
+    PullHeaders,
+    PullBlocksToTip,
+    PushHeaders,
+    UploadBlocks,
+    BlockSubscription,
+    FragmentSubscription,
+    GossipSubscription,
+}
+
+const SERVICE_PATH_PREFIX: &str = "/iohk.chain.node.Node/";
+
+impl Rpc {
+    /// Returns the method name of the procedure as used in the gRPC
+    /// request path.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rpc::Handshake => "Handshake",
+            Rpc::Tip => "Tip",
+            Rpc::Peers => "Peers",
+            Rpc::GetBlocks => "GetBlocks",
+            Rpc::GetHeaders => "GetHeaders",
+            Rpc::GetFragments => "GetFragments",
+            Rpc::PullHeaders => "PullHeaders",
+            Rpc::PullBlocksToTip => "PullBlocksToTip",
+            Rpc::PushHeaders => "PushHeaders",
+            Rpc::UploadBlocks => "UploadBlocks",
+            Rpc::BlockSubscription => "BlockSubscription",
+            Rpc::FragmentSubscription => "FragmentSubscription",
+            Rpc::GossipSubscription => "GossipSubscription",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        let rpc = match name {
+            "Handshake" => Rpc::Handshake,
+            "Tip" => Rpc::Tip,
+            "Peers" => Rpc::Peers,
+            "GetBlocks" => Rpc::GetBlocks,
+            "GetHeaders" => Rpc::GetHeaders,
+            "GetFragments" => Rpc::GetFragments,
+            "PullHeaders" => Rpc::PullHeaders,
+            "PullBlocksToTip" => Rpc::PullBlocksToTip,
+            "PushHeaders" => Rpc::PushHeaders,
+            "UploadBlocks" => Rpc::UploadBlocks,
+            "BlockSubscription" => Rpc::BlockSubscription,
+            "FragmentSubscription" => Rpc::FragmentSubscription,
+            "GossipSubscription" => Rpc::GossipSubscription,
+            _ => return None,
+        };
+        Some(rpc)
+    }
+
+    /// Returns the procedure called with the gRPC request path,
+    /// if the path belongs to the node protocol.
+    pub fn from_path(path: &str) -> Option<Self> {
+        path.strip_prefix(SERVICE_PATH_PREFIX)
+            .and_then(Rpc::from_name)
+    }
+}
+
+impl fmt::Display for Rpc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Receiver of the metrics reported by a protocol client or server.
+///
+/// The calls are reported with the procedure and the address of
+/// the remote peer, if known. The methods have no-op default
+/// implementations, so an implementation only needs to override
+/// the ones it is interested in.
+pub trait Metrics: Send + Sync {
+    /// A call has been started.
+    fn call_started(&self, _rpc: Rpc, _peer: Option<SocketAddr>) {}
+
+    /// The response headers of a call have been sent or received,
+    /// after the given latency since the call has been started.
+    fn response_started(&self, _rpc: Rpc, _peer: Option<SocketAddr>, _latency: Duration) {}
+
+    /// Bytes have been sent to the peer.
+    fn bytes_sent(&self, _rpc: Rpc, _peer: Option<SocketAddr>, _bytes: usize) {}
+
+    /// Bytes have been received from the peer.
+    fn bytes_received(&self, _rpc: Rpc, _peer: Option<SocketAddr>, _bytes: usize) {}
+
+    /// A call has finished with the given status code.
+    ///
+    /// For calls with streamed responses, including subscriptions,
+    /// the duration is the lifetime of the stream. A call that has been
+    /// dropped before completion is reported with the `Cancelled` code.
+    fn call_finished(
+        &self,
+        _rpc: Rpc,
+        _peer: Option<SocketAddr>,
+        _code: Code,
+        _duration: Duration,
+    ) {
+    }
+}
+
+/// Metrics implementation discarding all reports.
+#[derive(Clone, Debug, Default)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+/// Statistics of protocol calls.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CallStats {
+    /// The number of started calls.
+    pub started: u64,
+    /// The number of finished calls.
+    pub finished: u64,
+    /// The number of bytes sent.
+    pub bytes_sent: u64,
+    /// The number of bytes received.
+    pub bytes_received: u64,
+    /// The sum of the response latencies.
+    pub total_latency: Duration,
+    /// The sum of the durations of finished calls.
+    pub total_duration: Duration,
+    // Counts of finished calls by the status code.
+    codes: Vec<(Code, u64)>,
+}
+
+impl CallStats {
+    /// Returns the number of calls finished with the given status code.
+    pub fn count(&self, code: Code) -> u64 {
+        self.codes
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map_or(0, |(_, n)| *n)
+    }
+
+    /// Returns the number of calls finished with a code other than `Ok`.
+    pub fn errors(&self) -> u64 {
+        self.codes
+            .iter()
+            .filter(|(c, _)| *c != Code::Ok)
+            .map(|(_, n)| n)
+            .sum()
+    }
+
+    fn add_code(&mut self, code: Code, n: u64) {
+        match self.codes.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, count)) => *count += n,
+            None => self.codes.push((code, n)),
+        }
+    }
+
+    fn merge(&mut self, other: &CallStats) {
+        self.started += other.started;
+        self.finished += other.finished;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.total_latency += other.total_latency;
+        self.total_duration += other.total_duration;
+        for (code, n) in &other.codes {
+            self.add_code(*code, *n);
+        }
+    }
+}
+
+/// Metrics implementation accumulating the call statistics in memory,
+/// per remote peer and procedure.
+#[derive(Debug, Default)]
+pub struct MemoryMetrics {
+    stats: Mutex<HashMap<(Option<SocketAddr>, Rpc), CallStats>>,
+}
+
+impl MemoryMetrics {
+    pub fn new() -> Self {
+        MemoryMetrics::default()
+    }
+
+    fn update<F>(&self, rpc: Rpc, peer: Option<SocketAddr>, f: F)
+    where
+        F: FnOnce(&mut CallStats),
+    {
+        let mut stats = self.stats.lock().unwrap();
+        f(stats.entry((peer, rpc)).or_default())
+    }
+
+    fn sum<F>(&self, filter: F) -> CallStats
+    where
+        F: Fn(Option<SocketAddr>, Rpc) -> bool,
+    {
+        let stats = self.stats.lock().unwrap();
+        let mut sum = CallStats::default();
+        for (_, s) in stats.iter().filter(|((peer, rpc), _)| filter(*peer, *rpc)) {
+            sum.merge(s);
+        }
+        sum
+    }
+
+    /// Returns the statistics of all calls.
+    pub fn total(&self) -> CallStats {
+        self.sum(|_, _| true)
+    }
+
+    /// Returns the statistics of calls to the procedure.
+    pub fn rpc(&self, rpc: Rpc) -> CallStats {
+        self.sum(|_, r| r == rpc)
+    }
+
+    /// Returns the statistics of calls with the peer.
+    pub fn peer(&self, peer: SocketAddr) -> CallStats {
+        self.sum(|p, _| p == Some(peer))
+    }
+
+    /// Returns the statistics of calls to the procedure with the peer.
+    pub fn get(&self, rpc: Rpc, peer: Option<SocketAddr>) -> CallStats {
+        self.sum(|p, r| p == peer && r == rpc)
+    }
+
+    /// Clears the accumulated statistics.
+    pub fn reset(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+}
+
+impl Metrics for MemoryMetrics {
+    fn call_started(&self, rpc: Rpc, peer: Option<SocketAddr>) {
+        self.update(rpc, peer, |s| s.started += 1);
+    }
+
+    fn response_started(&self, rpc: Rpc, peer: Option<SocketAddr>, latency: Duration) {
+        self.update(rpc, peer, |s| s.total_latency += latency);
+    }
+
+    fn bytes_sent(&self, rpc: Rpc, peer: Option<SocketAddr>, bytes: usize) {
+        self.update(rpc, peer, |s| s.bytes_sent += bytes as u64);
+    }
+
+    fn bytes_received(&self, rpc: Rpc, peer: Option<SocketAddr>, bytes: usize) {
+        self.update(rpc, peer, |s| s.bytes_received += bytes as u64);
+    }
+
+    fn call_finished(&self, rpc: Rpc, peer: Option<SocketAddr>, code: Code, duration: Duration) {
+        self.update(rpc, peer, |s| {
+            s.finished += 1;
+            s.total_duration += duration;
+            s.add_code(code, 1);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_from_path() {
+        assert_eq!(
+            Rpc::from_path("/iohk.chain.node.Node/PullBlocksToTip"),
+            Some(Rpc::PullBlocksToTip)
+        );
+        assert_eq!(Rpc::from_path("/iohk.chain.node.Node/Unknown"), None);
+        assert_eq!(Rpc::from_path("/other.Service/Tip"), None);
+        let rpc = Rpc::GossipSubscription;
+        assert_eq!(
+            Rpc::from_path(&format!("{}{}", SERVICE_PATH_PREFIX, rpc)),
+            Some(rpc)
+        );
+    }
+
+    #[test]
+    fn memory_metrics_aggregate() {
+        let metrics = MemoryMetrics::new();
+        let addr1 = "1.1.1.1:3000".parse().unwrap();
+        let peer1 = Some(addr1);
+        let peer2 = Some("2.2.2.2:3000".parse().unwrap());
+        metrics.call_started(Rpc::GetBlocks, peer1);
+        metrics.bytes_received(Rpc::GetBlocks, peer1, 100);
+        metrics.call_finished(Rpc::GetBlocks, peer1, Code::Ok, Duration::from_secs(1));
+        metrics.call_started(Rpc::GetBlocks, peer2);
+        metrics.call_finished(
+            Rpc::GetBlocks,
+            peer2,
+            Code::NotFound,
+            Duration::from_secs(2),
+        );
+        metrics.call_started(Rpc::Tip, peer1);
+        metrics.bytes_received(Rpc::Tip, peer1, 10);
+
+        let blocks = metrics.rpc(Rpc::GetBlocks);
+        assert_eq!(blocks.started, 2);
+        assert_eq!(blocks.finished, 2);
+        assert_eq!(blocks.bytes_received, 100);
+        assert_eq!(blocks.total_duration, Duration::from_secs(3));
+        assert_eq!(blocks.count(Code::Ok), 1);
+        assert_eq!(blocks.count(Code::NotFound), 1);
+        assert_eq!(blocks.errors(), 1);
+
+        let peer = metrics.peer(addr1);
+        assert_eq!(peer.started, 2);
+        assert_eq!(peer.bytes_received, 110);
+        assert_eq!(metrics.get(Rpc::Tip, peer2), CallStats::default());
+
+        metrics.reset();
+        assert_eq!(metrics.total(), CallStats::default());
+    }
+}
@@ -0,0 +1,483 @@
+use super::{Metrics, Rpc};
+
+use bytes::Buf;
+use futures::prelude::*;
+use http_body::Body as HttpBody;
+use tower_grpc::{BoxBody, Code};
+use tower_service::Service;
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+const GRPC_STATUS_HEADER: &str = "grpc-status";
+
+/// The side of the connection a `Meter` is installed on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+// State of a call shared by the futures and the bodies
+// of the request and the response.
+struct Call {
+    metrics: Arc<dyn Metrics>,
+    side: Side,
+    rpc: Rpc,
+    peer: Option<SocketAddr>,
+    started: Instant,
+    finished: AtomicBool,
+}
+
+impl Call {
+    fn report_request_data(&self, bytes: usize) {
+        match self.side {
+            Side::Client => self.metrics.bytes_sent(self.rpc, self.peer, bytes),
+            Side::Server => self.metrics.bytes_received(self.rpc, self.peer, bytes),
+        }
+    }
+
+    fn report_response_data(&self, bytes: usize) {
+        match self.side {
+            Side::Client => self.metrics.bytes_received(self.rpc, self.peer, bytes),
+            Side::Server => self.metrics.bytes_sent(self.rpc, self.peer, bytes),
+        }
+    }
+
+    fn finish(&self, code: Code) {
+        if !self.finished.swap(true, Ordering::AcqRel) {
+            let duration = self.started.elapsed();
+            self.metrics
+                .call_finished(self.rpc, self.peer, code, duration);
+        }
+    }
+}
+
+fn grpc_status(headers: &http::HeaderMap) -> Option<Code> {
+    let value = headers.get(GRPC_STATUS_HEADER)?;
+    let code = value.to_str().ok()?.parse().ok()?;
+    Some(Code::from_i32(code))
+}
+
+/// Service middleware reporting the calls made through it to `Metrics`.
+#[derive(Clone)]
+pub struct Meter<S> {
+    inner: S,
+    metrics: Arc<dyn Metrics>,
+    side: Side,
+    peer: Option<SocketAddr>,
+}
+
+impl<S> Meter<S> {
+    pub fn new(inner: S, metrics: Arc<dyn Metrics>, side: Side, peer: Option<SocketAddr>) -> Self {
+        Meter {
+            inner,
+            metrics,
+            side,
+            peer,
+        }
+    }
+
+    fn start_call<B>(&self, req: &http::Request<B>) -> Option<Arc<Call>> {
+        let rpc = Rpc::from_path(req.uri().path())?;
+        self.metrics.call_started(rpc, self.peer);
+        Some(Arc::new(Call {
+            metrics: self.metrics.clone(),
+            side: self.side,
+            rpc,
+            peer: self.peer,
+            started: Instant::now(),
+            finished: AtomicBool::new(false),
+        }))
+    }
+}
+
+// Client side: requests are made with gRPC-boxed bodies.
+impl<S, B> Service<http::Request<BoxBody>> for Meter<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+{
+    type Response = http::Response<MeteredBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), S::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+        let call = self.start_call(&req);
+        let req = match &call {
+            Some(call) => {
+                let call = call.clone();
+                req.map(move |body| BoxBody::new(Box::new(RequestBody { inner: body, call })))
+            }
+            None => req,
+        };
+        ResponseFuture {
+            inner: self.inner.call(req),
+            call,
+        }
+    }
+}
+
+// Server side: requests come with hyper bodies, and the generated
+// service accepts gRPC-boxed bodies.
+impl<S, B> Service<http::Request<hyper::Body>> for Meter<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+{
+    type Response = http::Response<MeteredBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), S::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: http::Request<hyper::Body>) -> Self::Future {
+        let call = self.start_call(&req);
+        let req = match &call {
+            Some(call) => {
+                let call = call.clone();
+                req.map(move |body| BoxBody::map_from(RequestBody { inner: body, call }))
+            }
+            None => req.map(BoxBody::map_from),
+        };
+        ResponseFuture {
+            inner: self.inner.call(req),
+            call,
+        }
+    }
+}
+
+// Makes per-connection services for the server.
+impl<S: Clone> Service<()> for Meter<S> {
+    type Response = Self;
+    type Error = tower_grpc::codegen::server::grpc::Never;
+    type Future = futures::future::FutureResult<Self, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, _target: ()) -> Self::Future {
+        futures::future::ok(self.clone())
+    }
+}
+
+/// Future of a response with the body metered.
+#[must_use = "futures do nothing unless polled"]
+pub struct ResponseFuture<F> {
+    inner: F,
+    call: Option<Arc<Call>>,
+}
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<MeteredBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, F::Error> {
+        let res = match self.inner.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(res)) => res,
+            Err(e) => {
+                if let Some(call) = self.call.take() {
+                    call.finish(Code::Unavailable);
+                }
+                return Err(e);
+            }
+        };
+        let call = self.call.take();
+        let mut header_code = None;
+        if let Some(call) = &call {
+            call.metrics
+                .response_started(call.rpc, call.peer, call.started.elapsed());
+            // A response without messages may carry the status
+            // in the headers.
+            header_code = grpc_status(res.headers());
+        }
+        Ok(Async::Ready(res.map(|inner| MeteredBody {
+            inner,
+            call,
+            header_code,
+        })))
+    }
+}
+
+impl<F> Drop for ResponseFuture<F> {
+    fn drop(&mut self) {
+        if let Some(call) = &self.call {
+            call.finish(Code::Cancelled);
+        }
+    }
+}
+
+// Request body reporting the data passed through it.
+struct RequestBody<B> {
+    inner: B,
+    call: Arc<Call>,
+}
+
+impl<B: HttpBody> HttpBody for RequestBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<B::Data>, B::Error> {
+        let data = try_ready!(self.inner.poll_data());
+        if let Some(buf) = &data {
+            self.call.report_request_data(buf.remaining());
+        }
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, B::Error> {
+        self.inner.poll_trailers()
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// Response body reporting the data passed through it and the
+/// completion of the call.
+pub struct MeteredBody<B> {
+    inner: B,
+    call: Option<Arc<Call>>,
+    header_code: Option<Code>,
+}
+
+impl<B> MeteredBody<B> {
+    fn finish(&mut self, code: Code) {
+        if let Some(call) = self.call.take() {
+            call.finish(code);
+        }
+    }
+}
+
+impl<B: HttpBody> HttpBody for MeteredBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<B::Data>, B::Error> {
+        match self.inner.poll_data() {
+            Ok(Async::Ready(Some(buf))) => {
+                if let Some(call) = &self.call {
+                    call.report_response_data(buf.remaining());
+                }
+                Ok(Async::Ready(Some(buf)))
+            }
+            Err(e) => {
+                self.finish(Code::Unknown);
+                Err(e)
+            }
+            res => res,
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, B::Error> {
+        match self.inner.poll_trailers() {
+            Ok(Async::Ready(trailers)) => {
+                let code = trailers
+                    .as_ref()
+                    .and_then(grpc_status)
+                    .or(self.header_code)
+                    .unwrap_or(Code::Unknown);
+                self.finish(code);
+                Ok(Async::Ready(trailers))
+            }
+            Err(e) => {
+                self.finish(Code::Unknown);
+                Err(e)
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+impl<B> Drop for MeteredBody<B> {
+    fn drop(&mut self) {
+        // The body of a response carrying the status in the headers
+        // may be dropped without polling.
+        let code = self.header_code.unwrap_or(Code::Cancelled);
+        self.finish(code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MemoryMetrics;
+
+    use bytes::{Bytes, IntoBuf};
+    use futures::future::{self, FutureResult};
+    use http::HeaderMap;
+    use std::{collections::VecDeque, io::Cursor};
+
+    struct TestBody {
+        chunks: VecDeque<Bytes>,
+        trailers: Option<HeaderMap>,
+    }
+
+    impl HttpBody for TestBody {
+        type Data = Cursor<Bytes>;
+        type Error = ();
+
+        fn poll_data(&mut self) -> Poll<Option<Cursor<Bytes>>, ()> {
+            Ok(Async::Ready(self.chunks.pop_front().map(IntoBuf::into_buf)))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, ()> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    fn status_headers(code: Code) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(GRPC_STATUS_HEADER, (code as i32).into());
+        headers
+    }
+
+    // Consumes the request body and responds with the given messages,
+    // and the status in the trailers, or in the headers if there are
+    // no messages.
+    struct TestService {
+        messages: Vec<&'static [u8]>,
+        code: Code,
+    }
+
+    impl Service<http::Request<BoxBody>> for TestService {
+        type Response = http::Response<TestBody>;
+        type Error = ();
+        type Future = FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+            let mut body = req.into_body();
+            while let Async::Ready(Some(_)) = body.poll_data().unwrap() {}
+            let chunks = self.messages.iter().map(|m| Bytes::from(*m)).collect();
+            let res = if self.messages.is_empty() {
+                let mut res = http::Response::new(TestBody {
+                    chunks,
+                    trailers: None,
+                });
+                *res.headers_mut() = status_headers(self.code);
+                res
+            } else {
+                http::Response::new(TestBody {
+                    chunks,
+                    trailers: Some(status_headers(self.code)),
+                })
+            };
+            future::ok(res)
+        }
+    }
+
+    fn request(rpc: Rpc, body: &'static str) -> http::Request<hyper::Body> {
+        http::Request::builder()
+            .uri(format!("/iohk.chain.node.Node/{}", rpc))
+            .body(hyper::Body::from(body))
+            .unwrap()
+    }
+
+    fn meter(service: TestService, metrics: &Arc<MemoryMetrics>) -> Meter<TestService> {
+        let peer = "1.1.1.1:3000".parse().unwrap();
+        Meter::new(service, metrics.clone(), Side::Server, Some(peer))
+    }
+
+    #[test]
+    fn server_call_is_metered() {
+        future::lazy(|| {
+            let metrics = Arc::new(MemoryMetrics::new());
+            let service = TestService {
+                messages: vec![b"block1", b"block22"],
+                code: Code::NotFound,
+            };
+            let mut meter = meter(service, &metrics);
+            let res = meter.call(request(Rpc::GetBlocks, "ids")).wait().unwrap();
+            let mut body = res.into_body();
+            while let Async::Ready(Some(_)) = body.poll_data().unwrap() {}
+            assert_eq!(metrics.total().finished, 0);
+            body.poll_trailers().unwrap();
+
+            let stats = metrics.rpc(Rpc::GetBlocks);
+            assert_eq!(stats.started, 1);
+            assert_eq!(stats.finished, 1);
+            assert_eq!(stats.bytes_received, 3);
+            assert_eq!(stats.bytes_sent, 13);
+            assert_eq!(stats.count(Code::NotFound), 1);
+            assert_eq!(metrics.peer("1.1.1.1:3000".parse().unwrap()), stats);
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn status_in_headers_is_reported() {
+        future::lazy(|| {
+            let metrics = Arc::new(MemoryMetrics::new());
+            let service = TestService {
+                messages: vec![],
+                code: Code::Unimplemented,
+            };
+            let mut meter = meter(service, &metrics);
+            let res = meter.call(request(Rpc::Tip, "")).wait().unwrap();
+            drop(res);
+            let stats = metrics.rpc(Rpc::Tip);
+            assert_eq!(stats.finished, 1);
+            assert_eq!(stats.count(Code::Unimplemented), 1);
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn dropped_stream_is_cancelled() {
+        future::lazy(|| {
+            let metrics = Arc::new(MemoryMetrics::new());
+            let service = TestService {
+                messages: vec![b"header"],
+                code: Code::Ok,
+            };
+            let mut meter = meter(service, &metrics);
+            let res = meter
+                .call(request(Rpc::BlockSubscription, ""))
+                .wait()
+                .unwrap();
+            let mut body = res.into_body();
+            body.poll_data().unwrap();
+            drop(body);
+            let stats = metrics.rpc(Rpc::BlockSubscription);
+            assert_eq!(stats.bytes_sent, 6);
+            assert_eq!(stats.count(Code::Cancelled), 1);
+
+            let mut meter = meter;
+            let mut req = request(Rpc::Tip, "");
+            *req.uri_mut() = "/other.Service/Tip".parse().unwrap();
+            meter.call(req).wait().unwrap();
+            assert_eq!(metrics.total().started, 1);
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
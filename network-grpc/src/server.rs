@@ -1,6 +1,13 @@
 use crate::{
+    auth::NodeKey,
+    compression::Compression,
     gen::node::server as gen_server,
-    service::{protocol_bounds, NodeService},
+    metrics::{Meter, Metrics, NoMetrics, Side},
+    service::{
+        limits::{MessageSizeLimit, PeerLimits, ServerLimits},
+        protocol_bounds, NodeService,
+    },
+    tls::{self, ServerConfig, ServerSession, TlsStream},
 };
 
 use network_core::server::{BlockService, FragmentService, GossipService, Node};
@@ -16,6 +23,7 @@ use tokio_uds::{UnixListener, UnixStream};
 
 use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 #[cfg(unix)]
 use std::os::unix::net::SocketAddr as UnixSocketAddr;
@@ -36,13 +44,58 @@ where
     <T::FragmentService as FragmentService>::Fragment: protocol_bounds::Fragment,
     <T::GossipService as GossipService>::Node: protocol_bounds::Node,
 {
-    inner: tower_hyper::Server<
-        gen_server::NodeServer<NodeService<T>>,
-        gen_server::node::ResponseBody<NodeService<T>>,
-    >,
+    node: T,
+    node_key: Option<Arc<NodeKey>>,
+    limits: Arc<ServerLimits>,
+    compact_blocks: bool,
+    compression: Arc<[Compression]>,
+    metrics: Arc<dyn Metrics>,
     http: Http,
 }
 
+/// Limits on resources that client peers can use on the server.
+///
+/// Per-peer limits apply to all connections made from the IP address
+/// of a client peer only if the connections are served with
+/// `serve_with_peer_addr`. Connections served with `serve` are
+/// limited separately, each as a peer of its own, so a client can
+/// multiply its per-peer allowance by opening more connections;
+/// only the global limits bound the total usage of such connections.
+/// Requests rejected due to a per-peer limit fail with the
+/// `ResourceExhausted` status, while those exceeding a global limit
+/// fail with `Unavailable`.
+/// The default value sets no limits.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// Maximum number of concurrent HTTP/2 streams on a connection,
+    /// including both requests and subscriptions.
+    pub max_concurrent_streams: Option<u32>,
+    /// Maximum number of concurrent subscriptions made by a peer.
+    pub max_subscriptions_per_peer: Option<usize>,
+    /// Maximum number of concurrent subscriptions made by all peers.
+    pub max_subscriptions: Option<usize>,
+    /// Rate limit of fetch requests made by a peer. The fetch requests are
+    /// `GetBlocks`, `GetHeaders`, `GetFragments`, `PullHeaders`,
+    /// and `PullBlocksToTip`.
+    pub peer_request_rate: Option<RateLimit>,
+    /// Rate limit of fetch requests made by all peers.
+    pub request_rate: Option<RateLimit>,
+    /// Maximum number of block or fragment identifiers in a fetch request.
+    pub max_ids_per_request: Option<usize>,
+    /// Maximum size in bytes of an inbound message in any request.
+    /// The limit is checked before the message is received in full.
+    pub max_message_size: Option<usize>,
+}
+
+/// Parameters of a request rate limit.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    /// The number of requests allowed per second on average.
+    pub per_second: f64,
+    /// The number of requests that can be made in a burst.
+    pub burst: u32,
+}
+
 /// The error type for gRPC server operations.
 pub type Error = tower_hyper::server::Error<NeverError>;
 
@@ -71,21 +124,119 @@ where
 {
     /// Creates a server instance around the node service implementation.
     pub fn new(node: T) -> Self {
-        let grpc_service = gen_server::NodeServer::new(NodeService::new(node));
-        let inner = tower_hyper::Server::new(grpc_service);
         let mut http = Http::new();
         http.http2_only(true);
-        Server { inner, http }
+        Server {
+            node,
+            node_key: None,
+            limits: Arc::new(ServerLimits::new(Limits::default())),
+            compact_blocks: false,
+            compression: Arc::new([]),
+            metrics: Arc::new(NoMetrics),
+            http,
+        }
+    }
+
+    /// Sets the key this node uses to authenticate itself in the handshake.
+    ///
+    /// When the node key is set, clients need to authenticate with
+    /// their own node keys before making subscription requests, and
+    /// the node identifiers of subscribers are the ones they have
+    /// authenticated with.
+    pub fn node_key(&mut self, key: NodeKey) -> &mut Self {
+        self.node_key = Some(Arc::new(key));
+        self
+    }
+
+    /// Sets the limits on resources that client peers can use.
+    ///
+    /// The limits apply to connections served after this call.
+    pub fn limits(&mut self, limits: Limits) -> &mut Self {
+        self.http
+            .http2_max_concurrent_streams(limits.max_concurrent_streams);
+        self.limits = Arc::new(ServerLimits::new(limits));
+        self
+    }
+
+    /// Enables compact block relay for clients requesting it
+    /// in the handshake.
+    ///
+    /// The block service of the node may then announce blocks with
+    /// `BlockEvent::AnnounceCompact`, and needs to serve the fragments
+    /// of announced blocks with `get_block_fragments`. Compact
+    /// announcements are converted to header announcements for
+    /// subscribers that have not negotiated compact block relay.
+    pub fn compact_blocks(&mut self) -> &mut Self {
+        self.compact_blocks = true;
+        self
+    }
+
+    /// Enables compression of block content with the given algorithms.
+    ///
+    /// Of the algorithms listed by a client in the handshake, the server
+    /// selects the first one that is enabled. Block content is not
+    /// compressed on connections with clients that do not support any
+    /// of the enabled algorithms.
+    pub fn compression(&mut self, algorithms: &[Compression]) -> &mut Self {
+        self.compression = algorithms.into();
+        self
+    }
+
+    /// Sets the receiver of the metrics of calls served by this server.
+    ///
+    /// The metrics apply to connections served after this call.
+    pub fn metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self
     }
 
     /// Initializes a client peer connection based on an accepted connection
     /// socket. The socket can be obtained from a stream returned by `listen`.
+    ///
+    /// The address of the client peer is not known to the server,
+    /// so the connection does not share the per-peer limits with other
+    /// connections from the same peer; see `Limits`. Servers enforcing
+    /// per-peer limits should use `serve_with_peer_addr` instead.
     pub fn serve<S>(&mut self, sock: S) -> Connection
     where
         S: AsyncRead + AsyncWrite + Send + 'static,
     {
+        self.serve_connection(sock, None)
+    }
+
+    /// Like `serve`, but also passes the address of the client peer
+    /// to be reported with the metrics of the connection. The per-peer
+    /// limits are shared by all connections served from the IP address.
+    pub fn serve_with_peer_addr<S>(&mut self, sock: S, peer_addr: SocketAddr) -> Connection
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        self.serve_connection(sock, Some(peer_addr))
+    }
+
+    fn serve_connection<S>(&mut self, sock: S, peer_addr: Option<SocketAddr>) -> Connection
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        // The service is instantiated per connection to keep track of
+        // the authentication and the resource usage of the client.
+        // The resource usage is shared by the connections from
+        // the same address.
+        let service = NodeService::new(
+            self.node.clone(),
+            self.node_key.clone(),
+            PeerLimits::new(self.limits.clone(), peer_addr.map(|addr| addr.ip())),
+            self.compact_blocks,
+            self.compression.clone(),
+        );
+        let service = MessageSizeLimit::new(
+            gen_server::NodeServer::new(service),
+            self.limits.limits().max_message_size,
+        );
+        let service = Meter::new(service, self.metrics.clone(), Side::Server, peer_addr);
+        let mut server = tower_hyper::Server::new(service);
         Connection {
-            inner: self.inner.serve_with(sock, self.http.clone()),
+            inner: server.serve_with(sock, self.http.clone()),
         }
     }
 }
@@ -100,6 +251,24 @@ pub fn listen(addr: &SocketAddr) -> Result<TcpListen, io::Error> {
     Ok(TcpListen { inner })
 }
 
+/// Sets up a listening TCP socket bound to the given address, accepting
+/// connections secured with TLS.
+/// If successful, returns an asynchronous stream of `TlsStream` objects
+/// wrapping accepted TCP connections from clients. The TLS handshake is
+/// performed when the connection is served.
+///
+/// The ALPN protocol list in the configuration is replaced with `h2`.
+/// Client certificate verification, if desired, is set up with
+/// the configuration; see `tls::server_config`.
+pub fn listen_tls(addr: &SocketAddr, mut config: ServerConfig) -> Result<TlsListen, io::Error> {
+    let inner = listen(addr)?;
+    tls::set_alpn_h2(&mut config.alpn_protocols);
+    Ok(TlsListen {
+        inner,
+        config: Arc::new(config),
+    })
+}
+
 /// Sets up a listening Unix socket bound to the specified path.
 /// If successful, returns an asynchronous stream of `UnixStream` socket
 /// objects representing accepted connections from clients.
@@ -164,6 +333,39 @@ impl Stream for TcpListen {
     }
 }
 
+impl TcpListen {
+    /// Returns the local address that the listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.inner.local_addr()
+    }
+}
+
+pub struct TlsListen {
+    inner: TcpListen,
+    config: Arc<ServerConfig>,
+}
+
+impl TlsListen {
+    /// Returns the local address that the listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.inner.local_addr()
+    }
+}
+
+impl Stream for TlsListen {
+    type Item = (TlsStream<TcpStream, ServerSession>, SocketAddr);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        let (sock, addr) = match try_ready!(self.inner.poll()) {
+            Some(accepted) => accepted,
+            None => return Ok(Async::Ready(None)),
+        };
+        let stream = TlsStream::accept(sock, &self.config);
+        Ok(Async::Ready(Some((stream, addr))))
+    }
+}
+
 #[cfg(unix)]
 pub struct UnixListen {
     inner: UnixListener,
@@ -1,36 +1,143 @@
+mod compact;
+pub mod limits;
 mod request_stream;
 mod response_future;
 mod response_stream;
 mod subscription;
 
+use compact::{CompactFilter, CompactFilterFuture};
+use limits::{Inbound, PeerLimits};
 use response_future::ResponseFuture;
 use response_stream::ResponseStream;
 use subscription::{Subscription, SubscriptionFuture};
 
 use crate::{
+    auth::{self, NodeKey, Role},
+    compression::{self, Compress, CompressResponse, Compression},
     convert::{
-        decode_node_id, deserialize_bytes, deserialize_repeated_bytes, error_into_grpc,
-        serialize_to_bytes,
+        decode_node_auth, decode_node_id, deserialize_bytes, deserialize_repeated_bytes,
+        error_into_grpc, serialize_to_bytes,
     },
     gen, PROTOCOL_VERSION,
 };
 
+use network_core::gossip::PeerIdentity;
 use network_core::server::{BlockService, FragmentService, GossipService, Node, P2pService};
 
 use futures::future::{self, FutureResult};
-use tower_grpc::{self, Code, Request, Response, Status, Streaming};
+use tower_grpc::{self, metadata::MetadataMap, Code, Request, Response, Status, Streaming};
 
-#[derive(Clone, Debug)]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+/// The service serving requests of a single client connection.
+#[derive(Clone)]
 pub struct NodeService<T> {
     inner: T,
+    node_key: Option<Arc<NodeKey>>,
+    auth: Arc<Mutex<ConnectionAuth>>,
+    // Whether compact block relay is enabled on the server.
+    enable_compact_blocks: bool,
+    // Set when the client has negotiated compact block relay.
+    compact_blocks: Arc<AtomicBool>,
+    // Compression algorithms enabled on the server.
+    enabled_compression: Arc<[Compression]>,
+    // Compression negotiated with the client for block content.
+    compression: Arc<Mutex<Option<Compression>>>,
+    limits: Arc<PeerLimits>,
+}
+
+/// Authentication state of a client connection.
+#[derive(Default)]
+struct ConnectionAuth {
+    // The challenge sent to the client in the handshake response.
+    nonce: Option<Vec<u8>>,
+    // Serialized identifier of the authenticated client node.
+    peer_node_id: Option<Vec<u8>>,
 }
 
 impl<T: Node> NodeService<T> {
-    pub fn new(node: T) -> Self {
-        NodeService { inner: node }
+    pub fn new(
+        node: T,
+        node_key: Option<Arc<NodeKey>>,
+        limits: PeerLimits,
+        enable_compact_blocks: bool,
+        enabled_compression: Arc<[Compression]>,
+    ) -> Self {
+        NodeService {
+            inner: node,
+            node_key,
+            auth: Default::default(),
+            enable_compact_blocks,
+            compact_blocks: Default::default(),
+            enabled_compression,
+            compression: Default::default(),
+            limits: Arc::new(limits),
+        }
     }
 }
 
+// Returns the node ID of the client making a subscription request.
+// If the server has a node key, the client needs to pass the proof of
+// possession of its node key in the request metadata; once the client
+// is authenticated, its node ID is used for all subscriptions on
+// the connection. Otherwise, the node ID claimed in each request
+// is checked with the service.
+fn authenticate_subscriber<S>(
+    node_key: &Option<Arc<NodeKey>>,
+    auth: &Mutex<ConnectionAuth>,
+    service: &mut S,
+    metadata: &MetadataMap,
+) -> Result<S::NodeId, Status>
+where
+    S: P2pService,
+{
+    if node_key.is_none() {
+        let node_id = decode_node_id(metadata).map_err(error_into_grpc)?;
+        service
+            .accept_unauthenticated_peer(&node_id)
+            .map_err(error_into_grpc)?;
+        return Ok(node_id);
+    }
+    let mut auth = auth.lock().unwrap();
+    if let Some(node_id) = &auth.peer_node_id {
+        return deserialize_bytes(node_id).map_err(error_into_grpc);
+    }
+    let node_auth = match decode_node_auth(metadata).map_err(error_into_grpc)? {
+        Some(node_auth) => node_auth,
+        None => {
+            return Err(Status::new(
+                Code::Unauthenticated,
+                "the client node has not authenticated",
+            ))
+        }
+    };
+    let nonce = match &auth.nonce {
+        Some(nonce) => nonce,
+        None => {
+            return Err(Status::new(
+                Code::FailedPrecondition,
+                "no authentication challenge has been issued on this connection",
+            ))
+        }
+    };
+    auth::verify(
+        &node_auth.public_key,
+        &node_auth.signature,
+        Role::Client,
+        nonce,
+        &node_auth.node_id,
+    )
+    .map_err(|e| Status::new(Code::Unauthenticated, e.to_string()))?;
+    let node_id: S::NodeId = deserialize_bytes(&node_auth.node_id).map_err(error_into_grpc)?;
+    let peer = PeerIdentity::new(node_id.clone(), &node_auth.public_key);
+    service.accept_peer(&peer).map_err(error_into_grpc)?;
+    auth.peer_node_id = Some(node_auth.node_id.to_vec());
+    Ok(node_id)
+}
+
 macro_rules! try_get_service {
     ($opt_ref:expr) => {
         match $opt_ref {
@@ -58,15 +165,75 @@ macro_rules! try_get_service_sub {
     };
 }
 
-macro_rules! try_decode_node_id {
-    ($req:expr) => {
-        match decode_node_id($req.metadata()) {
+macro_rules! try_check_fetch {
+    ($self:ident, $num_ids:expr) => {
+        if let Err(status) = $self.limits.check_fetch($num_ids) {
+            return ResponseFuture::error(status);
+        }
+    };
+}
+
+macro_rules! try_acquire_subscription {
+    ($self:ident) => {
+        match $self.limits.acquire_subscription() {
+            Ok(permit) => permit,
+            Err(status) => return SubscriptionFuture::error(status),
+        }
+    };
+}
+
+macro_rules! try_authenticate_subscriber {
+    ($self:ident, $service:expr, $req:expr) => {
+        match authenticate_subscriber(&$self.node_key, &$self.auth, $service, $req.metadata()) {
             Ok(id) => id,
-            Err(e) => return SubscriptionFuture::error(error_into_grpc(e)),
+            Err(status) => return SubscriptionFuture::error(status),
         }
     };
 }
 
+// Response to a block fetching request, before compression.
+type BlocksResponseFuture<S, F> = ResponseFuture<ResponseStream<gen::node::Block, S>, F>;
+
+// Block fetching requests, producing responses to be compressed
+// as negotiated on the connection.
+impl<T: Node> NodeService<T> {
+    fn get_blocks_uncompressed(
+        &mut self,
+        req: Request<gen::node::BlockIds>,
+    ) -> BlocksResponseFuture<
+        <T::BlockService as BlockService>::GetBlocksStream,
+        <T::BlockService as BlockService>::GetBlocksFuture,
+    > {
+        let service = try_get_service!(self.inner.block_service());
+        try_check_fetch!(self, req.get_ref().ids.len());
+        let block_ids = match deserialize_repeated_bytes(&req.get_ref().ids) {
+            Ok(block_ids) => block_ids,
+            Err(e) => {
+                return ResponseFuture::error(error_into_grpc(e));
+            }
+        };
+        ResponseFuture::new(service.get_blocks(&block_ids))
+    }
+
+    fn pull_blocks_to_tip_uncompressed(
+        &mut self,
+        req: Request<gen::node::PullBlocksToTipRequest>,
+    ) -> BlocksResponseFuture<
+        <T::BlockService as BlockService>::PullBlocksStream,
+        <T::BlockService as BlockService>::PullBlocksToTipFuture,
+    > {
+        let service = try_get_service!(self.inner.block_service());
+        try_check_fetch!(self, req.get_ref().from.len());
+        let block_ids = match deserialize_repeated_bytes(&req.get_ref().from) {
+            Ok(block_ids) => block_ids,
+            Err(e) => {
+                return ResponseFuture::error(error_into_grpc(e));
+            }
+        };
+        ResponseFuture::new(service.pull_blocks_to_tip(&block_ids))
+    }
+}
+
 pub mod protocol_bounds {
     use chain_core::{mempack, property};
     use network_core::gossip;
@@ -111,13 +278,17 @@ where
     type PeersFuture =
         ResponseFuture<gen::node::PeersResponse, <T::GossipService as GossipService>::PeersFuture>;
 
-    type GetBlocksStream = ResponseStream<
-        gen::node::Block,
-        <<T as Node>::BlockService as BlockService>::GetBlocksStream,
+    type GetBlocksStream = Compress<
+        ResponseStream<
+            gen::node::Block,
+            <<T as Node>::BlockService as BlockService>::GetBlocksStream,
+        >,
     >;
-    type GetBlocksFuture = ResponseFuture<
-        Self::GetBlocksStream,
-        <<T as Node>::BlockService as BlockService>::GetBlocksFuture,
+    type GetBlocksFuture = CompressResponse<
+        BlocksResponseFuture<
+            <<T as Node>::BlockService as BlockService>::GetBlocksStream,
+            <<T as Node>::BlockService as BlockService>::GetBlocksFuture,
+        >,
     >;
     type GetHeadersStream = ResponseStream<
         gen::node::Header,
@@ -135,13 +306,17 @@ where
         Self::PullHeadersStream,
         <<T as Node>::BlockService as BlockService>::PullHeadersFuture,
     >;
-    type PullBlocksToTipStream = ResponseStream<
-        gen::node::Block,
-        <<T as Node>::BlockService as BlockService>::PullBlocksStream,
+    type PullBlocksToTipStream = Compress<
+        ResponseStream<
+            gen::node::Block,
+            <<T as Node>::BlockService as BlockService>::PullBlocksStream,
+        >,
     >;
-    type PullBlocksToTipFuture = ResponseFuture<
-        Self::PullBlocksToTipStream,
-        <<T as Node>::BlockService as BlockService>::PullBlocksToTipFuture,
+    type PullBlocksToTipFuture = CompressResponse<
+        BlocksResponseFuture<
+            <<T as Node>::BlockService as BlockService>::PullBlocksStream,
+            <<T as Node>::BlockService as BlockService>::PullBlocksToTipFuture,
+        >,
     >;
     type GetFragmentsStream = ResponseStream<
         gen::node::Fragment,
@@ -152,59 +327,79 @@ where
         <<T as Node>::FragmentService as FragmentService>::GetFragmentsFuture,
     >;
     type PushHeadersFuture = request_stream::Processing<
-        Streaming<gen::node::Header>,
+        Inbound<Streaming<gen::node::Header>>,
         <T::BlockService as BlockService>::PushHeadersSink,
         gen::node::PushHeadersResponse,
     >;
     type UploadBlocksFuture = request_stream::Processing<
-        Streaming<gen::node::Block>,
+        Inbound<Streaming<gen::node::Block>>,
         <T::BlockService as BlockService>::UploadBlocksSink,
         gen::node::UploadBlocksResponse,
     >;
     type BlockSubscriptionStream = Subscription<
         gen::node::BlockEvent,
-        Streaming<gen::node::Header>,
-        <T::BlockService as BlockService>::BlockSubscription,
+        Inbound<Streaming<gen::node::Header>>,
+        CompactFilter<<T::BlockService as BlockService>::BlockSubscription>,
     >;
     type BlockSubscriptionFuture = SubscriptionFuture<
         gen::node::BlockEvent,
-        Streaming<gen::node::Header>,
+        Inbound<Streaming<gen::node::Header>>,
         <T::BlockService as P2pService>::NodeId,
-        <T::BlockService as BlockService>::BlockSubscriptionFuture,
+        CompactFilterFuture<<T::BlockService as BlockService>::BlockSubscriptionFuture>,
     >;
     type FragmentSubscriptionStream = Subscription<
         gen::node::Fragment,
-        Streaming<gen::node::Fragment>,
+        Inbound<Streaming<gen::node::Fragment>>,
         <T::FragmentService as FragmentService>::FragmentSubscription,
     >;
     type FragmentSubscriptionFuture = SubscriptionFuture<
         gen::node::Fragment,
-        Streaming<gen::node::Fragment>,
+        Inbound<Streaming<gen::node::Fragment>>,
         <T::FragmentService as P2pService>::NodeId,
         <T::FragmentService as FragmentService>::FragmentSubscriptionFuture,
     >;
     type GossipSubscriptionStream = Subscription<
         gen::node::Gossip,
-        Streaming<gen::node::Gossip>,
+        Inbound<Streaming<gen::node::Gossip>>,
         <T::GossipService as GossipService>::GossipSubscription,
     >;
     type GossipSubscriptionFuture = SubscriptionFuture<
         gen::node::Gossip,
-        Streaming<gen::node::Gossip>,
+        Inbound<Streaming<gen::node::Gossip>>,
         <T::GossipService as P2pService>::NodeId,
         <T::GossipService as GossipService>::GossipSubscriptionFuture,
     >;
 
-    fn handshake(&mut self, _req: Request<gen::node::HandshakeRequest>) -> Self::HandshakeFuture {
+    fn handshake(&mut self, req: Request<gen::node::HandshakeRequest>) -> Self::HandshakeFuture {
         let service = match self.inner.block_service() {
             Some(service) => service,
             None => return future::err(Status::new(Code::Unimplemented, "not implemented")),
         };
         let block0 = serialize_to_bytes(&service.block0()).unwrap();
-        let res = gen::node::HandshakeResponse {
+        let mut res = gen::node::HandshakeResponse {
             version: PROTOCOL_VERSION,
             block0,
+            ..Default::default()
         };
+        if let Some(key) = &self.node_key {
+            let client_nonce = &req.get_ref().nonce;
+            if !client_nonce.is_empty() {
+                let node_id = serialize_to_bytes(&service.node_id()).unwrap();
+                res.signature = auth::sign(key, Role::Server, client_nonce, &node_id);
+                res.public_key = key.to_public().as_ref().to_vec();
+                res.node_id = node_id;
+            }
+            let nonce = auth::generate_nonce();
+            self.auth.lock().unwrap().nonce = Some(nonce.clone());
+            res.nonce = nonce;
+        }
+        let compact_blocks = self.enable_compact_blocks && req.get_ref().compact_blocks;
+        self.compact_blocks.store(compact_blocks, Ordering::Relaxed);
+        res.compact_blocks = compact_blocks;
+        let compression =
+            compression::negotiate(&req.get_ref().accept_compression, &self.enabled_compression);
+        *self.compression.lock().unwrap() = compression;
+        res.compression = compression::into_proto(compression);
         future::ok(Response::new(res))
     }
 
@@ -219,18 +414,13 @@ where
     }
 
     fn get_blocks(&mut self, req: Request<gen::node::BlockIds>) -> Self::GetBlocksFuture {
-        let service = try_get_service!(self.inner.block_service());
-        let block_ids = match deserialize_repeated_bytes(&req.get_ref().ids) {
-            Ok(block_ids) => block_ids,
-            Err(e) => {
-                return ResponseFuture::error(error_into_grpc(e));
-            }
-        };
-        ResponseFuture::new(service.get_blocks(&block_ids))
+        let compression = *self.compression.lock().unwrap();
+        CompressResponse::new(self.get_blocks_uncompressed(req), compression)
     }
 
     fn get_headers(&mut self, req: Request<gen::node::BlockIds>) -> Self::GetHeadersFuture {
         let service = try_get_service!(self.inner.block_service());
+        try_check_fetch!(self, req.get_ref().ids.len());
         let block_ids = match deserialize_repeated_bytes(&req.get_ref().ids) {
             Ok(block_ids) => block_ids,
             Err(e) => {
@@ -245,6 +435,7 @@ where
         req: Request<gen::node::PullHeadersRequest>,
     ) -> Self::PullHeadersFuture {
         let service = try_get_service!(self.inner.block_service());
+        try_check_fetch!(self, req.get_ref().from.len());
         let from = match deserialize_repeated_bytes(&req.get_ref().from) {
             Ok(block_ids) => block_ids,
             Err(e) => {
@@ -264,19 +455,28 @@ where
         &mut self,
         req: Request<gen::node::PullBlocksToTipRequest>,
     ) -> Self::PullBlocksToTipFuture {
-        let service = try_get_service!(self.inner.block_service());
-        let block_ids = match deserialize_repeated_bytes(&req.get_ref().from) {
-            Ok(block_ids) => block_ids,
-            Err(e) => {
-                return ResponseFuture::error(error_into_grpc(e));
-            }
-        };
-        ResponseFuture::new(service.pull_blocks_to_tip(&block_ids))
+        let compression = *self.compression.lock().unwrap();
+        CompressResponse::new(self.pull_blocks_to_tip_uncompressed(req), compression)
     }
 
     fn get_fragments(&mut self, req: Request<gen::node::FragmentIds>) -> Self::GetFragmentsFuture {
         let service = try_get_service!(self.inner.fragment_service());
-        let tx_ids = match deserialize_repeated_bytes(&req.get_ref().ids) {
+        let req = req.get_ref();
+        try_check_fetch!(self, req.ids.len() + req.indices.len());
+        if !req.block_id.is_empty() {
+            let block_id: <T::BlockService as BlockService>::BlockId =
+                match deserialize_bytes(&req.block_id) {
+                    Ok(block_id) => block_id,
+                    Err(e) => {
+                        return ResponseFuture::error(error_into_grpc(e));
+                    }
+                };
+            return match service.get_block_fragments(&block_id, &req.indices) {
+                Ok(future) => ResponseFuture::new(future),
+                Err(e) => ResponseFuture::error(error_into_grpc(e)),
+            };
+        }
+        let tx_ids = match deserialize_repeated_bytes(&req.ids) {
             Ok(tx_ids) => tx_ids,
             Err(e) => {
                 return ResponseFuture::error(error_into_grpc(e));
@@ -291,7 +491,8 @@ where
     ) -> Self::PushHeadersFuture {
         let service = try_get_service_push!(self.inner.block_service());
         let future_sink = service.push_headers();
-        request_stream::Processing::new(req.into_inner(), future_sink)
+        let inbound = self.limits.inbound(req.into_inner(), None);
+        request_stream::Processing::new(inbound, future_sink)
     }
 
     fn upload_blocks(
@@ -300,7 +501,8 @@ where
     ) -> Self::UploadBlocksFuture {
         let service = try_get_service_push!(self.inner.block_service());
         let future_sink = service.upload_blocks();
-        request_stream::Processing::new(req.into_inner(), future_sink)
+        let inbound = self.limits.inbound(req.into_inner(), None);
+        request_stream::Processing::new(inbound, future_sink)
     }
 
     fn block_subscription(
@@ -308,11 +510,14 @@ where
         req: Request<Streaming<gen::node::Header>>,
     ) -> Self::BlockSubscriptionFuture {
         let service = try_get_service_sub!(self.inner.block_service());
-        let subscriber = try_decode_node_id!(&req);
+        let subscriber = try_authenticate_subscriber!(self, service, &req);
+        let permit = try_acquire_subscription!(self);
+        let inbound = self.limits.inbound(req.into_inner(), Some(permit));
+        let compact_blocks = self.compact_blocks.load(Ordering::Relaxed);
         SubscriptionFuture::new(
             service.node_id(),
-            req.into_inner(),
-            service.block_subscription(subscriber),
+            inbound,
+            CompactFilterFuture::new(service.block_subscription(subscriber), compact_blocks),
         )
     }
 
@@ -321,8 +526,9 @@ where
         req: Request<Streaming<gen::node::Fragment>>,
     ) -> Self::FragmentSubscriptionFuture {
         let service = try_get_service_sub!(self.inner.fragment_service());
-        let subscriber = try_decode_node_id!(&req);
-        let inbound = req.into_inner();
+        let subscriber = try_authenticate_subscriber!(self, service, &req);
+        let permit = try_acquire_subscription!(self);
+        let inbound = self.limits.inbound(req.into_inner(), Some(permit));
         SubscriptionFuture::new(
             service.node_id(),
             inbound,
@@ -335,8 +541,9 @@ where
         req: Request<Streaming<gen::node::Gossip>>,
     ) -> Self::GossipSubscriptionFuture {
         let service = try_get_service_sub!(self.inner.gossip_service());
-        let subscriber = try_decode_node_id!(&req);
-        let inbound = req.into_inner();
+        let subscriber = try_authenticate_subscriber!(self, service, &req);
+        let permit = try_acquire_subscription!(self);
+        let inbound = self.limits.inbound(req.into_inner(), Some(permit));
         SubscriptionFuture::new(
             service.node_id(),
             inbound,
@@ -344,3 +551,252 @@ where
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{encode_node_auth, encode_node_id};
+    use crate::server::Limits;
+    use limits::ServerLimits;
+
+    use network_core::testing::{MockNode, TestNodeId};
+
+    use futures::prelude::*;
+    use rand_core::OsRng;
+
+    use std::time::{Duration, Instant};
+
+    fn node_service(node_key: Option<NodeKey>) -> NodeService<MockNode> {
+        NodeService::new(
+            MockNode::new(1),
+            node_key.map(Arc::new),
+            PeerLimits::new(Arc::new(ServerLimits::new(Limits::default())), None),
+            false,
+            Arc::new([]),
+        )
+    }
+
+    // Makes the handshake requesting compact block relay if `request`
+    // is true and returns whether the server has agreed.
+    fn negotiate_compact_blocks(enable: bool, request: bool) -> bool {
+        let mut service = node_service(None);
+        service.enable_compact_blocks = enable;
+        let req = gen::node::HandshakeRequest {
+            compact_blocks: request,
+            ..Default::default()
+        };
+        let res = gen::node::server::Node::handshake(&mut service, Request::new(req))
+            .wait()
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            service.compact_blocks.load(Ordering::Relaxed),
+            res.compact_blocks
+        );
+        res.compact_blocks
+    }
+
+    // Makes the handshake request with the given challenge for the server.
+    fn handshake(
+        service: &mut NodeService<MockNode>,
+        nonce: &[u8],
+    ) -> gen::node::HandshakeResponse {
+        let req = gen::node::HandshakeRequest {
+            nonce: nonce.to_vec(),
+            ..Default::default()
+        };
+        gen::node::server::Node::handshake(service, Request::new(req))
+            .wait()
+            .unwrap()
+            .into_inner()
+    }
+
+    // Metadata of a subscription request, authenticated with the signature
+    // if present.
+    fn subscription_metadata(
+        node_id: &TestNodeId,
+        auth: Option<(&NodeKey, Vec<u8>)>,
+    ) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        encode_node_id(node_id, &mut metadata).unwrap();
+        if let Some((key, signature)) = auth {
+            encode_node_auth(key.to_public().as_ref(), &signature, &mut metadata);
+        }
+        metadata
+    }
+
+    // Binds the node ID to the public key of the node key in the
+    // service's trusted peer keys.
+    fn pin(service: &NodeService<MockNode>, node_id: &TestNodeId, key: &NodeKey) {
+        service
+            .inner
+            .peer_keys
+            .lock()
+            .unwrap()
+            .pin(node_id.clone(), key.to_public().as_ref());
+    }
+
+    fn authenticate(
+        service: &mut NodeService<MockNode>,
+        metadata: &MetadataMap,
+    ) -> Result<TestNodeId, Status> {
+        authenticate_subscriber(
+            &service.node_key,
+            &service.auth,
+            &mut service.inner,
+            metadata,
+        )
+    }
+
+    #[test]
+    fn mutual_authentication() {
+        let server_key = NodeKey::generate(OsRng);
+        let client_key = NodeKey::generate(OsRng);
+        let client_id = TestNodeId(2);
+        let mut service = node_service(Some(server_key.clone()));
+        pin(&service, &client_id, &client_key);
+
+        let client_nonce = auth::generate_nonce();
+        let res = handshake(&mut service, &client_nonce);
+        assert_eq!(res.public_key, server_key.to_public().as_ref());
+        auth::verify(
+            &res.public_key,
+            &res.signature,
+            Role::Server,
+            &client_nonce,
+            &res.node_id,
+        )
+        .unwrap();
+        assert_eq!(
+            deserialize_bytes::<TestNodeId>(&res.node_id).unwrap(),
+            TestNodeId(1)
+        );
+
+        let signature = auth::sign(
+            &client_key,
+            Role::Client,
+            &res.nonce,
+            &serialize_to_bytes(&client_id).unwrap(),
+        );
+        let metadata = subscription_metadata(&client_id, Some((&client_key, signature)));
+        assert_eq!(authenticate(&mut service, &metadata).unwrap(), client_id);
+
+        // The authenticated identity is used for the later subscriptions
+        // on the connection, regardless of the claimed node ID.
+        let metadata = subscription_metadata(&TestNodeId(3), None);
+        assert_eq!(authenticate(&mut service, &metadata).unwrap(), client_id);
+    }
+
+    #[test]
+    fn bad_signature_is_rejected() {
+        let client_key = NodeKey::generate(OsRng);
+        let client_id = TestNodeId(2);
+        let mut service = node_service(Some(NodeKey::generate(OsRng)));
+        handshake(&mut service, &auth::generate_nonce());
+
+        // signed over a challenge the server has not issued
+        let signature = auth::sign(
+            &client_key,
+            Role::Client,
+            &auth::generate_nonce(),
+            &serialize_to_bytes(&client_id).unwrap(),
+        );
+        let metadata = subscription_metadata(&client_id, Some((&client_key, signature)));
+        let status = authenticate(&mut service, &metadata).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let metadata = subscription_metadata(&client_id, None);
+        let status = authenticate(&mut service, &metadata).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[test]
+    fn spoofed_node_id_is_rejected() {
+        let client_key = NodeKey::generate(OsRng);
+        let other_id = TestNodeId(3);
+        let mut service = node_service(Some(NodeKey::generate(OsRng)));
+        pin(&service, &other_id, &NodeKey::generate(OsRng));
+        let res = handshake(&mut service, &auth::generate_nonce());
+
+        // a validly signed claim of the ID of another node
+        let signature = auth::sign(
+            &client_key,
+            Role::Client,
+            &res.nonce,
+            &serialize_to_bytes(&other_id).unwrap(),
+        );
+        let metadata = subscription_metadata(&other_id, Some((&client_key, signature)));
+        let status = authenticate(&mut service, &metadata).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        // a node ID that has no key pinned
+        let client_id = TestNodeId(2);
+        let signature = auth::sign(
+            &client_key,
+            Role::Client,
+            &res.nonce,
+            &serialize_to_bytes(&client_id).unwrap(),
+        );
+        let metadata = subscription_metadata(&client_id, Some((&client_key, signature)));
+        let status = authenticate(&mut service, &metadata).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert!(service.auth.lock().unwrap().peer_node_id.is_none());
+    }
+
+    #[test]
+    fn server_without_node_key() {
+        let mut service = node_service(None);
+        let res = handshake(&mut service, &auth::generate_nonce());
+        assert!(res.signature.is_empty());
+        assert!(res.nonce.is_empty());
+
+        let metadata = subscription_metadata(&TestNodeId(2), None);
+        assert_eq!(
+            authenticate(&mut service, &metadata).unwrap(),
+            TestNodeId(2)
+        );
+    }
+
+    #[test]
+    fn banned_peer_is_rejected() {
+        let client_id = TestNodeId(2);
+        let ban = |service: &NodeService<MockNode>| {
+            let until = Instant::now() + Duration::from_secs(60);
+            service
+                .inner
+                .scores
+                .lock()
+                .unwrap()
+                .ban(client_id.clone(), until);
+        };
+
+        let mut service = node_service(None);
+        ban(&service);
+        let metadata = subscription_metadata(&client_id, None);
+        let status = authenticate(&mut service, &metadata).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        let client_key = NodeKey::generate(OsRng);
+        let mut service = node_service(Some(NodeKey::generate(OsRng)));
+        pin(&service, &client_id, &client_key);
+        ban(&service);
+        let res = handshake(&mut service, &auth::generate_nonce());
+        let signature = auth::sign(
+            &client_key,
+            Role::Client,
+            &res.nonce,
+            &serialize_to_bytes(&client_id).unwrap(),
+        );
+        let metadata = subscription_metadata(&client_id, Some((&client_key, signature)));
+        let status = authenticate(&mut service, &metadata).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[test]
+    fn compact_blocks_negotiation() {
+        assert!(negotiate_compact_blocks(true, true));
+        assert!(!negotiate_compact_blocks(true, false));
+        assert!(!negotiate_compact_blocks(false, true));
+        assert!(!negotiate_compact_blocks(false, false));
+    }
+}
@@ -0,0 +1,160 @@
+use chain_core::property::{Block, HasHeader};
+use network_core::error as core_error;
+use network_core::server::request_stream::{MapResponse, ProcessingError};
+use network_core::subscription::BlockEvent;
+
+use futures::prelude::*;
+
+/// Wraps a block subscription to replace compact block announcements
+/// with plain header announcements, if the subscriber has not negotiated
+/// compact block relay.
+#[must_use = "streams do nothing unless polled"]
+pub struct CompactFilter<S> {
+    inner: S,
+    compact_blocks: bool,
+}
+
+impl<S> CompactFilter<S> {
+    pub fn new(inner: S, compact_blocks: bool) -> Self {
+        CompactFilter {
+            inner,
+            compact_blocks,
+        }
+    }
+}
+
+pub trait Downgrade {
+    fn downgrade(self) -> Self;
+}
+
+impl<B> Downgrade for BlockEvent<B>
+where
+    B: Block + HasHeader,
+{
+    fn downgrade(self) -> Self {
+        match self {
+            BlockEvent::AnnounceCompact(compact) => BlockEvent::Announce(compact.into_header()),
+            event => event,
+        }
+    }
+}
+
+impl<S> Stream for CompactFilter<S>
+where
+    S: Stream,
+    S::Item: Downgrade,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        let item = try_ready!(self.inner.poll());
+        if self.compact_blocks {
+            Ok(Async::Ready(item))
+        } else {
+            Ok(Async::Ready(item.map(Downgrade::downgrade)))
+        }
+    }
+}
+
+impl<S: Sink> Sink for CompactFilter<S> {
+    type SinkItem = S::SinkItem;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: S::SinkItem) -> StartSend<S::SinkItem, S::SinkError> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), S::SinkError> {
+        self.inner.close()
+    }
+}
+
+impl<S: MapResponse> MapResponse for CompactFilter<S> {
+    type Response = S::Response;
+    type ResponseFuture = S::ResponseFuture;
+
+    fn on_stream_termination(&mut self, res: Result<(), ProcessingError>) -> Self::ResponseFuture {
+        self.inner.on_stream_termination(res)
+    }
+}
+
+/// Resolves to a `CompactFilter` over the subscription produced by
+/// the inner future.
+#[must_use = "futures do nothing unless polled"]
+pub struct CompactFilterFuture<F> {
+    inner: F,
+    compact_blocks: bool,
+}
+
+impl<F> CompactFilterFuture<F> {
+    pub fn new(inner: F, compact_blocks: bool) -> Self {
+        CompactFilterFuture {
+            inner,
+            compact_blocks,
+        }
+    }
+}
+
+impl<F> Future for CompactFilterFuture<F>
+where
+    F: Future<Error = core_error::Error>,
+{
+    type Item = CompactFilter<F::Item>;
+    type Error = core_error::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(CompactFilter::new(inner, self.compact_blocks)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network_core::compact::{CompactBlock, ShortFragmentId};
+    use network_core::testing::{Block, BlockId, MockNode};
+
+    use futures::stream;
+
+    // Passes the events through the filter, returning the announced
+    // block identifiers tagged with whether the announcement was compact.
+    fn filter(chain: &[Block], compact_blocks: bool) -> Vec<(BlockId, bool)> {
+        let short_ids = vec![ShortFragmentId::from_bytes([1; 6])];
+        let events: Vec<BlockEvent<Block>> = vec![
+            BlockEvent::Announce(chain[0].clone()),
+            BlockEvent::AnnounceCompact(CompactBlock::new(chain[1].clone(), 42, short_ids)),
+        ];
+        CompactFilter::new(stream::iter_ok::<_, ()>(events), compact_blocks)
+            .map(|event| match event {
+                BlockEvent::Announce(header) => (header.id(), false),
+                BlockEvent::AnnounceCompact(compact) => (compact.header().id(), true),
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn compact_announcements_are_downgraded() {
+        let chain = MockNode::new(2).chain;
+        assert_eq!(
+            filter(&chain, false),
+            vec![(chain[0].id(), false), (chain[1].id(), false)]
+        );
+    }
+
+    #[test]
+    fn compact_announcements_pass_when_negotiated() {
+        let chain = MockNode::new(2).chain;
+        assert_eq!(
+            filter(&chain, true),
+            vec![(chain[0].id(), false), (chain[1].id(), true)]
+        );
+    }
+}
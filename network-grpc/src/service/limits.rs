@@ -0,0 +1,563 @@
+use crate::server::{Limits, RateLimit};
+
+use bytes::Buf;
+use futures::prelude::*;
+use http_body::Body as HttpBody;
+use tower_grpc::{BoxBody, Code, Status};
+use tower_service::Service;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, Weak,
+};
+use std::time::Instant;
+
+// Token bucket rate limiter.
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        let burst = f64::from(limit.burst.max(1));
+        TokenBucket {
+            rate: limit.per_second,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn try_take(bucket: &Option<Mutex<TokenBucket>>, now: Instant) -> bool {
+    match bucket {
+        Some(bucket) => bucket.lock().unwrap().try_take(now),
+        None => true,
+    }
+}
+
+// Counter of concurrent subscriptions.
+struct Counter {
+    count: AtomicUsize,
+    max: Option<usize>,
+}
+
+impl Counter {
+    fn new(max: Option<usize>) -> Self {
+        Counter {
+            count: AtomicUsize::new(0),
+            max,
+        }
+    }
+
+    fn try_increment(&self) -> bool {
+        let prev = self.count.fetch_add(1, Ordering::AcqRel);
+        match self.max {
+            Some(max) if prev >= max => {
+                self.count.fetch_sub(1, Ordering::AcqRel);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn decrement(&self) {
+        self.count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Resource usage state shared by all connections of a server.
+pub struct ServerLimits {
+    limits: Limits,
+    subscriptions: Arc<Counter>,
+    requests: Option<Mutex<TokenBucket>>,
+    // Usage state of the peers with connections open to the server.
+    peers: Mutex<HashMap<IpAddr, Weak<PeerState>>>,
+}
+
+impl ServerLimits {
+    pub fn new(limits: Limits) -> Self {
+        let now = Instant::now();
+        ServerLimits {
+            subscriptions: Arc::new(Counter::new(limits.max_subscriptions)),
+            requests: limits
+                .request_rate
+                .as_ref()
+                .map(|limit| Mutex::new(TokenBucket::new(limit, now))),
+            limits,
+            peers: Default::default(),
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // Returns the usage state of the peer at the given address, shared
+    // with the other connections from the same address that are open.
+    fn peer_state(&self, addr: IpAddr) -> Arc<PeerState> {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(state) = peers.get(&addr).and_then(Weak::upgrade) {
+            return state;
+        }
+        peers.retain(|_, state| state.strong_count() > 0);
+        let state = Arc::new(PeerState::new(&self.limits));
+        peers.insert(addr, Arc::downgrade(&state));
+        state
+    }
+}
+
+// Resource usage state of a client peer.
+struct PeerState {
+    subscriptions: Arc<Counter>,
+    requests: Option<Mutex<TokenBucket>>,
+}
+
+impl PeerState {
+    fn new(limits: &Limits) -> Self {
+        PeerState {
+            subscriptions: Arc::new(Counter::new(limits.max_subscriptions_per_peer)),
+            requests: limits
+                .peer_request_rate
+                .as_ref()
+                .map(|limit| Mutex::new(TokenBucket::new(limit, Instant::now()))),
+        }
+    }
+}
+
+/// Resource usage state of a client connection.
+///
+/// The per-peer usage is accounted together for all connections
+/// made from the same IP address. Without a known address, as with
+/// connections served by `Server::serve`, the connection is accounted
+/// as a separate peer, so the per-peer limits do not bound the usage
+/// of a peer making multiple connections.
+pub struct PeerLimits {
+    server: Arc<ServerLimits>,
+    peer: Arc<PeerState>,
+}
+
+impl PeerLimits {
+    pub fn new(server: Arc<ServerLimits>, addr: Option<IpAddr>) -> Self {
+        let peer = match addr {
+            Some(addr) => server.peer_state(addr),
+            None => Arc::new(PeerState::new(server.limits())),
+        };
+        PeerLimits { server, peer }
+    }
+
+    /// Checks a fetch request carrying the given number of identifiers
+    /// against the limits, accounting for the request rate.
+    pub fn check_fetch(&self, num_ids: usize) -> Result<(), Status> {
+        self.check_fetch_at(num_ids, Instant::now())
+    }
+
+    fn check_fetch_at(&self, num_ids: usize, now: Instant) -> Result<(), Status> {
+        if let Some(max) = self.server.limits.max_ids_per_request {
+            if num_ids > max {
+                return Err(Status::new(
+                    Code::ResourceExhausted,
+                    format!("too many identifiers in the request, the limit is {}", max),
+                ));
+            }
+        }
+        if !try_take(&self.peer.requests, now) {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                "request rate limit exceeded for the peer",
+            ));
+        }
+        if !try_take(&self.server.requests, now) {
+            return Err(Status::new(
+                Code::Unavailable,
+                "the server is overloaded with requests",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Accounts for a new subscription, returning a permit that releases
+    /// it when dropped.
+    pub fn acquire_subscription(&self) -> Result<SubscriptionPermit, Status> {
+        if !self.peer.subscriptions.try_increment() {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                "too many subscriptions from the peer",
+            ));
+        }
+        if !self.server.subscriptions.try_increment() {
+            self.peer.subscriptions.decrement();
+            return Err(Status::new(
+                Code::Unavailable,
+                "the server has too many subscriptions",
+            ));
+        }
+        Ok(SubscriptionPermit {
+            peer: self.peer.subscriptions.clone(),
+            server: self.server.subscriptions.clone(),
+        })
+    }
+
+    /// Wraps an inbound request stream to hold the subscription permit,
+    /// if any, for the lifetime of the stream.
+    pub fn inbound<S>(&self, stream: S, permit: Option<SubscriptionPermit>) -> Inbound<S> {
+        Inbound {
+            inner: stream,
+            _permit: permit,
+        }
+    }
+}
+
+/// Accounts for an active subscription until dropped.
+pub struct SubscriptionPermit {
+    peer: Arc<Counter>,
+    server: Arc<Counter>,
+}
+
+impl Drop for SubscriptionPermit {
+    fn drop(&mut self) {
+        self.peer.decrement();
+        self.server.decrement();
+    }
+}
+
+/// Inbound request stream holding the permit of the subscription
+/// it belongs to, if any.
+#[must_use = "streams do nothing unless polled"]
+pub struct Inbound<S> {
+    inner: S,
+    _permit: Option<SubscriptionPermit>,
+}
+
+impl<S: Stream> Stream for Inbound<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        self.inner.poll()
+    }
+}
+
+/// Service middleware enforcing the size limit on inbound messages.
+///
+/// The limit is checked against the length prefix of each message
+/// in the request body, so that oversized messages are rejected
+/// with the `ResourceExhausted` status before they are buffered
+/// for decoding.
+#[derive(Clone)]
+pub struct MessageSizeLimit<S> {
+    inner: S,
+    max_message_size: Option<usize>,
+}
+
+impl<S> MessageSizeLimit<S> {
+    pub fn new(inner: S, max_message_size: Option<usize>) -> Self {
+        MessageSizeLimit {
+            inner,
+            max_message_size,
+        }
+    }
+}
+
+impl<S> Service<http::Request<BoxBody>> for MessageSizeLimit<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), S::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> S::Future {
+        let req = match self.max_message_size {
+            Some(max) => req.map(|body| BoxBody::new(Box::new(LimitedBody::new(body, max)))),
+            None => req,
+        };
+        self.inner.call(req)
+    }
+}
+
+// Size of the gRPC message prefix: the compression flag
+// followed by the big-endian 32-bit length of the message.
+const MESSAGE_PREFIX_SIZE: usize = 5;
+
+// Position of the body in the gRPC message framing.
+enum Framing {
+    Prefix {
+        buf: [u8; MESSAGE_PREFIX_SIZE],
+        filled: usize,
+    },
+    Message {
+        remaining: usize,
+    },
+}
+
+impl Framing {
+    fn prefix() -> Self {
+        Framing::Prefix {
+            buf: [0; MESSAGE_PREFIX_SIZE],
+            filled: 0,
+        }
+    }
+}
+
+// Request body failing when a message in it exceeds the size limit.
+struct LimitedBody {
+    inner: BoxBody,
+    max_message_size: usize,
+    framing: Framing,
+}
+
+impl LimitedBody {
+    fn new(inner: BoxBody, max_message_size: usize) -> Self {
+        LimitedBody {
+            inner,
+            max_message_size,
+            framing: Framing::prefix(),
+        }
+    }
+
+    fn scan(&mut self, mut data: &[u8]) -> Result<(), Status> {
+        while !data.is_empty() {
+            match &mut self.framing {
+                Framing::Prefix { buf, filled } => {
+                    let n = cmp::min(MESSAGE_PREFIX_SIZE - *filled, data.len());
+                    buf[*filled..*filled + n].copy_from_slice(&data[..n]);
+                    *filled += n;
+                    data = &data[n..];
+                    if *filled < MESSAGE_PREFIX_SIZE {
+                        continue;
+                    }
+                    let mut len = [0; 4];
+                    len.copy_from_slice(&buf[1..]);
+                    let len = u32::from_be_bytes(len) as usize;
+                    if len > self.max_message_size {
+                        return Err(Status::new(
+                            Code::ResourceExhausted,
+                            format!(
+                                "inbound message exceeds the size limit of {} bytes",
+                                self.max_message_size
+                            ),
+                        ));
+                    }
+                    self.framing = if len == 0 {
+                        Framing::prefix()
+                    } else {
+                        Framing::Message { remaining: len }
+                    };
+                }
+                Framing::Message { remaining } => {
+                    let n = cmp::min(*remaining, data.len());
+                    *remaining -= n;
+                    data = &data[n..];
+                    if *remaining == 0 {
+                        self.framing = Framing::prefix();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HttpBody for LimitedBody {
+    type Data = <BoxBody as HttpBody>::Data;
+    type Error = Status;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Status> {
+        let data = try_ready!(self.inner.poll_data());
+        if let Some(buf) = &data {
+            // The data buffers of gRPC bodies are contiguous.
+            self.scan(buf.bytes())?;
+        }
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Status> {
+        self.inner.poll_trailers()
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen;
+    use futures::stream;
+    use prost::Message;
+    use std::time::Duration;
+
+    fn peer_limits(limits: Limits) -> PeerLimits {
+        PeerLimits::new(Arc::new(ServerLimits::new(limits)), None)
+    }
+
+    #[test]
+    fn request_rate_is_limited() {
+        let limits = Limits {
+            peer_request_rate: Some(RateLimit {
+                per_second: 1.0,
+                burst: 2,
+            }),
+            ..Default::default()
+        };
+        let peer = peer_limits(limits);
+        let now = Instant::now();
+        assert!(peer.check_fetch_at(1, now).is_ok());
+        assert!(peer.check_fetch_at(1, now).is_ok());
+        let status = peer.check_fetch_at(1, now).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(peer.check_fetch_at(1, now + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn global_request_rate_is_shared() {
+        let limits = Limits {
+            request_rate: Some(RateLimit {
+                per_second: 1.0,
+                burst: 1,
+            }),
+            ..Default::default()
+        };
+        let server = Arc::new(ServerLimits::new(limits));
+        let peer1 = PeerLimits::new(server.clone(), None);
+        let peer2 = PeerLimits::new(server, None);
+        let now = Instant::now();
+        assert!(peer1.check_fetch_at(1, now).is_ok());
+        let status = peer2.check_fetch_at(1, now).unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[test]
+    fn number_of_ids_is_limited() {
+        let limits = Limits {
+            max_ids_per_request: Some(2),
+            ..Default::default()
+        };
+        let peer = peer_limits(limits);
+        assert!(peer.check_fetch(2).is_ok());
+        let status = peer.check_fetch(3).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    #[test]
+    fn subscriptions_are_released() {
+        let limits = Limits {
+            max_subscriptions_per_peer: Some(1),
+            max_subscriptions: Some(2),
+            ..Default::default()
+        };
+        let server = Arc::new(ServerLimits::new(limits));
+        let peer1 = PeerLimits::new(server.clone(), None);
+        let peer2 = PeerLimits::new(server.clone(), None);
+        let peer3 = PeerLimits::new(server, None);
+        let permit = peer1.acquire_subscription().unwrap();
+        let status = peer1.acquire_subscription().err().unwrap();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        let _permit2 = peer2.acquire_subscription().unwrap();
+        let status = peer3.acquire_subscription().err().unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+        drop(permit);
+        assert!(peer3.acquire_subscription().is_ok());
+    }
+
+    #[test]
+    fn peer_limits_are_shared_by_address() {
+        let limits = Limits {
+            max_subscriptions_per_peer: Some(1),
+            ..Default::default()
+        };
+        let server = Arc::new(ServerLimits::new(limits));
+        let addr1 = "10.0.0.1".parse().unwrap();
+        let addr2 = "10.0.0.2".parse().unwrap();
+        let conn1 = PeerLimits::new(server.clone(), Some(addr1));
+        let conn2 = PeerLimits::new(server.clone(), Some(addr1));
+        let other = PeerLimits::new(server.clone(), Some(addr2));
+        let permit = conn1.acquire_subscription().unwrap();
+        let status = conn2.acquire_subscription().err().unwrap();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(other.acquire_subscription().is_ok());
+        drop(permit);
+        drop((conn1, conn2, other));
+        assert!(server
+            .peers
+            .lock()
+            .unwrap()
+            .get(&addr1)
+            .unwrap()
+            .upgrade()
+            .is_none());
+
+        let conn = PeerLimits::new(server.clone(), Some(addr1));
+        let _permit = conn.acquire_subscription().unwrap();
+        assert_eq!(server.peers.lock().unwrap().len(), 1);
+    }
+
+    // Encodes the messages with gRPC framing into a request body
+    // delivered in chunks of the given size.
+    fn request_body(messages: &[gen::node::Fragment], chunk_size: usize) -> BoxBody {
+        let mut data = Vec::new();
+        for msg in messages {
+            data.push(0);
+            data.extend_from_slice(&(msg.encoded_len() as u32).to_be_bytes());
+            msg.encode(&mut data).unwrap();
+        }
+        let chunks = data
+            .chunks(chunk_size)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
+        let body = hyper::Body::wrap_stream(stream::iter_ok::<_, Status>(chunks));
+        BoxBody::map_from(body)
+    }
+
+    #[test]
+    fn oversized_message_is_rejected() {
+        let messages = vec![
+            gen::node::Fragment { content: vec![] },
+            gen::node::Fragment {
+                content: vec![0; 4],
+            },
+            gen::node::Fragment {
+                content: vec![0; 16],
+            },
+        ];
+        let max_message_size = messages[1].encoded_len();
+        let mut body = LimitedBody::new(request_body(&messages, 3), max_message_size);
+        // the data passes until the chunk completing the prefix
+        // of the oversized message
+        let prefix_end = 5 + messages[0].encoded_len() + 5 + messages[1].encoded_len() + 5;
+        let mut received = 0;
+        let status = loop {
+            match body.poll_data() {
+                Ok(Async::Ready(Some(buf))) => received += buf.remaining(),
+                Ok(res) => panic!("unexpected poll result {:?}", res.map(|_| ())),
+                Err(status) => break status,
+            }
+        };
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(received < prefix_end && received + 3 >= prefix_end);
+    }
+}
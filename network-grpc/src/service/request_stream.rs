@@ -1,140 +1,60 @@
 use crate::convert::{error_from_grpc, error_into_grpc, FromProtobuf, IntoProtobuf};
 use network_core::error as core_error;
-use network_core::server::request_stream::{MapResponse, ProcessingError};
+use network_core::server::request_stream::{Forward, MapResponse, ProcessingError};
 
 use futures::prelude::*;
-use futures::stream::Fuse;
 use tower_grpc::{Code, Status};
 
-use std::convert::Infallible;
 use std::hint::unreachable_unchecked;
 use std::marker::PhantomData;
 use std::mem;
 
-/// Low level implementation of request stream forwarding to a sink.
-#[must_use = "futures do nothing unless polled"]
-pub struct Forward<In, S>
-where
-    S: Sink,
-{
-    inbound: Fuse<In>,
-    sink: Option<S>,
-    buffered: Option<S::SinkItem>,
-}
-
-impl<In: Stream, S: Sink> Forward<In, S> {
-    pub fn new(inbound: In, sink: S) -> Self {
-        Forward {
-            inbound: inbound.fuse(),
-            sink: Some(sink),
-            buffered: None,
-        }
-    }
-}
-
-impl<In, S: Sink> Forward<In, S> {
-    pub fn sink_mut(&mut self) -> &mut S {
-        self.sink
-            .as_mut()
-            .expect("attempted to poll request stream processing after completion")
-    }
-
-    pub fn break_up(&mut self) -> S {
-        self.sink
-            .take()
-            .expect("can't break up stream forwarding twice")
-    }
+/// Stream of the items received from the client-streamed request,
+/// decoded for the service sink.
+#[must_use = "streams do nothing unless polled"]
+pub struct DecodeRequests<In, T> {
+    inbound: In,
+    _phantom: PhantomData<fn() -> T>,
 }
 
-impl<In, S> Forward<In, S>
-where
-    S: Sink<SinkError = core_error::Error>,
-{
-    fn try_send_item(&mut self, item: S::SinkItem) -> Poll<(), ProcessingError> {
-        match self
-            .sink_mut()
-            .start_send(item)
-            .map_err(ProcessingError::Sink)?
-        {
-            AsyncSink::Ready => Ok(Async::Ready(())),
-            AsyncSink::NotReady(item) => {
-                debug_assert!(self.buffered.is_none());
-                self.buffered = Some(item);
-                Ok(Async::NotReady)
-            }
+impl<In, T> DecodeRequests<In, T> {
+    pub fn new(inbound: In) -> Self {
+        DecodeRequests {
+            inbound,
+            _phantom: PhantomData,
         }
     }
 }
 
-impl<In, S> Forward<In, S>
+impl<In, T> Stream for DecodeRequests<In, T>
 where
     In: Stream<Error = Status>,
-    S: Sink<SinkError = core_error::Error>,
-    S::SinkItem: FromProtobuf<In::Item>,
+    T: FromProtobuf<In::Item>,
 {
-    fn poll_step_internal(&mut self) -> Poll<Option<()>, ProcessingError> {
-        if let Some(item) = self.buffered.take() {
-            try_ready!(self.try_send_item(item));
-            Ok(None.into())
-        } else {
-            match self
-                .inbound
-                .poll()
-                .map_err(|e| ProcessingError::Inbound(error_from_grpc(e)))?
-            {
-                Async::NotReady => {
-                    try_ready!(self
-                        .sink_mut()
-                        .poll_complete()
-                        .map_err(ProcessingError::Sink));
-                    Ok(Async::NotReady)
-                }
-                Async::Ready(Some(msg)) => {
-                    let item =
-                        FromProtobuf::from_message(msg).map_err(ProcessingError::Decoding)?;
-                    try_ready!(self.try_send_item(item));
-                    Ok(None.into())
-                }
-                Async::Ready(None) => {
-                    try_ready!(self.sink_mut().close().map_err(ProcessingError::Sink));
-                    Ok(Some(()).into())
-                }
+    type Item = T;
+    type Error = ProcessingError;
+
+    fn poll(&mut self) -> Poll<Option<T>, ProcessingError> {
+        let maybe_msg = try_ready!(self
+            .inbound
+            .poll()
+            .map_err(|e| ProcessingError::Inbound(error_from_grpc(e))));
+        match maybe_msg {
+            Some(msg) => {
+                let item = T::from_message(msg).map_err(ProcessingError::Decoding)?;
+                Ok(Some(item).into())
             }
+            None => Ok(None.into()),
         }
     }
 }
 
-impl<In, S> Forward<In, S>
-where
-    In: Stream<Error = Status>,
-    S: Sink<SinkError = core_error::Error>,
-    S: MapResponse,
-    S::SinkItem: FromProtobuf<In::Item>,
-{
-    pub fn poll_step(&mut self) -> Poll<Option<(S, S::ResponseFuture)>, Infallible> {
-        let terminated = match self.poll_step_internal() {
-            Ok(Async::NotReady) => return Ok(Async::NotReady),
-            Ok(Async::Ready(None)) => return Ok(None.into()),
-            Ok(Async::Ready(Some(()))) => Ok(()),
-            Err(e) => Err(e),
-        };
-        let mut sink = self.sink.take().unwrap();
-        let shutdown = sink.on_stream_termination(terminated);
-        Ok(Some((sink, shutdown)).into())
-    }
-
-    pub fn poll_step_infallible(&mut self) -> Async<Option<(S, S::ResponseFuture)>> {
-        self.poll_step()
-            .unwrap_or_else(|_: Infallible| unsafe { unreachable_unchecked() })
-    }
-}
-
 #[must_use = "futures do nothing unless polled"]
 pub enum Processing<In, S, R>
 where
     S: Sink + MapResponse,
 {
-    Forwarding(Forward<In, S>),
+    Forwarding(Forward<DecodeRequests<In, S::SinkItem>, S>),
     PendingResponse(S::ResponseFuture),
     Failed(Status),
     Finished(PhantomData<R>),
@@ -142,11 +62,12 @@ where
 
 impl<In, S, R> Processing<In, S, R>
 where
-    In: Stream,
+    In: Stream<Error = Status>,
     S: Sink + MapResponse,
+    S::SinkItem: FromProtobuf<In::Item>,
 {
     pub fn new(inbound: In, sink: S) -> Self {
-        let forward = Forward::new(inbound, sink);
+        let forward = Forward::new(DecodeRequests::new(inbound), sink);
         Processing::Forwarding(forward)
     }
 
@@ -170,7 +91,7 @@ where
         use Processing::*;
         loop {
             match self {
-                Forwarding(forward) => match forward.poll_step_infallible() {
+                Forwarding(forward) => match forward.poll_step() {
                     Async::NotReady => return Ok(Async::NotReady),
                     Async::Ready(None) => {}
                     Async::Ready(Some((_sink, shutdown))) => {
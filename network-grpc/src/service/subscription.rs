@@ -1,9 +1,9 @@
-use super::{request_stream::Forward, response_stream};
+use super::{request_stream::DecodeRequests, response_stream};
 use crate::convert::{encode_node_id, error_into_grpc, FromProtobuf, IntoProtobuf};
 use chain_core::property;
 use network_core::error as core_error;
 use network_core::gossip::NodeId;
-use network_core::server::request_stream::MapResponse;
+use network_core::server::request_stream::{Forward, MapResponse};
 
 use futures::prelude::*;
 use tower_grpc::{self, Code, Status};
@@ -24,10 +24,10 @@ enum State<In, S>
 where
     S: Sink + MapResponse,
 {
-    Full(Forward<In, S>),
+    Full(Forward<DecodeRequests<In, S::SinkItem>, S>),
     InboundClosed {
         outbound: Option<S>,
-        shutdown: S::ResponseFuture,
+        shutdown: Option<S::ResponseFuture>,
     },
     OutboundGone {
         sink: S,
@@ -49,11 +49,12 @@ where
 
 impl<T, In, S> Subscription<T, In, S>
 where
-    In: Stream,
+    In: Stream<Error = Status>,
     S: Sink + MapResponse,
+    S::SinkItem: FromProtobuf<In::Item>,
 {
     fn new(inbound: In, core_subscription: S) -> Self {
-        let forward = Forward::new(inbound, core_subscription);
+        let forward = Forward::new(DecodeRequests::new(inbound), core_subscription);
         Subscription {
             state: State::Full(forward),
             _phantom: PhantomData,
@@ -77,20 +78,30 @@ where
         use InboundOutcome::{Closed, Continue};
 
         match &mut self.state {
-            State::Full(forward) => match forward.poll_step_infallible() {
+            State::Full(forward) => match forward.poll_step() {
                 Async::NotReady => Ok(Async::NotReady),
                 Async::Ready(None) => Ok(Continue.into()),
                 Async::Ready(Some((outbound, shutdown))) => {
                     self.state = State::InboundClosed {
                         outbound: Some(outbound),
-                        shutdown,
+                        shutdown: Some(shutdown),
                     };
                     Ok(Continue.into())
                 }
             },
-            State::InboundClosed { shutdown, .. } => {
-                try_ready!(shutdown.poll().map_err(error_into_grpc));
-                Ok(Closed.into())
+            State::InboundClosed { outbound, shutdown } => {
+                if let Some(future) = shutdown {
+                    try_ready!(future.poll().map_err(error_into_grpc));
+                    *shutdown = None;
+                    return Ok(Continue.into());
+                }
+                // The client has half-closed the stream, but the response
+                // goes on until the service ends its subscription.
+                if outbound.is_some() {
+                    Ok(Async::NotReady)
+                } else {
+                    Ok(Closed.into())
+                }
             }
             State::OutboundGone { sink } => {
                 try_ready!(sink.close().map_err(error_into_grpc));
@@ -199,6 +210,7 @@ where
     F: Future<Error = core_error::Error>,
     F::Item: Sink<SinkError = core_error::Error>,
     F::Item: MapResponse,
+    <F::Item as Sink>::SinkItem: FromProtobuf<In::Item>,
 {
     type Item = tower_grpc::Response<Subscription<T, In, F::Item>>;
     type Error = Status;
@@ -0,0 +1,332 @@
+//! Tests of client connections to the server over the network.
+
+use crate::auth::NodeKey;
+use crate::client::{Connect, Connection, ProtocolConfig};
+use crate::server::{self, Server};
+use crate::tls::{self, Certificate, PrivateKey, RootCertStore};
+
+use network_core::client::{BlockService as _, FragmentService as _, HandshakeError};
+use network_core::compact::PartialBlock;
+use network_core::error::{Code, Error};
+use network_core::gossip::keys::PeerKeys;
+use network_core::subscription::BlockEvent;
+use network_core::testing::{
+    Block, BlockDate, BlockId, MockNode, TestFragment, TestFragmentId, TestGossipNode, TestNodeId,
+};
+
+use futures::prelude::*;
+use futures::stream;
+use http::uri;
+use hyper::client::HttpConnector;
+use rand_core::OsRng;
+use tokio::runtime::Runtime;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tower_hyper::util::Destination;
+
+use std::io;
+use std::net::SocketAddr;
+
+struct TestProtocol;
+
+impl ProtocolConfig for TestProtocol {
+    type BlockId = BlockId;
+    type BlockDate = BlockDate;
+    type Header = Block;
+    type Block = Block;
+    type FragmentId = TestFragmentId;
+    type Fragment = TestFragment;
+    type Node = TestGossipNode;
+    type NodeId = TestNodeId;
+}
+
+type TestConnect = Connect<TestProtocol, HttpConnector, tokio::executor::DefaultExecutor>;
+
+// Spawns the server accepting connections from the listener.
+fn spawn_server<L, S>(rt: &mut Runtime, mut server: Server<MockNode>, listener: L)
+where
+    L: Stream<Item = (S, SocketAddr), Error = io::Error> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let accept = listener
+        .for_each(move |(sock, peer_addr)| {
+            let conn = server.serve_with_peer_addr(sock, peer_addr);
+            tokio::spawn(conn.map_err(|e| panic!("server connection failed: {}", e)));
+            Ok(())
+        })
+        .map_err(|e| panic!("failed to accept a connection: {}", e));
+    rt.spawn(accept);
+}
+
+// Spawns the server accepting connections on a local TCP port.
+fn serve(rt: &mut Runtime, server: Server<MockNode>) -> SocketAddr {
+    let listener = server::listen(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    spawn_server(rt, server, listener);
+    addr
+}
+
+// Like `serve`, but secures the connections with TLS
+// using a self-signed certificate for "localhost". Returns the address
+// and the root certificate store trusting the server.
+fn serve_tls(rt: &mut Runtime, server: Server<MockNode>) -> (SocketAddr, RootCertStore) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_der = Certificate(cert.serialize_der().unwrap());
+    let key = PrivateKey(cert.serialize_private_key_der());
+    let mut roots = RootCertStore::empty();
+    roots.add(&cert_der).unwrap();
+    let config = tls::server_config(vec![cert_der], key, None).unwrap();
+
+    let listener = server::listen_tls(&"127.0.0.1:0".parse().unwrap(), config).unwrap();
+    let addr = listener.local_addr().unwrap();
+    spawn_server(rt, server, listener);
+    (addr, roots)
+}
+
+fn connect(
+    rt: &mut Runtime,
+    connect: &mut TestConnect,
+    addr: SocketAddr,
+) -> Connection<TestProtocol> {
+    let uri = format!("http://{}", addr).parse().unwrap();
+    let future = connect.connect(Destination::try_from_uri(uri).unwrap());
+    rt.block_on(future).unwrap()
+}
+
+// Client settings authenticating as node 2 with the key and
+// trusting the given server keys.
+fn client_settings(node_key: NodeKey, server_keys: PeerKeys<TestNodeId>) -> TestConnect {
+    let mut connect = Connect::new(HttpConnector::new(1));
+    connect
+        .node_id(TestNodeId(2))
+        .node_key(node_key)
+        .peer_keys(server_keys);
+    connect
+}
+
+fn pinned_key(node_id: TestNodeId, key: &NodeKey) -> PeerKeys<TestNodeId> {
+    let mut keys = PeerKeys::new();
+    keys.pin(node_id, key.to_public().as_ref());
+    keys
+}
+
+// Subscribes to the blocks of the server and returns the
+// node identifier reported by the server and the announced blocks.
+fn subscribe(rt: &mut Runtime, conn: &mut Connection<TestProtocol>) -> (TestNodeId, Vec<BlockId>) {
+    let outbound = stream::empty::<Block, Error>();
+    let future = conn
+        .block_subscription(outbound)
+        .and_then(|(subscription, node_id)| {
+            subscription
+                .map(|event| match event {
+                    BlockEvent::Announce(header) => header.id(),
+                    other => panic!("unexpected event {:?}", other),
+                })
+                .collect()
+                .map(|ids| (node_id, ids))
+        });
+    rt.block_on(future).unwrap()
+}
+
+#[test]
+fn mutual_authentication() {
+    let mut rt = Runtime::new().unwrap();
+    let node = MockNode::new(2);
+    let subscribers = node.subscribers.clone();
+    let server_key = NodeKey::generate(OsRng);
+    let server_public_key = server_key.to_public();
+    let client_key = NodeKey::generate(OsRng);
+    node.peer_keys
+        .lock()
+        .unwrap()
+        .pin(TestNodeId(2), client_key.to_public().as_ref());
+    let server_keys = pinned_key(TestNodeId(1), &server_key);
+    let mut server = Server::new(node.clone());
+    server.node_key(server_key);
+    let addr = serve(&mut rt, server);
+
+    let mut conn = connect(&mut rt, &mut client_settings(client_key, server_keys), addr);
+    let block0 = rt.block_on(conn.handshake()).unwrap();
+    assert_eq!(block0, node.chain[0].id());
+    let peer = conn.peer_identity().unwrap();
+    assert_eq!(*peer.node_id(), TestNodeId(1));
+    assert_eq!(peer.public_key(), server_public_key.as_ref());
+
+    let (node_id, announced) = subscribe(&mut rt, &mut conn);
+    assert_eq!(node_id, TestNodeId(1));
+    assert_eq!(announced, vec![node.chain[0].id(), node.chain[1].id()]);
+    assert_eq!(*subscribers.lock().unwrap(), vec![TestNodeId(2)]);
+}
+
+#[test]
+fn client_key_with_server_without_key() {
+    let mut rt = Runtime::new().unwrap();
+    let node = MockNode::new(1);
+    let subscribers = node.subscribers.clone();
+    let addr = serve(&mut rt, Server::new(node.clone()));
+
+    let mut conn = connect(
+        &mut rt,
+        &mut client_settings(NodeKey::generate(OsRng), PeerKeys::new()),
+        addr,
+    );
+    let block0 = rt.block_on(conn.handshake()).unwrap();
+    assert_eq!(block0, node.chain[0].id());
+    assert!(conn.peer_identity().is_none());
+
+    let (node_id, _) = subscribe(&mut rt, &mut conn);
+    assert_eq!(node_id, TestNodeId(1));
+    assert_eq!(*subscribers.lock().unwrap(), vec![TestNodeId(2)]);
+}
+
+#[test]
+fn spoofed_node_id_is_rejected() {
+    let mut rt = Runtime::new().unwrap();
+    let node = MockNode::new(1);
+    let subscribers = node.subscribers.clone();
+    let server_key = NodeKey::generate(OsRng);
+    let client_key = NodeKey::generate(OsRng);
+    // the server is trusted, but node 2 is bound to another key
+    node.peer_keys
+        .lock()
+        .unwrap()
+        .pin(TestNodeId(2), NodeKey::generate(OsRng).to_public().as_ref());
+    let server_keys = pinned_key(TestNodeId(1), &server_key);
+    let mut server = Server::new(node);
+    server.node_key(server_key);
+    let addr = serve(&mut rt, server);
+
+    let mut conn = connect(&mut rt, &mut client_settings(client_key, server_keys), addr);
+    rt.block_on(conn.handshake()).unwrap();
+    let err = rt
+        .block_on(conn.block_subscription(stream::empty::<Block, Error>()))
+        .map(|_| ())
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    assert!(subscribers.lock().unwrap().is_empty());
+
+    // the server claims the node ID bound to another key
+    let server_keys = pinned_key(TestNodeId(1), &NodeKey::generate(OsRng));
+    let mut conn = connect(
+        &mut rt,
+        &mut client_settings(NodeKey::generate(OsRng), server_keys),
+        addr,
+    );
+    match rt.block_on(conn.handshake()) {
+        Err(HandshakeError::Authentication(_)) => {}
+        other => panic!("unexpected handshake result {:?}", other),
+    }
+    assert!(conn.peer_identity().is_none());
+}
+
+#[test]
+fn call_over_tls() {
+    let mut rt = Runtime::new().unwrap();
+    let node = MockNode::new(3);
+    let (addr, roots) = serve_tls(&mut rt, Server::new(node.clone()));
+
+    let mut settings: TestConnect = Connect::new(HttpConnector::new(1));
+    settings
+        .tls(tls::client_config(roots, None))
+        .origin(uri::Scheme::HTTPS, uri::Authority::from_static("localhost"));
+    let mut conn = connect(&mut rt, &mut settings, addr);
+    let block0 = rt.block_on(conn.handshake()).unwrap();
+    assert_eq!(block0, node.chain[0].id());
+    let tip = rt.block_on(conn.tip()).unwrap();
+    assert_eq!(tip, node.chain[2]);
+}
+
+#[test]
+fn compact_blocks_negotiation() {
+    let mut rt = Runtime::new().unwrap();
+    let mut server = Server::new(MockNode::new(1));
+    server.compact_blocks();
+    let addr = serve(&mut rt, server);
+    let legacy_addr = serve(&mut rt, Server::new(MockNode::new(1)));
+
+    let mut settings: TestConnect = Connect::new(HttpConnector::new(1));
+    let mut conn = connect(&mut rt, &mut settings, addr);
+    rt.block_on(conn.handshake()).unwrap();
+    assert!(!conn.compact_blocks());
+
+    settings.compact_blocks();
+    let mut conn = connect(&mut rt, &mut settings, addr);
+    assert!(!conn.compact_blocks());
+    rt.block_on(conn.handshake()).unwrap();
+    assert!(conn.compact_blocks());
+
+    let mut conn = connect(&mut rt, &mut settings, legacy_addr);
+    rt.block_on(conn.handshake()).unwrap();
+    assert!(!conn.compact_blocks());
+}
+
+#[test]
+fn compact_block_reconstruction() {
+    let mut rt = Runtime::new().unwrap();
+    let mut node = MockNode::new(2);
+    let block = node.chain[1].clone();
+    let block_fragments: Vec<_> = (10..14).map(TestFragment).collect();
+    node.block_fragments
+        .insert(block.id(), block_fragments.clone());
+    let mut server = Server::new(node);
+    server.compact_blocks();
+    let addr = serve(&mut rt, server);
+
+    let mut settings: TestConnect = Connect::new(HttpConnector::new(1));
+    settings.node_id(TestNodeId(2)).compact_blocks();
+    let mut conn = connect(&mut rt, &mut settings, addr);
+    rt.block_on(conn.handshake()).unwrap();
+    let future = conn
+        .block_subscription(stream::empty::<Block, Error>())
+        .and_then(|(subscription, _)| subscription.collect());
+    let compact = rt
+        .block_on(future)
+        .unwrap()
+        .into_iter()
+        .find_map(|event| match event {
+            BlockEvent::AnnounceCompact(compact) => Some(compact),
+            _ => None,
+        })
+        .expect("the block should be announced in the compact form");
+    assert_eq!(compact.header().id(), block.id());
+
+    // the pool has two of the block's fragments and an unrelated one
+    let pool = vec![TestFragment(11), TestFragment(13), TestFragment(1)];
+    let mut partial = PartialBlock::reconstruct(compact, pool).unwrap();
+    let missing = partial.missing_indices();
+    assert_eq!(missing, vec![0, 2]);
+    let future = conn
+        .get_block_fragments(&block.id(), &missing)
+        .unwrap()
+        .and_then(|fragments| fragments.collect());
+    let fetched = rt.block_on(future).unwrap();
+    for (index, fragment) in missing.into_iter().zip(fetched) {
+        partial.fill(index, fragment).unwrap();
+    }
+    let (header, fragments) = partial.into_parts().unwrap();
+    assert_eq!(header.id(), block.id());
+    assert_eq!(fragments, block_fragments);
+}
+
+#[test]
+fn oversized_message_is_rejected() {
+    let mut rt = Runtime::new().unwrap();
+    let node = MockNode::new(1);
+    let uploaded = node.uploaded_blocks.clone();
+    let mut server = Server::new(node);
+    server.limits(server::Limits {
+        max_message_size: Some(1),
+        ..Default::default()
+    });
+    let addr = serve(&mut rt, server);
+
+    let mut settings: TestConnect = Connect::new(HttpConnector::new(1));
+    let mut conn = connect(&mut rt, &mut settings, addr);
+    let blocks = MockNode::new(2).chain;
+    rt.block_on(conn.upload_blocks(stream::iter_ok(blocks)))
+        .unwrap();
+    // the service observes the failure of the request stream
+    // before any block is decoded
+    let uploaded = uploaded.lock().unwrap();
+    assert!(uploaded.items.is_empty());
+    assert_eq!(uploaded.terminated, Some(false));
+}
@@ -0,0 +1,429 @@
+//! TLS support for the protocol connections, implemented with rustls.
+//!
+//! The TLS session is established transparently on the first reads and
+//! writes on a `TlsStream`, so the handshake is driven by the HTTP/2
+//! connection machinery and handshake failures are reported as
+//! connection errors.
+
+pub use rustls::{
+    Certificate, ClientConfig, ClientSession, PrivateKey, RootCertStore, ServerConfig,
+    ServerSession, Session, TLSError,
+};
+
+use futures::prelude::*;
+use http_connection::HttpConnection;
+use rustls::{AllowAnyAuthenticatedClient, NoClientAuth};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// The ALPN protocol identifier of HTTP/2 over TLS.
+const ALPN_H2: &[u8] = b"h2";
+
+pub(crate) fn set_alpn_h2(protocols: &mut Vec<Vec<u8>>) {
+    protocols.clear();
+    protocols.push(ALPN_H2.to_vec());
+}
+
+/// Creates a TLS configuration for the server with the given certificate
+/// chain and private key.
+///
+/// If `client_roots` is provided, clients are required to present
+/// a certificate issued by one of the root certificates. Otherwise,
+/// client certificates are not requested.
+pub fn server_config(
+    cert_chain: Vec<Certificate>,
+    key: PrivateKey,
+    client_roots: Option<RootCertStore>,
+) -> Result<ServerConfig, TLSError> {
+    let verifier = match client_roots {
+        Some(roots) => AllowAnyAuthenticatedClient::new(roots),
+        None => NoClientAuth::new(),
+    };
+    let mut config = ServerConfig::new(verifier);
+    config.set_single_cert(cert_chain, key)?;
+    set_alpn_h2(&mut config.alpn_protocols);
+    Ok(config)
+}
+
+/// Creates a TLS configuration for the client, trusting the server
+/// certificates issued by the given root certificates.
+///
+/// If `client_cert` is provided, the client presents the certificate chain
+/// and proves possession of the private key when requested by the server.
+pub fn client_config(
+    roots: RootCertStore,
+    client_cert: Option<(Vec<Certificate>, PrivateKey)>,
+) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config.root_store = roots;
+    if let Some((cert_chain, key)) = client_cert {
+        config.set_single_client_cert(cert_chain, key);
+    }
+    set_alpn_h2(&mut config.alpn_protocols);
+    config
+}
+
+/// A stream secured with TLS over an underlying I/O object.
+pub struct TlsStream<IO, S> {
+    io: IO,
+    session: S,
+    eof: bool,
+    close_notify_sent: bool,
+}
+
+impl<IO, S> TlsStream<IO, S> {
+    pub(crate) fn new(io: IO, session: S) -> Self {
+        TlsStream {
+            io,
+            session,
+            eof: false,
+            close_notify_sent: false,
+        }
+    }
+
+    /// Returns references to the underlying I/O object and the TLS session.
+    ///
+    /// The session can be inspected for the negotiated parameters and
+    /// the peer's certificates once the handshake is complete.
+    pub fn get_ref(&self) -> (&IO, &S) {
+        (&self.io, &self.session)
+    }
+}
+
+impl<IO> TlsStream<IO, ServerSession> {
+    pub(crate) fn accept(io: IO, config: &Arc<ServerConfig>) -> Self {
+        TlsStream::new(io, ServerSession::new(config))
+    }
+}
+
+impl<IO, S> TlsStream<IO, S>
+where
+    IO: Read + Write,
+    S: Session,
+{
+    fn read_tls(&mut self) -> io::Result<usize> {
+        let n = self.session.read_tls(&mut self.io)?;
+        if let Err(e) = self.session.process_new_packets() {
+            // Try to deliver the alert to the peer before failing.
+            let _ = self.session.write_tls(&mut self.io);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        Ok(n)
+    }
+
+    // Writes out as much of the buffered TLS data as the underlying
+    // I/O object accepts without blocking. Returns true if all data
+    // has been written.
+    fn write_tls_nonblocking(&mut self) -> io::Result<bool> {
+        while self.session.wants_write() {
+            match self.session.write_tls(&mut self.io) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<IO, S> Read for TlsStream<IO, S>
+where
+    IO: Read + Write,
+    S: Session,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // Handshake messages may need to be sent before the session
+            // can make progress.
+            self.write_tls_nonblocking()?;
+            match self.session.read(buf) {
+                Ok(0) if !buf.is_empty() && !self.eof => {}
+                Ok(n) => return Ok(n),
+                // The peer has closed the session with close_notify.
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => return Ok(0),
+                Err(e) => return Err(e),
+            }
+            if self.read_tls()? == 0 {
+                self.eof = true;
+            }
+        }
+    }
+}
+
+impl<IO, S> Write for TlsStream<IO, S>
+where
+    IO: Read + Write,
+    S: Session,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut n = self.session.write(buf)?;
+        let flushed = self.write_tls_nonblocking()?;
+        if n == 0 && !buf.is_empty() {
+            if flushed {
+                n = self.session.write(buf)?;
+            }
+            if n == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush()?;
+        if !self.write_tls_nonblocking()? {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.io.flush()
+    }
+}
+
+impl<IO, S> AsyncRead for TlsStream<IO, S>
+where
+    IO: AsyncRead + AsyncWrite,
+    S: Session,
+{
+}
+
+impl<IO, S> AsyncWrite for TlsStream<IO, S>
+where
+    IO: AsyncRead + AsyncWrite,
+    S: Session,
+{
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if !self.close_notify_sent {
+            self.session.send_close_notify();
+            self.close_notify_sent = true;
+        }
+        match self.flush() {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+            Err(e) => return Err(e),
+        }
+        self.io.shutdown()
+    }
+}
+
+impl<IO, S> HttpConnection for TlsStream<IO, S>
+where
+    IO: HttpConnection,
+    S: Session,
+{
+    fn negotiated_version(&self) -> Option<http::Version> {
+        match self.session.get_alpn_protocol() {
+            Some(ALPN_H2) => Some(http::Version::HTTP_2),
+            _ => None,
+        }
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.io.remote_addr()
+    }
+}
+
+/// A client connection transport, secured with TLS if it has been
+/// enabled on the client `Connect`.
+pub enum MaybeTlsStream<T> {
+    Plain(T),
+    Tls(Box<TlsStream<T, ClientSession>>),
+}
+
+macro_rules! delegate {
+    ($self:ident, $stream:ident => $e:expr) => {
+        match $self {
+            MaybeTlsStream::Plain($stream) => $e,
+            MaybeTlsStream::Tls($stream) => $e,
+        }
+    };
+}
+
+impl<T: Read + Write> Read for MaybeTlsStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        delegate!(self, stream => stream.read(buf))
+    }
+}
+
+impl<T: Read + Write> Write for MaybeTlsStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        delegate!(self, stream => stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        delegate!(self, stream => stream.flush())
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> AsyncRead for MaybeTlsStream<T> {}
+
+impl<T: AsyncRead + AsyncWrite> AsyncWrite for MaybeTlsStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        delegate!(self, stream => stream.shutdown())
+    }
+}
+
+impl<T: HttpConnection> HttpConnection for MaybeTlsStream<T> {
+    fn negotiated_version(&self) -> Option<http::Version> {
+        delegate!(self, stream => stream.negotiated_version())
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        delegate!(self, stream => stream.remote_addr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::runtime::current_thread::Runtime;
+    use tokio_tcp::{TcpListener, TcpStream};
+    use webpki::DNSNameRef;
+
+    struct Credentials {
+        cert: Certificate,
+        key: PrivateKey,
+    }
+
+    impl Credentials {
+        fn generate() -> Self {
+            let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            Credentials {
+                cert: Certificate(cert.serialize_der().unwrap()),
+                key: PrivateKey(cert.serialize_private_key_der()),
+            }
+        }
+
+        fn roots(&self) -> RootCertStore {
+            let mut roots = RootCertStore::empty();
+            roots.add(&self.cert).unwrap();
+            roots
+        }
+
+        fn chain(&self) -> (Vec<Certificate>, PrivateKey) {
+            (vec![self.cert.clone()], self.key.clone())
+        }
+    }
+
+    // Connects the client and the server over a local TCP socket,
+    // sends a message from the client and echoes it back from the server.
+    // Returns the client's result and the peer certificates seen
+    // by the server.
+    fn exchange(
+        client_config: ClientConfig,
+        server_config: ServerConfig,
+    ) -> (io::Result<Vec<u8>>, Option<Vec<Certificate>>) {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_config = Arc::new(server_config);
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(sock, _)| {
+                let stream = TlsStream::accept(sock.unwrap(), &server_config);
+                tokio_io::io::read_exact(stream, [0; 4])
+            })
+            .and_then(|(stream, buf)| tokio_io::io::write_all(stream, buf))
+            .and_then(|(stream, _)| tokio_io::io::flush(stream))
+            .map(|stream| stream.get_ref().1.get_peer_certificates());
+
+        let client_config = Arc::new(client_config);
+        let client = TcpStream::connect(&addr)
+            .and_then(move |sock| {
+                let name = DNSNameRef::try_from_ascii_str("localhost").unwrap();
+                let session = ClientSession::new(&client_config, name);
+                let stream = TlsStream::new(sock, session);
+                tokio_io::io::write_all(stream, *b"ping")
+            })
+            .and_then(|(stream, _)| tokio_io::io::flush(stream))
+            .and_then(|stream| tokio_io::io::read_exact(stream, [0; 4]))
+            .map(|(stream, buf)| {
+                assert_eq!(stream.get_ref().1.get_alpn_protocol(), Some(ALPN_H2));
+                buf.to_vec()
+            });
+
+        let server = server.then(|res| Ok::<_, ()>(res.ok().and_then(|certs| certs)));
+        let client = client.then(Ok::<_, ()>);
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(client.join(server)).unwrap()
+    }
+
+    #[test]
+    fn exchanges_data_over_tls() {
+        let server = Credentials::generate();
+        let (cert_chain, key) = server.chain();
+        let server_config = server_config(cert_chain, key, None).unwrap();
+        let client_config = client_config(server.roots(), None);
+        let (res, peer_certs) = exchange(client_config, server_config);
+        assert_eq!(res.unwrap(), b"ping");
+        assert!(peer_certs.is_none());
+    }
+
+    #[test]
+    fn verifies_client_certificate() {
+        let server = Credentials::generate();
+        let client = Credentials::generate();
+        let (cert_chain, key) = server.chain();
+        let server_config = server_config(cert_chain, key, Some(client.roots())).unwrap();
+        let client_config = client_config(server.roots(), Some(client.chain()));
+        let (res, peer_certs) = exchange(client_config, server_config);
+        assert_eq!(res.unwrap(), b"ping");
+        assert_eq!(peer_certs, Some(vec![client.cert.clone()]));
+    }
+
+    #[test]
+    fn rejects_missing_client_certificate() {
+        let server = Credentials::generate();
+        let client = Credentials::generate();
+        let (cert_chain, key) = server.chain();
+        let server_config = server_config(cert_chain, key, Some(client.roots())).unwrap();
+        let client_config = client_config(server.roots(), None);
+        let (res, _) = exchange(client_config, server_config);
+        assert!(res.is_err());
+    }
+
+    // Connection that does not accept any more data.
+    struct ClosedForWriting;
+
+    impl Read for ClosedForWriting {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for ClosedForWriting {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_zero_is_an_error() {
+        let config = Arc::new(client_config(RootCertStore::empty(), None));
+        let name = DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let mut stream = TlsStream::new(ClosedForWriting, ClientSession::new(&config, name));
+        let err = stream.write(b"ping").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        let err = stream.flush().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn rejects_untrusted_server() {
+        let server = Credentials::generate();
+        let other = Credentials::generate();
+        let (cert_chain, key) = server.chain();
+        let server_config = server_config(cert_chain, key, None).unwrap();
+        let client_config = client_config(other.roots(), None);
+        let (res, _) = exchange(client_config, server_config);
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::{
+    request_stream::Processing,
+    response_future::ResponseFuture,
+    subscription::{Subscription, SubscriptionFuture},
+};
+
+use chain_core::property;
+use network_core::client::{self, Client, HandshakeError};
+use network_core::error::Error;
+use network_core::gossip::Gossip;
+use network_core::server::{self, Node};
+
+use futures::future::{self, FutureResult};
+use futures::prelude::*;
+
+type BoxStream<T> = Box<dyn Stream<Item = T, Error = Error> + Send>;
+
+type BlockService<T> = <T as Node>::BlockService;
+type FragmentService<T> = <T as Node>::FragmentService;
+type GossipService<T> = <T as Node>::GossipService;
+type NodeId<T> = <BlockService<T> as server::P2pService>::NodeId;
+type Header<T> = <BlockService<T> as server::BlockService>::Header;
+type Fragment<T> = <FragmentService<T> as server::FragmentService>::Fragment;
+type GossipNode<T> = <GossipService<T> as server::GossipService>::Node;
+
+// The loopback connection does not authenticate the client node,
+// so the serving node is asked to accept the identifier it has been given.
+macro_rules! try_accept_subscriber {
+    ($service:expr, $subscriber:expr) => {
+        if let Err(e) = $service.accept_unauthenticated_peer(&$subscriber) {
+            return SubscriptionFuture::error(e);
+        }
+    };
+}
+
+/// Client connection to a node served in the same process.
+///
+/// The requests made on the connection are passed directly to the
+/// services of the wrapped `Node` implementation.
+pub struct Connection<T: Node> {
+    node: T,
+    node_id: Option<NodeId<T>>,
+}
+
+impl<T: Node> Connection<T> {
+    pub fn new(node: T) -> Self {
+        Connection {
+            node,
+            node_id: None,
+        }
+    }
+
+    /// Sets the identifier of the client node, which is passed to the
+    /// services of the serving node when establishing subscriptions.
+    pub fn node_id(&mut self, id: NodeId<T>) -> &mut Self {
+        self.node_id = Some(id);
+        self
+    }
+
+    fn subscriber(&self) -> Option<NodeId<T>> {
+        self.node_id.clone()
+    }
+}
+
+impl<T: Node> Client for Connection<T> {
+    fn poll_ready(&mut self) -> Poll<(), Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<T: Node> client::P2pService for Connection<T> {
+    type NodeId = NodeId<T>;
+}
+
+impl<T: Node> client::BlockService for Connection<T> {
+    type Block = <BlockService<T> as server::BlockService>::Block;
+
+    type HandshakeFuture =
+        FutureResult<<BlockService<T> as server::BlockService>::BlockId, HandshakeError>;
+
+    type TipFuture = ResponseFuture<<BlockService<T> as server::BlockService>::TipFuture>;
+
+    type PullBlocksStream = <BlockService<T> as server::BlockService>::PullBlocksStream;
+    type PullBlocksToTipFuture =
+        ResponseFuture<<BlockService<T> as server::BlockService>::PullBlocksToTipFuture>;
+
+    type PullHeadersStream = <BlockService<T> as server::BlockService>::PullHeadersStream;
+    type PullHeadersFuture =
+        ResponseFuture<<BlockService<T> as server::BlockService>::PullHeadersFuture>;
+
+    type GetBlocksStream = <BlockService<T> as server::BlockService>::GetBlocksStream;
+    type GetBlocksFuture =
+        ResponseFuture<<BlockService<T> as server::BlockService>::GetBlocksFuture>;
+
+    type PushHeadersFuture = Processing<
+        BoxStream<Header<T>>,
+        <BlockService<T> as server::BlockService>::PushHeadersSink,
+    >;
+
+    type UploadBlocksFuture = Processing<
+        BoxStream<Self::Block>,
+        <BlockService<T> as server::BlockService>::UploadBlocksSink,
+    >;
+
+    type BlockSubscription = Subscription<
+        BoxStream<Header<T>>,
+        <BlockService<T> as server::BlockService>::BlockSubscription,
+    >;
+    type BlockSubscriptionFuture = SubscriptionFuture<
+        BoxStream<Header<T>>,
+        NodeId<T>,
+        <BlockService<T> as server::BlockService>::BlockSubscriptionFuture,
+    >;
+
+    fn handshake(&mut self) -> Self::HandshakeFuture {
+        use server::BlockService;
+
+        match self.node.block_service() {
+            Some(service) => future::ok(service.block0()),
+            None => future::err(HandshakeError::Rpc(Error::unimplemented())),
+        }
+    }
+
+    fn tip(&mut self) -> Self::TipFuture {
+        use server::BlockService;
+
+        match self.node.block_service() {
+            Some(service) => ResponseFuture::new(service.tip()),
+            None => ResponseFuture::unimplemented(),
+        }
+    }
+
+    fn pull_blocks_to_tip(
+        &mut self,
+        from: &[<Self::Block as property::Block>::Id],
+    ) -> Self::PullBlocksToTipFuture {
+        use server::BlockService;
+
+        match self.node.block_service() {
+            Some(service) => ResponseFuture::new(service.pull_blocks_to_tip(from)),
+            None => ResponseFuture::unimplemented(),
+        }
+    }
+
+    fn pull_headers(
+        &mut self,
+        from: &[<Self::Block as property::Block>::Id],
+        to: &<Self::Block as property::Block>::Id,
+    ) -> Self::PullHeadersFuture {
+        use server::BlockService;
+
+        match self.node.block_service() {
+            Some(service) => ResponseFuture::new(service.pull_headers(from, to)),
+            None => ResponseFuture::unimplemented(),
+        }
+    }
+
+    fn get_blocks(
+        &mut self,
+        ids: &[<Self::Block as property::Block>::Id],
+    ) -> Self::GetBlocksFuture {
+        use server::BlockService;
+
+        match self.node.block_service() {
+            Some(service) => ResponseFuture::new(service.get_blocks(ids)),
+            None => ResponseFuture::unimplemented(),
+        }
+    }
+
+    fn push_headers<S>(&mut self, headers: S) -> Self::PushHeadersFuture
+    where
+        S: Stream<Item = Header<T>, Error = Error> + Send + 'static,
+    {
+        use server::BlockService;
+
+        match self.node.block_service() {
+            Some(service) => Processing::new(Box::new(headers), service.push_headers()),
+            None => Processing::unimplemented(),
+        }
+    }
+
+    fn upload_blocks<S>(&mut self, blocks: S) -> Self::UploadBlocksFuture
+    where
+        S: Stream<Item = Self::Block, Error = Error> + Send + 'static,
+    {
+        use server::BlockService;
+
+        match self.node.block_service() {
+            Some(service) => Processing::new(Box::new(blocks), service.upload_blocks()),
+            None => Processing::unimplemented(),
+        }
+    }
+
+    fn block_subscription<Out>(&mut self, outbound: Out) -> Self::BlockSubscriptionFuture
+    where
+        Out: Stream<Item = Header<T>, Error = Error> + Send + 'static,
+    {
+        use server::{BlockService, P2pService};
+
+        let subscriber = match self.subscriber() {
+            Some(id) => id,
+            None => return SubscriptionFuture::missing_node_id(),
+        };
+        match self.node.block_service() {
+            Some(service) => {
+                try_accept_subscriber!(service, subscriber);
+                SubscriptionFuture::new(
+                    service.node_id(),
+                    Box::new(outbound),
+                    service.block_subscription(subscriber),
+                )
+            }
+            None => SubscriptionFuture::unimplemented(),
+        }
+    }
+}
+
+impl<T> client::FragmentService for Connection<T>
+where
+    T: Node,
+    FragmentService<T>: server::P2pService<NodeId = NodeId<T>>,
+    Fragment<T>:
+        property::Fragment<Id = <FragmentService<T> as server::FragmentService>::FragmentId>,
+{
+    type Fragment = Fragment<T>;
+    type BlockId = <FragmentService<T> as server::FragmentService>::BlockId;
+
+    type GetFragmentsStream = <FragmentService<T> as server::FragmentService>::GetFragmentsStream;
+    type GetFragmentsFuture =
+        ResponseFuture<<FragmentService<T> as server::FragmentService>::GetFragmentsFuture>;
+
+    type FragmentSubscription = Subscription<
+        BoxStream<Fragment<T>>,
+        <FragmentService<T> as server::FragmentService>::FragmentSubscription,
+    >;
+    type FragmentSubscriptionFuture = SubscriptionFuture<
+        BoxStream<Fragment<T>>,
+        NodeId<T>,
+        <FragmentService<T> as server::FragmentService>::FragmentSubscriptionFuture,
+    >;
+
+    fn get_fragments(
+        &mut self,
+        ids: &[<Self::Fragment as property::Fragment>::Id],
+    ) -> Self::GetFragmentsFuture {
+        use server::FragmentService;
+
+        match self.node.fragment_service() {
+            Some(service) => ResponseFuture::new(service.get_fragments(ids)),
+            None => ResponseFuture::unimplemented(),
+        }
+    }
+
+    fn get_block_fragments(
+        &mut self,
+        block_id: &Self::BlockId,
+        indices: &[u32],
+    ) -> Result<Self::GetFragmentsFuture, Error> {
+        use server::FragmentService;
+
+        match self.node.fragment_service() {
+            Some(service) => service
+                .get_block_fragments(block_id, indices)
+                .map(ResponseFuture::new),
+            None => Ok(ResponseFuture::unimplemented()),
+        }
+    }
+
+    fn fragment_subscription<Out>(&mut self, outbound: Out) -> Self::FragmentSubscriptionFuture
+    where
+        Out: Stream<Item = Fragment<T>, Error = Error> + Send + 'static,
+    {
+        use server::{FragmentService, P2pService};
+
+        let subscriber = match self.subscriber() {
+            Some(id) => id,
+            None => return SubscriptionFuture::missing_node_id(),
+        };
+        match self.node.fragment_service() {
+            Some(service) => {
+                try_accept_subscriber!(service, subscriber);
+                SubscriptionFuture::new(
+                    service.node_id(),
+                    Box::new(outbound),
+                    service.fragment_subscription(subscriber),
+                )
+            }
+            None => SubscriptionFuture::unimplemented(),
+        }
+    }
+}
+
+impl<T> client::GossipService for Connection<T>
+where
+    T: Node,
+    GossipService<T>: server::P2pService<NodeId = NodeId<T>>,
+{
+    type Node = GossipNode<T>;
+
+    type GossipSubscription = Subscription<
+        BoxStream<Gossip<GossipNode<T>>>,
+        <GossipService<T> as server::GossipService>::GossipSubscription,
+    >;
+    type GossipSubscriptionFuture = SubscriptionFuture<
+        BoxStream<Gossip<GossipNode<T>>>,
+        NodeId<T>,
+        <GossipService<T> as server::GossipService>::GossipSubscriptionFuture,
+    >;
+
+    type PeersFuture = ResponseFuture<<GossipService<T> as server::GossipService>::PeersFuture>;
+
+    fn gossip_subscription<Out>(&mut self, outbound: Out) -> Self::GossipSubscriptionFuture
+    where
+        Out: Stream<Item = Gossip<GossipNode<T>>, Error = Error> + Send + 'static,
+    {
+        use server::{GossipService, P2pService};
+
+        let subscriber = match self.subscriber() {
+            Some(id) => id,
+            None => return SubscriptionFuture::missing_node_id(),
+        };
+        match self.node.gossip_service() {
+            Some(service) => {
+                try_accept_subscriber!(service, subscriber);
+                SubscriptionFuture::new(
+                    service.node_id(),
+                    Box::new(outbound),
+                    service.gossip_subscription(subscriber),
+                )
+            }
+            None => SubscriptionFuture::unimplemented(),
+        }
+    }
+
+    fn peers(&mut self) -> Self::PeersFuture {
+        use server::GossipService;
+
+        match self.node.gossip_service() {
+            Some(service) => ResponseFuture::new(service.peers()),
+            None => ResponseFuture::unimplemented(),
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! In-process transport for the network subsystem of a blockchain node.
+//!
+//! A `Connection` implements the client-side service traits of
+//! `network-core` by calling directly into an implementation of
+//! `network_core::server::Node`, without any serialization or sockets.
+//! Streamed requests and subscriptions are forwarded into the sinks
+//! provided by the server-side services, respecting their readiness,
+//! so the back-pressure behavior is the same as with a network transport.
+//!
+//! No tasks are spawned by the transport: all the work is done when
+//! the futures and streams returned by the client connection are polled,
+//! which makes it possible to run deterministic simulations of multiple
+//! nodes in tests.
+
+#![warn(clippy::all)]
+
+#[macro_use]
+extern crate futures;
+
+mod connection;
+
+pub mod request_stream;
+pub mod response_future;
+pub mod subscription;
+
+pub use connection::Connection;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,94 @@
+use network_core::error::Error;
+use network_core::server::request_stream::{Forward, MapResponse, ProcessingError};
+
+use futures::prelude::*;
+use futures::stream;
+
+use std::mem;
+
+/// Inbound stream of a client-streamed request, with the errors
+/// attributed to the client stream.
+pub(crate) type ClientStream<In> = stream::MapErr<In, fn(Error) -> ProcessingError>;
+
+pub(crate) fn client_stream<In>(stream: In) -> ClientStream<In>
+where
+    In: Stream<Error = Error>,
+{
+    stream.map_err(ProcessingError::Inbound)
+}
+
+/// Future for a client-streamed request, resolving to the response
+/// of the server-side service.
+#[must_use = "futures do nothing unless polled"]
+pub struct Processing<In, S>
+where
+    S: Sink + MapResponse,
+{
+    state: State<In, S>,
+}
+
+enum State<In, S>
+where
+    S: Sink + MapResponse,
+{
+    Forwarding(Forward<ClientStream<In>, S>),
+    PendingResponse(S::ResponseFuture),
+    Failed(Error),
+    Finished,
+}
+
+impl<In, S> Processing<In, S>
+where
+    In: Stream<Error = Error>,
+    S: Sink + MapResponse,
+{
+    pub(crate) fn new(inbound: In, sink: S) -> Self {
+        Processing {
+            state: State::Forwarding(Forward::new(client_stream(inbound), sink)),
+        }
+    }
+
+    pub(crate) fn unimplemented() -> Self {
+        Processing {
+            state: State::Failed(Error::unimplemented()),
+        }
+    }
+}
+
+impl<In, S> Future for Processing<In, S>
+where
+    In: Stream<Error = Error>,
+    S: Sink<SinkItem = In::Item, SinkError = Error>,
+    S: MapResponse,
+{
+    type Item = S::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<S::Response, Error> {
+        use State::*;
+        loop {
+            match &mut self.state {
+                Forwarding(forward) => match forward.poll_step() {
+                    Async::NotReady => return Ok(Async::NotReady),
+                    Async::Ready(None) => {}
+                    Async::Ready(Some((_sink, shutdown))) => {
+                        self.state = PendingResponse(shutdown);
+                    }
+                },
+                PendingResponse(future) => {
+                    let res = try_ready!(future.poll());
+                    self.state = Finished;
+                    return Ok(Async::Ready(res));
+                }
+                Failed(_) => {
+                    if let Failed(e) = mem::replace(&mut self.state, Finished) {
+                        return Err(e);
+                    } else {
+                        unreachable!()
+                    }
+                }
+                Finished => panic!("polled a finished request processing future"),
+            }
+        }
+    }
+}
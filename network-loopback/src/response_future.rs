@@ -0,0 +1,49 @@
+use network_core::error::Error;
+
+use futures::prelude::*;
+
+use std::mem;
+
+/// Future resolving to the response of a service request,
+/// or to an error if the service is not available.
+#[must_use = "futures do nothing unless polled"]
+pub enum ResponseFuture<F> {
+    Pending(F),
+    Failed(Error),
+    Finished,
+}
+
+impl<F> ResponseFuture<F> {
+    pub fn new(future: F) -> Self {
+        ResponseFuture::Pending(future)
+    }
+
+    pub fn unimplemented() -> Self {
+        ResponseFuture::Failed(Error::unimplemented())
+    }
+}
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future<Error = Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<F::Item, Error> {
+        if let ResponseFuture::Pending(f) = self {
+            let res = f.poll();
+            if let Ok(Async::NotReady) = res {
+                return Ok(Async::NotReady);
+            }
+            *self = ResponseFuture::Finished;
+            res
+        } else {
+            match mem::replace(self, ResponseFuture::Finished) {
+                ResponseFuture::Pending(_) => unreachable!(),
+                ResponseFuture::Failed(e) => Err(e),
+                ResponseFuture::Finished => panic!("polled a finished response"),
+            }
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use crate::request_stream::{client_stream, ClientStream};
+
+use network_core::error::{Code, Error};
+use network_core::server::request_stream::{Forward, MapResponse};
+
+use futures::prelude::*;
+
+use std::mem;
+
+/// Client side of a bidirectional subscription.
+///
+/// Polling this stream receives the items sent by the server-side
+/// subscription, and also forwards the items of the client's outbound
+/// stream into the subscription sink. Both directions only make progress
+/// while the subscription is polled.
+#[must_use = "streams do nothing unless polled"]
+pub struct Subscription<Out, S>
+where
+    S: Sink + MapResponse,
+{
+    state: State<Out, S>,
+}
+
+enum State<Out, S>
+where
+    S: Sink + MapResponse,
+{
+    Full(Forward<ClientStream<Out>, S>),
+    OutboundClosed {
+        inbound: Option<S>,
+        shutdown: Option<S::ResponseFuture>,
+    },
+    InboundGone {
+        sink: S,
+    },
+}
+
+impl<Out, S> State<Out, S>
+where
+    S: Stream + Sink + MapResponse,
+{
+    fn inbound_stream(&mut self) -> Option<&mut S> {
+        match self {
+            State::Full(forward) => Some(forward.sink_mut()),
+            State::OutboundClosed { inbound, .. } => inbound.as_mut(),
+            State::InboundGone { .. } => None,
+        }
+    }
+}
+
+impl<Out, S> Subscription<Out, S>
+where
+    Out: Stream<Error = Error>,
+    S: Sink + MapResponse,
+{
+    fn new(outbound: Out, service_subscription: S) -> Self {
+        Subscription {
+            state: State::Full(Forward::new(client_stream(outbound), service_subscription)),
+        }
+    }
+}
+
+enum OutboundOutcome {
+    Continue,
+    Closed,
+}
+
+impl<Out, S> Subscription<Out, S>
+where
+    Out: Stream<Error = Error>,
+    S: Sink<SinkItem = Out::Item, SinkError = Error>,
+    S: MapResponse,
+{
+    fn process_outbound(&mut self) -> Poll<OutboundOutcome, Error> {
+        use OutboundOutcome::{Closed, Continue};
+
+        match &mut self.state {
+            State::Full(forward) => match forward.poll_step() {
+                Async::NotReady => Ok(Async::NotReady),
+                Async::Ready(None) => Ok(Continue.into()),
+                Async::Ready(Some((inbound, shutdown))) => {
+                    self.state = State::OutboundClosed {
+                        inbound: Some(inbound),
+                        shutdown: Some(shutdown),
+                    };
+                    Ok(Continue.into())
+                }
+            },
+            State::OutboundClosed { inbound, shutdown } => {
+                if let Some(future) = shutdown {
+                    try_ready!(future.poll());
+                    *shutdown = None;
+                    return Ok(Continue.into());
+                }
+                // Same as with the network transports, keep receiving
+                // until the service ends its stream.
+                if inbound.is_some() {
+                    Ok(Async::NotReady)
+                } else {
+                    Ok(Closed.into())
+                }
+            }
+            State::InboundGone { sink } => {
+                try_ready!(sink.close());
+                Ok(Closed.into())
+            }
+        }
+    }
+
+    fn drop_inbound(&mut self) {
+        match &mut self.state {
+            State::Full(forward) => {
+                let sink = forward.break_up();
+                self.state = State::InboundGone { sink };
+            }
+            State::OutboundClosed {
+                ref mut inbound, ..
+            } => {
+                *inbound = None;
+            }
+            State::InboundGone { .. } => {
+                unreachable!("should not poll None more than once from the inbound stream")
+            }
+        }
+    }
+}
+
+impl<Out, S> Stream for Subscription<Out, S>
+where
+    Out: Stream<Error = Error>,
+    S: Stream<Error = Error>,
+    S: Sink<SinkItem = Out::Item, SinkError = Error>,
+    S: MapResponse,
+{
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        use OutboundOutcome::{Closed, Continue};
+
+        loop {
+            if let Some(stream) = self.state.inbound_stream() {
+                match stream.poll()? {
+                    Async::NotReady => {
+                        // Let outbound processing decide
+                        // if the whole thing is ready.
+                    }
+                    Async::Ready(Some(item)) => {
+                        // Make sure outbound is processed in turn and
+                        // handle termination, but otherwise
+                        // don't worry if it's not ready as we have an
+                        // item to return.
+                        match self.process_outbound()? {
+                            Async::Ready(Closed) => return Ok(None.into()),
+                            Async::NotReady | Async::Ready(Continue) => {}
+                        }
+                        return Ok(Some(item).into());
+                    }
+                    Async::Ready(None) => {
+                        // Same as with the network transports, the
+                        // subscription is over once the service ends
+                        // its stream. Stop forwarding and close the sink.
+                        self.drop_inbound();
+                    }
+                }
+            }
+            match try_ready!(self.process_outbound()) {
+                Continue => continue,
+                Closed => return Ok(None.into()),
+            }
+        }
+    }
+}
+
+/// Future resolving to a subscription established with the service
+/// and the identifier of the serving node.
+#[must_use = "futures do nothing unless polled"]
+pub struct SubscriptionFuture<Out, Id, F> {
+    state: FutureState<Out, Id, F>,
+}
+
+enum FutureState<Out, Id, F> {
+    Normal {
+        inner: F,
+        outbound: Out,
+        node_id: Id,
+    },
+    Failed(Error),
+    Finished,
+}
+
+impl<Out, Id, F> SubscriptionFuture<Out, Id, F> {
+    pub(crate) fn new(node_id: Id, outbound: Out, service_subscription: F) -> Self {
+        SubscriptionFuture {
+            state: FutureState::Normal {
+                inner: service_subscription,
+                outbound,
+                node_id,
+            },
+        }
+    }
+
+    pub(crate) fn unimplemented() -> Self {
+        SubscriptionFuture {
+            state: FutureState::Failed(Error::unimplemented()),
+        }
+    }
+
+    pub(crate) fn error(e: Error) -> Self {
+        SubscriptionFuture {
+            state: FutureState::Failed(e),
+        }
+    }
+
+    pub(crate) fn missing_node_id() -> Self {
+        Self::error(Error::new(
+            Code::InvalidArgument,
+            "subscriber node identifier is not set on the connection",
+        ))
+    }
+}
+
+impl<Out, Id, F> Future for SubscriptionFuture<Out, Id, F>
+where
+    Out: Stream<Error = Error>,
+    F: Future<Error = Error>,
+    F::Item: Sink + MapResponse,
+{
+    type Item = (Subscription<Out, F::Item>, Id);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Error> {
+        let service_subscription = match &mut self.state {
+            FutureState::Normal { inner, .. } => Some(try_ready!(inner.poll())),
+            _ => None,
+        };
+        match mem::replace(&mut self.state, FutureState::Finished) {
+            FutureState::Normal {
+                outbound, node_id, ..
+            } => {
+                let subscription = Subscription::new(outbound, service_subscription.unwrap());
+                Ok(Async::Ready((subscription, node_id)))
+            }
+            FutureState::Failed(e) => Err(e),
+            FutureState::Finished => panic!("polled a finished subscription future"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network_core::server::request_stream::ProcessingError;
+
+    use futures::{future, stream};
+
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct Observed {
+        accepted: Vec<u32>,
+        terminated: Option<bool>,
+    }
+
+    /// Service-side subscription which accepts one item at a time
+    /// and sends a fixed sequence of items.
+    struct MockSubscription {
+        outbound: VecDeque<u32>,
+        pending: Option<u32>,
+        observed: Rc<RefCell<Observed>>,
+    }
+
+    impl MockSubscription {
+        fn new(outbound: Vec<u32>) -> Self {
+            MockSubscription {
+                outbound: outbound.into(),
+                pending: None,
+                observed: Default::default(),
+            }
+        }
+    }
+
+    impl Stream for MockSubscription {
+        type Item = u32;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Option<u32>, Error> {
+            match self.outbound.pop_front() {
+                Some(item) => Ok(Some(item).into()),
+                // Keep the subscription open for the client to finish
+                None if self.observed.borrow().terminated.is_none() => Ok(Async::NotReady),
+                None => Ok(None.into()),
+            }
+        }
+    }
+
+    impl Sink for MockSubscription {
+        type SinkItem = u32;
+        type SinkError = Error;
+
+        fn start_send(&mut self, item: u32) -> StartSend<u32, Error> {
+            if let Some(pending) = self.pending.take() {
+                // Free the capacity as if the item got processed
+                // concurrently, waking up the task as a full sink must.
+                self.observed.borrow_mut().accepted.push(pending);
+                futures::task::current().notify();
+                return Ok(AsyncSink::NotReady(item));
+            }
+            self.pending = Some(item);
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), Error> {
+            let pending = self.pending.take();
+            self.observed.borrow_mut().accepted.extend(pending);
+            Ok(Async::Ready(()))
+        }
+    }
+
+    impl MapResponse for MockSubscription {
+        type Response = ();
+        type ResponseFuture = future::FutureResult<(), Error>;
+
+        fn on_stream_termination(
+            &mut self,
+            res: Result<(), ProcessingError>,
+        ) -> Self::ResponseFuture {
+            self.observed.borrow_mut().terminated = Some(res.is_ok());
+            future::ok(())
+        }
+    }
+
+    fn subscribe(
+        client_items: Vec<u32>,
+        service: MockSubscription,
+    ) -> Subscription<stream::IterOk<std::vec::IntoIter<u32>, Error>, MockSubscription> {
+        let future =
+            SubscriptionFuture::new((), stream::iter_ok(client_items), future::ok(service));
+        let (subscription, ()) = future.wait().unwrap();
+        subscription
+    }
+
+    #[test]
+    fn exchanges_items_in_both_directions() {
+        let service = MockSubscription::new(vec![10, 20]);
+        let observed = service.observed.clone();
+        let subscription = subscribe(vec![1, 2, 3], service);
+        let received = subscription.wait().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(received, vec![10, 20]);
+
+        let observed = observed.borrow();
+        assert_eq!(observed.accepted, vec![1, 2, 3]);
+        assert_eq!(observed.terminated, Some(true));
+    }
+
+    #[test]
+    fn unimplemented_subscription_fails() {
+        let future: SubscriptionFuture<
+            stream::Empty<u32, Error>,
+            (),
+            future::FutureResult<MockSubscription, Error>,
+        > = SubscriptionFuture::unimplemented();
+        match future.wait() {
+            Err(e) => assert_eq!(e.code(), Code::Unimplemented),
+            Ok(_) => panic!("subscription should not be established"),
+        }
+    }
+}
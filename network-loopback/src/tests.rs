@@ -0,0 +1,200 @@
+//! End-to-end tests of the loopback connection against a mock node.
+
+use crate::Connection;
+
+use network_core::client::{self, BlockService as _, GossipService as _};
+use network_core::error::{Code, Error};
+use network_core::subscription::BlockEvent;
+use network_core::testing::{Block, BlockId, MockNode, TestFragment, TestFragmentId, TestNodeId};
+
+use futures::prelude::*;
+use futures::stream;
+
+use std::time::{Duration, Instant};
+
+fn ids(blocks: &[Block]) -> Vec<BlockId> {
+    blocks.iter().map(|block| block.id()).collect()
+}
+
+#[test]
+fn handshake_returns_block0() {
+    let node = MockNode::new(3);
+    let block0 = node.chain[0].id();
+    let mut conn = Connection::new(node);
+    assert_eq!(conn.handshake().wait().unwrap(), block0);
+}
+
+#[test]
+fn unary_requests() {
+    let node = MockNode::new(5);
+    let chain = node.chain.clone();
+    let mut conn = Connection::new(node);
+
+    assert_eq!(conn.tip().wait().unwrap(), chain[4]);
+
+    let peers = conn.peers().wait().unwrap();
+    assert_eq!(peers.peers.len(), 1);
+
+    let unknown = chain[4].make_child(None).id();
+    let err = client::FragmentService::get_block_fragments(&mut conn, &unknown, &[0])
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[test]
+fn streamed_responses() {
+    let node = MockNode::new(6);
+    let chain = node.chain.clone();
+    let mut conn = Connection::new(node);
+
+    let blocks = conn
+        .pull_blocks_to_tip(&[chain[2].id()])
+        .wait()
+        .unwrap()
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(ids(&blocks), ids(&chain[3..]));
+
+    let headers = conn
+        .pull_headers(&[chain[0].id(), chain[1].id()], &chain[4].id())
+        .wait()
+        .unwrap()
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(ids(&headers), ids(&chain[2..=4]));
+
+    let blocks = conn
+        .get_blocks(&[chain[5].id(), chain[0].id()])
+        .wait()
+        .unwrap()
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(ids(&blocks), vec![chain[5].id(), chain[0].id()]);
+
+    let fragments =
+        client::FragmentService::get_fragments(&mut conn, &[TestFragmentId(1), TestFragmentId(3)])
+            .wait()
+            .unwrap()
+            .collect()
+            .wait()
+            .unwrap();
+    assert_eq!(fragments, vec![TestFragment(1), TestFragment(3)]);
+
+    let err = conn.pull_blocks_to_tip(&[BlockId(0)]).wait().err().unwrap();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[test]
+fn uploads_are_forwarded_to_the_service() {
+    let node = MockNode::new(1);
+    let uploaded = node.uploaded_blocks.clone();
+    let pushed = node.pushed_headers.clone();
+    let mut conn = Connection::new(node);
+
+    let blocks = MockNode::new(4).chain;
+    conn.upload_blocks(stream::iter_ok(blocks.clone()))
+        .wait()
+        .unwrap();
+    {
+        let uploaded = uploaded.lock().unwrap();
+        assert_eq!(ids(&uploaded.items), ids(&blocks));
+        assert_eq!(uploaded.terminated, Some(true));
+    }
+
+    // the service observes the failure of the client stream
+    let headers = stream::iter_ok(blocks[..2].to_vec()).chain(stream::once(Err(Error::new(
+        Code::Aborted,
+        "client gave up",
+    ))));
+    conn.push_headers(headers).wait().unwrap();
+    let pushed = pushed.lock().unwrap();
+    assert_eq!(ids(&pushed.items), ids(&blocks[..2]));
+    assert_eq!(pushed.terminated, Some(false));
+}
+
+#[test]
+fn block_subscription_exchanges_items() {
+    let node = MockNode::new(3);
+    let chain = node.chain.clone();
+    let received = node.subscription_headers.clone();
+    let subscribers = node.subscribers.clone();
+    let mut conn = Connection::new(node);
+    conn.node_id(TestNodeId(2));
+
+    let announced = MockNode::new(2).chain;
+    let (subscription, node_id) = conn
+        .block_subscription(stream::iter_ok(announced.clone()))
+        .wait()
+        .unwrap();
+    assert_eq!(node_id, TestNodeId(1));
+
+    let events = subscription.collect().wait().unwrap();
+    let headers: Vec<BlockId> = events
+        .into_iter()
+        .map(|event| match event {
+            BlockEvent::Announce(header) => header.id(),
+            other => panic!("unexpected event {:?}", other),
+        })
+        .collect();
+    assert_eq!(headers, ids(&chain));
+
+    let received = received.lock().unwrap();
+    assert_eq!(ids(&received.items), ids(&announced));
+    assert_eq!(received.terminated, Some(true));
+    assert_eq!(*subscribers.lock().unwrap(), vec![TestNodeId(2)]);
+}
+
+#[test]
+fn fragment_subscription_receives_fragments() {
+    let mut conn = Connection::new(MockNode::new(1));
+    conn.node_id(TestNodeId(2));
+
+    let (subscription, _) =
+        client::FragmentService::fragment_subscription(&mut conn, stream::empty())
+            .wait()
+            .unwrap();
+    let fragments = subscription.collect().wait().unwrap();
+    assert_eq!(fragments, (0..4).map(TestFragment).collect::<Vec<_>>());
+}
+
+#[test]
+fn subscription_requires_node_id() {
+    let mut conn = Connection::new(MockNode::new(1));
+    let err = conn
+        .block_subscription(stream::empty())
+        .wait()
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
+
+#[test]
+fn missing_service_is_unimplemented() {
+    let mut node = MockNode::new(1);
+    node.serve_blocks = false;
+    let mut conn = Connection::new(node);
+
+    assert_eq!(conn.tip().wait().err().unwrap().code(), Code::Unimplemented);
+    let err = conn.upload_blocks(stream::empty()).wait().err().unwrap();
+    assert_eq!(err.code(), Code::Unimplemented);
+}
+
+#[test]
+fn banned_subscriber_is_rejected() {
+    let node = MockNode::new(1);
+    let until = Instant::now() + Duration::from_secs(60);
+    node.scores.lock().unwrap().ban(TestNodeId(2), until);
+    let mut conn = Connection::new(node);
+    conn.node_id(TestNodeId(2));
+
+    let err = conn
+        .block_subscription(stream::empty())
+        .wait()
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), Code::PermissionDenied);
+}